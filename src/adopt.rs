@@ -0,0 +1,93 @@
+use crate::{is_arborist_worktree, plan_worktree, require_repo};
+use arborist::error::{ArboristError, Result};
+use arborist::{git, state};
+use std::fs;
+use std::path::Path;
+
+// `arborist adopt`: take over a worktree made with `git worktree add`, so arborist reuses,
+// reports and cleans it up like one of its own
+pub fn run_adopt(path: &Path, name: Option<&str>, rename_branch: bool) -> Result<i32> {
+    let repo = require_repo()?;
+    let path = fs::canonicalize(path)
+        .map_err(|err| ArboristError::InvalidPath(format!("{}: {}", path.display(), err)))?;
+    let Some(entry) = git::list_worktrees()?
+        .into_iter()
+        .find(|entry| entry.path == path)
+    else {
+        return Err(ArboristError::InvalidPath(format!(
+            "{} is not a worktree of {}",
+            path.display(),
+            repo.root.display()
+        )));
+    };
+    if entry.path == repo.root {
+        return Err(ArboristError::InvalidPath(format!(
+            "{} is the repository's main checkout",
+            path.display()
+        )));
+    }
+    if is_arborist_worktree(&repo, &entry)? {
+        return Err(ArboristError::InvalidPath(format!(
+            "{} is already an arborist worktree",
+            path.display()
+        )));
+    }
+
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    if name.is_empty() || name.contains('/') {
+        return Err(ArboristError::InvalidPath(format!(
+            "'{}' can't name a worktree; pick another with --name",
+            name
+        )));
+    }
+    let existing = plan_worktree(repo.clone(), name.clone())?;
+    if state::load_worktree(&repo.root, &name)?.is_some() || git::worktree_exists(&existing.path)? {
+        return Err(ArboristError::InvalidPath(format!(
+            "There is already an arborist worktree named '{}'; pick another with --name",
+            name
+        )));
+    }
+
+    // Only what the worktree has over the current checkout counts as its work
+    let head = git::head_commit(&path)?;
+    let base_commit = git::merge_base(&head, &repo.current_commit)?.unwrap_or(head);
+
+    // A branch arborist doesn't own is left alone when the worktree is removed
+    let detached = entry.branch.is_none();
+    let branch = match entry.branch {
+        Some(branch) if rename_branch => {
+            let owned = format!("arborist/{}", name);
+            git::rename_branch(&branch, &owned)?;
+            notice!("Renamed branch {} to {}", branch, owned);
+            None
+        }
+        branch => {
+            if rename_branch && detached {
+                warning!("{} has no branch to rename", path.display());
+            }
+            branch
+        }
+    };
+
+    let meta = state::WorktreeMeta {
+        base_commit,
+        base_branch: repo.current_branch.clone(),
+        detached,
+        branch,
+        path: Some(path.clone()),
+        created_at: state::unix_now(),
+        parked: false,
+    };
+    git::ensure_repo_id(&repo.root)?;
+    state::record_repo(&repo.root)?;
+    state::record_worktree(&repo.root, &name, &meta)?;
+
+    notice!("Adopted {} as worktree {}", path.display(), name);
+    Ok(0)
+}
@@ -0,0 +1,96 @@
+use crate::WorktreePlan;
+use arborist::git;
+use arborist::webhook;
+use serde::Serialize;
+use std::path::Path;
+
+// Message posted to the announce endpoint at the start and end of a run
+#[derive(Serialize)]
+struct Announcement<'a> {
+    event: &'a str,
+    worktree: &'a str,
+    branch: Option<&'a str>,
+    path: &'a Path,
+    repo: &'a Path,
+    command: &'a [String],
+    author: String,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kept: Option<bool>,
+}
+
+// Posts run start/finish messages to a team endpoint; failures only warn
+pub struct Announcer<'a> {
+    url: &'a str,
+    plan: &'a WorktreePlan,
+    command: &'a [String],
+    author: String,
+}
+
+impl<'a> Announcer<'a> {
+    pub fn new(url: &'a str, plan: &'a WorktreePlan, command: &'a [String]) -> Self {
+        Announcer {
+            url,
+            plan,
+            command,
+            author: author(),
+        }
+    }
+
+    pub fn started(&self) {
+        let text = format!(
+            "{} started `{}` in {} ({})",
+            self.author,
+            self.command.join(" "),
+            self.plan.name,
+            self.plan.repo.root.display()
+        );
+        self.post("start", text, None, None);
+    }
+
+    pub fn finished(&self, exit_code: i32, kept: bool) {
+        let text = format!(
+            "{} finished `{}` in {} with exit code {}; worktree {}",
+            self.author,
+            self.command.join(" "),
+            self.plan.name,
+            exit_code,
+            if kept { "kept" } else { "removed" }
+        );
+        self.post("finish", text, Some(exit_code), Some(kept));
+    }
+
+    fn post(&self, event: &str, text: String, exit_code: Option<i32>, kept: Option<bool>) {
+        let announcement = Announcement {
+            event,
+            worktree: &self.plan.name,
+            branch: self.plan.branch.as_deref(),
+            path: &self.plan.path,
+            repo: &self.plan.repo.root,
+            command: self.command,
+            author: self.author.clone(),
+            text,
+            exit_code,
+            kept,
+        };
+
+        if let Err(e) = webhook::post_json(self.url, &announcement) {
+            warning!("Failed to announce run {}: {}", event, e);
+        }
+    }
+}
+
+// Identify who is running the command, preferring the git identity
+fn author() -> String {
+    let name = git::get_config("user.name").ok().flatten();
+    let email = git::get_config("user.email").ok().flatten();
+
+    match (name, email) {
+        (Some(name), Some(email)) => format!("{} <{}>", name, email),
+        (Some(name), None) => name,
+        (None, Some(email)) => email,
+        (None, None) => std::env::var("USER").unwrap_or_else(|_| "unknown".to_string()),
+    }
+}
@@ -0,0 +1,218 @@
+use crate::{
+    Args, WorktreePlan, create_planned_worktree, excluded_paths, plan_worktree, require_repo, style,
+};
+use arborist::error::{ArboristError, Result, ResultExt};
+use arborist::{git, state};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Files of an archive: the commits as an mbox for `git am`, uncommitted changes for `git apply`,
+// what the worktree was, and a marker left once `arborist undo` restored it
+const COMMITS_FILE: &str = "commits.patch";
+const CHANGES_FILE: &str = "changes.patch";
+const INFO_FILE: &str = "info.json";
+const RESTORED_FILE: &str = "restored";
+
+/// What an archived worktree was, so `arborist undo` can recreate it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ArchiveInfo {
+    name: String,
+    /// None for a detached worktree
+    branch: Option<String>,
+    /// Whether the branch was arborist's, and goes away with the worktree
+    owns_branch: bool,
+    /// Commit the archived commits apply on top of
+    base_commit: String,
+    base_branch: String,
+    /// Seconds since the Unix epoch
+    archived_at: u64,
+}
+
+/// Saves the work a worktree is about to lose, the commits on a branch of arborist's since the
+/// worktree's base and any uncommitted changes, as patches in the repository's archive
+/// Returns: the directory holding them, or None when there was nothing to save
+pub fn archive_worktree(plan: &WorktreePlan) -> Result<Option<PathBuf>> {
+    save_worktree(plan).context(format!("archiving the work in {}", plan.name))
+}
+
+fn save_worktree(plan: &WorktreePlan) -> Result<Option<PathBuf>> {
+    let (base, base_branch) = match state::load_worktree(&plan.repo.root, &plan.name)? {
+        Some(meta) => (meta.base_commit, meta.base_branch),
+        None => (
+            plan.repo.current_commit.clone(),
+            plan.repo.current_branch.clone(),
+        ),
+    };
+    // An existing branch keeps its commits when the worktree goes
+    let commits = if plan.branch.is_none() || plan.owns_branch {
+        git::format_patches(&plan.path, &format!("{}..HEAD", base))?
+    } else {
+        Vec::new()
+    };
+    let tree = git::snapshot_tree(&plan.path, &excluded_paths(plan)?)?;
+    let changes = git::binary_diff(&plan.path, "HEAD", &tree)?;
+
+    let info = ArchiveInfo {
+        name: plan.name.clone(),
+        branch: plan.branch.clone(),
+        owns_branch: plan.owns_branch,
+        base_commit: base,
+        base_branch,
+        archived_at: state::unix_now(),
+    };
+    save(&plan.repo.root, &info, commits, changes)
+}
+
+/// Saves the commits of a branch about to be deleted that the checked out branch lacks
+/// Returns: the directory holding them, or None when there was nothing to save
+/// Expects the current directory to be in the repository
+pub fn archive_branch(repo_root: &Path, branch: &str) -> Result<Option<PathBuf>> {
+    save_branch(repo_root, branch).context(format!("archiving branch {}", branch))
+}
+
+fn save_branch(repo_root: &Path, branch: &str) -> Result<Option<PathBuf>> {
+    // A branch sharing no history with HEAD is saved whole, on top of its own first commit
+    let base = match git::merge_base("HEAD", branch)? {
+        Some(base) => base,
+        None => git::root_commit(branch)?,
+    };
+    let commits = git::format_patches(repo_root, &format!("{}..{}", base, branch))?;
+
+    let info = ArchiveInfo {
+        name: branch.trim_start_matches("arborist/").replace('/', "-"),
+        branch: Some(branch.to_string()),
+        owns_branch: branch.starts_with("arborist/"),
+        base_commit: base,
+        base_branch: "HEAD".to_string(),
+        archived_at: state::unix_now(),
+    };
+    save(repo_root, &info, commits, Vec::new())
+}
+
+/// Tells the user where the work discarded from `name` was saved
+pub fn report(name: &str, dir: &Path) {
+    notice!(
+        "{} saved the discarded work of {} in {}",
+        style::info("arborist:"),
+        name,
+        dir.display()
+    );
+}
+
+fn save(
+    repo_root: &Path,
+    info: &ArchiveInfo,
+    commits: Vec<u8>,
+    changes: Vec<u8>,
+) -> Result<Option<PathBuf>> {
+    if commits.is_empty() && changes.is_empty() {
+        return Ok(None);
+    }
+
+    // A worktree restored and discarded again within the second needs a directory of its own
+    let archive = state::archive_dir(repo_root)?;
+    let mut dir = archive.join(format!("{}-{}", info.name, info.archived_at));
+    let mut n = 2;
+    while dir.exists() {
+        dir = archive.join(format!("{}-{}-{}", info.name, info.archived_at, n));
+        n += 1;
+    }
+    fs::create_dir_all(&dir)?;
+    for (file, contents) in [(COMMITS_FILE, commits), (CHANGES_FILE, changes)] {
+        if !contents.is_empty() {
+            fs::write(dir.join(file), contents)?;
+        }
+    }
+    fs::write(dir.join(INFO_FILE), serde_json::to_vec(info)?)?;
+    Ok(Some(dir))
+}
+
+// `arborist undo`: recreate the worktree whose work was archived last, at its old base, and
+// reapply its commits and changes
+pub fn run_undo(args: &Args) -> Result<i32> {
+    let repo = require_repo()?;
+    let Some((dir, info)) = latest(&repo.root)? else {
+        eprintln!(
+            "Nothing to undo: no archived worktree of {} is left to restore",
+            repo.root.display()
+        );
+        return Ok(1);
+    };
+
+    let mut plan = plan_worktree(repo, info.name.clone())?;
+    if git::worktree_exists(&plan.path)? {
+        return Err(ArboristError::InvalidPath(format!(
+            "Worktree {} exists again at {}; remove it to restore the archived one",
+            info.name,
+            plan.path.display()
+        )));
+    }
+    match &info.branch {
+        Some(branch) if info.owns_branch && git::branch_exists(branch)? => {
+            return Err(ArboristError::BranchExists {
+                branch: branch.clone(),
+            });
+        }
+        Some(branch) if !info.owns_branch && !git::branch_exists(branch)? => {
+            return Err(ArboristError::GitOperationFailed(format!(
+                "branch {} no longer exists",
+                branch
+            )));
+        }
+        _ => {}
+    }
+    plan.branch = info.branch.clone();
+    plan.owns_branch = info.owns_branch;
+    plan.repo.current_commit = info.base_commit.clone();
+    plan.repo.current_branch = info.base_branch.clone();
+    plan.repo.detached = info.base_branch == "HEAD";
+
+    create_planned_worktree(&plan, args)?;
+    let commits = dir.join(COMMITS_FILE);
+    if commits.exists() {
+        git::apply_mailbox(&plan.path, &commits)
+            .context(format!("reapplying the commits of {}", commits.display()))?;
+    }
+    let changes = dir.join(CHANGES_FILE);
+    if changes.exists() {
+        git::apply_patch(&plan.path, &changes)
+            .context(format!("reapplying the changes of {}", changes.display()))?;
+    }
+    fs::write(dir.join(RESTORED_FILE), "")?;
+
+    notice!(
+        "{} restored worktree {} at {}",
+        style::info("arborist:"),
+        style::worktree(&plan.name, &plan.name),
+        plan.path.display()
+    );
+    Ok(0)
+}
+
+// The most recent archive not yet restored
+fn latest(repo_root: &Path) -> Result<Option<(PathBuf, ArchiveInfo)>> {
+    let archive = state::archive_dir(repo_root)?;
+    if !archive.exists() {
+        return Ok(None);
+    }
+
+    let mut latest: Option<(PathBuf, ArchiveInfo)> = None;
+    for entry in fs::read_dir(archive)? {
+        let dir = entry?.path();
+        if dir.join(RESTORED_FILE).exists() {
+            continue;
+        }
+        // Archives from before undo existed have no info to restore from
+        let Ok(info) = fs::read(dir.join(INFO_FILE)) else {
+            continue;
+        };
+        let info: ArchiveInfo = serde_json::from_slice(&info)?;
+        if latest.as_ref().is_none_or(|(newest_dir, newest)| {
+            (info.archived_at, &dir) > (newest.archived_at, newest_dir)
+        }) {
+            latest = Some((dir, info));
+        }
+    }
+    Ok(latest)
+}
@@ -0,0 +1,125 @@
+use crate::WorktreePlan;
+use arborist::error::{ArboristError, Result};
+use arborist::{git, state};
+use std::fs;
+use std::path::{Component, Path};
+
+/// Links shared build-artifact directories from the per-repo cache into the worktree
+pub fn link_artifacts(plan: &WorktreePlan, dirs: &[String]) -> Result<()> {
+    let cache_dir = state::repo_cache_dir(&plan.repo.root)?;
+
+    for dir in dirs {
+        let target = plan.path.join(dir);
+        if target.symlink_metadata().is_ok() {
+            verbose!("Skipping artifact directory {}: already present", dir);
+            continue;
+        }
+
+        // Never shadow files that are part of the repository
+        if git::has_tracked_files(&plan.path, dir)? {
+            warning!(
+                "Not linking artifact directory {}: it contains tracked files",
+                dir
+            );
+            continue;
+        }
+
+        let source = cache_dir.join(dir);
+        fs::create_dir_all(&source)?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        verbose!("Linking artifact directory {}", dir);
+        symlink_dir(&source, &target)?;
+    }
+
+    Ok(())
+}
+
+/// Copies directories from the main checkout into the worktree using copy-on-write
+/// clones where the filesystem supports them, falling back to regular copies
+pub fn copy_artifacts_cow(plan: &WorktreePlan, dirs: &[String]) -> Result<()> {
+    for dir in dirs {
+        let source = plan.repo.root.join(dir);
+        let target = plan.path.join(dir);
+
+        if !source.is_dir() {
+            verbose!("Skipping copy of {}: not present in the main checkout", dir);
+            continue;
+        }
+        if target.symlink_metadata().is_ok() {
+            verbose!("Skipping copy of {}: already present", dir);
+            continue;
+        }
+        if git::has_tracked_files(&plan.path, dir)? {
+            warning!("Not copying {}: it contains tracked files", dir);
+            continue;
+        }
+
+        verbose!("Cloning {} into worktree...", dir);
+        clone_tree(&source, &target)?;
+    }
+
+    Ok(())
+}
+
+/// Checks that artifact directories stay inside the worktree
+pub fn validate_artifact_dirs(dirs: &[String]) -> Result<()> {
+    for dir in dirs {
+        let escapes = Path::new(dir)
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+
+        if dir.is_empty() || escapes {
+            return Err(ArboristError::InvalidConfig(format!(
+                "artifact directory '{}' must be a relative path inside the worktree",
+                dir
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn symlink_dir(source: &Path, target: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(source, target)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn symlink_dir(source: &Path, target: &Path) -> Result<()> {
+    std::os::windows::fs::symlink_dir(source, target)?;
+    Ok(())
+}
+
+// Recreate the directory tree at target, reflinking (or copying) every file
+pub fn clone_tree(source: &Path, target: &Path) -> Result<()> {
+    fs::create_dir_all(target)?;
+
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let dest = target.join(entry.file_name());
+
+        if file_type.is_dir() {
+            clone_tree(&entry.path(), &dest)?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&entry.path(), &dest)?;
+        } else {
+            reflink_copy::reflink_or_copy(entry.path(), &dest)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn copy_symlink(source: &Path, dest: &Path) -> Result<()> {
+    let link = fs::read_link(source)?;
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(link, dest)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(link, dest)?;
+    Ok(())
+}
@@ -0,0 +1,292 @@
+use crate::{WorktreePlan, tools};
+use arborist::error::Result;
+use arborist::state::{self, AuditRecord, AuditWorktree};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Stands in for the values of --env in recorded command lines
+const REDACTED: &str = "<redacted>";
+
+// What the current invocation touched so far, written to the audit log when it finishes; None
+// when the invocation isn't audited
+static PENDING: Mutex<Option<Pending>> = Mutex::new(None);
+
+#[derive(Default)]
+struct Pending {
+    repo: Option<PathBuf>,
+    worktrees: Vec<AuditWorktree>,
+}
+
+/// What happened to a worktree once an invocation was done with it
+#[derive(Debug, Clone, Copy)]
+pub enum Cleanup {
+    Kept,
+    Removed,
+}
+
+/// Which audit records `arborist log` shows
+pub struct Filter {
+    /// Repository root, or a directory containing the repositories
+    pub repo: Option<PathBuf>,
+    pub worktree: Option<String>,
+    pub user: Option<String>,
+    /// Only records at most this old
+    pub since: Option<Duration>,
+    /// Only invocations that exited non-zero
+    pub failed: bool,
+    /// Only the most recent records
+    pub limit: Option<usize>,
+}
+
+/// Starts auditing this invocation; it is written to the audit log by `finish`
+pub fn begin() {
+    *lock() = Some(Pending::default());
+}
+
+/// Records the repository the invocation operates on, unless one was recorded already
+pub fn note_repo(root: &Path) {
+    if let Some(pending) = lock().as_mut() {
+        pending.repo.get_or_insert_with(|| root.to_path_buf());
+    }
+}
+
+/// Records a worktree the invocation runs in or creates
+pub fn note_worktree(plan: &WorktreePlan) {
+    note(plan, None);
+}
+
+/// Records what happened to a worktree once the invocation was done with it
+pub fn note_cleanup(plan: &WorktreePlan, cleanup: Cleanup) {
+    note(plan, Some(cleanup));
+}
+
+fn note(plan: &WorktreePlan, cleanup: Option<Cleanup>) {
+    note_repo(&plan.repo.root);
+    let mut pending = lock();
+    let Some(pending) = pending.as_mut() else {
+        return;
+    };
+
+    let index = match pending
+        .worktrees
+        .iter()
+        .position(|worktree| worktree.name == plan.name)
+    {
+        Some(index) => index,
+        None => {
+            pending.worktrees.push(AuditWorktree {
+                name: plan.name.clone(),
+                branch: plan.branch.clone(),
+                cleanup: None,
+            });
+            pending.worktrees.len() - 1
+        }
+    };
+    if let Some(cleanup) = cleanup {
+        pending.worktrees[index].cleanup = Some(
+            match cleanup {
+                Cleanup::Kept => "kept",
+                Cleanup::Removed => "removed",
+            }
+            .to_string(),
+        );
+    }
+}
+
+/// Appends the invocation to the audit log, if it is audited
+/// A failure to write it is reported but doesn't change how arborist exits
+pub fn finish(exit_code: i32) {
+    let Some(pending) = lock().take() else {
+        return;
+    };
+
+    let uid = current_uid();
+    let record = AuditRecord {
+        timestamp: state::unix_now(),
+        user: user_name(uid),
+        uid,
+        argv: redact(
+            env::args_os()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+        ),
+        cwd: env::current_dir().ok(),
+        repo: pending.repo,
+        worktrees: pending.worktrees,
+        exit_code,
+    };
+    if let Err(err) = state::append_audit(&record) {
+        warning!(
+            "failed to write the audit log {}: {}",
+            state::audit_log_path().display(),
+            err.chain()
+        );
+    }
+}
+
+// Hides the values given with --env, which are often tokens, leaving only their keys
+fn redact(argv: Vec<String>) -> Vec<String> {
+    let hide = |assignment: &str| match assignment.split_once('=') {
+        Some((key, _)) => format!("{}={}", key, REDACTED),
+        None => assignment.to_string(),
+    };
+    let mut redacted = Vec::with_capacity(argv.len());
+    let mut value_next = false;
+    for arg in argv {
+        redacted.push(if value_next {
+            hide(&arg)
+        } else if let Some(assignment) = arg.strip_prefix("--env=") {
+            format!("--env={}", hide(assignment))
+        } else {
+            arg.clone()
+        });
+        value_next = arg == "--env";
+    }
+    redacted
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Option<Pending>> {
+    PENDING
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(unix)]
+fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and can't fail
+    unsafe { libc::getuid() }
+}
+
+#[cfg(not(unix))]
+fn current_uid() -> u32 {
+    0
+}
+
+// Login name of user `uid` from the password database, which unlike $USER the user can't change;
+// then $USER or $LOGNAME, and the user id itself as a last resort
+fn user_name(uid: u32) -> String {
+    #[cfg(unix)]
+    {
+        // SAFETY: getpwuid returns null or an entry that stays valid until the next call, and
+        // arborist makes no other; the name is copied out before returning
+        let entry = unsafe { libc::getpwuid(uid) };
+        if !entry.is_null() {
+            // SAFETY: a non-null entry has a nul-terminated pw_name
+            let name = unsafe { std::ffi::CStr::from_ptr((*entry).pw_name) };
+            return name.to_string_lossy().into_owned();
+        }
+    }
+
+    ["USER", "LOGNAME"]
+        .into_iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_else(|| uid.to_string())
+}
+
+// `arborist log`: print the audit records matching `filter`, oldest first
+pub fn run_log(filter: &Filter, json: bool) -> Result<i32> {
+    let repo = filter
+        .repo
+        .as_ref()
+        .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()));
+    let cutoff = filter
+        .since
+        .map(|since| state::unix_now().saturating_sub(since.as_secs()));
+
+    let mut records: Vec<AuditRecord> = state::load_audit()?
+        .into_iter()
+        .filter(|record| {
+            repo.as_ref().is_none_or(|repo| {
+                record
+                    .repo
+                    .as_ref()
+                    .is_some_and(|root| root.starts_with(repo))
+            })
+        })
+        .filter(|record| {
+            filter.worktree.as_ref().is_none_or(|name| {
+                record
+                    .worktrees
+                    .iter()
+                    .any(|worktree| &worktree.name == name)
+            })
+        })
+        .filter(|record| filter.user.as_ref().is_none_or(|user| &record.user == user))
+        .filter(|record| cutoff.is_none_or(|cutoff| record.timestamp >= cutoff))
+        .filter(|record| !filter.failed || record.exit_code != 0)
+        .collect();
+    if let Some(limit) = filter.limit {
+        records.drain(..records.len().saturating_sub(limit));
+    }
+
+    if json {
+        for record in &records {
+            println!("{}", serde_json::to_string(record)?);
+        }
+    } else if records.is_empty() {
+        eprintln!("No matching invocations recorded");
+    } else {
+        for record in &records {
+            print_record(record);
+        }
+    }
+
+    Ok(0)
+}
+
+fn print_record(record: &AuditRecord) {
+    println!(
+        "{}  {:<10} exit {:<3} {}",
+        format_timestamp(record.timestamp),
+        record.user,
+        record.exit_code,
+        tools::shell_join(&record.argv)
+    );
+    if let Some(repo) = &record.repo {
+        println!("  repo: {}", repo.display());
+    }
+    for worktree in &record.worktrees {
+        println!(
+            "  worktree {} ({}){}",
+            worktree.name,
+            worktree.branch.as_deref().unwrap_or("detached"),
+            worktree
+                .cleanup
+                .as_ref()
+                .map(|cleanup| format!(", {}", cleanup))
+                .unwrap_or_default()
+        );
+    }
+}
+
+/// Seconds since the Unix epoch as a UTC date and time, e.g. "2024-05-01 13:45:00Z"
+pub fn format_timestamp(timestamp: u64) -> String {
+    let (days, seconds) = (timestamp / 86400, timestamp % 86400);
+    // Civil date from days since the epoch, counting in 400-year eras starting on March 1st
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,262 @@
+use crate::lifecycle;
+use crate::steps::{self, StepResult};
+use crate::{
+    Args, CleanupOutcome, Invocation, Provisioning, WorktreePlan, branches, cleanup_worktree,
+    create_planned_worktree, error_exit_code, plan_worktree, prune, require_repo, style,
+};
+use arborist::context::RunContext;
+use arborist::error::{ArboristError, Result};
+use arborist::{git, process, state};
+use duct::cmd;
+use std::time::Duration;
+
+// One side of the comparison: a ref's worktree and what runs in it
+struct Side {
+    // "base" or "head"
+    role: &'static str,
+    rev: String,
+    commit: String,
+    plan: WorktreePlan,
+    provisioning: Provisioning,
+    invocation: Invocation,
+}
+
+// Outcome of one side for the comparison
+struct SideResult {
+    // None when the command could not be started
+    exit_code: Option<i32>,
+    // Most attempts any step took with --retries; 0 when the command could not be started
+    attempts: u32,
+    duration: Duration,
+    kept: bool,
+}
+
+/// What `arborist bench` compares and how
+pub struct Options<'a> {
+    pub base: &'a str,
+    pub head: &'a str,
+    /// Run both sides at the same time rather than one after the other
+    pub parallel: bool,
+    /// Capture each side's output and show how they differ
+    pub diff_output: bool,
+}
+
+// `arborist bench`: run the command in a detached worktree at each of two refs and compare how
+// the runs went, leaving the current checkout alone
+pub fn run_bench(args: &Args, options: &Options, invocation: &Invocation) -> Result<i32> {
+    if options.parallel && args.pty {
+        return Err(ArboristError::InvalidConfig(
+            "--pty can't be combined with --parallel, which labels each line of output".to_string(),
+        ));
+    }
+    let repo = require_repo()?;
+    let refs = [("base", options.base), ("head", options.head)];
+    let commits = refs
+        .iter()
+        .map(|(_, rev)| git::resolve_commit(rev))
+        .collect::<Result<Vec<_>>>()?;
+    if commits[0] == commits[1] {
+        return Err(ArboristError::InvalidConfig(format!(
+            "{} and {} are the same commit ({}), so there is nothing to compare",
+            options.base,
+            options.head,
+            short(&commits[0])
+        )));
+    }
+    // Leftovers of crashed runs would otherwise look reusable
+    prune::prune_stale(&repo)?;
+
+    let mut sides = Vec::new();
+    for ((role, rev), commit) in refs.into_iter().zip(commits) {
+        let mut repo = repo.clone();
+        repo.current_branch = rev.to_string();
+        repo.current_commit = commit.clone();
+        repo.detached = true;
+        // Named after the commit, so an existing worktree of the same name is already at it
+        let mut plan = plan_worktree(repo, format!("bench-{}", short(&commit)))?;
+        plan.branch = None;
+        plan.owns_branch = false;
+        let provisioning = create_planned_worktree(&plan, args)?;
+        let mut invocation = branches::prepare(&plan, args, invocation)?;
+        if args.capture || options.diff_output {
+            invocation.capture = Some(state::run_log_path(
+                &plan.repo.root,
+                &plan.name,
+                state::unix_now(),
+            )?);
+        } else if options.parallel {
+            invocation.label = Some(style::worktree(&plan.name, &format!("[{}]", role)));
+        }
+        sides.push(Side {
+            role,
+            rev: rev.to_string(),
+            commit,
+            plan,
+            provisioning,
+            invocation,
+        });
+    }
+
+    // Invocations carry their directory, so the runs don't depend on the current one
+    let outputs: Vec<Result<Vec<StepResult>>> = if options.parallel {
+        process::block_on(process::join_all(sides.iter().map(|side| async move {
+            lifecycle::run_steps(&[&side.plan], &side.invocation, args).await
+        })))
+    } else {
+        sides
+            .iter()
+            .map(|side| {
+                notice!(
+                    "{} running {} ({}) in {}",
+                    style::info("arborist:"),
+                    side.role,
+                    side.rev,
+                    style::worktree(&side.plan.name, &side.plan.name)
+                );
+                process::block_on(lifecycle::run_steps(&[&side.plan], &side.invocation, args))
+            })
+            .collect()
+    };
+
+    let mut results = Vec::new();
+    for (side, output) in sides.iter().zip(outputs) {
+        let steps = match output {
+            Ok(steps) => Some(steps),
+            Err(err) => {
+                eprintln!(
+                    "{} {}",
+                    style::error(&format!("Error in {}:", side.role)),
+                    err.chain()
+                );
+                None
+            }
+        };
+        let context = RunContext::enter(&side.plan.path, &side.plan.repo.root)?;
+        let exit_code = steps.as_deref().map(steps::exit_code);
+        let outcome = cleanup_worktree(&side.plan, context, args, exit_code)?;
+        let result = SideResult {
+            exit_code,
+            attempts: steps.as_deref().map_or(0, steps::attempts),
+            duration: steps.iter().flatten().map(|step| step.duration).sum(),
+            kept: outcome.kept(),
+        };
+        record_history(side, &result);
+        if let CleanupOutcome::Kept(_) = outcome {
+            notice!(
+                "{} kept {} at {}",
+                style::info("arborist:"),
+                style::worktree(&side.plan.name, &side.plan.name),
+                side.plan.path.display()
+            );
+        }
+        results.push(result);
+    }
+
+    if options.diff_output {
+        show_output_diff(&sides)?;
+    }
+    print_comparison(&sides, &results);
+    Ok(results
+        .iter()
+        .map(|result| result.exit_code.unwrap_or_else(error_exit_code))
+        .find(|code| *code != 0)
+        .unwrap_or(0))
+}
+
+fn short(commit: &str) -> &str {
+    &commit[..commit.len().min(10)]
+}
+
+// Prints how the captured output of head differs from base's, as a unified diff
+fn show_output_diff(sides: &[Side]) -> Result<()> {
+    let [Some(base), Some(head)] =
+        [&sides[0], &sides[1]].map(|side| side.invocation.capture.as_ref())
+    else {
+        return Ok(());
+    };
+
+    let output = cmd!("git", "diff", "--no-index", "--", base, head)
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()?;
+    // Exit code 1 only means the files differ
+    if !matches!(output.status.code(), Some(0 | 1)) {
+        return Err(ArboristError::GitOperationFailed(format!(
+            "comparing the output: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    if output.stdout.is_empty() {
+        notice!(
+            "{} both runs printed the same output",
+            style::info("arborist:")
+        );
+    } else {
+        print!("{}", String::from_utf8_lossy(&output.stdout));
+    }
+    Ok(())
+}
+
+fn record_history(side: &Side, result: &SideResult) {
+    let record = state::RunRecord {
+        name: side.plan.name.clone(),
+        command: side.invocation.command.join(" "),
+        started_at: state::unix_now().saturating_sub(result.duration.as_secs()),
+        duration_ms: result.duration.as_millis() as u64,
+        finished_at: Some(state::unix_now()),
+        exit_code: result.exit_code.unwrap_or_else(error_exit_code),
+        attempts: result.attempts,
+        head: None,
+        reused: side.provisioning.reused,
+        setup_ms: (!side.provisioning.reused)
+            .then_some(side.provisioning.duration.as_millis() as u64),
+        kept: result.kept,
+        log: side.invocation.capture.clone(),
+    };
+    if let Err(err) = state::append_history(&side.plan.repo.root, &record) {
+        verbose!("Warning: failed to record run history: {}", err.chain());
+    }
+}
+
+// Both sides next to each other, with how much slower or faster head was than base
+fn print_comparison(sides: &[Side], results: &[SideResult]) {
+    let width = sides.iter().map(|side| side.rev.len()).max().unwrap_or(0);
+
+    notice!("{} bench", style::info("arborist:"));
+    for (side, result) in sides.iter().zip(results) {
+        // Padded before styling so escape codes don't upset the alignment
+        let status = match result.exit_code {
+            Some(0) => style::success(&format!("{:<12}", "ok")),
+            Some(code) => style::failure(&format!("{:<12}", format!("failed ({})", code))),
+            None => style::failure(&format!("{:<12}", "error")),
+        };
+        notice!(
+            "  {}  {}  {}  {} {:>8.2?}  {}{}",
+            side.role,
+            style::worktree(
+                &side.plan.name,
+                &format!("{:<width$}", side.rev, width = width)
+            ),
+            style::dim(short(&side.commit)),
+            status,
+            result.duration,
+            if result.kept { "kept" } else { "removed" },
+            side.invocation
+                .capture
+                .as_ref()
+                .map(|log| format!(", output in {}", log.display()))
+                .unwrap_or_default()
+        );
+    }
+
+    let (base, head) = (results[0].duration, results[1].duration);
+    if !base.is_zero() && !head.is_zero() {
+        let change = (head.as_secs_f64() / base.as_secs_f64() - 1.0) * 100.0;
+        notice!(
+            "  head took {:.1}% {} than base",
+            change.abs(),
+            if change > 0.0 { "longer" } else { "less time" }
+        );
+    }
+}
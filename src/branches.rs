@@ -0,0 +1,293 @@
+use crate::lifecycle;
+use crate::steps::{self, StepResult};
+use crate::tail::OutputTail;
+use crate::{
+    Args, CleanupOutcome, DirectoryGuard, Invocation, Provisioning, WorktreePlan, cleanup_worktree,
+    create_planned_worktree, environment, error_exit_code, githooks, plan_new_worktree,
+    require_repo, resolve_subdir, style, wrap_invocation,
+};
+use arborist::context::RunContext;
+use arborist::error::{ArboristError, Result};
+use arborist::git::{self, BranchTip};
+use arborist::{process, state};
+use std::time::Duration;
+
+// A branch's worktree and what runs in it
+struct Target {
+    branch: BranchTip,
+    plan: WorktreePlan,
+    provisioning: Provisioning,
+    invocation: Invocation,
+}
+
+// Outcome of one branch for the summary
+struct BranchResult {
+    branch: String,
+    name: String,
+    // None when the command could not be started
+    exit_code: Option<i32>,
+    // Most attempts any step took with --retries; 0 when the command could not be started
+    attempts: u32,
+    duration: Duration,
+    kept: bool,
+}
+
+// `--branches`: run the command in a worktree at the tip of every matching branch
+pub fn run_branches(args: &Args, invocation: &Invocation, patterns: &[String]) -> Result<i32> {
+    if args.parallel && args.pty {
+        return Err(ArboristError::InvalidConfig(
+            "--pty can't be combined with --parallel, which labels each line of output".to_string(),
+        ));
+    }
+    let repo = require_repo()?;
+    let branches = matching_branches(patterns)?;
+    if branches.is_empty() {
+        return Err(ArboristError::InvalidConfig(format!(
+            "no branches match {}",
+            patterns.join(", ")
+        )));
+    }
+    verbose!(
+        "Matched branches: {}",
+        branches
+            .iter()
+            .map(|branch| branch.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let mut targets = Vec::new();
+    for branch in branches {
+        let mut repo = repo.clone();
+        repo.current_branch = branch.name.clone();
+        repo.detached = false;
+        repo.current_commit = branch.commit.clone();
+        let plan = plan_new_worktree(repo, worktree_name(&branch), args)?;
+        let provisioning = create_planned_worktree(&plan, args)?;
+        let mut invocation = prepare(&plan, args, invocation)?;
+        // Interleaved output from parallel runs is labelled with the worktree it came from
+        if args.capture {
+            invocation.capture = Some(state::run_log_path(
+                &plan.repo.root,
+                &plan.name,
+                state::unix_now(),
+            )?);
+        }
+        if args.parallel {
+            invocation.label = Some(style::worktree(&plan.name, &format!("[{}]", plan.name)));
+        }
+        targets.push(Target {
+            branch,
+            plan,
+            provisioning,
+            invocation,
+        });
+    }
+
+    // Invocations carry their directory, so the runs don't depend on the current one
+    let outputs: Vec<Result<Vec<StepResult>>> = if args.parallel {
+        process::block_on(process::join_all(targets.iter().map(|target| async move {
+            lifecycle::run_steps(&[&target.plan], &target.invocation, args).await
+        })))
+    } else {
+        targets
+            .iter()
+            .map(|target| {
+                notice!(
+                    "{} running in {}",
+                    style::info("arborist:"),
+                    style::worktree(&target.plan.name, &target.branch.name)
+                );
+                process::block_on(lifecycle::run_steps(
+                    &[&target.plan],
+                    &target.invocation,
+                    args,
+                ))
+            })
+            .collect()
+    };
+
+    let mut results = Vec::new();
+    for (target, output) in targets.iter().zip(outputs) {
+        let steps = match output {
+            Ok(steps) => Some(steps),
+            Err(err) => {
+                eprintln!(
+                    "{} {}",
+                    style::error(&format!("Error in {}:", target.branch.name)),
+                    err.chain()
+                );
+                None
+            }
+        };
+        let context = RunContext::enter(&target.plan.path, &target.plan.repo.root)?;
+        let exit_code = steps.as_deref().map(steps::exit_code);
+        let outcome = cleanup_worktree(&target.plan, context, args, exit_code)?;
+        let result = BranchResult {
+            branch: target.branch.name.clone(),
+            name: target.plan.name.clone(),
+            exit_code,
+            attempts: steps.as_deref().map_or(0, steps::attempts),
+            duration: steps.iter().flatten().map(|step| step.duration).sum(),
+            kept: outcome.kept(),
+        };
+        record_history(target, &result);
+        if let CleanupOutcome::Kept(_) = outcome {
+            notice!(
+                "{} kept {} at {}",
+                style::info("arborist:"),
+                style::worktree(&target.plan.name, &target.plan.name),
+                target.plan.path.display()
+            );
+        }
+        results.push(result);
+    }
+
+    print_summary(&results);
+    Ok(results
+        .iter()
+        .map(|result| result.exit_code.unwrap_or_else(error_exit_code))
+        .find(|code| *code != 0)
+        .unwrap_or(0))
+}
+
+// Local and remote-tracking branches matching any pattern, without duplicates
+// A remote branch also matches by its name without the remote, and a local branch of the
+// same name wins over it
+fn matching_branches(patterns: &[String]) -> Result<Vec<BranchTip>> {
+    let tips = git::list_branch_tips()?;
+    let local: Vec<&str> = tips
+        .iter()
+        .filter(|tip| !tip.remote)
+        .map(|tip| tip.name.as_str())
+        .collect();
+
+    let mut matched: Vec<BranchTip> = Vec::new();
+    for tip in tips.iter() {
+        let short = short_name(tip);
+        let wanted = patterns
+            .iter()
+            .any(|pattern| glob_match(pattern, &tip.name) || glob_match(pattern, short));
+        let shadowed = tip.remote && local.contains(&short);
+        let seen = matched.iter().any(|other| short_name(other) == short);
+        if wanted && !shadowed && !seen {
+            matched.push(tip.clone());
+        }
+    }
+    Ok(matched)
+}
+
+// Branch name without the remote, e.g. "release/1.2" for "origin/release/1.2"
+fn short_name(tip: &BranchTip) -> &str {
+    if tip.remote {
+        tip.name
+            .split_once('/')
+            .map_or(tip.name.as_str(), |(_, name)| name)
+    } else {
+        &tip.name
+    }
+}
+
+// Worktree named after the branch, e.g. "release-1.2"
+fn worktree_name(tip: &BranchTip) -> String {
+    short_name(tip)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+// Wildcard match where `*` matches any run of characters and `?` any single one
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it is currently matched up to
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p + 1, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The invocation for one worktree's run, wrapped for the worktree and started there
+pub fn prepare(plan: &WorktreePlan, args: &Args, invocation: &Invocation) -> Result<Invocation> {
+    let mut invocation = invocation.clone();
+    invocation.env.extend(githooks::shared_hooks_env(plan)?);
+    invocation.env.extend(environment::worktree_vars(plan));
+
+    let _guard = DirectoryGuard::with_path(&plan.path)?;
+    let workdir =
+        resolve_subdir(plan, args.subdir.as_deref())?.unwrap_or_else(|| plan.path.clone());
+    wrap_invocation(plan, args, &mut invocation, workdir.clone())?;
+    invocation.dir = Some(workdir);
+    invocation.tail = args.capture_tail.map(OutputTail::new);
+    Ok(invocation)
+}
+
+fn record_history(target: &Target, result: &BranchResult) {
+    let record = state::RunRecord {
+        name: target.plan.name.clone(),
+        command: target.invocation.command.join(" "),
+        started_at: state::unix_now().saturating_sub(result.duration.as_secs()),
+        duration_ms: result.duration.as_millis() as u64,
+        finished_at: Some(state::unix_now()),
+        exit_code: result.exit_code.unwrap_or_else(error_exit_code),
+        attempts: result.attempts,
+        head: None,
+        reused: target.provisioning.reused,
+        setup_ms: (!target.provisioning.reused)
+            .then_some(target.provisioning.duration.as_millis() as u64),
+        kept: result.kept,
+        log: target.invocation.capture.clone(),
+    };
+    if let Err(err) = state::append_history(&target.plan.repo.root, &record) {
+        verbose!("Warning: failed to record run history: {}", err.chain());
+    }
+}
+
+fn print_summary(results: &[BranchResult]) {
+    let width = results
+        .iter()
+        .map(|result| result.branch.len())
+        .max()
+        .unwrap_or(0);
+
+    notice!("{} branches", style::info("arborist:"));
+    for result in results {
+        // Padded before styling so escape codes don't upset the alignment
+        let status = match result.exit_code {
+            Some(0) => style::success(&format!("{:<12}", "ok")),
+            Some(code) => style::failure(&format!("{:<12}", format!("failed ({})", code))),
+            None => style::failure(&format!("{:<12}", "error")),
+        };
+        notice!(
+            "  {}  {} {:>8.2?}  {}",
+            style::worktree(
+                &result.name,
+                &format!("{:<width$}", result.branch, width = width)
+            ),
+            status,
+            result.duration,
+            if result.kept { "kept" } else { "removed" },
+        );
+    }
+}
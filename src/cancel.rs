@@ -0,0 +1,86 @@
+use arborist::error::{ArboristError, Result};
+use arborist::process::{self, Cancellation, Outcome};
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+// Cancelled by the first SIGINT, SIGTERM or SIGHUP received while a command runs
+static CANCELLATION: Cancellation = Cancellation::new();
+
+// Number of commands currently being supervised
+static SUPERVISING: AtomicUsize = AtomicUsize::new(0);
+
+// Time a cancelled command gets to exit on its own before it is killed
+const GRACE: Duration = Duration::from_secs(5);
+
+/// Exit code of a command killed after arborist was interrupted (128 + SIGINT)
+pub const INTERRUPTED: i32 = 130;
+
+/// Installs the signal handler that turns interrupts into cancellation
+///
+/// While a command runs, or a run is in its worktree, a signal cancels it instead of killing
+/// arborist, so the worktree is still cleaned up; a second signal exits at once. Outside of commands (e.g. at a
+/// prompt) signals exit immediately, as they would without the handler.
+pub fn install() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if SUPERVISING.load(Ordering::SeqCst) == 0 || CANCELLATION.cancel() {
+            std::process::exit(INTERRUPTED);
+        }
+    })
+    .map_err(|err| ArboristError::IoError(io::Error::other(err)))
+}
+
+/// Whether arborist was interrupted while a command ran
+pub fn is_cancelled() -> bool {
+    CANCELLATION.is_cancelled()
+}
+
+/// Marks a command (or a whole run) as supervised for as long as it is held, so signals cancel it
+pub struct Supervising(());
+
+impl Supervising {
+    pub fn new() -> Self {
+        SUPERVISING.fetch_add(1, Ordering::SeqCst);
+        Supervising(())
+    }
+}
+
+impl Drop for Supervising {
+    fn drop(&mut self) {
+        SUPERVISING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Waits for a started command, killing it when it is still running a grace period after
+/// arborist is interrupted; other commands, and the output being copied, carry on meanwhile on
+/// the same scheduler
+/// `exited` completes with the command's exit code; `kill` stops it and must make `exited`
+/// complete
+/// Returns: the command's exit code, or INTERRUPTED
+pub async fn supervise(
+    exited: impl Future<Output = Result<i32>>,
+    kill: impl FnOnce() -> io::Result<()>,
+) -> Result<i32> {
+    let _supervising = Supervising::new();
+    let outcome = process::supervise(exited, kill, None, &CANCELLATION, GRACE).await?;
+    Ok(interrupted(outcome))
+}
+
+/// Waits for a child process as `supervise` does
+pub async fn wait(child: &mut process::Child) -> Result<i32> {
+    let _supervising = Supervising::new();
+    let outcome = process::wait(child, None, &CANCELLATION, GRACE).await?;
+    Ok(interrupted(outcome))
+}
+
+// No timeout is set, so only an interrupt gets a command killed
+fn interrupted(outcome: Outcome) -> i32 {
+    match outcome {
+        Outcome::Exited(exit_code) => exit_code,
+        Outcome::Cancelled | Outcome::TimedOut => {
+            warning!("command still running after interrupt; killed it");
+            INTERRUPTED
+        }
+    }
+}
@@ -0,0 +1,528 @@
+use crate::error::{ArboristError, Result};
+use crate::git;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Name of the per-repository config file at the repository root
+const REPO_CONFIG_FILE: &str = ".arborist.toml";
+
+// Name of the file listing the repositories of a workspace, at the workspace root
+const WORKSPACE_FILE: &str = "arborist-workspace.toml";
+
+/// Settings read from the user config and the repository's `.arborist.toml`
+/// Every field is optional so the repository file can override the user file key by key
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Cone-mode sparse-checkout directories for new worktrees
+    pub sparse: Option<Vec<String>>,
+
+    /// Endpoint receiving run start/finish messages with --announce
+    /// Only honoured in the user config, since the messages carry the user's identity and command
+    pub announce_url: Option<String>,
+
+    /// Endpoint receiving a JSON message when a run finishes with --notify (default: desktop notification)
+    /// Only honoured in the user config, since the message carries the user's command and paths
+    pub notify_url: Option<String>,
+
+    /// Directories shared from the per-repo artifact cache into new worktrees
+    pub link_artifacts: Option<Vec<String>>,
+
+    /// How artifact directories are shared; "symlink", the default, is the only mode
+    pub artifact_link_mode: Option<LinkMode>,
+
+    /// Directories copied from the main checkout with copy-on-write clones
+    pub copy_cow: Option<Vec<String>>,
+
+    /// Print next-step hints after a worktree is kept (default: true)
+    pub hints: Option<bool>,
+
+    /// Container engine used by --container (default: podman or docker, whichever is on PATH)
+    /// Only honoured in the user config, since a repository could otherwise pick the program run
+    pub container_engine: Option<String>,
+
+    /// Run commands inside the worktree's Nix development environment
+    /// Only honoured in the user config, since a repository could otherwise run its own shellHook
+    pub nix: Option<bool>,
+
+    /// Allow new worktrees' .envrc and run commands through `direnv exec`
+    /// Only honoured in the user config, since a repository could otherwise enable its own .envrc
+    pub direnv: Option<bool>,
+
+    /// How hooks from a worktree-relative core.hooksPath reach new worktrees
+    pub hooks: Option<HooksMode>,
+
+    /// Editor command launched by --open with the worktree path appended (default: code or zed)
+    /// Only honoured in the user config, since it is run through the shell
+    pub editor: Option<String>,
+
+    /// Segment printed by `arborist prompt`; {name}, {branch} and {path} are substituted
+    pub prompt_template: Option<String>,
+
+    /// Exit code when arborist itself fails, as opposed to the command (default: 125)
+    /// Only read from the user config
+    pub error_exit_code: Option<i32>,
+
+    /// Times a git command is retried while another git process holds a lock (default: 5)
+    pub git_lock_retries: Option<u32>,
+
+    /// Directory new worktrees are created under (default: /tmp/arborist)
+    /// Only read from the user config; ARBORIST_HOME takes precedence
+    pub worktree_root: Option<PathBuf>,
+
+    /// Where worktrees of bare repositories are created (default: root)
+    pub bare_worktrees: Option<BareWorktrees>,
+
+    /// Branch new worktree branches track, e.g. origin/main, or "none" for no tracking
+    /// (default: the branch they were created from)
+    pub upstream: Option<String>,
+
+    /// Clean worktrees `arborist daemon` keeps parked for new runs to claim (default: 0)
+    pub pool_size: Option<usize>,
+
+    /// How long `arborist daemon` keeps a clean, unused worktree before removing it, e.g. "7d"
+    pub worktree_ttl: Option<String>,
+
+    /// Most worktrees `arborist daemon` lets a repository have, parked ones included
+    pub max_worktrees: Option<usize>,
+
+    /// What happens to the worktree after a command that succeeded (default: clean)
+    pub on_success: Option<RunAction>,
+
+    /// What happens to the worktree after a command that failed (default: clean)
+    pub on_failure: Option<RunAction>,
+
+    /// What happens to uncommitted changes when the worktree is cleaned (default: keep)
+    pub on_changes: Option<ChangesAction>,
+
+    /// What happens to new commits when the worktree is cleaned (default: keep)
+    /// Only "keep" is read from the repository config, since the others merge or delete commits
+    pub on_commits: Option<CommitsAction>,
+
+    /// Check worktrees for changes without looking for untracked files, using git's filesystem
+    /// monitor where available (default: false); a worktree is still only removed once it is
+    /// known to hold none
+    pub fast_status: Option<bool>,
+
+    /// How untracked files count as changes: no, normal or all (default: normal, or no with
+    /// fast_status)
+    pub untracked: Option<git::UntrackedFiles>,
+
+    /// Paths and globs (git pathspecs) whose changes never count when deciding whether to keep a
+    /// worktree
+    pub ignore_changes: Option<Vec<String>>,
+
+    /// Paths and globs (git pathspecs) of untracked files deleted from a worktree before its
+    /// final status check, ignored files included
+    /// Only read from the user config, since it deletes files the user may have written
+    pub autoclean: Option<Vec<String>>,
+
+    /// Repositories arborist creates worktrees in; commands elsewhere run in place
+    /// Entries are repository roots or directories containing them. Only read from the user config
+    pub allow_repos: Option<Vec<PathBuf>>,
+
+    /// Repositories arborist never creates worktrees in; commands there run in place
+    /// Only read from the user config
+    pub deny_repos: Option<Vec<PathBuf>>,
+
+    /// Repositories whose .arborist/hooks scripts run, by root or a directory containing them
+    /// Only read from the user config, since a repository could otherwise run its own scripts
+    pub trusted_repos: Option<Vec<PathBuf>>,
+
+    /// Author and committer name of commits made in worktrees, by the command or by arborist
+    /// Only read from the user config, as is author_email, since commits are made in its name
+    pub author_name: Option<String>,
+
+    /// Author and committer email of commits made in worktrees, by the command or by arborist
+    pub author_email: Option<String>,
+
+    /// Git config set in each worktree with `git config --worktree`, e.g. a user.email of its own
+    /// Only read from the user config, since keys like core.fsmonitor run commands
+    pub worktree_config: Option<BTreeMap<String, String>>,
+
+    /// Git config keys copied from your checkout into each worktree's own config where the
+    /// worktree sees a different value, e.g. an identity chosen by `includeIf "gitdir:..."` that
+    /// a worktree under another directory doesn't match
+    pub inherit_config: Option<Vec<String>>,
+
+    /// Files written into every new worktree, by worktree path, from template files with
+    /// {name}, {branch}, {path}, {repo}, {base_branch}, {base_commit} and {env:NAME} filled in
+    /// The repository file's templates must be read from inside it and can't use {env:NAME}
+    pub templates: Option<BTreeMap<String, PathBuf>>,
+
+    /// Named repo tasks runnable with `arborist run <name>`
+    pub tools: Option<BTreeMap<String, Tool>>,
+
+    /// Keys the repository file set that only the user config may, whose values were ignored
+    #[serde(skip)]
+    pub ignored_keys: Vec<&'static str>,
+
+    /// Whether templates came from the repository file, whose sources must stay inside it
+    #[serde(skip)]
+    pub repo_templates: bool,
+}
+
+/// A task from the tool manifest (`[tools.<name>]`)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tool {
+    /// Shell command to run
+    pub cmd: String,
+
+    /// Shell commands run before `cmd`, stopping at the first failure
+    pub setup: Vec<String>,
+
+    /// Extra environment variables for the setup commands and `cmd`
+    pub env: BTreeMap<String, String>,
+}
+
+/// What happens to a worktree once its command exits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunAction {
+    /// Remove it, unless on_changes or on_commits keep it
+    #[default]
+    Clean,
+    /// Keep it as it is
+    Keep,
+}
+
+/// What happens to uncommitted changes in a worktree being cleaned
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangesAction {
+    /// Keep the worktree
+    #[default]
+    Keep,
+    /// Discard them
+    Clean,
+    /// Ask, as --interactive does
+    Prompt,
+}
+
+/// What happens to new commits on the branch of a worktree being cleaned
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommitsAction {
+    /// Keep the worktree
+    #[default]
+    Keep,
+    /// Delete them along with the branch
+    Clean,
+    /// Merge the branch into the branch checked out in the main checkout
+    MergeBack,
+}
+
+/// How shared artifact directories are placed into a worktree
+/// There is no hardlink mode: builds writing files in place would write through the links into the
+/// shared cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    /// The worktree directory is a symlink to the cache; builds write straight into it
+    #[default]
+    Symlink,
+}
+
+/// How git hooks are provided to worktrees when core.hooksPath points inside the checkout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HooksMode {
+    /// Copy the main checkout's hooks directory into the worktree
+    Copy,
+    /// Point core.hooksPath at the main checkout's hooks directory for the command
+    Share,
+}
+
+/// Where the worktrees of a bare repository are created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BareWorktrees {
+    /// Under the worktree root, like those of other repositories
+    #[default]
+    Root,
+    /// In a directory beside the repository, e.g. project.worktrees/ next to project.git/
+    Sibling,
+    /// Inside the repository directory itself, as arborist-{color}
+    Inside,
+}
+
+impl Config {
+    // Fill unset fields of self from other
+    fn or(self, other: Config) -> Config {
+        Config {
+            sparse: self.sparse.or(other.sparse),
+            announce_url: self.announce_url.or(other.announce_url),
+            notify_url: self.notify_url.or(other.notify_url),
+            link_artifacts: self.link_artifacts.or(other.link_artifacts),
+            artifact_link_mode: self.artifact_link_mode.or(other.artifact_link_mode),
+            copy_cow: self.copy_cow.or(other.copy_cow),
+            hints: self.hints.or(other.hints),
+            container_engine: self.container_engine.or(other.container_engine),
+            nix: self.nix.or(other.nix),
+            direnv: self.direnv.or(other.direnv),
+            hooks: self.hooks.or(other.hooks),
+            editor: self.editor.or(other.editor),
+            prompt_template: self.prompt_template.or(other.prompt_template),
+            error_exit_code: self.error_exit_code.or(other.error_exit_code),
+            git_lock_retries: self.git_lock_retries.or(other.git_lock_retries),
+            worktree_root: self.worktree_root.or(other.worktree_root),
+            bare_worktrees: self.bare_worktrees.or(other.bare_worktrees),
+            upstream: self.upstream.or(other.upstream),
+            on_success: self.on_success.or(other.on_success),
+            on_failure: self.on_failure.or(other.on_failure),
+            on_changes: self.on_changes.or(other.on_changes),
+            on_commits: self.on_commits.or(other.on_commits),
+            fast_status: self.fast_status.or(other.fast_status),
+            untracked: self.untracked.or(other.untracked),
+            ignore_changes: self.ignore_changes.or(other.ignore_changes),
+            autoclean: self.autoclean.or(other.autoclean),
+            pool_size: self.pool_size.or(other.pool_size),
+            worktree_ttl: self.worktree_ttl.or(other.worktree_ttl),
+            max_worktrees: self.max_worktrees.or(other.max_worktrees),
+            allow_repos: self.allow_repos.or(other.allow_repos),
+            deny_repos: self.deny_repos.or(other.deny_repos),
+            trusted_repos: self.trusted_repos.or(other.trusted_repos),
+            worktree_config: self.worktree_config.or(other.worktree_config),
+            inherit_config: self.inherit_config.or(other.inherit_config),
+            author_name: self.author_name.or(other.author_name),
+            author_email: self.author_email.or(other.author_email),
+            templates: self.templates.or(other.templates),
+            // Tools merge by name, with this config's definitions winning
+            tools: match (self.tools, other.tools) {
+                (Some(mine), Some(mut theirs)) => {
+                    theirs.extend(mine);
+                    Some(theirs)
+                }
+                (mine, theirs) => mine.or(theirs),
+            },
+            ignored_keys: self.ignored_keys,
+            repo_templates: self.repo_templates,
+        }
+    }
+}
+
+impl Config {
+    /// Who commits in worktrees are made as, from author_name and author_email
+    pub fn identity(&self) -> git::Identity {
+        git::Identity {
+            name: self.author_name.clone(),
+            email: self.author_email.clone(),
+        }
+    }
+}
+
+/// Location of the user-wide config file
+/// Returns: $XDG_CONFIG_HOME/arborist/config.toml (or ~/.config/arborist/config.toml)
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(base.join("arborist").join("config.toml"))
+}
+
+/// Loads the effective config, with the repository file taking precedence over the user file
+pub fn load(repo_root: Option<&Path>) -> Result<Config> {
+    let user = match user_config_path() {
+        Some(path) => read_config(&path)?,
+        None => Config::default(),
+    };
+
+    let repo = match repo_root {
+        Some(root) => {
+            let mut config = read_config(&root.join(REPO_CONFIG_FILE))?;
+            config.ignored_keys = drop_user_only(&mut config);
+            config.repo_templates = config.templates.is_some();
+            config
+        }
+        None => Config::default(),
+    };
+
+    Ok(repo.or(user))
+}
+
+// Clears the keys a repository file mustn't set, since they run programs, send data elsewhere,
+// or make or drop commits on the user's behalf
+// Returns: the keys that were set
+fn drop_user_only(config: &mut Config) -> Vec<&'static str> {
+    let mut dropped = Vec::new();
+    let mut drop = |key, set: bool| {
+        if set {
+            dropped.push(key);
+        }
+    };
+    drop("direnv", config.direnv.take().is_some());
+    drop("announce_url", config.announce_url.take().is_some());
+    drop("notify_url", config.notify_url.take().is_some());
+    drop("container_engine", config.container_engine.take().is_some());
+    drop("nix", config.nix.take().is_some());
+    drop("editor", config.editor.take().is_some());
+    drop("error_exit_code", config.error_exit_code.take().is_some());
+    drop("worktree_root", config.worktree_root.take().is_some());
+    drop("allow_repos", config.allow_repos.take().is_some());
+    drop("deny_repos", config.deny_repos.take().is_some());
+    drop("trusted_repos", config.trusted_repos.take().is_some());
+    drop("worktree_config", config.worktree_config.take().is_some());
+    drop("autoclean", config.autoclean.take().is_some());
+    drop("author_name", config.author_name.take().is_some());
+    drop("author_email", config.author_email.take().is_some());
+    drop(
+        "on_commits",
+        config
+            .on_commits
+            .take_if(|action| *action != CommitsAction::Keep)
+            .is_some(),
+    );
+    dropped
+}
+
+/// A directory of sibling repositories that `arborist ws` gives worktrees together
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    /// Directory holding arborist-workspace.toml
+    pub root: PathBuf,
+    /// Member repositories, relative to the root
+    pub members: Vec<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorkspaceFile {
+    members: Vec<PathBuf>,
+}
+
+/// Finds the workspace containing `dir`: the nearest arborist-workspace.toml in it or above
+pub fn find_workspace(dir: &Path) -> Result<Option<Workspace>> {
+    let Some(root) = dir
+        .ancestors()
+        .find(|dir| dir.join(WORKSPACE_FILE).is_file())
+    else {
+        return Ok(None);
+    };
+
+    let path = root.join(WORKSPACE_FILE);
+    let file: WorkspaceFile = toml::from_str(&fs::read_to_string(&path)?)
+        .map_err(|e| ArboristError::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+    for member in &file.members {
+        let inside = member
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)));
+        if !inside || member.as_os_str().is_empty() {
+            return Err(ArboristError::InvalidConfig(format!(
+                "{}: member {} must be a relative path inside the workspace",
+                path.display(),
+                member.display()
+            )));
+        }
+    }
+
+    Ok(Some(Workspace {
+        root: root.to_path_buf(),
+        members: file.members,
+    }))
+}
+
+/// Whether the repository at `root` is one of `dirs` or inside one of them, as allow_repos,
+/// deny_repos and trusted_repos list repositories; `~` is expanded
+pub fn lists_repo(dirs: &[PathBuf], root: &Path) -> bool {
+    dirs.iter().any(|dir| {
+        let dir = expand_home(dir);
+        root.starts_with(fs::canonicalize(&dir).unwrap_or(dir))
+    })
+}
+
+/// Expands a leading `~` to the home directory, as the shell would
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn read_config(path: &Path) -> Result<Config> {
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+
+    let contents = fs::read_to_string(path)?;
+    toml::from_str(&contents)
+        .map_err(|e| ArboristError::InvalidConfig(format!("{}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo_config(toml: &str) -> Config {
+        let mut config: Config = toml::from_str(toml).unwrap();
+        config.ignored_keys = drop_user_only(&mut config);
+        config
+    }
+
+    #[test]
+    fn repository_cannot_set_user_only_keys() {
+        let config = repo_config(
+            r#"
+            direnv = true
+            error_exit_code = 3
+            autoclean = ["*"]
+            author_name = "Someone Else"
+            author_email = "else@example.com"
+            on_commits = "merge-back"
+            pool_size = 2
+            "#,
+        );
+        assert_eq!(
+            config.ignored_keys,
+            [
+                "direnv",
+                "error_exit_code",
+                "autoclean",
+                "author_name",
+                "author_email",
+                "on_commits"
+            ]
+        );
+        assert!(config.direnv.is_none());
+        assert!(config.error_exit_code.is_none());
+        assert!(config.autoclean.is_none());
+        assert!(config.identity().name.is_none());
+        assert!(config.on_commits.is_none());
+        assert_eq!(config.pool_size, Some(2));
+    }
+
+    #[test]
+    fn repository_cannot_trust_itself() {
+        let config = repo_config(r#"trusted_repos = ["/"]"#);
+        assert_eq!(config.ignored_keys, ["trusted_repos"]);
+        assert!(config.trusted_repos.is_none());
+    }
+
+    #[test]
+    fn lists_repositories_by_root_or_parent() {
+        let dirs = [PathBuf::from("/nonexistent/src")];
+        assert!(lists_repo(&dirs, Path::new("/nonexistent/src")));
+        assert!(lists_repo(&dirs, Path::new("/nonexistent/src/app")));
+        assert!(!lists_repo(&dirs, Path::new("/nonexistent/src-other/app")));
+        assert!(!lists_repo(&dirs, Path::new("/nonexistent")));
+    }
+
+    #[test]
+    fn repository_can_keep_commits() {
+        let config = repo_config(r#"on_commits = "keep""#);
+        assert!(config.ignored_keys.is_empty());
+        assert_eq!(config.on_commits, Some(CommitsAction::Keep));
+    }
+
+    #[test]
+    fn user_only_keys_fall_back_to_the_user_config() {
+        let user: Config = toml::from_str(r#"on_commits = "clean""#).unwrap();
+        let config = repo_config(r#"on_commits = "merge-back""#).or(user);
+        assert_eq!(config.on_commits, Some(CommitsAction::Clean));
+    }
+}
@@ -0,0 +1,139 @@
+use arborist::config::Config;
+use arborist::error::{ArboristError, Result};
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+// Engines tried in order when container_engine is not configured
+const ENGINES: &[&str] = &["podman", "docker"];
+
+/// A container the command runs in instead of directly on the host
+#[derive(Debug, Clone)]
+pub struct Container {
+    engine: String,
+    image: String,
+    // Bind-mounted at the same path inside the container
+    mounts: Vec<Mount>,
+    workdir: PathBuf,
+}
+
+/// A host path bind-mounted at the same path inside the container
+#[derive(Debug, Clone)]
+pub struct Mount {
+    pub path: PathBuf,
+    /// Whether the command may only read it
+    pub read_only: bool,
+}
+
+impl Mount {
+    pub fn writable(path: PathBuf) -> Self {
+        Mount {
+            path,
+            read_only: false,
+        }
+    }
+
+    pub fn read_only(path: PathBuf) -> Self {
+        Mount {
+            path,
+            read_only: true,
+        }
+    }
+}
+
+impl Container {
+    /// Prepares a container of `image` that mounts every path in `mounts` and starts in `workdir`
+    pub fn new(config: &Config, image: &str, mounts: Vec<Mount>, workdir: PathBuf) -> Result<Self> {
+        let engine = match &config.container_engine {
+            Some(engine) => engine.clone(),
+            None => detect_engine().ok_or_else(|| {
+                ArboristError::InvalidConfig(
+                    "--container needs podman or docker on PATH (or container_engine in the config)"
+                        .to_string(),
+                )
+            })?,
+        };
+
+        Ok(Container {
+            engine,
+            image: image.to_string(),
+            mounts,
+            workdir,
+        })
+    }
+
+    /// Argv running `command` in a throwaway container, with `flags` added to `run`
+    /// Variables in `env` are forwarded by name, so their values are taken from
+    /// the engine's environment rather than shown on its command line
+    pub fn argv(
+        &self,
+        command: &[String],
+        env: &[(String, String)],
+        flags: &[String],
+    ) -> Vec<String> {
+        let mut argv = vec![
+            self.engine.clone(),
+            "run".to_string(),
+            "--rm".to_string(),
+            "-i".to_string(),
+        ];
+        if io::stdin().is_terminal() && io::stdout().is_terminal() {
+            argv.push("-t".to_string());
+        }
+
+        // Keep files written in the worktree owned by the invoking user
+        if self.is_podman() {
+            argv.push("--userns=keep-id".to_string());
+        } else if let Some(user) = owner(&self.workdir) {
+            argv.push(format!("--user={}", user));
+        }
+
+        // Engines mount nested paths after their parents, whatever the order given
+        for mount in &self.mounts {
+            let path = mount.path.display();
+            let options = if mount.read_only { ":ro" } else { "" };
+            argv.push(format!("--volume={}:{}{}", path, path, options));
+        }
+        argv.push(format!("--workdir={}", self.workdir.display()));
+
+        let mut keys: Vec<_> = env.iter().map(|(key, _)| key).collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            argv.push(format!("--env={}", key));
+        }
+
+        argv.extend(flags.iter().cloned());
+        argv.push(self.image.clone());
+        argv.extend(command.iter().cloned());
+        argv
+    }
+
+    fn is_podman(&self) -> bool {
+        Path::new(&self.engine)
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("podman"))
+    }
+}
+
+// First supported engine found on PATH
+fn detect_engine() -> Option<String> {
+    let path = env::var_os("PATH")?;
+    ENGINES
+        .iter()
+        .find(|engine| env::split_paths(&path).any(|dir| dir.join(engine).is_file()))
+        .map(|engine| engine.to_string())
+}
+
+#[cfg(unix)]
+fn owner(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = path.metadata().ok()?;
+    Some(format!("{}:{}", metadata.uid(), metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn owner(_path: &Path) -> Option<String> {
+    None
+}
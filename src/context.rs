@@ -0,0 +1,225 @@
+use crate::error::{ArboristError, Result};
+use std::env;
+use std::path::{Path, PathBuf};
+
+// Something held for the duration of a run (a lock, a recorded heartbeat), released at its end
+type Release = Box<dyn FnOnce() -> Result<()> + Send>;
+
+/// A run in a worktree, from changing into it to removing it, tearing down in a fixed order
+/// whichever way the run ends: the original directory is restored first, then what was
+/// registered with `on_release` is released (last registered first), and only then is the
+/// worktree removed, so no part of arborist is still in it or holding it when it goes
+///
+/// Finishing explicitly reports failures; a context dropped without finishing (an early return,
+/// a panic unwinding, a run cut short by a signal) still restores the directory and releases
+/// everything, ignoring failures, but never removes the worktree
+pub struct RunContext {
+    worktree: PathBuf,
+    // Directory to return to; None once restored
+    original: Option<PathBuf>,
+    // Where to go instead when the original directory is inside the worktree
+    repo_root: PathBuf,
+    releases: Vec<Release>,
+    // Whether other processes still in the worktree keep it from being removed
+    check_processes: bool,
+}
+
+impl RunContext {
+    /// Changes into the worktree, remembering the current directory to return to
+    /// `repo_root` is returned to instead when the current directory is inside the worktree
+    /// (arborist was started from within it), so git still has a repository to work in once
+    /// the worktree is removed
+    pub fn enter(worktree: &Path, repo_root: &Path) -> Result<Self> {
+        let original = env::current_dir()?;
+        env::set_current_dir(worktree)?;
+        Ok(RunContext {
+            worktree: worktree.to_path_buf(),
+            original: Some(original),
+            repo_root: repo_root.to_path_buf(),
+            releases: Vec::new(),
+            check_processes: false,
+        })
+    }
+
+    /// Registers something to release once the run is over, after the original directory is
+    /// restored and before the worktree is removed
+    pub fn on_release(&mut self, release: impl FnOnce() -> Result<()> + Send + 'static) {
+        self.releases.push(Box::new(release));
+    }
+
+    /// Releases everything registered so far, last registered first, staying in the worktree:
+    /// for holds that must be gone before the run's end, e.g. before deciding whether the
+    /// worktree is still in use
+    /// Returns: the first failure, once everything has been attempted
+    pub fn release(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        while let Some(release) = self.releases.pop() {
+            let released = release();
+            if result.is_ok() {
+                result = released;
+            }
+        }
+        result
+    }
+
+    /// Makes `finish_and_remove` also refuse while another process, e.g. one the command left
+    /// running in the background, has its working directory in the worktree
+    pub fn check_processes(&mut self) {
+        self.check_processes = true;
+    }
+
+    /// Returns to the directory the run started from, or the repository root when that was
+    /// inside the worktree; a no-op once it has
+    pub fn restore_dir(&mut self) -> Result<()> {
+        let Some(original) = self.original.take() else {
+            return Ok(());
+        };
+        if original.starts_with(&self.worktree) {
+            env::set_current_dir(&self.repo_root)?;
+        } else {
+            env::set_current_dir(original)?;
+        }
+        Ok(())
+    }
+
+    /// Ends the run keeping the worktree: restores the directory and releases everything
+    /// Returns: the first failure, once everything has been attempted
+    pub fn finish(mut self) -> Result<()> {
+        self.teardown()
+    }
+
+    /// Ends the run and removes the worktree with `remove`, once the directory is restored and
+    /// everything is released; the worktree is kept when either fails
+    pub fn finish_and_remove(mut self, remove: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+        self.teardown()?;
+        if env::current_dir().is_ok_and(|dir| dir.starts_with(&self.worktree)) {
+            return Err(ArboristError::InvalidPath(format!(
+                "Still inside {} after leaving it, so it was not removed",
+                self.worktree.display()
+            )));
+        }
+        if self.check_processes {
+            let pids = processes_inside(&self.worktree);
+            if !pids.is_empty() {
+                let pids: Vec<String> = pids.iter().map(u32::to_string).collect();
+                return Err(ArboristError::InvalidPath(format!(
+                    "Process(es) {} still in {}, so it was not removed",
+                    pids.join(", "),
+                    self.worktree.display()
+                )));
+            }
+        }
+        remove(&self.worktree)
+    }
+
+    fn teardown(&mut self) -> Result<()> {
+        let restored = self.restore_dir();
+        let released = self.release();
+        restored.and(released)
+    }
+}
+
+/// Other processes whose working directory is `dir` or below it, as far as they can be seen
+/// (on Linux, those of the same user or any with enough privileges); none elsewhere
+pub fn processes_inside(dir: &Path) -> Vec<u32> {
+    if !cfg!(target_os = "linux") {
+        return Vec::new();
+    }
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    // /proc shows working directories with symlinks resolved
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let mut pids: Vec<u32> = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .filter(|pid| *pid != std::process::id())
+        .filter(|pid| {
+            std::fs::read_link(format!("/proc/{}/cwd", pid)).is_ok_and(|cwd| cwd.starts_with(&dir))
+        })
+        .collect();
+    pids.sort_unstable();
+    pids
+}
+
+impl Drop for RunContext {
+    fn drop(&mut self) {
+        let _ = self.teardown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::panic;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex, MutexGuard};
+
+    // The current directory is shared by every test thread
+    static CWD: Mutex<()> = Mutex::new(());
+
+    fn serial() -> MutexGuard<'static, ()> {
+        CWD.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // (repository root, worktree) directories of a scratch layout for test `name`
+    fn scratch(name: &str) -> (PathBuf, PathBuf) {
+        let root =
+            env::temp_dir().join(format!("arborist-context-{}-{}", std::process::id(), name));
+        let repo = root.join("repo");
+        let worktree = root.join("worktree");
+        fs::create_dir_all(&repo).unwrap();
+        fs::create_dir_all(&worktree).unwrap();
+        (
+            repo.canonicalize().unwrap(),
+            worktree.canonicalize().unwrap(),
+        )
+    }
+
+    #[test]
+    fn panic_restores_dir_and_keeps_worktree() {
+        let _serial = serial();
+        let (repo, worktree) = scratch("panic");
+        let original = env::current_dir().unwrap();
+        let released = Arc::new(AtomicBool::new(false));
+
+        let result = panic::catch_unwind(|| {
+            let mut context = RunContext::enter(&worktree, &repo).unwrap();
+            let released = released.clone();
+            context.on_release(move || {
+                released.store(true, Ordering::SeqCst);
+                Ok(())
+            });
+            panic!("the run failed");
+        });
+
+        assert!(result.is_err());
+        assert_eq!(env::current_dir().unwrap(), original);
+        assert!(released.load(Ordering::SeqCst));
+        assert!(worktree.is_dir());
+        fs::remove_dir_all(worktree.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn finish_and_remove_refuses_from_inside() {
+        let _serial = serial();
+        let (repo, worktree) = scratch("inside");
+        let original = env::current_dir().unwrap();
+
+        let mut context = RunContext::enter(&worktree, &repo).unwrap();
+        // Something released at the end of the run goes back into the worktree
+        let inside = worktree.clone();
+        context.on_release(move || Ok(env::set_current_dir(inside)?));
+        let mut removed = false;
+        let result = context.finish_and_remove(|_| {
+            removed = true;
+            Ok(())
+        });
+
+        assert!(result.is_err());
+        assert!(!removed);
+        env::set_current_dir(&original).unwrap();
+        fs::remove_dir_all(worktree.parent().unwrap()).unwrap();
+    }
+}
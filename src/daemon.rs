@@ -0,0 +1,268 @@
+use crate::{Args, DirectoryGuard, maintenance};
+use arborist::error::{ArboristError, Result};
+use arborist::{git, state};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Socket the daemon listens on, in the state directory
+const SOCKET_FILE: &str = "daemon.sock";
+
+// How long a client waits for the daemon to answer
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A request sent to the daemon, one JSON object per line
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+enum Request {
+    /// Maintain one repository now, e.g. after a run claimed a worktree from its pool
+    Maintain {
+        repo: PathBuf,
+    },
+    Status,
+    Stop,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Reply {
+    ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    repos: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn socket_path() -> PathBuf {
+    state::state_root().join(SOCKET_FILE)
+}
+
+// `arborist daemon [--status | --stop]`
+pub fn run_daemon(args: &Args, interval: Duration, status: bool, stop: bool) -> Result<i32> {
+    if status {
+        return match send(&Request::Status)? {
+            Some(reply) => {
+                println!(
+                    "arborist daemon running (pid {}), maintaining {} repositories",
+                    reply.pid.unwrap_or_default(),
+                    reply.repos.len()
+                );
+                for repo in &reply.repos {
+                    println!("  {}", repo.display());
+                }
+                Ok(0)
+            }
+            None => {
+                println!("arborist daemon is not running");
+                Ok(1)
+            }
+        };
+    }
+    if stop {
+        return match send(&Request::Stop)? {
+            Some(_) => {
+                notice!("Stopped arborist daemon");
+                Ok(0)
+            }
+            None => {
+                notice!("arborist daemon is not running");
+                Ok(1)
+            }
+        };
+    }
+
+    serve(args, interval)?;
+    Ok(0)
+}
+
+/// Asks a running daemon to maintain a repository soon, without waiting for it
+/// Does nothing when no daemon runs
+pub fn request_maintenance(repo_root: &Path) {
+    let request = Request::Maintain {
+        repo: repo_root.to_path_buf(),
+    };
+    if let Err(err) = send(&request) {
+        verbose!("Couldn't reach arborist daemon: {}", err);
+    }
+}
+
+// Every repository arborist knows of that still exists
+// State left under an old directory of a repository lists it twice
+fn repos() -> Result<Vec<PathBuf>> {
+    let mut repos: Vec<PathBuf> = state::known_repos()?
+        .into_iter()
+        .map(|(_, root)| root)
+        .filter(|root| root.is_dir())
+        .collect();
+    repos.sort();
+    repos.dedup();
+    Ok(repos)
+}
+
+fn maintain_repo(root: &Path, args: &Args) {
+    let result = DirectoryGuard::with_path(root).and_then(|_guard| match git::read_repo_info()? {
+        Some(repo) => maintenance::maintain(&repo, args),
+        None => Ok(()),
+    });
+    if let Err(err) = result {
+        warning!("maintaining {}: {}", root.display(), err);
+    }
+}
+
+fn maintain_all(args: &Args) {
+    match repos() {
+        Ok(repos) => {
+            for root in repos {
+                maintain_repo(&root, args);
+            }
+        }
+        Err(err) => warning!("listing repositories: {}", err),
+    }
+}
+
+#[cfg(unix)]
+fn send(request: &Request) -> Result<Option<Reply>> {
+    use std::io::{BufRead, BufReader, ErrorKind, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = match UnixStream::connect(socket_path()) {
+        Ok(stream) => stream,
+        Err(err)
+            if matches!(
+                err.kind(),
+                ErrorKind::NotFound | ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None);
+        }
+        Err(err) => return Err(err.into()),
+    };
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    let reply: Reply = serde_json::from_str(&reply)?;
+    match reply.error {
+        Some(error) => Err(ArboristError::IoError(std::io::Error::other(error))),
+        None => Ok(Some(reply)),
+    }
+}
+
+#[cfg(unix)]
+fn serve(args: &Args, interval: Duration) -> Result<()> {
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::mpsc::{self, RecvTimeoutError};
+    use std::thread;
+    use std::time::Instant;
+
+    // Removes the socket once the daemon stops, however it stops
+    struct Socket(PathBuf);
+
+    impl Drop for Socket {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn handle(
+        stream: UnixStream,
+        requests: &mpsc::Sender<Request>,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let reply = match serde_json::from_str(&line) {
+            Ok(Request::Status) => Reply {
+                ok: true,
+                pid: Some(std::process::id()),
+                repos: repos()?,
+                error: None,
+            },
+            Ok(request) => {
+                requests.send(request)?;
+                Reply {
+                    ok: true,
+                    ..Reply::default()
+                }
+            }
+            Err(err) => Reply {
+                error: Some(format!("invalid request: {}", err)),
+                ..Reply::default()
+            },
+        };
+        let mut reply = serde_json::to_vec(&reply)?;
+        reply.push(b'\n');
+        (&stream).write_all(&reply)?;
+        Ok(())
+    }
+
+    let path = socket_path();
+    if send(&Request::Status)?.is_some() {
+        return Err(ArboristError::InvalidConfig(format!(
+            "arborist daemon is already running (socket {})",
+            path.display()
+        )));
+    }
+    // Left behind by a daemon that didn't get to clean up
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    let _socket = Socket(path.clone());
+    notice!(
+        "arborist daemon listening on {}, maintaining every {:?}",
+        path.display(),
+        interval
+    );
+
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream
+                .map_err(Into::into)
+                .and_then(|stream| handle(stream, &sender));
+            if let Err(err) = result {
+                verbose!("Warning: daemon request failed: {}", err);
+            }
+        }
+    });
+
+    let mut next = Instant::now();
+    loop {
+        if Instant::now() >= next {
+            maintain_all(args);
+            next = Instant::now() + interval;
+        }
+        match requests.recv_timeout(next.saturating_duration_since(Instant::now())) {
+            Ok(Request::Maintain { repo }) => maintain_repo(&repo, args),
+            Ok(Request::Stop) => return Ok(()),
+            Ok(Request::Status) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(ArboristError::IoError(std::io::Error::other(
+                    "the daemon stopped accepting requests",
+                )));
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn send(_request: &Request) -> Result<Option<Reply>> {
+    Ok(None)
+}
+
+#[cfg(not(unix))]
+fn serve(_args: &Args, _interval: Duration) -> Result<()> {
+    Err(ArboristError::InvalidConfig(
+        "arborist daemon is not supported on this platform".to_string(),
+    ))
+}
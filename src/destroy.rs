@@ -0,0 +1,363 @@
+use crate::{
+    DirectoryGuard, archive, heartbeat, is_arborist_worktree, lock, pick, plan_worktree,
+    remove_planned_worktree, worktrees_dir,
+};
+use arborist::error::{ArboristError, Result};
+use arborist::{config, git, state};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+// Phrase the user must type to confirm destroy-all without --force
+const CONFIRM_PHRASE: &str = "destroy all";
+
+// Everything destroy-all will remove for a single repository
+struct RepoTargets {
+    repo_root: PathBuf,
+    state_dir: PathBuf,
+    // Patches of work discarded earlier, if any were saved
+    archive_dir: Option<PathBuf>,
+    // Per-repo worktree directory; None for bare repositories
+    worktree_root: Option<PathBuf>,
+    worktrees: Vec<git::WorktreeEntry>,
+    branches: Vec<String>,
+    // Locked worktrees, with the process of the run using them if any
+    skipped: Vec<(git::WorktreeEntry, Option<u32>)>,
+    missing: bool,
+}
+
+// `arborist destroy-all`: remove all arborist worktrees, branches, archives and state
+// keep_archives saves the work being removed to the archive and leaves the archive in place
+pub fn run_destroy_all(
+    everywhere: bool,
+    force: bool,
+    include_pinned: bool,
+    keep_archives: bool,
+) -> Result<i32> {
+    let targets = if everywhere {
+        let mut targets = Vec::new();
+        for (state_dir, repo_root) in state::known_repos()? {
+            targets.push(collect_targets(&repo_root, state_dir, include_pinned)?);
+        }
+        targets
+    } else {
+        let repo = crate::require_repo()?;
+        let state_dir = state::repo_state_dir(&repo.root)?;
+        vec![collect_targets(&repo.root, state_dir, include_pinned)?]
+    };
+
+    if targets.is_empty() {
+        eprintln!("Nothing to destroy");
+        return Ok(0);
+    }
+
+    print_targets(&targets, keep_archives);
+
+    if !force && !confirm()? {
+        eprintln!("Aborted; nothing was removed");
+        return Ok(1);
+    }
+
+    for target in &targets {
+        destroy_repo(target, keep_archives)?;
+    }
+
+    Ok(0)
+}
+
+// Gather the worktrees and branches arborist created in a repository
+fn collect_targets(
+    repo_root: &Path,
+    state_dir: PathBuf,
+    include_pinned: bool,
+) -> Result<RepoTargets> {
+    let archive_dir = Some(state::archive_dir(repo_root)?).filter(|dir| dir.exists());
+    if !repo_root.exists() {
+        // The repository is gone; only its leftover directories can be cleaned
+        let worktree_root = git::compute_worktree_root(repo_root)?;
+        return Ok(RepoTargets {
+            repo_root: repo_root.to_path_buf(),
+            state_dir,
+            archive_dir,
+            worktree_root: Some(worktree_root),
+            worktrees: Vec::new(),
+            branches: Vec::new(),
+            skipped: Vec::new(),
+            missing: true,
+        });
+    }
+
+    let _guard = DirectoryGuard::with_path(repo_root)?;
+    let repo = git::get_repo_info()?.ok_or_else(|| ArboristError::NotARepo {
+        dir: repo_root.to_path_buf(),
+    })?;
+
+    // git refuses to delete the branch the main checkout is on, which would only be found out
+    // once the worktrees are already gone
+    if !repo.is_bare
+        && let Some(branch) = git::checked_out_branch(&repo.root)?
+        && branch.starts_with("arborist/")
+    {
+        return Err(ArboristError::InvalidPath(format!(
+            "{} has {} checked out; switch it to another branch before destroying arborist's branches",
+            repo.root.display(),
+            branch
+        )));
+    }
+
+    let mut worktrees = Vec::new();
+    let mut skipped = Vec::new();
+    let mut entries = git::list_worktrees()?;
+    lock::release_stale(&repo, &mut entries)?;
+    for entry in entries {
+        if entry.path == repo.root || !is_arborist_worktree(&repo, &entry)? {
+            continue;
+        }
+        let running = match pick::worktree_name(&repo, &entry)? {
+            Some(name) => heartbeat::active_run(&repo.root, &name)?.map(|run| run.pid),
+            None => None,
+        }
+        .or_else(|| lock::active_run(&entry));
+        if is_kept(&entry, running, include_pinned) {
+            skipped.push((entry, running));
+        } else {
+            worktrees.push(entry);
+        }
+    }
+
+    // Branches checked out in skipped worktrees must survive
+    let branches = git::list_branches("arborist/")?
+        .into_iter()
+        .filter(|branch| {
+            !skipped
+                .iter()
+                .any(|(entry, _)| entry.branch.as_deref() == Some(branch.as_str()))
+        })
+        .collect();
+
+    let worktree_root = worktrees_dir(&repo, &config::load(None)?)?;
+
+    Ok(RepoTargets {
+        repo_root: repo.root,
+        state_dir,
+        archive_dir,
+        worktree_root,
+        worktrees,
+        branches,
+        skipped,
+        missing: false,
+    })
+}
+
+// Worktrees with a command running in them are never pulled out from under it, and locked ones
+// are kept unless include_pinned; parked worktrees are only locked to keep them out of the way
+// until claimed
+fn is_kept(entry: &git::WorktreeEntry, running: Option<u32>, include_pinned: bool) -> bool {
+    running.is_some() || entry.locked && !include_pinned && !lock::is_parked(entry)
+}
+
+fn print_targets(targets: &[RepoTargets], keep_archives: bool) {
+    if keep_archives {
+        eprintln!(
+            "The following will be removed, with their commits and changes saved as patches:"
+        );
+    } else {
+        eprintln!("The following will be removed, with their commits and changes:");
+    }
+    for target in targets {
+        eprintln!();
+        if target.missing {
+            eprintln!(
+                "{} (repository no longer exists)",
+                target.repo_root.display()
+            );
+            if let Some(root) = &target.worktree_root {
+                eprintln!("  directory: {}", root.display());
+            }
+        } else {
+            eprintln!("{}", target.repo_root.display());
+        }
+        for entry in &target.worktrees {
+            eprintln!("  worktree:  {}", entry.path.display());
+        }
+        for branch in &target.branches {
+            eprintln!("  branch:    {}", branch);
+        }
+        eprintln!("  state:     {}", target.state_dir.display());
+        match &target.archive_dir {
+            Some(dir) if keep_archives => eprintln!("  keeping archive {}", dir.display()),
+            Some(dir) => eprintln!("  archive:   {}", dir.display()),
+            None => {}
+        }
+        for (entry, running) in &target.skipped {
+            match running {
+                Some(pid) => eprintln!(
+                    "  keeping worktree {} in use by process {}",
+                    entry.path.display(),
+                    pid
+                ),
+                None => eprintln!(
+                    "  keeping locked worktree {} (use --include-pinned to remove)",
+                    entry.path.display()
+                ),
+            }
+        }
+    }
+    eprintln!();
+}
+
+// Ask the user to type the confirmation phrase
+fn confirm() -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        eprintln!("Refusing to destroy without confirmation; pass --force when not interactive");
+        return Ok(false);
+    }
+
+    eprint!("Type '{}' to confirm: ", CONFIRM_PHRASE);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+
+    Ok(answer.trim() == CONFIRM_PHRASE)
+}
+
+fn destroy_repo(target: &RepoTargets, keep_archives: bool) -> Result<()> {
+    if !keep_archives && let Some(dir) = &target.archive_dir {
+        fs::remove_dir_all(dir)?;
+    }
+
+    if target.missing {
+        if let Some(root) = &target.worktree_root
+            && root.exists()
+        {
+            fs::remove_dir_all(root)?;
+        }
+        state::remove_repo_state(&target.state_dir)?;
+        eprintln!("Removed leftovers of {}", target.repo_root.display());
+        return Ok(());
+    }
+
+    let _guard = DirectoryGuard::with_path(&target.repo_root)?;
+    let repo = git::get_repo_info()?.ok_or_else(|| ArboristError::NotARepo {
+        dir: target.repo_root.clone(),
+    })?;
+
+    // Branches whose work was saved along with their worktree's
+    let mut archived = Vec::new();
+    // Worktrees their pre-remove hook kept, and their branches
+    let mut kept = 0;
+    let mut kept_branches = Vec::new();
+    for entry in &target.worktrees {
+        let plan = match pick::worktree_name(&repo, entry)? {
+            Some(name) if entry.path.is_dir() => Some(plan_worktree(repo.clone(), name)?),
+            _ => None,
+        };
+        let Some(plan) = plan else {
+            // Its directory is gone or it can't be named, so there is no work to save or
+            // directory to run the hook in
+            verbose!("Removing worktree {}", entry.path.display());
+            git::force_remove_locked_worktree(&entry.path)?;
+            continue;
+        };
+
+        if keep_archives {
+            if let Some(dir) = archive::archive_worktree(&plan)? {
+                archive::report(&plan.name, &dir);
+            }
+            archived.extend(entry.branch.clone());
+        }
+        verbose!("Removing worktree {}", entry.path.display());
+        if entry.locked {
+            git::unlock_worktree(&entry.path)?;
+        }
+        // Branches are deleted below, archived or not, once every worktree is gone
+        if let Err(err) = remove_planned_worktree(&plan, true) {
+            warning!("{}; keeping worktree {}", err.chain(), entry.path.display());
+            if entry.locked {
+                git::lock_worktree(&entry.path, entry.lock_reason.as_deref().unwrap_or(""))?;
+            }
+            kept += 1;
+            kept_branches.extend(entry.branch.clone());
+        }
+    }
+
+    let mut deleted = 0;
+    for branch in &target.branches {
+        if kept_branches.contains(branch) {
+            continue;
+        }
+        if keep_archives
+            && !archived.contains(branch)
+            && let Some(dir) = archive::archive_branch(&target.repo_root, branch)?
+        {
+            archive::report(branch, &dir);
+        }
+        verbose!("Deleting branch {}", branch);
+        git::delete_branch(branch)?;
+        deleted += 1;
+    }
+
+    git::prune_worktrees()?;
+
+    // Drop the per-repo worktree directory if nothing was left behind in it
+    if let Some(root) = &target.worktree_root {
+        let _ = fs::remove_dir(root);
+    }
+
+    // Keep state for repositories with locked or kept worktrees so they can be found again
+    if target.skipped.is_empty() && kept == 0 {
+        state::remove_repo_state(&target.state_dir)?;
+    }
+
+    eprintln!(
+        "Removed {} worktree(s) and {} branch(es) from {}",
+        target.worktrees.len() - kept,
+        deleted,
+        target.repo_root.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worktree(lock_reason: Option<&str>) -> git::WorktreeEntry {
+        git::WorktreeEntry {
+            path: PathBuf::from("/worktrees/fix-auth"),
+            branch: Some("arborist/fix-auth".to_string()),
+            locked: lock_reason.is_some(),
+            lock_reason: lock_reason.map(str::to_string),
+            prunable: false,
+        }
+    }
+
+    #[test]
+    fn keeps_worktrees_in_use_even_with_include_pinned() {
+        assert!(is_kept(&worktree(None), Some(42), true));
+        assert!(is_kept(
+            &worktree(Some("arborist: parked in the pool")),
+            Some(42),
+            true
+        ));
+    }
+
+    #[test]
+    fn keeps_locked_worktrees_unless_include_pinned() {
+        let locked = worktree(Some("on a USB stick"));
+        assert!(is_kept(&locked, None, false));
+        assert!(!is_kept(&locked, None, true));
+        assert!(!is_kept(&worktree(None), None, false));
+    }
+
+    #[test]
+    fn removes_parked_worktrees() {
+        assert!(!is_kept(
+            &worktree(Some("arborist: parked in the pool")),
+            None,
+            false
+        ));
+    }
+}
@@ -0,0 +1,57 @@
+use crate::{WorktreePlan, excluded_paths, plan_worktree, require_repo};
+use arborist::error::{ArboristError, Result};
+use arborist::{git, process, state};
+use duct::cmd;
+use std::ffi::OsStr;
+
+// `arborist diff`: review what a worktree changed without entering it
+pub fn run_diff(name: &str, stat: bool, patch: bool) -> Result<i32> {
+    let repo = require_repo()?;
+    let plan = plan_worktree(repo, name.to_string())?;
+    if !git::worktree_exists(&plan.path)? {
+        return Err(ArboristError::InvalidPath(format!(
+            "No arborist worktree named '{}' at {}",
+            name,
+            plan.path.display()
+        )));
+    }
+
+    show_changes(&plan, stat, patch)
+}
+
+/// Pages everything the worktree changed since it was created: its commits, uncommitted
+/// edits and untracked files. Shows the patch unless only `stat` is asked for
+pub fn show_changes(plan: &WorktreePlan, stat: bool, patch: bool) -> Result<i32> {
+    let base = match state::load_worktree(&plan.repo.root, &plan.name)? {
+        Some(meta) => meta.base_commit,
+        None => {
+            verbose!(
+                "No recorded base for {}; comparing against {}",
+                plan.name,
+                plan.repo.current_commit
+            );
+            plan.repo.current_commit.clone()
+        }
+    };
+
+    let excluded = excluded_paths(plan)?;
+    let tree = git::snapshot_tree(&plan.path, &excluded)?;
+
+    let mut args = vec![
+        OsStr::new("-C"),
+        plan.path.as_os_str(),
+        OsStr::new("--paginate"),
+        OsStr::new("diff"),
+    ];
+    if stat {
+        args.push(OsStr::new("--stat"));
+    }
+    if patch || !stat {
+        args.push(OsStr::new("--patch"));
+    }
+    args.push(OsStr::new(&base));
+    args.push(OsStr::new(&tree));
+
+    let output = cmd("git", &args).unchecked().run()?;
+    Ok(process::exit_code(&output.status))
+}
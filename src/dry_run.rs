@@ -0,0 +1,95 @@
+use crate::{Args, Invocation, WorktreePlan, tools, worktree_sparse, worktree_upstream};
+use arborist::error::Result;
+use arborist::git;
+use std::path::Path;
+
+/// Prints where a run would create its worktree and the git commands it would run,
+/// without creating anything or running the command
+pub fn print_plan(plan: &WorktreePlan, args: &Args, invocation: &Invocation) -> Result<()> {
+    let repo = &plan.repo;
+    println!(
+        "repo:     {} ({})",
+        repo.root.display(),
+        if repo.is_bare { "bare" } else { "normal" }
+    );
+    if repo.detached {
+        println!("base:     {}", repo.current_commit);
+    } else {
+        println!(
+            "base:     {} at {}",
+            repo.current_branch, repo.current_commit
+        );
+    }
+    println!("name:     {}", plan.name);
+    println!("worktree: {}", plan.path.display());
+    println!("branch:   {}", plan.branch_name());
+
+    println!();
+    if git::worktree_exists(&plan.path)? {
+        println!("The worktree already exists and would be reused.");
+    } else {
+        let partial = git::partial_clone()?;
+        if let Some(partial) = &partial {
+            println!(
+                "The repository is a partial clone; missing blobs would be fetched from {} (filter {}).",
+                partial.remote,
+                partial.filter.as_deref().unwrap_or("unknown")
+            );
+        }
+        let sparse = worktree_sparse(plan, args, partial.is_some())?;
+        println!("Would create the worktree with:");
+        if args.from_remote.is_some() || args.update {
+            print_git(&git::RemoteBranch::parse(&repo.current_branch)?.fetch_args());
+        }
+        let commands = git::create_worktree_commands(
+            &plan.path,
+            plan.worktree_branch(),
+            &repo.current_commit,
+            worktree_upstream(plan),
+            &sparse,
+        )?;
+        for command in &commands {
+            print_git(command);
+        }
+    }
+    if args.lfs {
+        let path = plan.path.display().to_string();
+        print_git(&["-C", &path, "lfs", "install", "--local"]);
+        print_git(&["-C", &path, "lfs", "pull"]);
+    }
+
+    let workdir = match &args.subdir {
+        Some(subdir) => plan.path.join(subdir),
+        None => plan.path.clone(),
+    };
+    println!();
+    println!("Would run in {}:", workdir.display());
+    println!("  {}", tools::shell_join(&invocation.command));
+    for script in &args.then {
+        println!("  {}", script);
+    }
+    if args.nix || plan.config.nix.unwrap_or(false) {
+        println!("  (inside the worktree's Nix environment)");
+    }
+    if let Some(image) = &args.container {
+        println!("  (inside a container of {})", image);
+    }
+
+    println!();
+    println!("Then, unless the worktree has changes or new commits:");
+    print_removal(&plan.path, plan.owned_branch());
+    Ok(())
+}
+
+fn print_removal(path: &Path, branch: Option<&str>) {
+    let path = path.display().to_string();
+    print_git(&["worktree", "remove", &path, "--force"]);
+    if let Some(branch) = branch {
+        print_git(&["branch", "-D", branch]);
+    }
+}
+
+fn print_git<S: AsRef<str>>(args: &[S]) {
+    let args: Vec<String> = args.iter().map(|arg| arg.as_ref().to_string()).collect();
+    println!("  git {}", tools::shell_join(&args));
+}
@@ -0,0 +1,55 @@
+use crate::tools::{shell_argv, shell_quote};
+use arborist::config::Config;
+use arborist::error::{ArboristError, Result, ResultExt};
+use std::env;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+// Editors looked up on PATH when the editor setting is not configured
+const EDITORS: &[&str] = &["code", "zed"];
+
+/// Launches the editor on `path` without waiting for it, so the command can run meanwhile
+pub fn open(config: &Config, path: &Path) -> Result<()> {
+    let editor = resolve_editor(config).ok_or_else(|| {
+        ArboristError::InvalidConfig(
+            "--open found no editor; set editor in the config or $VISUAL/$EDITOR".to_string(),
+        )
+    })?;
+
+    let script = format!("{} {}", editor, shell_quote(&path.display().to_string()));
+    verbose!("Opening editor: {}", script);
+    let argv = shell_argv(&script);
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context(format!("starting editor '{}'", editor))?;
+
+    // Reap the editor (or its launcher) whenever it exits
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+// Editor command from the config, the first known editor on PATH, or $VISUAL/$EDITOR
+// Graphical editors come before the environment, which usually names a terminal editor
+fn resolve_editor(config: &Config) -> Option<String> {
+    if let Some(editor) = &config.editor {
+        return Some(editor.clone());
+    }
+
+    let path = env::var_os("PATH").unwrap_or_default();
+    let found = EDITORS.iter().find(|editor| {
+        let file = format!("{}{}", editor, env::consts::EXE_SUFFIX);
+        env::split_paths(&path).any(|dir| dir.join(&file).is_file())
+    });
+    if let Some(editor) = found {
+        return Some(editor.to_string());
+    }
+
+    ["VISUAL", "EDITOR"]
+        .into_iter()
+        .find_map(|var| env::var(var).ok().filter(|editor| !editor.is_empty()))
+}
@@ -0,0 +1,139 @@
+use crate::WorktreePlan;
+use arborist::error::{ArboristError, Result};
+use duct::cmd;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Variables kept by --clean-env so the command can still find tools and the terminal
+const CLEAN_ENV_KEEP: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "SHELL", "TERM", "LANG", "TMPDIR", "TZ",
+];
+
+/// Parses a KEY=VALUE assignment from --env
+pub fn parse_assignment(value: &str) -> std::result::Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, val)) if is_valid_key(key) => Ok((key.to_string(), val.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", value)),
+    }
+}
+
+/// Reads a dotenv-style file: KEY=VALUE lines, `#` comments, optional `export`
+/// prefixes, and single- or double-quoted values
+pub fn load_env_file(path: &Path) -> Result<Vec<(String, String)>> {
+    let contents = fs::read_to_string(path)?;
+    let mut vars = Vec::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(ArboristError::InvalidConfig(format!(
+                "{}:{}: expected KEY=VALUE",
+                path.display(),
+                number + 1
+            )));
+        };
+
+        let key = key.trim();
+        if !is_valid_key(key) {
+            return Err(ArboristError::InvalidConfig(format!(
+                "{}:{}: invalid variable name '{}'",
+                path.display(),
+                number + 1,
+                key
+            )));
+        }
+
+        vars.push((key.to_string(), unquote(value.trim())));
+    }
+
+    Ok(vars)
+}
+
+/// The sanitized base environment used by --clean-env
+pub fn clean_base() -> BTreeMap<String, String> {
+    env::vars()
+        .filter(|(key, _)| CLEAN_ENV_KEEP.contains(&key.as_str()) || key.starts_with("LC_"))
+        .collect()
+}
+
+/// Variables describing the worktree, injected into every command run in it, and the identity
+/// its commits are made as when author_name or author_email is configured
+/// ARBORIST_BRANCH is empty in a detached worktree
+pub fn worktree_vars(plan: &WorktreePlan) -> Vec<(String, String)> {
+    let mut vars = vec![
+        ("ARBORIST_NAME".to_string(), plan.name.clone()),
+        (
+            "ARBORIST_BRANCH".to_string(),
+            plan.branch.clone().unwrap_or_default(),
+        ),
+        (
+            "ARBORIST_WORKTREE".to_string(),
+            plan.path.display().to_string(),
+        ),
+        (
+            "ARBORIST_REPO".to_string(),
+            plan.repo.root.display().to_string(),
+        ),
+        (
+            "ARBORIST_BASE_COMMIT".to_string(),
+            plan.repo.current_commit.clone(),
+        ),
+    ];
+    vars.extend(plan.config.identity().env_vars());
+    vars
+}
+
+/// Marks the worktree's .envrc as trusted so `direnv exec` will load it
+/// Returns: false when the worktree has no .envrc
+pub fn direnv_allow(worktree: &Path) -> Result<bool> {
+    if !worktree.join(".envrc").is_file() {
+        return Ok(false);
+    }
+
+    let output = cmd!("direnv", "allow", worktree)
+        .stderr_capture()
+        .stdout_capture()
+        .unchecked()
+        .run()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(ArboristError::InvalidConfig(format!(
+            "direnv allow failed: {}",
+            stderr.trim()
+        )));
+    }
+
+    Ok(true)
+}
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with(|c: char| c.is_ascii_digit())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn unquote(value: &str) -> String {
+    let quoted = |q: char| value.len() >= 2 && value.starts_with(q) && value.ends_with(q);
+
+    if quoted('\'') {
+        value[1..value.len() - 1].to_string()
+    } else if quoted('"') {
+        value[1..value.len() - 1]
+            .replace("\\n", "\n")
+            .replace("\\\"", "\"")
+            .replace("\\\\", "\\")
+    } else {
+        // Unquoted values may carry a trailing comment
+        match value.find(" #") {
+            Some(index) => value[..index].trim_end().to_string(),
+            None => value.to_string(),
+        }
+    }
+}
@@ -1,34 +1,176 @@
-use std::fmt;
 use std::io;
+use std::path::PathBuf;
 
-#[derive(Debug)]
+/// Errors produced by arborist operations
+///
+/// Variants carrying a cause expose it through `source()` rather than their message, so
+/// the full chain can be rendered with `Error::source` (see `ArboristError::chain`).
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum ArboristError {
+    #[error("Git operation failed: {0}")]
     GitOperationFailed(String),
+
+    #[error("`git {command}` failed in {dir}: {stderr}")]
+    GitCommandFailed {
+        command: String,
+        dir: PathBuf,
+        stderr: String,
+    },
+
+    #[error("Not inside a git repository: {}", dir.display())]
+    NotARepo { dir: PathBuf },
+
+    #[error("Failed to create worktree {}: {stderr}", path.display())]
+    WorktreeAddFailed { path: PathBuf, stderr: String },
+
+    #[error("Failed to remove worktree {}: {stderr}", path.display())]
+    WorktreeRemoveFailed { path: PathBuf, stderr: String },
+
+    /// The worktree is registered with git but its directory is gone (e.g. on an unmounted
+    /// volume), so it can be neither reused nor recreated
+    #[error("Worktree {name} is registered at {} but its directory is missing", path.display())]
+    WorktreeMissing { name: String, path: PathBuf },
+
+    /// Something that isn't the worktree is already where it would be created
+    #[error("{} already exists but isn't worktree {name}; choose another name", path.display())]
+    NameCollision { name: String, path: PathBuf },
+
+    #[error("Branch {branch} already exists")]
+    BranchExists { branch: String },
+
+    #[error("Branch {branch} has no commits yet, so there is nothing to create a worktree from")]
+    UnbornBranch { branch: String },
+
+    #[error("Branch {branch} is already checked out at {}", worktree.display())]
+    BranchInUse { branch: String, worktree: PathBuf },
+
+    #[error("No local or remote branch named {branch}")]
+    BranchNotFound { branch: String },
+
+    #[error("Upstream branch {upstream} does not exist")]
+    UpstreamMissing { upstream: String },
+
+    /// The installed git lacks something arborist needs
+    #[error("Arborist requires git >= {required} for {feature}, but git {found} is installed")]
+    GitTooOld {
+        feature: String,
+        required: String,
+        found: String,
+    },
+
+    /// The state directory was written by a newer arborist, whose records this one can't read
+    #[error(
+        "State in {} was written by a newer arborist (schema {found}, this one supports up to {supported}); upgrade arborist to use it",
+        dir.display()
+    )]
+    StateTooNew {
+        dir: PathBuf,
+        found: u32,
+        supported: u32,
+    },
+
+    /// Another git process holds a lock file, or one was left behind by a crashed one
+    #[error("{} is held by another git process (delete it if none is running)", path.display())]
+    LockHeld { path: PathBuf },
+
+    #[error("Commit signing failed: {0}")]
+    SigningFailed(String),
+
+    #[error("Invalid path: {0}")]
     InvalidPath(String),
-    IoError(io::Error),
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Webhook request failed: {0}")]
+    WebhookFailed(String),
+
+    #[error("Cannot watch for changes: {0}")]
+    WatchFailed(String),
+
+    /// A script in the repository's .arborist/hooks exited non-zero
+    #[error("The {hook} hook exited with {exit_code}")]
+    HookFailed { hook: String, exit_code: i32 },
+
+    #[error("IO error")]
+    IoError(#[from] io::Error),
+
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+
+    /// Wraps an error with the phase or operation that was in progress
+    #[error("{context}")]
+    Context {
+        context: String,
+        #[source]
+        source: Box<ArboristError>,
+    },
 }
 
-impl fmt::Display for ArboristError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl ArboristError {
+    /// Renders the error and all of its sources as "outer: inner: root cause"
+    pub fn chain(&self) -> String {
+        let mut message = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(err) = source {
+            message.push_str(": ");
+            message.push_str(&err.to_string());
+            source = err.source();
+        }
+        message
+    }
+
+    /// The innermost error, beneath any context added while it propagated
+    pub fn root_cause(&self) -> &ArboristError {
         match self {
-            ArboristError::GitOperationFailed(msg) => {
-                write!(f, "Git operation failed: {}", msg)
-            }
-            ArboristError::InvalidPath(msg) => {
-                write!(f, "Invalid path: {}", msg)
-            }
-            ArboristError::IoError(err) => {
-                write!(f, "IO error: {}", err)
-            }
+            ArboristError::Context { source, .. } => source.root_cause(),
+            other => other,
+        }
+    }
+
+    /// Stable snake_case name of the failure, for matching in scripts and JSON output
+    pub fn kind(&self) -> &'static str {
+        match self.root_cause() {
+            ArboristError::GitOperationFailed(_) => "git_operation_failed",
+            ArboristError::GitCommandFailed { .. } => "git_command_failed",
+            ArboristError::NotARepo { .. } => "not_a_repo",
+            ArboristError::WorktreeAddFailed { .. } => "worktree_add_failed",
+            ArboristError::WorktreeRemoveFailed { .. } => "worktree_remove_failed",
+            ArboristError::WorktreeMissing { .. } => "worktree_missing",
+            ArboristError::NameCollision { .. } => "name_collision",
+            ArboristError::BranchExists { .. } => "branch_exists",
+            ArboristError::UnbornBranch { .. } => "unborn_branch",
+            ArboristError::BranchInUse { .. } => "branch_in_use",
+            ArboristError::BranchNotFound { .. } => "branch_not_found",
+            ArboristError::UpstreamMissing { .. } => "upstream_missing",
+            ArboristError::GitTooOld { .. } => "git_too_old",
+            ArboristError::StateTooNew { .. } => "state_too_new",
+            ArboristError::LockHeld { .. } => "lock_held",
+            ArboristError::SigningFailed(_) => "signing_failed",
+            ArboristError::InvalidPath(_) => "invalid_path",
+            ArboristError::InvalidConfig(_) => "invalid_config",
+            ArboristError::WebhookFailed(_) => "webhook_failed",
+            ArboristError::WatchFailed(_) => "watch_failed",
+            ArboristError::HookFailed { .. } => "hook_failed",
+            ArboristError::IoError(_) => "io",
+            ArboristError::Json(_) => "json",
+            ArboristError::Context { .. } => unreachable!("root_cause skips context"),
         }
     }
 }
 
-impl std::error::Error for ArboristError {}
+/// Adds context describing what was being done when an error occurred
+pub trait ResultExt<T> {
+    fn context<C: Into<String>>(self, context: C) -> Result<T>;
+}
 
-impl From<io::Error> for ArboristError {
-    fn from(err: io::Error) -> Self {
-        ArboristError::IoError(err)
+impl<T, E: Into<ArboristError>> ResultExt<T> for std::result::Result<T, E> {
+    fn context<C: Into<String>>(self, context: C) -> Result<T> {
+        self.map_err(|err| ArboristError::Context {
+            context: context.into(),
+            source: Box::new(err.into()),
+        })
     }
 }
 
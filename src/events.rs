@@ -0,0 +1,56 @@
+use arborist::error::{Result, ResultExt};
+use arborist::state;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+// Destination of lifecycle events, set once from --events-file or --events-fd
+static SINK: Mutex<Option<File>> = Mutex::new(None);
+
+/// Sends events to a file, appending one JSON object per line
+pub fn open_file(path: &Path) -> Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("opening events file {}", path.display()))?;
+    set_sink(file);
+    Ok(())
+}
+
+/// Sends events to an already open file descriptor (e.g. `3>events.jsonl`)
+pub fn open_fd(fd: u32) -> Result<()> {
+    let file = crate::open_fd(fd).context(format!("opening events file descriptor {}", fd))?;
+    set_sink(file);
+    Ok(())
+}
+
+/// Emits a lifecycle event carrying the fields of `data`, if an event sink is configured
+/// Every event also has `event`, `timestamp` (seconds since the Unix epoch) and `pid`
+pub fn emit<T: Serialize>(event: &str, data: T) {
+    let mut sink = SINK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let Some(file) = sink.as_mut() else {
+        return;
+    };
+
+    let mut object = match serde_json::to_value(data) {
+        Ok(serde_json::Value::Object(object)) => object,
+        _ => serde_json::Map::new(),
+    };
+    object.insert("event".to_string(), event.into());
+    object.insert("timestamp".to_string(), state::unix_now().into());
+    object.insert("pid".to_string(), std::process::id().into());
+
+    let mut line = serde_json::Value::Object(object).to_string();
+    line.push('\n');
+    // Events are best effort; a reader going away must not break the run
+    if let Err(err) = file.write_all(line.as_bytes()) {
+        verbose!("Warning: failed to write {} event: {}", event, err);
+    }
+}
+
+fn set_sink(file: File) {
+    *SINK.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(file);
+}
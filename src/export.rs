@@ -0,0 +1,83 @@
+use crate::{excluded_paths, plan_worktree, require_repo, style, summary};
+use arborist::error::{ArboristError, Result, ResultExt};
+use arborist::{git, state};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// `arborist export`: write a worktree's commits and uncommitted changes to files that can be
+// carried to another machine or attached to an issue, leaving the worktree as it is
+pub fn run_export(name: &str, out: &Path, patches: bool) -> Result<i32> {
+    let repo = require_repo()?;
+    let plan = plan_worktree(repo, name.to_string())?;
+    if !git::worktree_exists(&plan.path)? {
+        return Err(ArboristError::InvalidPath(format!(
+            "No arborist worktree named '{}' at {}",
+            name,
+            plan.path.display()
+        )));
+    }
+    let base = match state::load_worktree(&plan.repo.root, &plan.name)? {
+        Some(meta) => meta.base_commit,
+        None => {
+            verbose!(
+                "No recorded base for {}; exporting what it has over {}",
+                plan.name,
+                plan.repo.current_commit
+            );
+            plan.repo.current_commit.clone()
+        }
+    };
+    // git writes the bundle from inside the worktree
+    let out = std::path::absolute(out)?;
+    let changes_out = changes_path(&out);
+
+    // A detached worktree's commits are bundled as HEAD
+    let tip = plan.branch.as_deref().unwrap_or("HEAD");
+    let range = format!("{}..{}", base, tip);
+    let commits = git::count_commits_between(&base, &git::head_commit(&plan.path)?)?;
+    let tree = git::snapshot_tree(&plan.path, &excluded_paths(&plan)?)?;
+    let changes = git::binary_diff(&plan.path, "HEAD", &tree)?;
+    if commits == 0 && changes.is_empty() {
+        eprintln!(
+            "Worktree {} has no commits or changes since it was created, so there is nothing to export",
+            name
+        );
+        return Ok(1);
+    }
+
+    if commits > 0 {
+        if patches {
+            fs::write(&out, git::format_patches(&plan.path, &range)?)
+                .context(format!("writing {}", out.display()))?;
+        } else {
+            git::create_bundle(&plan.path, &out, &range)?;
+        }
+        notice!(
+            "{} exported {} of {} to {}; {}",
+            style::info("arborist:"),
+            summary::count(commits, "commit"),
+            style::worktree(&plan.name, &plan.name),
+            out.display(),
+            if patches {
+                "apply them with `git am`".to_string()
+            } else {
+                format!("fetch them with `git fetch {} {}`", out.display(), tip)
+            }
+        );
+    }
+    if !changes.is_empty() {
+        fs::write(&changes_out, changes).context(format!("writing {}", changes_out.display()))?;
+        notice!(
+            "{} exported the uncommitted changes of {} to {}; apply them with `git apply`",
+            style::info("arborist:"),
+            style::worktree(&plan.name, &plan.name),
+            changes_out.display()
+        );
+    }
+    Ok(0)
+}
+
+// The uncommitted changes go next to the commits: work.bundle gets work.changes.patch
+fn changes_path(out: &Path) -> PathBuf {
+    out.with_extension("changes.patch")
+}
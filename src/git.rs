@@ -1,66 +1,499 @@
-use crate::error::{ArboristError, Result};
-use duct::cmd;
+use crate::error::{ArboristError, Result, ResultExt};
+use crate::process::{self, Command, Stdio};
+use async_io::Timer;
+use futures_lite::AsyncWriteExt;
+use rand::Rng;
 use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Retries of a git command that failed because another git process held a lock
+pub const DEFAULT_LOCK_RETRIES: u32 = 5;
+
+static LOCK_RETRIES: AtomicU32 = AtomicU32::new(DEFAULT_LOCK_RETRIES);
+
+/// Directory holding worktrees unless configured otherwise
+pub const DEFAULT_WORKTREE_ROOT: &str = "/tmp/arborist";
+
+// Worktree root from the user config, which ARBORIST_HOME overrides
+static WORKTREE_ROOT: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+// Git config key holding the identity arborist gave a repository
+const REPO_ID_KEY: &str = "arborist.id";
+
+// Git config key turning arborist off (false) or on (true) for a repository
+const ENABLED_KEY: &str = "arborist.enabled";
+
+// Repository identities already read, by repository root
+static REPO_IDS: Mutex<Vec<(PathBuf, String)>> = Mutex::new(Vec::new());
+
+// The installed git's version, probed once
+static VERSION: OnceLock<Option<GitVersion>> = OnceLock::new();
+
+/// Sets how many times git commands are retried while another git process holds a lock
+pub fn set_lock_retries(retries: u32) {
+    LOCK_RETRIES.store(retries, Ordering::Relaxed);
+}
+
+/// Runs `git -C <dir> <args>` on the async scheduler, retrying while another git process holds
+/// a lock, so callers can run several at once or race them against a timeout
+/// Returns: its stdout, trimmed
+pub async fn run_in(dir: &Path, args: &[&str]) -> Result<String> {
+    git_async(&in_dir(dir, args), &[]).await
+}
 
 // Helper function to run git commands and return stdout
-fn run_git_cmd(args: &[&str]) -> Result<String> {
-    let output = cmd("git", args)
-        .stderr_capture()
-        .stdout_capture()
-        .unchecked()
-        .run()?;
+// Arguments are OS strings so paths that aren't UTF-8 reach git unchanged
+fn run_git_cmd<S: AsRef<OsStr>>(args: &[S]) -> Result<String> {
+    run_git_cmd_env(args, &[])
+}
+
+// Run a git command with extra environment variables and return stdout
+fn run_git_cmd_env<S: AsRef<OsStr>>(args: &[S], vars: &[(&str, &Path)]) -> Result<String> {
+    process::block_on(git_async(args, vars))
+}
+
+async fn git_async<S: AsRef<OsStr>>(args: &[S], vars: &[(&str, &Path)]) -> Result<String> {
+    let mut command = git(args);
+    for (key, value) in vars {
+        command.env(key, value);
+    }
+    let output = output_retrying_locks(&mut command, None).await?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(ArboristError::GitOperationFailed(stderr.trim().to_string()));
+        return Err(git_command_failed(args, stderr.trim()));
     }
 
     Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
 
-// Helper function to safely convert Path to String
-fn path_to_string(path: &Path) -> Result<String> {
-    path.to_str()
-        .ok_or_else(|| {
-            ArboristError::InvalidPath(format!("Path contains non-UTF8 characters: {:?}", path))
-        })
-        .map(|s| s.to_string())
+// Run a git command and return its stdout untouched, e.g. for patches
+fn run_git_cmd_bytes<S: AsRef<OsStr>>(args: &[S]) -> Result<Vec<u8>> {
+    process::block_on(git_bytes_async(args))
+}
+
+async fn git_bytes_async<S: AsRef<OsStr>>(args: &[S]) -> Result<Vec<u8>> {
+    let output = output_retrying_locks(&mut git(args), None).await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(git_command_failed(args, stderr.trim()));
+    }
+    Ok(output.stdout)
+}
+
+// Run a git command that prints a path, which like any path need not be UTF-8
+fn run_git_path<S: AsRef<OsStr>>(args: &[S]) -> Result<PathBuf> {
+    let output = run_git_cmd_bytes(args)?;
+    Ok(path_from_bytes(output.trim_ascii_end()))
+}
+
+// Arguments of a git command run in the checkout at `dir`, i.e. `git -C <dir> <args>`
+fn in_dir<'a>(dir: &'a Path, args: &[&'a str]) -> Vec<&'a OsStr> {
+    let mut all = vec![OsStr::new("-C"), dir.as_os_str()];
+    all.extend(args.iter().map(|arg| OsStr::new(*arg)));
+    all
+}
+
+fn os_args<S: AsRef<OsStr>>(args: &[S]) -> Vec<&OsStr> {
+    args.iter().map(AsRef::as_ref).collect()
+}
+
+// A git command whose output is captured when it's run; like git run directly, it reads our stdin
+fn git<S: AsRef<OsStr>>(args: &[S]) -> Command {
+    let mut command = Command::new("git");
+    command.args(args).stdin(Stdio::inherit());
+    command
+}
+
+// Run a git command to completion, whatever its exit status
+fn output(command: &mut Command) -> Result<Output> {
+    Ok(process::block_on(command.output())?)
+}
+
+/// A path from its raw bytes, as git prints it or `as_encoded_bytes` gives it
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        PathBuf::from(OsStr::from_bytes(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+// Describe a failed git invocation, including the directory it ran in
+fn git_command_failed<S: AsRef<OsStr>>(args: &[S], stderr: &str) -> ArboristError {
+    if let Some(err) = lock_held(stderr) {
+        return err;
+    }
+
+    let args = os_args(args);
+    let (dir, args) = match args.as_slice() {
+        [flag, dir, rest @ ..] if *flag == "-C" => (PathBuf::from(dir), rest),
+        args => (env::current_dir().unwrap_or_default(), args),
+    };
+
+    ArboristError::GitCommandFailed {
+        command: args
+            .iter()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" "),
+        dir,
+        stderr: stderr.to_string(),
+    }
+}
+
+// Run a git command, retrying with jittered exponential backoff while it fails on a lock
+// held by a concurrent git process (e.g. another arborist run in the same repository)
+fn run_retrying_locks(command: &mut Command) -> Result<Output> {
+    process::block_on(output_retrying_locks(command, None))
+}
+
+// Run a git command on the scheduler as run_retrying_locks does, writing `input` to its stdin
+async fn output_retrying_locks(command: &mut Command, input: Option<&[u8]>) -> Result<Output> {
+    let retries = LOCK_RETRIES.load(Ordering::Relaxed);
+    let mut attempt = 0;
+    loop {
+        let output = match input {
+            Some(input) => output_with_input(command, input).await?,
+            None => command.output().await?,
+        };
+        if output.status.success()
+            || attempt >= retries
+            || !is_lock_contention(&String::from_utf8_lossy(&output.stderr))
+        {
+            return Ok(output);
+        }
+
+        // 50ms, 100ms, 200ms, ... up to 2s, each randomized to between half and all of it
+        let delay = 50u64.saturating_mul(1 << attempt.min(5)).min(2000);
+        let delay = rand::rng().random_range(delay / 2..=delay);
+        attempt += 1;
+        Timer::after(Duration::from_millis(delay)).await;
+    }
+}
+
+async fn output_with_input(command: &mut Command, input: &[u8]) -> Result<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input).await?;
+    }
+    Ok(child.output().await?)
+}
+
+// Messages git prints when it can't take a lock another process holds
+fn is_lock_contention(stderr: &str) -> bool {
+    lock_held(stderr).is_some()
+        || stderr.contains("could not lock config file")
+        || stderr.contains("cannot lock ref")
+        || stderr.contains("Unable to create") && stderr.contains(".lock")
+}
+
+// git reports a held lock as "Unable to create '<path>.lock': File exists."
+fn lock_held(stderr: &str) -> Option<ArboristError> {
+    let (_, rest) = stderr.split_once("Unable to create '")?;
+    let (path, _) = rest.split_once("': File exists")?;
+    Some(ArboristError::LockHeld {
+        path: PathBuf::from(path),
+    })
+}
+
+/// A git release, e.g. 2.39.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GitVersion(pub u32, pub u32, pub u32);
+
+impl fmt::Display for GitVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+/// Git features arborist uses that older versions lack
+#[derive(Debug, Clone, Copy)]
+pub enum Feature {
+    /// What every run needs: `git worktree list --porcelain`, `move` and `remove`, and
+    /// `git config --type`
+    Worktrees,
+    /// Per-worktree config, `git config --worktree`
+    WorktreeConfig,
+    /// `git worktree repair`
+    WorktreeRepair,
+    /// `git sparse-checkout set --cone`
+    SparseCheckout,
+    /// `--end-of-options`, which keeps a ref starting with a dash from being read as an option
+    EndOfOptions,
+}
+
+impl Feature {
+    /// The first git version with the feature
+    pub fn required_version(self) -> GitVersion {
+        match self {
+            Feature::Worktrees => GitVersion(2, 18, 0),
+            Feature::WorktreeConfig => GitVersion(2, 20, 0),
+            Feature::WorktreeRepair => GitVersion(2, 29, 0),
+            Feature::SparseCheckout => GitVersion(2, 35, 0),
+            Feature::EndOfOptions => GitVersion(2, 24, 0),
+        }
+    }
+
+    // What arborist uses the feature for, for errors
+    fn purpose(self) -> &'static str {
+        match self {
+            Feature::Worktrees => "managing worktrees",
+            Feature::WorktreeConfig => "per-worktree git config (worktree_config, inherit_config)",
+            Feature::WorktreeRepair => "repairing worktrees (git worktree repair)",
+            Feature::SparseCheckout => "sparse worktrees (git sparse-checkout set --cone)",
+            Feature::EndOfOptions => "separating refs from options (--end-of-options)",
+        }
+    }
+}
+
+/// The installed git's version, from `git --version`
+/// Returns: None when git can't be run or its version isn't understood, in which case every
+/// feature is assumed to be there and git reports what it lacks itself
+pub fn version() -> Option<GitVersion> {
+    *VERSION.get_or_init(|| {
+        let output = output(&mut git(&["--version"])).ok()?;
+        parse_version(&String::from_utf8_lossy(&output.stdout))
+    })
+}
+
+// "git version 2.39.2", also with suffixes like ".windows.1" or " (Apple Git-143)"
+fn parse_version(output: &str) -> Option<GitVersion> {
+    let version = output.trim().strip_prefix("git version ")?;
+    let mut numbers = version
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|part| part.parse::<u32>().ok());
+    let major = numbers.next()??;
+    let minor = numbers.next()??;
+    // Release candidates like 2.45.0-rc1 still have a patch number; anything else counts as 0
+    let patch = numbers.next().flatten().unwrap_or(0);
+    Some(GitVersion(major, minor, patch))
+}
+
+/// Whether the installed git has `feature`
+pub fn supports(feature: Feature) -> bool {
+    version().is_none_or(|version| version >= feature.required_version())
+}
+
+/// Fails with an error saying which git version `feature` needs when the installed one is older
+pub fn require(feature: Feature) -> Result<()> {
+    match version() {
+        Some(found) if found < feature.required_version() => Err(ArboristError::GitTooOld {
+            feature: feature.purpose().to_string(),
+            required: feature.required_version().to_string(),
+            found: found.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Computes the identifier used to namespace per-repository paths
+/// Returns: the identity recorded in the repository's config, which stays the same when the
+/// repository is moved, or for repositories without one the hash of their root
+pub fn repo_hash(repo_root: &Path) -> Result<String> {
+    match repo_id(repo_root)? {
+        Some(id) => Ok(id),
+        None => Ok(path_hash(repo_root)),
+    }
 }
 
-/// Computes the worktree path for a non-bare repository
-/// Returns: /tmp/arborist/{sha256_hash}/{color}
-pub fn compute_nonbare_worktree_path(repo_root: &Path, color: &str) -> Result<PathBuf> {
-    let repo_path_str = path_to_string(repo_root)?;
+/// Computes the identifier per-repository paths used before repositories had an identity
+/// Returns: hex-encoded sha256 of the repository root's bytes, which for a UTF-8 path are
+/// those of the string
+pub fn path_hash(repo_root: &Path) -> String {
     let mut hasher = Sha256::new();
-    hasher.update(repo_path_str.as_bytes());
+    hasher.update(repo_root.as_os_str().as_encoded_bytes());
     let hash = hasher.finalize();
-    let hash_hex = format!("{:x}", hash);
+    format!("{:x}", hash)
+}
+
+/// Reads the identity arborist gave a repository (git config arborist.id)
+/// Returns: None when it has none yet, or the repository no longer exists
+pub fn repo_id(repo_root: &Path) -> Result<Option<String>> {
+    let mut cache = REPO_IDS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some((_, id)) = cache.iter().find(|(root, _)| root == repo_root) {
+        return Ok(Some(id.clone()));
+    }
+
+    let output = output(&mut git(&in_dir(
+        repo_root,
+        &["config", "--local", "--get", REPO_ID_KEY],
+    )))?;
+    let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || id.is_empty() {
+        return Ok(None);
+    }
+    // It names directories, so it can't be allowed to climb out of them
+    if !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(ArboristError::InvalidConfig(format!(
+            "{} in {} must only contain letters, digits and dashes, not '{}'",
+            REPO_ID_KEY,
+            repo_root.display(),
+            id
+        )));
+    }
+
+    cache.push((repo_root.to_path_buf(), id.clone()));
+    Ok(Some(id))
+}
+
+/// Gives a repository a new random identity, replacing any it had
+pub fn create_repo_id(repo_root: &Path) -> Result<String> {
+    let id: String = (0..16)
+        .map(|_| format!("{:02x}", rand::rng().random::<u8>()))
+        .collect();
+    run_git_cmd(&in_dir(repo_root, &["config", "--local", REPO_ID_KEY, &id]))?;
 
-    let path = PathBuf::from("/tmp")
-        .join("arborist")
-        .join(hash_hex)
-        .join(color);
+    let mut cache = REPO_IDS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    cache.retain(|(root, _)| root != repo_root);
+    cache.push((repo_root.to_path_buf(), id.clone()));
+    Ok(id)
+}
+
+/// The repository's identity, giving it one first if it has none
+pub fn ensure_repo_id(repo_root: &Path) -> Result<String> {
+    match repo_id(repo_root)? {
+        Some(id) => Ok(id),
+        None => create_repo_id(repo_root),
+    }
+}
+
+/// Re-links a repository and its worktrees after either moved
+/// `worktrees` are the new paths of worktrees that moved; ones that stayed put are found
+/// through the repository
+/// Returns: what git fixed, e.g. ".git file broken: /path"
+pub fn repair_worktrees(repo_root: &Path, worktrees: &[PathBuf]) -> Result<Vec<String>> {
+    require(Feature::WorktreeRepair)?;
+    let mut args = in_dir(repo_root, &["worktree", "repair"]);
+    args.extend(worktrees.iter().map(|path| path.as_os_str()));
+    let output = run_retrying_locks(&mut git(&args))?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(git_command_failed(&args, stderr.trim()));
+    }
+    Ok(stderr
+        .lines()
+        .filter_map(|line| line.strip_prefix("repair: "))
+        .map(str::to_string)
+        .collect())
+}
+
+/// The upstream a local branch tracks, e.g. "origin/main", or None when it tracks nothing
+pub fn branch_upstream(branch: &str) -> Result<Option<String>> {
+    let upstream = format!("{}@{{upstream}}", branch);
+    let output = output(&mut git(&["rev-parse", "--abbrev-ref", &upstream]))?;
+    Ok(output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string()))
+}
+
+/// Makes a local branch track nothing, if it tracked anything
+pub fn unset_branch_upstream(branch: &str) -> Result<()> {
+    if branch_upstream(branch)?.is_some() {
+        run_git_cmd(&["branch", "--unset-upstream", branch])?;
+    }
+    Ok(())
+}
+
+/// Makes a local branch track `upstream`
+pub fn set_branch_upstream(branch: &str, upstream: &str) -> Result<()> {
+    let set_upstream = format!("--set-upstream-to={}", upstream);
+    run_git_cmd(&["branch", &set_upstream, branch]).map_err(|err| match err {
+        ArboristError::GitCommandFailed { ref stderr, .. } if stderr.contains("does not exist") => {
+            ArboristError::UpstreamMissing {
+                upstream: upstream.to_string(),
+            }
+        }
+        err => err,
+    })?;
+    Ok(())
+}
+
+/// Sets the directory new worktrees are created under
+pub fn set_worktree_root(root: PathBuf) {
+    *WORKTREE_ROOT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(root);
+}
+
+/// Directory holding worktrees, each repository's in a directory of its own
+/// Returns: $ARBORIST_HOME, else the root given to `set_worktree_root`, else /tmp/arborist
+pub fn worktree_root() -> PathBuf {
+    if let Some(home) = env::var_os("ARBORIST_HOME").filter(|home| !home.is_empty()) {
+        return PathBuf::from(home);
+    }
+    WORKTREE_ROOT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_WORKTREE_ROOT))
+}
 
-    Ok(path)
+/// Computes the directory holding all worktrees of a repository under the worktree root
+/// Returns: {worktree root}/{repo_hash}
+pub fn compute_worktree_root(repo_root: &Path) -> Result<PathBuf> {
+    let hash_hex = repo_hash(repo_root)?;
+
+    Ok(worktree_root().join(hash_hex))
+}
+
+/// Computes the worktree path for a repository under the worktree root
+/// Returns: {worktree root}/{repo_hash}/{color}
+pub fn compute_worktree_path(repo_root: &Path, color: &str) -> Result<PathBuf> {
+    Ok(compute_worktree_root(repo_root)?.join(color))
+}
+
+/// Computes the directory worktrees of a non-bare repository were created in before the
+/// worktree root was configurable
+/// Returns: /tmp/arborist/{sha256 of the repository root}
+pub fn default_worktree_root(repo_root: &Path) -> Result<PathBuf> {
+    Ok(PathBuf::from(DEFAULT_WORKTREE_ROOT).join(path_hash(repo_root)))
+}
+
+/// Computes the directory next to a bare repository that holds its worktrees when they are
+/// kept beside it
+/// Returns: {parent}/{name without .git}.worktrees, e.g. /srv/project.worktrees for /srv/project.git
+pub fn sibling_worktree_root(repo_root: &Path) -> PathBuf {
+    let name = repo_root
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = name.strip_suffix(".git").unwrap_or(&name);
+    repo_root.with_file_name(format!("{}.worktrees", name))
 }
 
 /// Ensures the base directory for a worktree path exists
 fn ensure_worktree_base_dir(worktree_path: &Path) -> Result<()> {
-    if let Some(parent) = worktree_path.parent() {
-        if !parent.exists() {
-            fs::create_dir_all(parent).map_err(|e| {
-                ArboristError::IoError(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!(
-                        "Failed to create worktree base directory {}: {}",
-                        parent.display(),
-                        e
-                    ),
-                ))
-            })?;
-        }
+    if let Some(parent) = worktree_path.parent()
+        && !parent.exists()
+    {
+        fs::create_dir_all(parent).context(format!(
+            "Failed to create worktree base directory {}",
+            parent.display()
+        ))?;
     }
     Ok(())
 }
@@ -68,9 +501,56 @@ fn ensure_worktree_base_dir(worktree_path: &Path) -> Result<()> {
 #[derive(Debug, Clone)]
 pub struct GitRepo {
     pub root: PathBuf,
+    /// The checked-out branch, or "HEAD" when detached
     pub current_branch: String,
     pub current_commit: String,
     pub is_bare: bool,
+    /// Whether HEAD points at a commit rather than a branch
+    pub detached: bool,
+    /// Whether the checked-out branch has no commits yet (e.g. right after `git init`), leaving
+    /// current_commit empty and nothing to base a worktree on
+    pub unborn: bool,
+}
+
+impl GitRepo {
+    /// The branch new worktrees track, or None on a detached HEAD, which has nothing to track
+    pub fn upstream(&self) -> Option<&str> {
+        (!self.detached).then_some(self.current_branch.as_str())
+    }
+
+    /// What new worktrees are based on, for messages: the branch, or "detached HEAD at <commit>"
+    pub fn base_name(&self) -> String {
+        if self.detached {
+            let short = self.current_commit.get(..7).unwrap_or(&self.current_commit);
+            format!("detached HEAD at {}", short)
+        } else {
+            self.current_branch.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WorktreeEntry {
+    pub path: PathBuf,
+    pub branch: Option<String>,
+    pub locked: bool,
+    /// Reason given when it was locked, if any
+    pub lock_reason: Option<String>,
+    /// Whether its directory is gone, so `git worktree prune` would unregister it
+    pub prunable: bool,
+}
+
+/// Per-file index bits that are local to a checkout and not copied by `git worktree add`
+#[derive(Debug, Clone, Default)]
+pub struct IndexFlags {
+    pub skip_worktree: Vec<String>,
+    pub assume_unchanged: Vec<String>,
+}
+
+impl IndexFlags {
+    pub fn is_empty(&self) -> bool {
+        self.skip_worktree.is_empty() && self.assume_unchanged.is_empty()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -79,202 +559,1596 @@ pub struct WorktreeStatus {
     pub commits_ahead: usize,
 }
 
-fn is_bare_repository() -> Result<bool> {
-    // Get the common git directory (handles both normal repos and worktrees)
-    // For worktrees, this points to the main repository's git directory
-    let common_dir = run_git_cmd(&["rev-parse", "--git-common-dir"])?;
+// Repository info per directory, so a run asks git only once
+static REPO_INFO: Mutex<Vec<(PathBuf, GitRepo)>> = Mutex::new(Vec::new());
 
-    // Check if the repository at the common directory is bare
-    // This correctly identifies bare repos even when called from within a worktree
-    let output = cmd(
-        "git",
-        &["-C", &common_dir, "rev-parse", "--is-bare-repository"],
-    )
-    .stderr_capture()
-    .stdout_capture()
-    .unchecked()
-    .run()?;
+/// Describes the repository containing the current directory, or None outside of one
+/// The answer is cached per directory for the rest of the process; long-running callers
+/// that need to notice a moved HEAD use `read_repo_info`
+pub fn get_repo_info() -> Result<Option<GitRepo>> {
+    let cwd = env::current_dir()?;
+    let mut cache = REPO_INFO
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some((_, repo)) = cache.iter().find(|(dir, _)| *dir == cwd) {
+        return Ok(Some(repo.clone()));
+    }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(ArboristError::GitOperationFailed(stderr.trim().to_string()));
+    let repo = read_repo_info()?;
+    if let Some(repo) = &repo {
+        cache.push((cwd, repo.clone()));
     }
+    Ok(repo)
+}
 
-    let result = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    Ok(result == "true")
+/// Describes the repository containing the current directory without using the cache
+pub fn read_repo_info() -> Result<Option<GitRepo>> {
+    match rev_parse_repo(None) {
+        // HEAD doesn't resolve on a branch without commits; ask again without it
+        Err(err) => match unborn_branch()? {
+            Some(branch) => rev_parse_repo(Some(branch)),
+            None => Err(err),
+        },
+        result => result,
+    }
 }
 
-pub fn get_repo_info() -> Result<Option<GitRepo>> {
-    if !is_git_repo()? {
+// The branch HEAD points at when it has no commits yet
+fn unborn_branch() -> Result<Option<String>> {
+    let born = output(&mut git(&["rev-parse", "--quiet", "--verify", "HEAD"]))?;
+    if born.status.success() {
         return Ok(None);
     }
 
-    let root = get_repo_root()?;
-    let current_branch = get_current_branch()?;
-    let current_commit = get_current_commit()?;
-    let is_bare = is_bare_repository()?;
+    let branch = output(&mut git(&["symbolic-ref", "--quiet", "--short", "HEAD"]))?;
+    Ok(branch
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&branch.stdout).trim().to_string()))
+}
+
+// One rev-parse answers everything, including the commit and branch unless the branch is known to
+// be `unborn`; --show-cdup prints nothing outside a working tree, so it comes last
+fn rev_parse_repo(unborn: Option<String>) -> Result<Option<GitRepo>> {
+    let mut args = vec![
+        "rev-parse",
+        "--git-dir",
+        "--git-common-dir",
+        "--is-bare-repository",
+        "--is-inside-work-tree",
+    ];
+    if unborn.is_none() {
+        args.extend(["HEAD", "--abbrev-ref", "HEAD"]);
+    }
+    args.push("--show-cdup");
+    let output = output(&mut git(&args))?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        if stderr.contains("not a git repository") {
+            return Ok(None);
+        }
+        return Err(git_command_failed(&args, stderr.trim()));
+    }
+
+    // The directories are paths, which need not be UTF-8
+    let lines: Vec<&[u8]> = output.stdout.split(|&byte| byte == b'\n').collect();
+    let [git_dir, common_dir, is_bare, inside_work_tree, rest @ ..] = lines.as_slice() else {
+        return Err(git_command_failed(&args, "unexpected output"));
+    };
+    let is_unborn = unborn.is_some();
+    let (commit, branch, rest) = match unborn {
+        Some(branch) => (String::new(), branch, rest),
+        None => {
+            let [commit, branch, rest @ ..] = rest else {
+                return Err(git_command_failed(&args, "unexpected output"));
+            };
+            (
+                String::from_utf8_lossy(commit).to_string(),
+                String::from_utf8_lossy(branch).to_string(),
+                rest,
+            )
+        }
+    };
+
+    let cwd = env::current_dir()?;
+    // The bareness that matters is the main repository's, which a linked worktree of a
+    // bare repository doesn't report as its own
+    let is_bare = if git_dir == common_dir {
+        *is_bare == b"true"
+    } else {
+        let common_dir = path_from_bytes(common_dir);
+        run_git_cmd(&in_dir(&common_dir, &["rev-parse", "--is-bare-repository"]))? == "true"
+    };
+
+    let root = if is_bare {
+        // Bare repositories are rooted at the git directory itself, which worktrees are
+        // created inside of
+        fs::canonicalize(cwd.join(path_from_bytes(common_dir)))?
+    } else if *inside_work_tree == b"true" {
+        fs::canonicalize(cwd.join(path_from_bytes(rest.first().copied().unwrap_or_default())))?
+    } else {
+        return Err(ArboristError::InvalidPath(format!(
+            "{} is inside a git directory rather than a working tree",
+            cwd.display()
+        )));
+    };
 
     Ok(Some(GitRepo {
         root,
-        current_branch,
-        current_commit,
         is_bare,
+        // --abbrev-ref prints HEAD itself when no branch is checked out
+        detached: branch == "HEAD",
+        unborn: is_unborn,
+        current_commit: commit,
+        current_branch: branch,
     }))
 }
 
-fn is_git_repo() -> Result<bool> {
-    let output = cmd!("git", "rev-parse", "--is-inside-work-tree")
-        .stderr_capture()
-        .stdout_capture()
-        .unchecked()
-        .run()?;
+/// Absolute path of the git directory shared by all worktrees of the current repository
+pub fn get_common_dir() -> Result<PathBuf> {
+    let git_dir = run_git_path(&["rev-parse", "--git-common-dir"])?;
+    Ok(fs::canonicalize(git_dir)?)
+}
 
-    Ok(output.status.success())
+/// Absolute path of the git directory of the worktree at `path` only: for a linked worktree, its
+/// admin directory under the common directory's worktrees/
+pub fn git_dir_of(path: &Path) -> Result<PathBuf> {
+    run_git_path(&in_dir(path, &["rev-parse", "--absolute-git-dir"]))
 }
 
-fn get_repo_root() -> Result<PathBuf> {
-    // For bare repositories, --show-toplevel doesn't work, so we use --absolute-git-dir
-    // For normal repositories, we use --show-toplevel to get the working tree root
-    let is_bare = is_bare_repository()?;
+/// Top level of the current repository's working tree, or None in a bare repository
+pub fn toplevel() -> Result<Option<PathBuf>> {
+    if run_git_cmd(&["rev-parse", "--is-bare-repository"])? == "true" {
+        return Ok(None);
+    }
+    Ok(Some(run_git_path(&["rev-parse", "--show-toplevel"])?))
+}
 
-    let path = if is_bare {
-        // For bare repos, get the common git directory (the bare repo itself)
-        // Then use its parent directory as the base for creating worktrees
-        let git_dir = run_git_cmd(&["rev-parse", "--git-common-dir"])?;
-        PathBuf::from(git_dir)
-    } else {
-        PathBuf::from(run_git_cmd(&["rev-parse", "--show-toplevel"])?)
-    };
+/// Absolute path of the git directory shared by all worktrees of the repository at `path`
+pub fn common_dir_of(path: &Path) -> Result<PathBuf> {
+    let git_dir = run_git_path(&in_dir(path, &["rev-parse", "--git-common-dir"]))?;
+    Ok(fs::canonicalize(path.join(git_dir))?)
+}
 
-    Ok(path)
+/// Reads a git config value, returning None when the key is unset
+pub fn get_config(key: &str) -> Result<Option<String>> {
+    read_config_value(&[OsStr::new("config"), OsStr::new("--get"), OsStr::new(key)])
 }
 
-fn get_current_branch() -> Result<String> {
-    run_git_cmd(&["rev-parse", "--abbrev-ref", "HEAD"])
+/// Reads a git config value as git sees it in the checkout at `path`, conditional includes
+/// matching that location included; None when the key is unset there
+pub fn get_config_at(path: &Path, key: &str) -> Result<Option<String>> {
+    read_config_value(&in_dir(path, &["config", "--get", key]))
 }
 
-fn get_current_commit() -> Result<String> {
-    run_git_cmd(&["rev-parse", "HEAD"])
+fn read_config_value(args: &[&OsStr]) -> Result<Option<String>> {
+    let output = output(&mut git(args))?;
+
+    // Exit code 1 means the key is not set
+    match output.status.code() {
+        Some(0) => Ok(Some(
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        )),
+        Some(1) => Ok(None),
+        _ => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(git_command_failed(args, stderr.trim()))
+        }
+    }
+}
+
+/// Reads arborist.enabled from the current repository's git config, which turns arborist off
+/// (false) or on (true) for the repository; None when it is unset
+pub fn repo_enabled() -> Result<Option<bool>> {
+    let args = ["config", "--type=bool", "--get", ENABLED_KEY];
+    let output = output(&mut git(&args))?;
+
+    // Exit code 1 means the key is not set
+    match output.status.code() {
+        Some(0) => Ok(Some(
+            String::from_utf8_lossy(&output.stdout).trim() == "true",
+        )),
+        Some(1) => Ok(None),
+        _ => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(git_command_failed(&args, stderr.trim()))
+        }
+    }
 }
 
+/// Whether a worktree is registered at exactly `path` (not merely one whose path starts with it)
 pub fn worktree_exists(path: &Path) -> Result<bool> {
-    let output = run_git_cmd(&["worktree", "list"])?;
-    let path_str = path_to_string(path)?;
-    Ok(output.contains(&path_str))
+    Ok(is_registered(&list_worktrees()?, path))
+}
+
+// Whether `path` is one of the entries' paths, either as given or with symlinks resolved
+fn is_registered(entries: &[WorktreeEntry], path: &Path) -> bool {
+    let resolved = path.canonicalize().ok();
+    entries
+        .iter()
+        .any(|entry| entry.path == path || resolved.as_ref() == Some(&entry.path))
+}
+
+/// Lists all worktrees registered with the repository, including the main checkout
+pub fn list_worktrees() -> Result<Vec<WorktreeEntry>> {
+    let output = run_git_cmd_bytes(&["worktree", "list", "--porcelain"])?;
+    Ok(parse_worktree_list(&output))
+}
+
+/// Lists the worktrees of the repository at `dir` as `list_worktrees` does, on the async
+/// scheduler
+pub async fn list_worktrees_in(dir: &Path) -> Result<Vec<WorktreeEntry>> {
+    let output = git_bytes_async(&in_dir(dir, &["worktree", "list", "--porcelain"])).await?;
+    Ok(parse_worktree_list(&output))
 }
 
+// Entries of `git worktree list --porcelain` output
+fn parse_worktree_list(output: &[u8]) -> Vec<WorktreeEntry> {
+    let mut entries = Vec::new();
+    let mut current: Option<WorktreeEntry> = None;
+
+    for line in output.split(|&byte| byte == b'\n') {
+        if let Some(path) = line.strip_prefix(b"worktree ") {
+            entries.extend(current.take());
+            current = Some(WorktreeEntry {
+                path: path_from_bytes(path),
+                branch: None,
+                locked: false,
+                lock_reason: None,
+                prunable: false,
+            });
+        } else if let Some(entry) = current.as_mut() {
+            let line = String::from_utf8_lossy(line);
+            if let Some(branch) = line.strip_prefix("branch ") {
+                let branch = branch.strip_prefix("refs/heads/").unwrap_or(branch);
+                entry.branch = Some(branch.to_string());
+            } else if line == "locked" {
+                entry.locked = true;
+            } else if let Some(reason) = line.strip_prefix("locked ") {
+                entry.locked = true;
+                entry.lock_reason = Some(reason.to_string());
+            } else if line == "prunable" || line.starts_with("prunable ") {
+                entry.prunable = true;
+            }
+        }
+    }
+    entries.extend(current);
+    entries
+}
+
+/// Lists local branches under the given prefix (e.g. "arborist/")
+pub fn list_branches(prefix: &str) -> Result<Vec<String>> {
+    let pattern = format!("refs/heads/{}", prefix);
+    let output = run_git_cmd(&["for-each-ref", "--format=%(refname:short)", &pattern])?;
+    Ok(output.lines().map(|line| line.to_string()).collect())
+}
+
+/// A local or remote-tracking branch and the commit at its tip
+#[derive(Debug, Clone)]
+pub struct BranchTip {
+    /// Short name, e.g. "main" or "origin/main"
+    pub name: String,
+    pub commit: String,
+    pub remote: bool,
+}
+
+/// Lists local and remote-tracking branches with their tip commits
+pub fn list_branch_tips() -> Result<Vec<BranchTip>> {
+    let output = run_git_cmd(&[
+        "for-each-ref",
+        "--format=%(refname) %(objectname)",
+        "refs/heads",
+        "refs/remotes",
+    ])?;
+
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let (refname, commit) = line.split_once(' ')?;
+            let (name, remote) = match refname.strip_prefix("refs/heads/") {
+                Some(name) => (name, false),
+                None => (refname.strip_prefix("refs/remotes/")?, true),
+            };
+            // refs/remotes/<remote>/HEAD only points at another remote branch
+            if remote && name.ends_with("/HEAD") {
+                return None;
+            }
+            Some(BranchTip {
+                name: name.to_string(),
+                commit: commit.to_string(),
+                remote,
+            })
+        })
+        .collect())
+}
+
+/// Finds the commit `git worktree add` would check out for an existing branch: the local
+/// branch, or else the one remote-tracking branch of that name
+/// Fails if the branch is checked out in another worktree, which git doesn't allow
+pub fn resolve_existing_branch(branch: &str) -> Result<String> {
+    if let Some(entry) = list_worktrees()?
+        .into_iter()
+        .find(|entry| entry.branch.as_deref() == Some(branch))
+    {
+        return Err(ArboristError::BranchInUse {
+            branch: branch.to_string(),
+            worktree: entry.path,
+        });
+    }
+
+    let tips = list_branch_tips()?;
+    if let Some(tip) = tips.iter().find(|tip| !tip.remote && tip.name == branch) {
+        return Ok(tip.commit.clone());
+    }
+
+    let remote: Vec<&BranchTip> = tips
+        .iter()
+        .filter(|tip| tip.remote && tip.name.split_once('/').map(|(_, name)| name) == Some(branch))
+        .collect();
+    match remote.as_slice() {
+        [tip] => Ok(tip.commit.clone()),
+        [] => Err(ArboristError::BranchNotFound {
+            branch: branch.to_string(),
+        }),
+        _ => Err(ArboristError::GitOperationFailed(format!(
+            "{} exists on several remotes ({}); create a local branch to choose one",
+            branch,
+            remote
+                .iter()
+                .map(|tip| tip.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+/// What a new worktree has checked out
+#[derive(Debug, Clone, Copy)]
+pub enum WorktreeBranch<'a> {
+    /// A branch created for the worktree at its base commit
+    New(&'a str),
+    /// A branch that already exists, locally or as a single remote's branch of that name
+    /// (which git then creates a local tracking branch for)
+    Existing(&'a str),
+    /// The base commit, with no branch
+    Detached,
+}
+
+/// Creates a worktree at `commit` with `branch` checked out; the upstream is only set on
+/// a new branch, since an existing one keeps its own
 pub fn create_worktree(
     path: &Path,
-    branch: &str,
+    branch: WorktreeBranch,
     commit: &str,
     upstream_branch: Option<&str>,
+    sparse: &[String],
 ) -> Result<()> {
-    // Ensure base directory exists (for non-bare repos in /tmp)
+    // Ensure base directory exists (for non-bare repos under the worktree root)
     ensure_worktree_base_dir(path)?;
 
     // If worktree already exists, skip creation
     if worktree_exists(path)? {
         return Ok(());
     }
+    // Checked before anything is created
+    if !sparse.is_empty() {
+        require(Feature::SparseCheckout)?;
+    }
+
+    let add_args = worktree_add_args(path, branch, commit, sparse);
 
-    let path_str = path_to_string(path)?;
-    let output = cmd!("git", "worktree", "add", "-b", branch, &path_str, commit)
-        .stderr_capture()
-        .stdout_capture()
-        .unchecked()
-        .run()?;
+    let output = run_retrying_locks(&mut git(&add_args))?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(ArboristError::GitOperationFailed(format!(
-            "Failed to create worktree: {}",
-            stderr
-        )));
+        return Err(worktree_add_failed(path, branch, stderr.trim()));
+    }
+
+    if !sparse.is_empty() {
+        // `sparse-checkout set` writes per-worktree config; the checkout then
+        // populates the still-empty index with just the selected cone
+        let mut sparse_args = in_dir(path, &["sparse-checkout", "set", "--cone"]);
+        sparse_args.extend(sparse.iter().map(OsStr::new));
+        run_git_cmd(&sparse_args).context("configuring sparse checkout")?;
+        run_git_cmd(&in_dir(path, &["checkout"])).context("checking out sparse worktree")?;
     }
 
     // Set upstream tracking branch if specified
-    if let Some(upstream) = upstream_branch {
-        let output = cmd!(
-            "git",
-            "-C",
-            &path_str,
-            "branch",
-            "--set-upstream-to",
-            upstream
-        )
-        .stderr_capture()
-        .stdout_capture()
-        .unchecked()
-        .run()?;
+    if let Some(upstream) = upstream_branch.filter(|_| matches!(branch, WorktreeBranch::New(_))) {
+        let output = run_retrying_locks(&mut git(&in_dir(
+            path,
+            &["branch", "--set-upstream-to", upstream],
+        )))?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(ArboristError::GitOperationFailed(format!(
-                "Failed to set upstream tracking branch: {}",
-                stderr
-            )));
+            if stderr.contains("does not exist") {
+                return Err(ArboristError::UpstreamMissing {
+                    upstream: upstream.to_string(),
+                });
+            }
+            return Err(git_command_failed(
+                &in_dir(path, &["branch", "--set-upstream-to", upstream]),
+                stderr.trim(),
+            ))
+            .context("setting upstream tracking branch");
         }
     }
 
     Ok(())
 }
 
-pub fn remove_worktree(path: &Path) -> Result<()> {
-    let path_str = path_to_string(path)?;
-    let output = cmd!("git", "worktree", "remove", &path_str, "--force")
-        .stderr_capture()
-        .unchecked()
-        .run()?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(ArboristError::GitOperationFailed(format!(
-            "Failed to remove worktree: {}",
-            stderr
-        )));
+// Classify a failed `git worktree add`
+fn worktree_add_failed(path: &Path, branch: WorktreeBranch, stderr: &str) -> ArboristError {
+    if let Some(err) = lock_held(stderr) {
+        err
+    } else if let WorktreeBranch::New(branch) = branch
+        && stderr.contains(&format!("a branch named '{}' already exists", branch))
+    {
+        ArboristError::BranchExists {
+            branch: branch.to_string(),
+        }
+    } else if let WorktreeBranch::Existing(branch) = branch
+        && let Some(worktree) = checked_out_at(stderr)
+    {
+        ArboristError::BranchInUse {
+            branch: branch.to_string(),
+            worktree,
+        }
+    } else {
+        ArboristError::WorktreeAddFailed {
+            path: path.to_path_buf(),
+            stderr: stderr.to_string(),
+        }
     }
-
-    Ok(())
 }
 
-pub fn remove_worktree_and_branch(path: &Path, branch: &str) -> Result<()> {
-    // First remove the worktree
-    remove_worktree(path)?;
-
-    // Then delete the associated branch
-    delete_branch(branch)?;
-
-    Ok(())
+// Arguments of the `git worktree add` that creates a worktree
+fn worktree_add_args<'a>(
+    path: &'a Path,
+    branch: WorktreeBranch<'a>,
+    commit: &'a str,
+    sparse: &[String],
+) -> Vec<&'a OsStr> {
+    let mut args = vec![OsStr::new("worktree"), OsStr::new("add")];
+    // Sparse worktrees are populated by sparse-checkout instead of a full checkout
+    if !sparse.is_empty() {
+        args.push(OsStr::new("--no-checkout"));
+    }
+    let path = path.as_os_str();
+    match branch {
+        WorktreeBranch::New(branch) => args.extend([
+            OsStr::new("-b"),
+            OsStr::new(branch),
+            path,
+            OsStr::new(commit),
+        ]),
+        WorktreeBranch::Existing(branch) => args.extend([path, OsStr::new(branch)]),
+        WorktreeBranch::Detached => args.extend([OsStr::new("--detach"), path, OsStr::new(commit)]),
+    }
+    args
 }
 
-pub fn get_worktree_status() -> Result<WorktreeStatus> {
-    let has_changes = has_uncommitted_changes()?;
-    let commits_ahead = get_commits_ahead()?;
-
-    Ok(WorktreeStatus {
-        has_changes,
-        commits_ahead,
-    })
+// The worktree git names in "'<branch>' is already checked out at '<path>'" (or, in newer
+// versions, "is already used by worktree at")
+fn checked_out_at(stderr: &str) -> Option<PathBuf> {
+    let (_, rest) = stderr
+        .split_once("is already checked out at '")
+        .or_else(|| stderr.split_once("is already used by worktree at '"))?;
+    let (path, _) = rest.split_once('\'')?;
+    Some(PathBuf::from(path))
 }
 
-fn has_uncommitted_changes() -> Result<bool> {
-    let output = run_git_cmd(&["status", "--porcelain"])?;
-    Ok(!output.is_empty())
+/// The remote a partial clone fetches missing objects from, and the filter it was cloned with
+#[derive(Debug, Clone)]
+pub struct PartialClone {
+    pub remote: String,
+    /// e.g. "blob:none"; None when the remote has no filter configured
+    pub filter: Option<String>,
 }
 
-fn get_commits_ahead() -> Result<usize> {
-    // Check if upstream exists
-    match run_git_cmd(&["rev-parse", "--abbrev-ref", "@{upstream}"]) {
-        Ok(_) => {
-            // Get count of commits ahead
-            let output = run_git_cmd(&["rev-list", "--count", "@{upstream}..HEAD"])?;
-            Ok(output.parse().unwrap_or(0))
+/// Whether the repository is a partial clone, whose checkouts download missing blobs on demand
+/// Returns: the promisor remote named by extensions.partialClone, or else the first remote with
+/// remote.<name>.promisor set
+pub fn partial_clone() -> Result<Option<PartialClone>> {
+    let remote = match get_config("extensions.partialClone")? {
+        Some(remote) => Some(remote),
+        None => {
+            let output = output(&mut git(&[
+                "config",
+                "--type=bool",
+                "--get-regexp",
+                r"^remote\..*\.promisor$",
+            ]))?;
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| line.split_once(' '))
+                .find(|(_, value)| *value == "true")
+                .and_then(|(key, _)| key.strip_prefix("remote.")?.strip_suffix(".promisor"))
+                .map(str::to_string)
         }
-        Err(_) => Ok(0), // No upstream = 0 ahead (intentional, not an error)
-    }
+    };
+
+    let Some(remote) = remote.filter(|remote| !remote.is_empty()) else {
+        return Ok(None);
+    };
+    let filter = get_config(&format!("remote.{}.partialclonefilter", remote))?;
+    Ok(Some(PartialClone { remote, filter }))
+}
+
+/// Cone-mode sparse-checkout directories of the checkout at `path`
+/// Returns: empty when it isn't sparse, or uses non-cone patterns rather than directories
+pub fn sparse_checkout_dirs(path: &Path) -> Result<Vec<String>> {
+    let flag = |key| {
+        run_git_cmd(&in_dir(
+            path,
+            &["config", "--type=bool", "--default=false", key],
+        ))
+        .map(|value| value == "true")
+    };
+    if !flag("core.sparseCheckout")? || !flag("core.sparseCheckoutCone")? {
+        return Ok(Vec::new());
+    }
+
+    Ok(run_git_cmd(&in_dir(path, &["sparse-checkout", "list"]))?
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
+
+/// Git arguments `create_worktree` would run for a new worktree, one command per entry
+pub fn create_worktree_commands(
+    path: &Path,
+    branch: WorktreeBranch,
+    commit: &str,
+    upstream_branch: Option<&str>,
+    sparse: &[String],
+) -> Result<Vec<Vec<String>>> {
+    // For display only, so a path that isn't UTF-8 is shown lossily
+    let owned = |args: &[&OsStr]| {
+        args.iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+    };
+
+    let mut commands = vec![owned(&worktree_add_args(path, branch, commit, sparse))];
+    if !sparse.is_empty() {
+        let mut sparse_args = owned(&in_dir(path, &["sparse-checkout", "set", "--cone"]));
+        sparse_args.extend(sparse.iter().cloned());
+        commands.push(sparse_args);
+        commands.push(owned(&in_dir(path, &["checkout"])));
+    }
+    if let Some(upstream) = upstream_branch.filter(|_| matches!(branch, WorktreeBranch::New(_))) {
+        commands.push(owned(&in_dir(
+            path,
+            &["branch", "--set-upstream-to", upstream],
+        )));
+    }
+    Ok(commands)
+}
+
+pub fn remove_worktree(path: &Path) -> Result<()> {
+    let output = run_retrying_locks(
+        git(&["worktree", "remove"])
+            .arg(path)
+            .arg("--force")
+            .stdout(Stdio::inherit()),
+    )?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(ArboristError::WorktreeRemoveFailed {
+            path: path.to_path_buf(),
+            stderr: stderr.trim().to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Locks a worktree so git refuses to prune or remove it until it is unlocked
+pub fn lock_worktree(path: &Path, reason: &str) -> Result<()> {
+    run_git_cmd(&[
+        OsStr::new("worktree"),
+        OsStr::new("lock"),
+        OsStr::new("--reason"),
+        OsStr::new(reason),
+        path.as_os_str(),
+    ])?;
+    Ok(())
+}
+
+pub fn unlock_worktree(path: &Path) -> Result<()> {
+    run_git_cmd(&[
+        OsStr::new("worktree"),
+        OsStr::new("unlock"),
+        path.as_os_str(),
+    ])?;
+    Ok(())
+}
+
+/// Moves an unlocked worktree to another directory
+pub fn move_worktree(from: &Path, to: &Path) -> Result<()> {
+    ensure_worktree_base_dir(to)?;
+    run_git_cmd(&[
+        OsStr::new("worktree"),
+        OsStr::new("move"),
+        from.as_os_str(),
+        to.as_os_str(),
+    ])?;
+    Ok(())
+}
+
+/// Points a worktree's checked-out branch (or HEAD) at `commit`, discarding any changes
+pub fn reset_worktree(path: &Path, commit: &str) -> Result<()> {
+    run_git_cmd(&in_dir(path, &["reset", "--hard", "--quiet", commit]))?;
+    Ok(())
+}
+
+/// Removes a worktree even if it is locked
+pub fn force_remove_locked_worktree(path: &Path) -> Result<()> {
+    let output = run_retrying_locks(
+        git(&["worktree", "remove", "--force", "--force"])
+            .arg(path)
+            .stdout(Stdio::inherit()),
+    )?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(ArboristError::WorktreeRemoveFailed {
+            path: path.to_path_buf(),
+            stderr: stderr.trim().to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+pub fn prune_worktrees() -> Result<()> {
+    run_git_cmd(&["worktree", "prune"])?;
+    Ok(())
+}
+
+pub fn remove_worktree_and_branch(path: &Path, branch: &str) -> Result<()> {
+    // First remove the worktree
+    remove_worktree(path)?;
+
+    // Then delete the associated branch
+    delete_branch(branch)?;
+
+    Ok(())
+}
+
+/// How `get_worktree_status` looks for uncommitted changes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatusMode {
+    pub untracked: UntrackedFiles,
+    pub fsmonitor: Fsmonitor,
+    /// core.untrackedCache is on, so git remembers which directories hold no new files
+    pub untracked_cache: bool,
+}
+
+/// How untracked files count as changes, after git's `--untracked-files` modes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum UntrackedFiles {
+    /// Not at all: looking for them is the slowest part of a status in a large tree, so the
+    /// status leaves them out; a worktree holding nothing but new files still counts as changed
+    No,
+    /// A new directory counts once, however many files it holds
+    #[default]
+    Normal,
+    /// Every new file counts, inside new directories too
+    All,
+}
+
+impl UntrackedFiles {
+    fn as_arg(self) -> &'static str {
+        match self {
+            UntrackedFiles::No => "--untracked-files=no",
+            UntrackedFiles::Normal => "--untracked-files=normal",
+            UntrackedFiles::All => "--untracked-files=all",
+        }
+    }
+}
+
+/// Whether a filesystem monitor tells status which files may have changed, sparing it a scan
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fsmonitor {
+    #[default]
+    Off,
+    /// core.fsmonitor is set in the repository's config, which git uses on its own
+    Configured,
+    /// git's builtin daemon, turned on for arborist's status checks alone
+    Builtin,
+}
+
+impl StatusMode {
+    /// The mode for a repository: `fast` uses git's builtin filesystem monitor where it has one;
+    /// a monitor in the repository's config is always used
+    pub fn detect(fast: bool, untracked: UntrackedFiles) -> Result<StatusMode> {
+        let configured = get_config("core.fsmonitor")?
+            .is_some_and(|value| !matches!(value.as_str(), "" | "false" | "0" | "no" | "off"));
+        let fsmonitor = if configured {
+            Fsmonitor::Configured
+        } else if fast && has_builtin_fsmonitor() {
+            Fsmonitor::Builtin
+        } else {
+            Fsmonitor::Off
+        };
+        // git uses the cache on its own; it only matters when untracked files are looked for
+        let untracked_cache = untracked != UntrackedFiles::No
+            && get_config("core.untrackedCache")?.as_deref() == Some("true");
+        Ok(StatusMode {
+            untracked,
+            fsmonitor,
+            untracked_cache,
+        })
+    }
+
+    /// e.g. "full" or "untracked files skipped, fsmonitor"
+    pub fn describe(&self) -> String {
+        let mut parts = vec![match self.untracked {
+            UntrackedFiles::No => "untracked files skipped",
+            UntrackedFiles::Normal => "full",
+            UntrackedFiles::All => "full, every untracked file",
+        }];
+        if self.fsmonitor != Fsmonitor::Off {
+            parts.push("fsmonitor");
+        }
+        if self.untracked_cache {
+            parts.push("untracked cache");
+        }
+        parts.join(", ")
+    }
+}
+
+// Whether this git was built with its own filesystem monitor daemon (core.fsmonitor=true)
+fn has_builtin_fsmonitor() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(|| {
+        run_git_cmd(&["version", "--build-options"])
+            .is_ok_and(|options| options.contains("fsmonitor--daemon"))
+    })
+}
+
+/// Gets the worktree status, ignoring changes under the excluded paths
+pub fn get_worktree_status(excluded: &[String], mode: StatusMode) -> Result<WorktreeStatus> {
+    let mut has_changes = has_uncommitted_changes(excluded, mode)?;
+    // Untracked files left out of the status still keep the worktree: only whether there are any
+    // is looked up, and only when nothing else would keep it
+    if !has_changes && mode.untracked == UntrackedFiles::No {
+        has_changes = has_untracked_files(excluded)?;
+    }
+    let commits_ahead = get_commits_ahead()?;
+
+    Ok(WorktreeStatus {
+        has_changes,
+        commits_ahead,
+    })
+}
+
+fn has_uncommitted_changes(excluded: &[String], mode: StatusMode) -> Result<bool> {
+    let excludes = exclude_pathspecs(excluded);
+    let mut args = Vec::new();
+    if mode.fsmonitor == Fsmonitor::Builtin {
+        args.extend(["-c", "core.fsmonitor=true"]);
+    }
+    args.extend(["status", "--porcelain=v2", mode.untracked.as_arg()]);
+    args.extend(["--", "."]);
+    args.extend(excludes.iter().map(String::as_str));
+
+    let output = run_git_cmd(&args)?;
+    if output.is_empty() {
+        return Ok(false);
+    }
+
+    // Collect worktree-only modifications as (path, index blob) pairs; anything
+    // else (staged, added, deleted, untracked) is always a real change
+    let mut modified = Vec::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.splitn(9, ' ').collect();
+        if fields.len() == 9 && fields[0] == "1" && fields[1] == ".M" {
+            modified.push((fields[8], fields[7]));
+        } else {
+            return Ok(true);
+        }
+    }
+
+    Ok(!is_lfs_smudge_only(&modified)?)
+}
+
+// Whether the current directory holds untracked files that aren't ignored or excluded; untracked
+// directories are listed without looking inside them
+fn has_untracked_files(excluded: &[String]) -> Result<bool> {
+    let excludes = exclude_pathspecs(excluded);
+    let mut args = vec![
+        "ls-files",
+        "--others",
+        "--exclude-standard",
+        "--directory",
+        "--no-empty-directory",
+        "--",
+        ".",
+    ];
+    args.extend(excludes.iter().map(String::as_str));
+    Ok(!run_git_cmd(&args)?.is_empty())
+}
+
+// Checks whether modified files are LFS objects whose cleaned content still
+// matches the index, i.e. they only differ because of smudging
+fn is_lfs_smudge_only(modified: &[(&str, &str)]) -> Result<bool> {
+    let mut args = vec!["check-attr", "filter", "--"];
+    args.extend(modified.iter().map(|(path, _)| *path));
+    let attrs = run_git_cmd(&args)?;
+
+    let all_lfs = attrs.lines().all(|line| line.ends_with(": filter: lfs"));
+    if !all_lfs {
+        return Ok(false);
+    }
+
+    // hash-object applies the clean filter, turning content back into a pointer
+    let mut args = vec!["hash-object", "--"];
+    args.extend(modified.iter().map(|(path, _)| *path));
+    let hashes = match run_git_cmd(&args) {
+        Ok(hashes) => hashes,
+        Err(_) => return Ok(false),
+    };
+
+    Ok(hashes
+        .lines()
+        .zip(modified)
+        .all(|(hash, (_, index_hash))| hash == *index_hash))
+}
+
+/// Reads the skip-worktree and assume-unchanged bits of a checkout's index
+pub fn get_index_flags(checkout: &Path) -> Result<IndexFlags> {
+    let mut flags = IndexFlags::default();
+
+    // Sparse checkouts set skip-worktree on every excluded path; those bits are
+    // not user intent and must not be copied into a full worktree
+    let sparse = run_git_cmd(&in_dir(
+        checkout,
+        &["config", "--bool", "core.sparseCheckout"],
+    ))
+    .unwrap_or_default();
+    if sparse == "true" {
+        return Ok(flags);
+    }
+
+    let output = run_git_cmd(&in_dir(checkout, &["ls-files", "-v", "-z"]))?;
+    for entry in output.split('\0') {
+        let Some((tag, path)) = entry.split_once(' ') else {
+            continue;
+        };
+        // ls-files -v marks skip-worktree with "S" and assume-unchanged with lowercase tags
+        if tag.eq_ignore_ascii_case("S") {
+            flags.skip_worktree.push(path.to_string());
+        }
+        if tag.chars().all(|c| c.is_ascii_lowercase()) {
+            flags.assume_unchanged.push(path.to_string());
+        }
+    }
+
+    Ok(flags)
+}
+
+/// Sets skip-worktree and assume-unchanged bits in a worktree's index
+pub fn apply_index_flags(path: &Path, flags: &IndexFlags) -> Result<()> {
+    for (flag, paths) in [
+        ("--skip-worktree", &flags.skip_worktree),
+        ("--assume-unchanged", &flags.assume_unchanged),
+    ] {
+        if paths.is_empty() {
+            continue;
+        }
+
+        let args = in_dir(path, &["update-index", flag, "-z", "--stdin"]);
+        let input = paths.join("\0");
+        let output = process::block_on(output_retrying_locks(
+            &mut git(&args),
+            Some(input.as_bytes()),
+        ))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(git_command_failed(&args, stderr.trim()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks whether any tracked files live under the given path of a worktree
+pub fn has_tracked_files(worktree: &Path, path: &str) -> Result<bool> {
+    let output = run_git_cmd(&in_dir(worktree, &["ls-files", "--", path]))?;
+    Ok(!output.is_empty())
+}
+
+/// Directory git runs hooks from for a checkout, honouring core.hooksPath
+/// Returns: a path relative to the checkout, or an absolute path
+pub fn get_hooks_dir(checkout: &Path) -> Result<PathBuf> {
+    run_git_path(&in_dir(checkout, &["rev-parse", "--git-path", "hooks"]))
+}
+
+/// Sets git config for the worktree at `path` alone (`git config --worktree`), turning on the
+/// repository's extensions.worktreeConfig first, without which worktree config is ignored
+pub fn set_worktree_config(path: &Path, values: &BTreeMap<String, String>) -> Result<()> {
+    if values.is_empty() {
+        return Ok(());
+    }
+    require(Feature::WorktreeConfig)?;
+
+    run_git_cmd(&in_dir(
+        path,
+        &["config", "extensions.worktreeConfig", "true"],
+    ))?;
+    for (key, value) in values {
+        run_git_cmd(&in_dir(path, &["config", "--worktree", key, value]))?;
+    }
+    Ok(())
+}
+
+/// Installs LFS hooks for the worktree and downloads its LFS objects
+pub fn lfs_pull(path: &Path) -> Result<()> {
+    run_git_cmd(&in_dir(path, &["lfs", "install", "--local"]))?;
+    run_git_cmd(&in_dir(path, &["lfs", "pull"]))?;
+    Ok(())
+}
+
+fn get_commits_ahead() -> Result<usize> {
+    // A branch tracking nothing, or a detached HEAD, has nothing to be ahead of
+    if branch_upstream("HEAD")?.is_none() {
+        return Ok(0);
+    }
+    let output = run_git_cmd(&["rev-list", "--count", "@{upstream}..HEAD"])?;
+    Ok(output.parse().unwrap_or(0))
+}
+
+/// Who commits are made as, overriding user.name and user.email where set
+#[derive(Debug, Clone, Default)]
+pub struct Identity {
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+impl Identity {
+    /// GIT_AUTHOR_* and GIT_COMMITTER_* variables making a command's commits with this identity
+    pub fn env_vars(&self) -> Vec<(String, String)> {
+        let mut vars = Vec::new();
+        for (field, value) in [("NAME", &self.name), ("EMAIL", &self.email)] {
+            if let Some(value) = value {
+                for role in ["AUTHOR", "COMMITTER"] {
+                    vars.push((format!("GIT_{}_{}", role, field), value.clone()));
+                }
+            }
+        }
+        vars
+    }
+
+    // `-c` options making one git command's commits with this identity
+    fn config_args(&self) -> Vec<String> {
+        [("user.name", &self.name), ("user.email", &self.email)]
+            .into_iter()
+            .filter_map(|(key, value)| value.as_ref().map(|value| format!("{}={}", key, value)))
+            .flat_map(|setting| ["-c".to_string(), setting])
+            .collect()
+    }
+}
+
+/// Stages everything in the worktree except the excluded paths and commits it as `identity`
+/// `sign` overrides commit.gpgsign: Some(true) always signs, Some(false) never does,
+/// and None follows the repository's configuration (GPG or SSH, per gpg.format)
+pub fn commit_all(
+    path: &Path,
+    message: &str,
+    sign: Option<bool>,
+    excluded: &[String],
+    identity: &Identity,
+) -> Result<()> {
+    let excludes = exclude_pathspecs(excluded);
+    let mut add_args = in_dir(path, &["add", "--all", "--", "."]);
+    add_args.extend(excludes.iter().map(OsStr::new));
+    run_git_cmd(&add_args)?;
+
+    let identity = identity.config_args();
+    let mut args = in_dir(path, &[]);
+    args.extend(identity.iter().map(OsStr::new));
+    args.extend(["commit", "--quiet", "--message", message].map(OsStr::new));
+    args.extend(sign_arg(sign).map(OsStr::new));
+
+    run_git_cmd(&args).map(drop).map_err(signing_error)
+}
+
+// Flag overriding commit.gpgsign for a command that commits: `sign` as for commit_all
+fn sign_arg(sign: Option<bool>) -> Option<&'static str> {
+    match sign {
+        Some(true) => Some("--gpg-sign"),
+        Some(false) => Some("--no-gpg-sign"),
+        None => None,
+    }
+}
+
+// A failed git command as SigningFailed when it was the GPG or SSH signing step that failed
+fn signing_error(err: ArboristError) -> ArboristError {
+    match err {
+        ArboristError::GitCommandFailed { stderr, .. } if is_signing_failure(&stderr) => {
+            ArboristError::SigningFailed(stderr)
+        }
+        err => err,
+    }
+}
+
+/// Records the working tree of `checkout`, including untracked files, as a tree object
+/// Works on a copy of the index so the checkout's staged changes are left alone
+pub fn snapshot_tree(checkout: &Path, excluded: &[String]) -> Result<String> {
+    snapshot(checkout, excluded, true)
+}
+
+fn snapshot(checkout: &Path, excluded: &[String], untracked: bool) -> Result<String> {
+    let index = checkout.join(run_git_path(&in_dir(
+        checkout,
+        &["rev-parse", "--git-path", "index"],
+    ))?);
+    let scratch = env::temp_dir().join(format!("arborist-snapshot-{}.index", std::process::id()));
+    // The copied index keeps stat data, so unchanged files aren't hashed again
+    if index.exists() {
+        fs::copy(&index, &scratch).context("copying the index for a snapshot")?;
+    }
+
+    let vars = [("GIT_INDEX_FILE", scratch.as_path())];
+    let excludes = exclude_pathspecs(excluded);
+    let add = if untracked { "--all" } else { "--update" };
+    let mut add_args = in_dir(checkout, &["add", add, "--", "."]);
+    add_args.extend(excludes.iter().map(OsStr::new));
+    let tree = run_git_cmd_env(&add_args, &vars)
+        .and_then(|_| run_git_cmd_env(&in_dir(checkout, &["write-tree"]), &vars));
+    let _ = fs::remove_file(&scratch);
+    tree
+}
+
+/// The commits of `range` (e.g. base..HEAD) in the checkout at `path` as an mbox of patches,
+/// which `git am` applies again
+pub fn format_patches(path: &Path, range: &str) -> Result<Vec<u8>> {
+    run_git_cmd_bytes(&in_dir(
+        path,
+        &["format-patch", "--stdout", "--binary", range],
+    ))
+}
+
+/// Writes the commits of `range` (e.g. base..HEAD) in the checkout at `path` to a bundle file,
+/// which `git fetch` reads like a remote
+pub fn create_bundle(path: &Path, bundle: &Path, range: &str) -> Result<()> {
+    let mut args = in_dir(path, &["bundle", "create"]);
+    args.push(bundle.as_os_str());
+    args.push(OsStr::new(range));
+    run_git_cmd(&args)?;
+    Ok(())
+}
+
+/// The difference between two trees or commits in the checkout at `path`, binary files included,
+/// as a patch `git apply` accepts
+pub fn binary_diff(path: &Path, from: &str, to: &str) -> Result<Vec<u8>> {
+    run_git_cmd_bytes(&in_dir(path, &["diff", "--binary", from, to]))
+}
+
+/// Applies an mbox of patches from `format_patches` as commits on the checkout at `path`
+pub fn apply_mailbox(path: &Path, mailbox: &Path) -> Result<()> {
+    let mut args = in_dir(path, &["am", "--quiet", "--3way"]);
+    args.push(mailbox.as_os_str());
+    if let Err(err) = run_git_cmd(&args) {
+        let _ = run_git_cmd(&in_dir(path, &["am", "--abort"]));
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Applies a patch from `binary_diff` to the files of the checkout at `path`
+pub fn apply_patch(path: &Path, patch: &Path) -> Result<()> {
+    let mut args = in_dir(path, &["apply"]);
+    args.push(patch.as_os_str());
+    run_git_cmd(&args)?;
+    Ok(())
+}
+
+/// Makes the files of worktree `path` match `tree`, removing untracked files other than ignored
+/// and excluded ones
+pub fn checkout_tree(path: &Path, tree: &str, excluded: &[String]) -> Result<()> {
+    run_git_cmd(&in_dir(path, &["read-tree", "-u", "--reset", tree]))?;
+
+    let excludes = exclude_pathspecs(excluded);
+    let mut args = in_dir(path, &["clean", "-d", "--force", "--quiet", "--", "."]);
+    args.extend(excludes.iter().map(OsStr::new));
+    run_git_cmd(&args)?;
+    Ok(())
+}
+
+/// Throws away uncommitted changes in the current worktree, sparing the excluded paths
+pub fn discard_changes(excluded: &[String]) -> Result<()> {
+    run_git_cmd(&["reset", "--hard", "--quiet"])?;
+
+    let excludes = exclude_pathspecs(excluded);
+    let mut args = vec!["clean", "-d", "--force", "--quiet", "--", "."];
+    args.extend(excludes.iter().map(String::as_str));
+    run_git_cmd(&args)?;
+    Ok(())
+}
+
+/// Deletes untracked files in the current worktree matching `pathspecs`, ignored ones included
+pub fn clean_paths(pathspecs: &[String]) -> Result<()> {
+    let mut args = vec!["clean", "-d", "-x", "--force", "--quiet", "--"];
+    args.extend(pathspecs.iter().map(String::as_str));
+    run_git_cmd(&args)?;
+    Ok(())
+}
+
+/// Moves the current worktree's changes, including untracked files, onto the stash
+pub fn stash_changes(message: &str, excluded: &[String]) -> Result<()> {
+    let excludes = exclude_pathspecs(excluded);
+    let mut args = vec![
+        "stash",
+        "push",
+        "--include-untracked",
+        "--quiet",
+        "--message",
+        message,
+        "--",
+        ".",
+    ];
+    args.extend(excludes.iter().map(String::as_str));
+    run_git_cmd(&args)?;
+    Ok(())
+}
+
+/// Branch checked out in `checkout`, or None when its HEAD is detached
+pub fn checked_out_branch(checkout: &Path) -> Result<Option<String>> {
+    let output = output(&mut git(&in_dir(
+        checkout,
+        &["symbolic-ref", "--quiet", "--short", "HEAD"],
+    )))?;
+
+    Ok(output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string()))
+}
+
+/// Merges `branch` into the branch checked out in `checkout`, with a merge commit by `identity`
+/// when one is needed, signed as `sign` says (as for commit_all)
+/// Returns: false when the merge conflicted, in which case it was aborted
+pub fn merge_into(
+    checkout: &Path,
+    branch: &str,
+    identity: &Identity,
+    sign: Option<bool>,
+) -> Result<bool> {
+    let merged = merge(checkout, branch, identity, sign)?;
+    if !merged {
+        abort_merge(checkout)?;
+    }
+    Ok(merged)
+}
+
+/// Merges `branch` into the branch checked out in `checkout` like `merge_into`
+/// Returns: false when the merge conflicted, leaving it in progress for the conflicts to be resolved
+pub fn merge(
+    checkout: &Path,
+    branch: &str,
+    identity: &Identity,
+    sign: Option<bool>,
+) -> Result<bool> {
+    let identity = identity.config_args();
+    let mut args = in_dir(checkout, &[]);
+    args.extend(identity.iter().map(OsStr::new));
+    args.extend(["merge", "--no-edit", "--quiet", branch].map(OsStr::new));
+    args.extend(sign_arg(sign).map(OsStr::new));
+    match run_git_cmd(&args).map_err(signing_error) {
+        // Only the merge commit failed, but git leaves the merge in progress as for a conflict
+        Err(err @ ArboristError::SigningFailed(_)) => {
+            abort_merge(checkout)?;
+            Err(err)
+        }
+        result => stopped_on_conflict(checkout, result, "MERGE_HEAD"),
+    }
+}
+
+/// Rebases the branch checked out in `checkout` onto `onto`, the rewritten commits committed by
+/// `identity` and signed as `sign` says (as for commit_all)
+/// Returns: false when a commit conflicted, leaving the rebase in progress for the conflicts to be
+/// resolved
+pub fn rebase(
+    checkout: &Path,
+    onto: &str,
+    identity: &Identity,
+    sign: Option<bool>,
+) -> Result<bool> {
+    let identity = identity.config_args();
+    let mut args = in_dir(checkout, &[]);
+    args.extend(identity.iter().map(OsStr::new));
+    args.extend(["rebase", "--quiet"].map(OsStr::new));
+    args.extend(sign_arg(sign).map(OsStr::new));
+    args.push(OsStr::new(onto));
+    match run_git_cmd(&args).map_err(signing_error) {
+        // A commit that couldn't be signed stops the rebase part way, with the branch half rewritten
+        Err(err @ ArboristError::SigningFailed(_)) => {
+            abort_rebase(checkout)?;
+            Err(err)
+        }
+        result => stopped_on_conflict(checkout, result, "REBASE_HEAD"),
+    }
+}
+
+// Whether a merge or rebase went through: false when it stopped on a conflict, which leaves
+// `marker` (MERGE_HEAD or REBASE_HEAD) behind; any other failure is an error
+fn stopped_on_conflict(checkout: &Path, result: Result<String>, marker: &str) -> Result<bool> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(err) => {
+            let stopped = run_git_cmd(&in_dir(
+                checkout,
+                &["rev-parse", "--quiet", "--verify", marker],
+            ))
+            .is_ok();
+            if stopped { Ok(false) } else { Err(err) }
+        }
+    }
+}
+
+/// Abandons the merge in progress in `checkout`, restoring the branch as it was
+pub fn abort_merge(checkout: &Path) -> Result<()> {
+    run_git_cmd(&in_dir(checkout, &["merge", "--abort"]))?;
+    Ok(())
+}
+
+/// Abandons the rebase in progress in `checkout`, restoring the branch as it was
+pub fn abort_rebase(checkout: &Path) -> Result<()> {
+    run_git_cmd(&in_dir(checkout, &["rebase", "--abort"]))?;
+    Ok(())
+}
+
+/// Files with unresolved conflicts in `checkout`
+pub fn conflicted_files(checkout: &Path) -> Result<Vec<String>> {
+    Ok(run_git_cmd(&in_dir(
+        checkout,
+        &["diff", "--name-only", "--diff-filter=U"],
+    ))?
+    .lines()
+    .map(str::to_string)
+    .collect())
+}
+
+/// Applies the latest stash in another checkout of the repository and drops it
+/// The stash is left in place if it doesn't apply cleanly
+pub fn apply_stash(checkout: &Path) -> Result<()> {
+    run_git_cmd(&in_dir(checkout, &["stash", "apply", "--quiet"]))?;
+    run_git_cmd(&in_dir(checkout, &["stash", "drop", "--quiet"]))?;
+    Ok(())
+}
+
+// Pathspecs excluding each path from a "." pathspec
+fn exclude_pathspecs(excluded: &[String]) -> Vec<String> {
+    excluded
+        .iter()
+        .map(|path| format!(":(exclude){}", path))
+        .collect()
+}
+
+// Whether git's error output comes from the GPG or SSH signing step
+fn is_signing_failure(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    [
+        "failed to sign",
+        "gpg failed",
+        "ssh-keygen",
+        "signing key",
+        "load public key",
+        "load key",
+    ]
+    .iter()
+    .any(|marker| stderr.contains(marker))
+}
+
+/// A branch of a remote, e.g. "feature-x" of "origin"
+#[derive(Debug, Clone)]
+pub struct RemoteBranch {
+    pub remote: String,
+    pub branch: String,
+}
+
+impl RemoteBranch {
+    /// Splits "<remote>/<branch>" after the configured remote it starts with
+    pub fn parse(name: &str) -> Result<Self> {
+        let remotes = run_git_cmd(&["remote"])?;
+        remotes
+            .lines()
+            .filter_map(|remote| {
+                let branch = name.strip_prefix(remote)?.strip_prefix('/')?;
+                (!branch.is_empty()).then(|| RemoteBranch {
+                    remote: remote.to_string(),
+                    branch: branch.to_string(),
+                })
+            })
+            // Remote names may contain slashes themselves; the longest match is meant
+            .max_by_key(|remote_branch| remote_branch.remote.len())
+            .ok_or_else(|| {
+                ArboristError::GitOperationFailed(format!(
+                    "{} is not <remote>/<branch> for a configured remote",
+                    name
+                ))
+            })
+    }
+
+    /// The remote branch a local branch tracks, or None when it tracks nothing or a local branch
+    pub fn upstream_of(branch: &str) -> Result<Option<Self>> {
+        let output = run_git_cmd(&[
+            "for-each-ref",
+            "--format=%(upstream:remotename) %(upstream:remoteref)",
+            &format!("refs/heads/{}", branch),
+        ])?;
+        let Some((remote, remote_ref)) = output.split_once(' ') else {
+            return Ok(None);
+        };
+        match remote_ref.strip_prefix("refs/heads/") {
+            Some(upstream) if !remote.is_empty() && remote != "." => Ok(Some(RemoteBranch {
+                remote: remote.to_string(),
+                branch: upstream.to_string(),
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    /// Short name, as in "origin/main"
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.remote, self.branch)
+    }
+
+    /// Arguments of the `git fetch` that updates the remote-tracking branch
+    pub fn fetch_args(&self) -> Vec<String> {
+        vec![
+            "fetch".to_string(),
+            "--quiet".to_string(),
+            self.remote.clone(),
+            format!("+refs/heads/{}:{}", self.branch, self.tracking_ref()),
+        ]
+    }
+
+    /// Fetches the branch from its remote
+    /// Returns: the commit at its tip
+    pub fn fetch(&self) -> Result<String> {
+        let args = self.fetch_args();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        run_git_cmd(&args).context(format!("fetching {}", self.name()))?;
+        self.tip()
+    }
+
+    /// The commit at the tip of the remote-tracking branch as last fetched
+    pub fn tip(&self) -> Result<String> {
+        let rev = format!("{}^{{commit}}", self.tracking_ref());
+        run_git_cmd(&["rev-parse", "--verify", "--quiet", &rev]).map_err(|_| {
+            ArboristError::BranchNotFound {
+                branch: self.name(),
+            }
+        })
+    }
+
+    fn tracking_ref(&self) -> String {
+        format!("refs/remotes/{}/{}", self.remote, self.branch)
+    }
+}
+
+/// The commit at the tip of a local branch
+pub fn branch_tip(branch: &str) -> Result<String> {
+    let rev = format!("refs/heads/{}^{{commit}}", branch);
+    run_git_cmd(&["rev-parse", "--verify", "--quiet", &rev]).map_err(|_| {
+        ArboristError::BranchNotFound {
+            branch: branch.to_string(),
+        }
+    })
+}
+
+/// The commit a revision names, e.g. a branch, a tag or `HEAD~2`
+pub fn resolve_commit(rev: &str) -> Result<String> {
+    let not_a_commit =
+        || ArboristError::GitOperationFailed(format!("{} doesn't name a commit", rev));
+    let commit = format!("{}^{{commit}}", rev);
+    let mut args = vec!["rev-parse", "--verify", "--quiet"];
+    if supports(Feature::EndOfOptions) {
+        args.push("--end-of-options");
+    } else if rev.starts_with('-') {
+        // Without --end-of-options it would be taken for an option
+        return Err(not_a_commit());
+    }
+    args.push(&commit);
+    run_git_cmd(&args).map_err(|_| not_a_commit())
+}
+
+/// Whether `name` is a branch another can track: local (main) or remote-tracking (origin/main)
+pub fn is_branch(name: &str) -> Result<bool> {
+    let output = output(&mut git(&[
+        "rev-parse",
+        "--verify",
+        "--quiet",
+        "--symbolic-full-name",
+        name,
+    ]))?;
+    let full_name = String::from_utf8_lossy(&output.stdout);
+    Ok(output.status.success()
+        && (full_name.starts_with("refs/heads/") || full_name.starts_with("refs/remotes/")))
+}
+
+/// Whether a local branch exists
+pub fn branch_exists(branch: &str) -> Result<bool> {
+    let reference = format!("refs/heads/{}", branch);
+    let output = output(&mut git(&["rev-parse", "--verify", "--quiet", &reference]))?;
+
+    Ok(output.status.success())
+}
+
+/// Counts commits reachable from `to` but not from `from`
+pub fn count_commits_between(from: &str, to: &str) -> Result<usize> {
+    let output = run_git_cmd(&["rev-list", "--count", &format!("{}..{}", from, to)])?;
+    Ok(output.parse().unwrap_or(0))
+}
+
+/// The first commit of `rev`'s history (the one reached following first parents)
+pub fn root_commit(rev: &str) -> Result<String> {
+    let output = run_git_cmd(&["rev-list", "--first-parent", "--max-parents=0", rev])?;
+    Ok(output.lines().last().unwrap_or_default().to_string())
+}
+
+/// Counts commits on HEAD that are not reachable from `base`
+pub fn count_commits_not_in(base: &str) -> Result<usize> {
+    let output = run_git_cmd(&["rev-list", "--count", &format!("{}..HEAD", base)])?;
+    Ok(output.parse().unwrap_or(0))
+}
+
+/// Counts files in worktree `path` that differ from `base`, whether committed or not
+/// Untracked files count as `untracked` says
+pub fn count_changed_files(
+    path: &Path,
+    base: &str,
+    excluded: &[String],
+    untracked: UntrackedFiles,
+) -> Result<usize> {
+    let tree = snapshot(path, excluded, false)?;
+    let tracked = run_git_cmd(&in_dir(path, &["diff", "--name-only", base, &tree]))?;
+    Ok(tracked.lines().count() + count_untracked(path, excluded, untracked)?)
+}
+
+// Untracked files that aren't ignored, with a new directory as one entry in normal mode
+fn count_untracked(path: &Path, excluded: &[String], untracked: UntrackedFiles) -> Result<usize> {
+    let mut args = in_dir(path, &["ls-files", "--others", "--exclude-standard"]);
+    // As in git status, empty directories don't count
+    match untracked {
+        UntrackedFiles::No => return Ok(0),
+        UntrackedFiles::Normal => {
+            args.extend(["--directory", "--no-empty-directory"].map(OsStr::new))
+        }
+        UntrackedFiles::All => {}
+    }
+    args.extend(["--", "."].map(OsStr::new));
+    let excludes = exclude_pathspecs(excluded);
+    args.extend(excludes.iter().map(OsStr::new));
+    Ok(run_git_cmd(&args)?.lines().count())
+}
+
+/// The commit checked out in a worktree
+pub fn head_commit(checkout: &Path) -> Result<String> {
+    run_git_cmd(&in_dir(checkout, &["rev-parse", "HEAD"]))
+}
+
+/// The best common ancestor of two commits, or None when their histories are unrelated
+pub fn merge_base(a: &str, b: &str) -> Result<Option<String>> {
+    let output = output(&mut git(&["merge-base", a, b]))?;
+    Ok(output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string()))
+}
+
+/// Renames a local branch, including where a worktree has it checked out
+pub fn rename_branch(from: &str, to: &str) -> Result<()> {
+    if branch_exists(to)? {
+        return Err(ArboristError::BranchExists {
+            branch: to.to_string(),
+        });
+    }
+    run_git_cmd(&["branch", "-m", from, to])?;
+    Ok(())
 }
 
 pub fn delete_branch(branch: &str) -> Result<()> {
     run_git_cmd(&["branch", "-D", branch])?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORKTREE_LIST: &[u8] = b"worktree /repo
+HEAD 1111111111111111111111111111111111111111
+branch refs/heads/main
+
+worktree /worktrees/fix-auth
+HEAD 2222222222222222222222222222222222222222
+branch refs/heads/arborist/fix-auth
+locked arborist: parked in the pool
+
+";
+
+    #[test]
+    fn parses_porcelain_worktree_list() {
+        let entries = parse_worktree_list(WORKTREE_LIST);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, Path::new("/repo"));
+        assert_eq!(entries[0].branch.as_deref(), Some("main"));
+        assert!(!entries[0].locked);
+        assert_eq!(entries[1].branch.as_deref(), Some("arborist/fix-auth"));
+        assert!(entries[1].locked);
+        assert_eq!(
+            entries[1].lock_reason.as_deref(),
+            Some("arborist: parked in the pool")
+        );
+    }
+
+    #[test]
+    fn names_sharing_a_prefix_are_different_worktrees() {
+        let entries = parse_worktree_list(WORKTREE_LIST);
+        assert!(is_registered(&entries, Path::new("/worktrees/fix-auth")));
+        assert!(!is_registered(&entries, Path::new("/worktrees/fix")));
+        assert!(!is_registered(&entries, Path::new("/worktrees/fix-auth-2")));
+        assert!(!is_registered(&entries, Path::new("/rep")));
+    }
+
+    #[test]
+    fn reports_who_holds_a_lock() {
+        let stderr = "fatal: Unable to create '/repo/.git/index.lock': File exists.\n\nAnother git process seems to be running in this repository";
+        match lock_held(stderr) {
+            Some(ArboristError::LockHeld { path }) => {
+                assert_eq!(path, Path::new("/repo/.git/index.lock"))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(is_lock_contention(stderr));
+        assert!(lock_held("fatal: not a git repository").is_none());
+    }
+
+    #[test]
+    fn only_lock_failures_are_retried() {
+        assert!(is_lock_contention(
+            "error: could not lock config file .git/config: File exists"
+        ));
+        assert!(is_lock_contention(
+            "fatal: cannot lock ref 'refs/heads/arborist/x': is at 1111111 but expected 2222222"
+        ));
+        assert!(!is_lock_contention("fatal: invalid reference: arborist/x"));
+        assert!(!is_lock_contention(
+            "fatal: 'x' is already checked out at '/repo'"
+        ));
+    }
+
+    #[test]
+    fn lists_worktrees_of_several_repositories_at_once() {
+        let root = env::temp_dir().join(format!("arborist-git-{}-async", std::process::id()));
+        let repos = [root.join("one"), root.join("two")];
+        for repo in &repos {
+            fs::create_dir_all(repo).unwrap();
+            run_git_cmd(&in_dir(repo, &["init", "--quiet"])).unwrap();
+        }
+
+        let listed = process::block_on(process::join_all(
+            repos.iter().map(|repo| list_worktrees_in(repo)),
+        ));
+        for (repo, entries) in repos.iter().zip(listed) {
+            let entries = entries.unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].path, repo.canonicalize().unwrap());
+        }
+
+        let err = process::block_on(run_in(&root, &["rev-parse", "HEAD"])).unwrap_err();
+        assert!(matches!(err, ArboristError::GitCommandFailed { .. }));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
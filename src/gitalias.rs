@@ -0,0 +1,141 @@
+use arborist::error::{ArboristError, Result};
+use arborist::git;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+// Name git looks for on PATH to run `git arborist`
+const EXTERNAL_COMMAND: &str = "git-arborist";
+
+/// The command line arborist parses, after taking over what git set up when it ran arborist
+/// As `git arborist` (a git-arborist on PATH), git's own leading `-C <path>` and
+/// `-c <name>=<value>` options are applied and removed, so `git-arborist -C repo -- make` works too.
+/// Run by git, from a shell alias or `git --git-dir`, arborist starts in the repository git found
+/// rather than discovering one from the directory git left it in
+pub fn args() -> Result<Vec<OsString>> {
+    let mut args: Vec<OsString> = env::args_os().collect();
+    if args.is_empty() {
+        return Ok(args);
+    }
+
+    if is_external_command(Path::new(&args[0])) {
+        // Help and errors name the command users know, whichever way it was run
+        args[0] = OsString::from("arborist");
+        let consumed = apply_git_options(&args[1..])?;
+        args.drain(1..1 + consumed);
+    }
+    inherit_repo()?;
+    Ok(args)
+}
+
+fn is_external_command(argv0: &Path) -> bool {
+    argv0
+        .file_stem()
+        .is_some_and(|stem| stem == EXTERNAL_COMMAND)
+}
+
+// Applies the leading -C and -c options; returns: how many arguments they took
+// `-c` only counts as git's when it sets a config key, so `-c <script>` keeps arborist's meaning
+fn apply_git_options(args: &[OsString]) -> Result<usize> {
+    let mut parameters = Vec::new();
+    let mut index = 0;
+    while index < args.len() {
+        let Some(arg) = args[index].to_str() else {
+            break;
+        };
+        let (flag, value, taken) = match arg {
+            "-C" | "-c" => match args.get(index + 1) {
+                Some(value) => (arg, value.clone(), 2),
+                None => break,
+            },
+            _ if arg.starts_with("-C") && arg.len() > 2 => ("-C", OsString::from(&arg[2..]), 1),
+            _ => break,
+        };
+
+        if flag == "-C" {
+            // As with git, an empty path leaves the directory alone and relative ones accumulate
+            if !value.is_empty() {
+                env::set_current_dir(&value).map_err(|err| {
+                    ArboristError::InvalidPath(format!(
+                        "Cannot change to '{}': {}",
+                        Path::new(&value).display(),
+                        err
+                    ))
+                })?;
+            }
+        } else {
+            match value.to_str().filter(|value| is_config_assignment(value)) {
+                Some(assignment) => parameters.push(assignment.to_string()),
+                None => break,
+            }
+        }
+        index += taken;
+    }
+
+    if !parameters.is_empty() {
+        // git passes `git -c` settings to the git commands it runs the same way
+        let mut value = env::var("GIT_CONFIG_PARAMETERS").unwrap_or_default();
+        for parameter in &parameters {
+            if !value.is_empty() {
+                value.push(' ');
+            }
+            value.push_str(&single_quote(parameter));
+        }
+        // SAFETY: arborist has started no other threads yet
+        unsafe { env::set_var("GIT_CONFIG_PARAMETERS", value) };
+    }
+    Ok(index)
+}
+
+// `<section>[.<subsection>].<key>=<value>`, or the key alone, which git sets to true
+fn is_config_assignment(value: &str) -> bool {
+    let key = value.split_once('=').map_or(value, |(key, _)| key);
+    let Some((section, name)) = key.split_once('.') else {
+        return false;
+    };
+    let valid = |part: &str| {
+        part.starts_with(|c: char| c.is_ascii_alphabetic())
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    valid(section) && valid(name.rsplit('.').next().unwrap_or(name))
+}
+
+// Quotes `value` for GIT_CONFIG_PARAMETERS, which git reads like a shell's single quotes
+fn single_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+// A shell alias runs from the top of the working tree with the directory it was invoked from in
+// GIT_PREFIX, and `git --git-dir` points GIT_DIR (and GIT_WORK_TREE) at the repository. arborist
+// moves to where git was invoked and drops the variables, which would otherwise send the git
+// commands run in worktrees to the repository's main checkout
+fn inherit_repo() -> Result<()> {
+    let prefix = env::var_os("GIT_PREFIX").map(PathBuf::from);
+    if env::var_os("GIT_DIR").is_some() {
+        let common_dir = git::get_common_dir()?;
+        // A bare repository is found from inside its git directory
+        let start = git::toplevel()?.unwrap_or_else(|| common_dir.clone());
+        env::set_current_dir(start.join(prefix.unwrap_or_default()))?;
+        // SAFETY: arborist has started no other threads yet
+        unsafe {
+            env::remove_var("GIT_DIR");
+            env::remove_var("GIT_WORK_TREE");
+            env::remove_var("GIT_PREFIX");
+        }
+
+        if git::get_common_dir().ok().as_ref() != Some(&common_dir) {
+            return Err(ArboristError::InvalidPath(format!(
+                "The repository at {} isn't found from its work tree, which arborist needs to \
+                 create worktrees of it",
+                common_dir.display()
+            )));
+        }
+    } else if let Some(prefix) = prefix {
+        if !prefix.as_os_str().is_empty() {
+            env::set_current_dir(&prefix)?;
+        }
+        // SAFETY: arborist has started no other threads yet
+        unsafe { env::remove_var("GIT_PREFIX") };
+    }
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use crate::WorktreePlan;
+use crate::artifacts;
+use arborist::config::HooksMode;
+use arborist::error::Result;
+use arborist::git;
+use std::env;
+use std::path::PathBuf;
+
+// Hooks in the shared git directory already fire in every worktree; only a
+// worktree-relative core.hooksPath (e.g. husky's `.husky/_`) needs help
+
+/// Copies the main checkout's hooks directory into a new worktree (`hooks = "copy"`)
+pub fn copy_hooks(plan: &WorktreePlan) -> Result<()> {
+    if plan.config.hooks != Some(HooksMode::Copy) {
+        return Ok(());
+    }
+    let Some(main_hooks) = main_checkout_hooks(plan)? else {
+        return Ok(());
+    };
+
+    let worktree_hooks = plan.path.join(git::get_hooks_dir(&plan.path)?);
+    if !worktree_hooks.exists() {
+        verbose!("Copying hooks from {}", main_hooks.display());
+        artifacts::clone_tree(&main_hooks, &worktree_hooks)?;
+    }
+    Ok(())
+}
+
+/// Environment pointing the command's git at the main checkout's hooks (`hooks = "share"`)
+pub fn shared_hooks_env(plan: &WorktreePlan) -> Result<Vec<(String, String)>> {
+    if plan.config.hooks != Some(HooksMode::Share) {
+        return Ok(Vec::new());
+    }
+    let Some(main_hooks) = main_checkout_hooks(plan)? else {
+        return Ok(Vec::new());
+    };
+
+    verbose!("Using hooks from {}", main_hooks.display());
+    Ok(config_env(
+        "core.hooksPath",
+        &main_hooks.display().to_string(),
+    ))
+}
+
+/// Worktree-relative hooks directory that was copied in and has no tracked files,
+/// so it shouldn't keep the worktree alive
+pub fn copied_hooks_dir(plan: &WorktreePlan) -> Result<Option<String>> {
+    if plan.config.hooks != Some(HooksMode::Copy) || plan.repo.is_bare {
+        return Ok(None);
+    }
+
+    let hooks = git::get_hooks_dir(&plan.path)?;
+    if hooks.is_absolute() {
+        return Ok(None);
+    }
+
+    let hooks = hooks.to_string_lossy().into_owned();
+    if git::has_tracked_files(&plan.path, &hooks)? {
+        return Ok(None);
+    }
+    Ok(Some(hooks))
+}
+
+// The main checkout's hooks directory, when the worktree would resolve hooks elsewhere
+fn main_checkout_hooks(plan: &WorktreePlan) -> Result<Option<PathBuf>> {
+    if plan.repo.is_bare {
+        return Ok(None);
+    }
+
+    let main_hooks = plan.repo.root.join(git::get_hooks_dir(&plan.repo.root)?);
+    let worktree_hooks = plan.path.join(git::get_hooks_dir(&plan.path)?);
+    if main_hooks == worktree_hooks || !main_hooks.is_dir() {
+        return Ok(None);
+    }
+
+    Ok(Some(main_hooks))
+}
+
+// Environment passing a config override to every git command the wrapped command runs
+// (GIT_CONFIG_COUNT/KEY/VALUE), appended to any overrides already in the environment
+fn config_env(key: &str, value: &str) -> Vec<(String, String)> {
+    let index: usize = env::var("GIT_CONFIG_COUNT")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0);
+
+    vec![
+        (format!("GIT_CONFIG_KEY_{}", index), key.to_string()),
+        (format!("GIT_CONFIG_VALUE_{}", index), value.to_string()),
+        ("GIT_CONFIG_COUNT".to_string(), (index + 1).to_string()),
+    ]
+}
@@ -0,0 +1,97 @@
+use crate::lock;
+use arborist::error::Result;
+use arborist::state;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// How often a running command's heartbeat is renewed
+const INTERVAL: Duration = Duration::from_secs(30);
+
+// Age after which a heartbeat no longer shows its run is alive: several missed beats, so a
+// briefly stalled machine isn't mistaken for a dead run
+const FRESH_FOR_SECS: u64 = 120;
+
+/// Records a command as running in a worktree and keeps renewing its heartbeat in the
+/// worktree's run state until dropped, so cleanup leaves the worktree alone however long the
+/// command takes, including from other machines or containers where its pid means nothing
+pub struct Heartbeat {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Heartbeat {
+    pub fn start(repo_root: &Path, name: &str, run: &state::RunInfo) -> Result<Self> {
+        let mut run = run.clone();
+        run.heartbeat_at = state::unix_now();
+        state::record_run(repo_root, name, &run)?;
+
+        let repo_root = PathBuf::from(repo_root);
+        let name = name.to_string();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(INTERVAL) {
+                run.heartbeat_at = state::unix_now();
+                if let Err(err) = state::record_run(&repo_root, &name, &run) {
+                    verbose!("Warning: failed to renew heartbeat: {}", err);
+                }
+            }
+        });
+
+        Ok(Heartbeat {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Name of the machine (or container) arborist runs on, recorded with its runs
+#[cfg(unix)]
+pub fn host() -> Option<String> {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } != 0 {
+        return None;
+    }
+    let len = name
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(name.len());
+    Some(String::from_utf8_lossy(&name[..len]).into_owned()).filter(|name| !name.is_empty())
+}
+
+#[cfg(not(unix))]
+pub fn host() -> Option<String> {
+    None
+}
+
+/// The commands running in any worktree of the repository whose state is in `state_dir`, by
+/// worktree name
+pub fn active_runs(state_dir: &Path) -> Result<Vec<(String, state::RunInfo)>> {
+    let mut runs = state::list_runs(state_dir)?;
+    runs.retain(|(_, run)| is_alive(run));
+    Ok(runs)
+}
+
+/// The command running in a worktree, if any
+/// A run is alive while its heartbeat is fresh; runs recorded before heartbeats existed
+/// count while their process does
+pub fn active_run(repo_root: &Path, name: &str) -> Result<Option<state::RunInfo>> {
+    Ok(state::load_run(repo_root, name)?.filter(is_alive))
+}
+
+fn is_alive(run: &state::RunInfo) -> bool {
+    if run.heartbeat_at == 0 {
+        return lock::is_alive(run.pid);
+    }
+    state::unix_now().saturating_sub(run.heartbeat_at) <= FRESH_FOR_SECS
+}
@@ -0,0 +1,37 @@
+use crate::{WorktreePlan, style};
+use arborist::git::WorktreeStatus;
+
+/// Suggests what to do next with a kept worktree, tailored to why it was kept
+pub fn print_hints(plan: &WorktreePlan, status: &WorktreeStatus) {
+    let name = &plan.name;
+    let path = plan.path.display();
+    let base = plan.repo.base_name();
+    let hint = style::hint("hint:");
+
+    // Kept only because it is locked, which the user was already told about
+    if !status.has_changes && status.commits_ahead == 0 {
+        return;
+    }
+
+    if status.has_changes {
+        eprintln!(
+            "{} worktree {} kept with uncommitted changes at {}",
+            hint, name, path
+        );
+        eprintln!("{}   review:   arborist diff {}", hint, name);
+        eprintln!("{}   continue: arborist exec {} -- <command>", hint, name);
+        eprintln!("{}   discard:  arborist rm --force {}", hint, name);
+    } else {
+        eprintln!(
+            "{} worktree {} kept with {} commit(s) not on {}",
+            hint, name, status.commits_ahead, base
+        );
+        eprintln!("{}   review:   arborist diff {}", hint, name);
+        eprintln!("{}   merge:    git merge {}", hint, plan.branch_name());
+        eprintln!("{}   remove:   arborist rm {} (once merged)", hint, name);
+    }
+    eprintln!(
+        "{} disable these hints with `hints = false` in arborist config",
+        hint
+    );
+}
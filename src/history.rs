@@ -0,0 +1,75 @@
+use crate::{audit, resolve_nested, style};
+use arborist::error::{ArboristError, Result};
+use arborist::{git, state};
+use std::env;
+use std::time::Duration;
+
+// `arborist history`: the runs recorded in a worktree, oldest first, to piece together what
+// happened in one that several commands (or agents) took turns in. Without a name, those of the
+// worktree the current directory is in, or else of every worktree of the repository
+pub fn run_history(name: Option<&str>, limit: Option<usize>, json: bool) -> Result<i32> {
+    let repo = git::get_repo_info()?.ok_or_else(|| ArboristError::NotARepo {
+        dir: env::current_dir().unwrap_or_default(),
+    })?;
+    let (repo, current) = resolve_nested(repo)?;
+    let name = name.map(str::to_string).or(current);
+
+    let mut records: Vec<state::RunRecord> =
+        state::load_history(&state::repo_state_dir(&repo.root)?)?
+            .into_iter()
+            .filter(|record| name.as_ref().is_none_or(|name| &record.name == name))
+            .collect();
+    if let Some(limit) = limit {
+        records.drain(..records.len().saturating_sub(limit));
+    }
+
+    if json {
+        for record in &records {
+            println!("{}", serde_json::to_string(record)?);
+        }
+    } else if records.is_empty() {
+        match &name {
+            Some(name) => eprintln!("No runs recorded in worktree {}", name),
+            None => eprintln!("No runs recorded in {}", repo.root.display()),
+        }
+    } else {
+        let width = records.iter().map(|record| record.name.len()).max();
+        for record in &records {
+            print_record(record, width.unwrap_or(0));
+        }
+    }
+    Ok(0)
+}
+
+fn print_record(record: &state::RunRecord, width: usize) {
+    // Padded before styling so escape codes don't upset the alignment
+    let exit = format!("{:<8}", format!("exit {}", record.exit_code));
+    println!(
+        "{}  {}  {}  {:>8.2?}  {}",
+        audit::format_timestamp(record.started_at),
+        style::worktree(&record.name, &format!("{:<width$}", record.name)),
+        if record.exit_code == 0 {
+            style::success(&exit)
+        } else {
+            style::failure(&exit)
+        },
+        Duration::from_millis(record.duration_ms),
+        record.command
+    );
+
+    let mut details = vec![format!(
+        "finished {}",
+        audit::format_timestamp(record.finished_at())
+    )];
+    if record.attempts > 1 {
+        details.push(format!("{} attempts", record.attempts));
+    }
+    if let Some(head) = &record.head {
+        details.push(format!("at {}", &head[..head.len().min(10)]));
+    }
+    details.push(if record.kept { "kept" } else { "removed" }.to_string());
+    if let Some(log) = &record.log {
+        details.push(format!("output in {}", log.display()));
+    }
+    println!("  {}", style::dim(&details.join(", ")));
+}
@@ -0,0 +1,85 @@
+use crate::{WorktreePlan, archive, style};
+use arborist::error::{Result, ResultExt};
+use arborist::git;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// Whether the user can be asked questions (stdin and stderr are terminals)
+pub fn is_available() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+/// Asks what to do with a worktree left with uncommitted changes and does it
+/// Choosing keep (or closing stdin) leaves the worktree untouched
+pub fn resolve_changes(plan: &WorktreePlan, excluded: &[String], sign: Option<bool>) -> Result<()> {
+    eprintln!(
+        "{} worktree {} has uncommitted changes",
+        style::info("arborist:"),
+        plan.name
+    );
+    eprintln!("  [k]eep the worktree as it is");
+    eprintln!("  [d]iscard the changes");
+    // Commits in a detached worktree would be lost when it is removed
+    if let Some(branch) = &plan.branch {
+        eprintln!("  [c]ommit them to {}", branch);
+    }
+    // Bare repositories have no checkout to move changes into
+    if !plan.repo.is_bare {
+        eprintln!("  [s]tash them and apply to {}", plan.repo.root.display());
+    }
+
+    loop {
+        let Some(answer) = ask("What now? [k/d/c/s] ")? else {
+            return Ok(());
+        };
+
+        match answer.as_str() {
+            "" | "k" | "keep" => return Ok(()),
+            "d" | "discard" => {
+                if let Some(dir) = archive::archive_worktree(plan)? {
+                    archive::report(&plan.name, &dir);
+                }
+                git::discard_changes(excluded)?;
+                eprintln!("Discarded changes in {}", plan.name);
+                return Ok(());
+            }
+            "c" | "commit" if plan.branch.is_some() => {
+                let default = format!("arborist: changes from {}", plan.name);
+                let message = ask(&format!("Commit message [{}]: ", default))?
+                    .filter(|message| !message.is_empty())
+                    .unwrap_or(default);
+                git::commit_all(
+                    &plan.path,
+                    &message,
+                    sign,
+                    excluded,
+                    &plan.config.identity(),
+                )?;
+                eprintln!("Committed changes to {}", plan.branch_name());
+                return Ok(());
+            }
+            "s" | "stash" if !plan.repo.is_bare => {
+                git::stash_changes(&format!("arborist: {}", plan.name), excluded)?;
+                git::apply_stash(&plan.repo.root).context(format!(
+                    "changes are saved in the stash but did not apply to {}; use `git stash pop` once resolved",
+                    plan.repo.root.display()
+                ))?;
+                eprintln!("Applied changes to {}", plan.repo.root.display());
+                return Ok(());
+            }
+            _ => eprintln!("Please answer k, d, c or s"),
+        }
+    }
+}
+
+// Prompt on stderr and read a trimmed line; None on end of input
+fn ask(prompt: &str) -> Result<Option<String>> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        eprintln!();
+        return Ok(None);
+    }
+    Ok(Some(answer.trim().to_string()))
+}
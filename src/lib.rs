@@ -0,0 +1,19 @@
+//! Git worktree and branch management used by the `arborist` command wrapper
+//!
+//! The binary orchestrates runs; this library exposes the reusable pieces: repository
+//! inspection and worktree operations (`git`), configuration (`config`), per-repo
+//! state (`state`), the ordered teardown of a run in a worktree (`context`), the scheduler
+//! commands and git run on (`process`), and the error type shared by all of them (`error`).
+//!
+//! Git runs on the async scheduler in `process`. Most of the API blocks on it, running each call
+//! to completion on the calling thread; async functions such as `git::run_in` and
+//! `git::list_worktrees_in` let callers run several at once, or race them against a timeout or
+//! a `process::Cancellation`, from a single thread.
+
+pub mod config;
+pub mod context;
+pub mod error;
+pub mod git;
+pub mod process;
+pub mod state;
+pub mod webhook;
@@ -0,0 +1,138 @@
+use crate::steps::{self, StepResult};
+use crate::{Args, Invocation, WorktreePlan, environment, error_exit_code};
+use arborist::error::{ArboristError, Result};
+use arborist::{config, process};
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Directory of the checkout holding the scripts, versioned with the repository
+const HOOKS_DIR: &str = ".arborist/hooks";
+
+/// A point in a worktree's life where the repository's script of the same name runs
+#[derive(Debug, Clone, Copy)]
+pub enum Hook {
+    /// Once the worktree is created and set up, before anything runs in it
+    PostCreate,
+    /// Before each run's command; failing stops the run
+    PreRun,
+    /// After each run's command and its steps, with their exit code in ARBORIST_EXIT_CODE
+    PostRun,
+    /// Before the worktree is removed; failing keeps it
+    PreRemove,
+}
+
+impl Hook {
+    fn name(self) -> &'static str {
+        match self {
+            Hook::PostCreate => "post-create",
+            Hook::PreRun => "pre-run",
+            Hook::PostRun => "post-run",
+            Hook::PreRemove => "pre-remove",
+        }
+    }
+}
+
+/// Runs the checkout's .arborist/hooks script for `hook`, if it has one, in the worktree with
+/// the ARBORIST_* variables its commands get. Its output goes to stderr, leaving stdout to
+/// arborist's own, e.g. the path `arborist path` prints
+/// Returns: Err when the script exited non-zero
+pub fn run(plan: &WorktreePlan, hook: Hook, vars: &[(&str, String)]) -> Result<()> {
+    process::block_on(run_hook(plan, hook, vars))
+}
+
+async fn run_hook(plan: &WorktreePlan, hook: Hook, vars: &[(&str, String)]) -> Result<()> {
+    let Some(script) = script(plan, hook) else {
+        return Ok(());
+    };
+    if !is_executable(&script) {
+        warning!(
+            "{} isn't executable, so the {} hook was skipped",
+            script.display(),
+            hook.name()
+        );
+        return Ok(());
+    }
+
+    verbose!("Running the {} hook {}", hook.name(), script.display());
+    let mut command = process::Command::new(&script);
+    command
+        .current_dir(&plan.path)
+        .env("ARBORIST_HOOK", hook.name())
+        .envs(environment::worktree_vars(plan))
+        .envs(vars.iter().map(|(key, value)| (key, value)))
+        .stdout(io::stderr());
+
+    let status = command.status().await?;
+    if !status.success() {
+        return Err(ArboristError::HookFailed {
+            hook: hook.name().to_string(),
+            exit_code: process::exit_code(&status),
+        });
+    }
+    Ok(())
+}
+
+/// Runs the command and its steps between the pre-run and post-run hooks of each worktree they
+/// run in, several for a workspace. A failing post-run hook is only a warning; the run's outcome
+/// is the command's
+pub async fn run_steps(
+    plans: &[&WorktreePlan],
+    invocation: &Invocation,
+    args: &Args,
+) -> Result<Vec<StepResult>> {
+    for plan in plans {
+        run_hook(plan, Hook::PreRun, &[]).await?;
+    }
+    let results = steps::run_steps(invocation, args).await;
+
+    let exit_code = match &results {
+        Ok(results) => steps::exit_code(results),
+        Err(_) => error_exit_code(),
+    };
+    for plan in plans {
+        let vars = [("ARBORIST_EXIT_CODE", exit_code.to_string())];
+        if let Err(err) = run_hook(plan, Hook::PostRun, &vars).await {
+            warning!("{}", err.chain());
+        }
+    }
+    results
+}
+
+// Scripts are read from the main checkout, so a worktree created from an older commit still gets
+// the current ones; bare repositories have no checkout to read them from. They only run in
+// repositories the user listed in trusted_repos
+fn script(plan: &WorktreePlan, hook: Hook) -> Option<PathBuf> {
+    if plan.repo.is_bare {
+        return None;
+    }
+    let script = plan.repo.root.join(HOOKS_DIR).join(hook.name());
+    if !script.is_file() {
+        return None;
+    }
+    let trusted = plan
+        .config
+        .trusted_repos
+        .as_deref()
+        .is_some_and(|dirs| config::lists_repo(dirs, &plan.repo.root));
+    if !trusted {
+        verbose!(
+            "Skipping the {} hook {}: the repository isn't in trusted_repos",
+            hook.name(),
+            script.display()
+        );
+        return None;
+    }
+    Some(script)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
@@ -0,0 +1,198 @@
+use crate::Args;
+use arborist::error::{ArboristError, Result};
+use duct::cmd;
+use std::env;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Resource limits for the command from --memory-limit, --cpu-limit and --nproc-limit
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    // Bytes of memory
+    pub memory: Option<u64>,
+    // CPU time, in whole seconds
+    pub cpu: Option<u64>,
+    // Processes
+    pub nproc: Option<u64>,
+}
+
+impl Limits {
+    pub fn from_args(args: &Args) -> Self {
+        Limits {
+            memory: args.memory_limit,
+            cpu: args.cpu_limit.map(|cpu| cpu.as_secs().max(1)),
+            nproc: args.nproc_limit,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_none() && self.cpu.is_none() && self.nproc.is_none()
+    }
+
+    /// Argv running `command` under these limits on the host
+    /// Memory and process limits go in a systemd scope's cgroup where cgroup v2 and a user
+    /// systemd instance are available, since they then cover the command's whole process tree;
+    /// otherwise they, and always the CPU time limit, are set with setrlimit by re-running
+    /// arborist as `arborist __limits ... -- command`, which execs the command
+    pub fn wrap(&self, command: Vec<String>) -> Vec<String> {
+        if self.is_empty() {
+            return command;
+        }
+
+        let scoped = (self.memory.is_some() || self.nproc.is_some()) && cgroup_scope_available();
+        let mut rlimits = self.clone();
+        let mut argv = Vec::new();
+        if scoped {
+            argv.extend(["systemd-run", "--user", "--scope", "--quiet"].map(String::from));
+            if let Some(memory) = rlimits.memory.take() {
+                argv.push(format!("--property=MemoryMax={}", memory));
+                argv.push("--property=MemorySwapMax=0".to_string());
+            }
+            if let Some(nproc) = rlimits.nproc.take() {
+                argv.push(format!("--property=TasksMax={}", nproc));
+            }
+            argv.push("--".to_string());
+        }
+
+        if !rlimits.is_empty() {
+            let exe = env::current_exe()
+                .map(|exe| exe.display().to_string())
+                .unwrap_or_else(|_| "arborist".to_string());
+            argv.push(exe);
+            argv.push("__limits".to_string());
+            if let Some(memory) = rlimits.memory {
+                argv.push(format!("--memory-limit={}", memory));
+            }
+            if let Some(cpu) = rlimits.cpu {
+                argv.push(format!("--cpu-limit={}s", cpu));
+            }
+            if let Some(nproc) = rlimits.nproc {
+                argv.push(format!("--nproc-limit={}", nproc));
+            }
+            argv.push("--".to_string());
+        }
+
+        argv.extend(command);
+        argv
+    }
+
+    /// Flags giving a podman or docker container these limits
+    pub fn container_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        if let Some(memory) = self.memory {
+            flags.push(format!("--memory={}", memory));
+        }
+        if let Some(cpu) = self.cpu {
+            flags.push(format!("--ulimit=cpu={}:{}", cpu, cpu));
+        }
+        if let Some(nproc) = self.nproc {
+            flags.push(format!("--pids-limit={}", nproc));
+        }
+        flags
+    }
+
+    /// The limit an exit code shows the command ran into, if any
+    /// Running out of processes only makes fork fail, which the command reports as it likes
+    pub fn violation(&self, exit_code: i32) -> Option<&'static str> {
+        let signal = exit_code - 128;
+        if self.cpu.is_some() && signal == libc::SIGXCPU {
+            Some("CPU time limit")
+        } else if self.memory.is_some() && (signal == libc::SIGKILL || signal == libc::SIGABRT) {
+            Some("memory limit")
+        } else {
+            None
+        }
+    }
+}
+
+// Parse sizes like "512M", "2G", "1.5g", "4096" (bytes)
+pub fn parse_size(value: &str) -> std::result::Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", value))?;
+    let multiplier: u64 = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("invalid size unit '{}' in '{}'", unit, value)),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+// `arborist __limits`: set the limits on this process, then become the command
+pub fn run_limited(args: &Args, command: &[String]) -> Result<i32> {
+    let limits = Limits::from_args(args);
+    let Some((program, program_args)) = command.split_first() else {
+        return Ok(0);
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        // The hard CPU limit is a second later, so the command gets SIGXCPU rather than SIGKILL
+        let rlimits = [
+            (libc::RLIMIT_AS, limits.memory, 0),
+            (libc::RLIMIT_CPU, limits.cpu, 1),
+            (libc::RLIMIT_NPROC, limits.nproc, 0),
+        ];
+        for (resource, value, slack) in rlimits {
+            let Some(value) = value else {
+                continue;
+            };
+            let limit = libc::rlimit {
+                rlim_cur: value as libc::rlim_t,
+                rlim_max: value.saturating_add(slack) as libc::rlim_t,
+            };
+            // SAFETY: setrlimit only reads the struct, which outlives the call
+            if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        let err = std::process::Command::new(program)
+            .args(program_args)
+            .exec();
+        Err(ArboristError::InvalidPath(format!(
+            "cannot run {}: {}",
+            program, err
+        )))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (limits, program, program_args);
+        Err(ArboristError::InvalidConfig(
+            "resource limits are not supported on this platform".to_string(),
+        ))
+    }
+}
+
+// Whether commands can be put in a transient systemd scope with cgroup v2 limits
+// Probed once, by starting a scope for `true`
+fn cgroup_scope_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        if !Path::new("/sys/fs/cgroup/cgroup.controllers").exists() {
+            return false;
+        }
+        let available = cmd!("systemd-run", "--user", "--scope", "--quiet", "true")
+            .stdout_null()
+            .stderr_null()
+            .unchecked()
+            .run()
+            .is_ok_and(|output| output.status.success());
+        if !available {
+            verbose!("No user systemd instance for cgroup limits; using setrlimit");
+        }
+        available
+    })
+}
@@ -0,0 +1,118 @@
+use crate::{DirectoryGuard, heartbeat, pick};
+use arborist::error::Result;
+use arborist::git;
+use duct::cmd;
+use std::path::{Path, PathBuf};
+
+// Reason given to the lock on a worktree a command is running in, followed by arborist's pid
+const RUN_LOCK_REASON: &str = "arborist run in process";
+
+// Reason given to the lock on a worktree parked in the pool, waiting for a run
+const PARKED_REASON: &str = "arborist: parked in the pool";
+
+/// A `git worktree lock` on the worktree a command is running in, so that neither git, nor
+/// other arborist processes cleaning up after themselves, nor `destroy-all` remove it from
+/// under the command; released when dropped
+/// A worktree that is already locked, by the user or a concurrent run, is left as it is
+/// Git is asked from inside the worktree, so it works whatever the current directory is
+pub struct RunLock {
+    path: Option<PathBuf>,
+}
+
+impl RunLock {
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let _guard = DirectoryGuard::with_path(path)?;
+        let locked = git::list_worktrees()?
+            .iter()
+            .any(|entry| entry.path == path && entry.locked && !is_stale(entry));
+        if locked {
+            return Ok(RunLock { path: None });
+        }
+
+        let reason = format!("{} {}", RUN_LOCK_REASON, std::process::id());
+        git::lock_worktree(path, &reason)?;
+        Ok(RunLock {
+            path: Some(path.to_path_buf()),
+        })
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let _guard = DirectoryGuard::with_path(path).ok();
+        if let Err(err) = git::unlock_worktree(path) {
+            warning!("failed to unlock worktree {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Locks a worktree parked in the pool, so nothing but a run claiming it removes it
+pub fn park(path: &Path) -> Result<()> {
+    git::lock_worktree(path, PARKED_REASON)
+}
+
+/// Whether a worktree is locked because it is parked in the pool
+pub fn is_parked(entry: &git::WorktreeEntry) -> bool {
+    entry.lock_reason.as_deref() == Some(PARKED_REASON)
+}
+
+/// Process id of the arborist run that locked a worktree, while that run is still going
+pub fn active_run(entry: &git::WorktreeEntry) -> Option<u32> {
+    run_pid(entry).filter(|pid| is_alive(*pid))
+}
+
+/// Unlocks worktrees whose lock was left behind by an arborist run that died (e.g. killed
+/// before it could clean up), updating the entries to match
+/// A lock whose process can't be seen but whose run still has a fresh heartbeat (e.g. one
+/// running in another pid namespace) is kept
+pub fn release_stale(repo: &git::GitRepo, entries: &mut [git::WorktreeEntry]) -> Result<()> {
+    for entry in entries.iter_mut().filter(|entry| is_stale(entry)) {
+        if let Some(name) = pick::worktree_name(repo, entry)?
+            && heartbeat::active_run(&repo.root, &name)?.is_some()
+        {
+            continue;
+        }
+        verbose!(
+            "Unlocking {}, locked by a run that no longer exists",
+            entry.path.display()
+        );
+        git::unlock_worktree(&entry.path)?;
+        entry.locked = false;
+        entry.lock_reason = None;
+    }
+    Ok(())
+}
+
+// Whether the worktree is locked by an arborist run that has since died
+fn is_stale(entry: &git::WorktreeEntry) -> bool {
+    entry.locked && run_pid(entry).is_some_and(|pid| !is_alive(pid))
+}
+
+fn run_pid(entry: &git::WorktreeEntry) -> Option<u32> {
+    entry
+        .lock_reason
+        .as_deref()?
+        .strip_prefix(RUN_LOCK_REASON)?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Whether a process is still running
+#[cfg(unix)]
+pub fn is_alive(pid: u32) -> bool {
+    cmd!("kill", "-0", pid.to_string())
+        .stdout_null()
+        .stderr_null()
+        .unchecked()
+        .run()
+        .is_ok_and(|output| output.status.success())
+}
+
+#[cfg(not(unix))]
+pub fn is_alive(_pid: u32) -> bool {
+    true
+}
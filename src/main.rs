@@ -1,26 +1,133 @@
-mod error;
-mod git;
-
+use arborist::context::RunContext;
+use arborist::error::{ArboristError, Result, ResultExt};
+use arborist::{config, git, process, state};
 use clap::Parser;
-use duct::cmd;
-use error::Result;
 use rand::prelude::*;
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::env;
-use std::path::{Path, PathBuf};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 // Global verbose flag
 static VERBOSE: AtomicBool = AtomicBool::new(false);
 
+// Global quiet flag; silences everything arborist prints except errors
+static QUIET: AtomicBool = AtomicBool::new(false);
+
 // Macro for verbose logging
 macro_rules! verbose {
     ($($arg:tt)*) => {
-        if VERBOSE.load(Ordering::Relaxed) {
+        if $crate::VERBOSE.load(::std::sync::atomic::Ordering::Relaxed) {
+            eprintln!("{}", $crate::style::dim(&format!($($arg)*)));
+        }
+    };
+}
+
+// Warning printed to stderr with a styled label
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if !$crate::QUIET.load(::std::sync::atomic::Ordering::Relaxed) {
+            eprintln!("{} {}", $crate::style::warning("Warning:"), format!($($arg)*));
+        }
+    };
+}
+
+// Arborist's own progress and summary output on stderr, silenced by --quiet
+macro_rules! notice {
+    ($($arg:tt)*) => {
+        if !$crate::QUIET.load(::std::sync::atomic::Ordering::Relaxed) {
             eprintln!($($arg)*);
         }
     };
 }
 
+mod adopt;
+mod announce;
+mod archive;
+mod artifacts;
+mod audit;
+mod bench;
+mod branches;
+mod cancel;
+mod container;
+mod daemon;
+mod destroy;
+mod diff;
+mod dry_run;
+mod editor;
+mod environment;
+mod events;
+mod export;
+mod gitalias;
+mod githooks;
+mod heartbeat;
+mod hints;
+mod history;
+mod interactive;
+mod lifecycle;
+mod limits;
+mod lock;
+mod maintenance;
+mod migrate;
+mod nix;
+mod notify;
+mod pick;
+mod plugins;
+mod pool;
+mod progress;
+mod prompt;
+mod prune;
+mod ps;
+mod pty;
+mod query;
+mod repair;
+mod stats;
+mod steps;
+mod style;
+mod summary;
+mod sync;
+mod tail;
+mod templates;
+mod timings;
+mod tmux;
+mod tools;
+mod ui;
+mod watch;
+mod workspace;
+
+/// How a run picks the worktree it uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Selection {
+    /// A color derived from the parent process, so each shell keeps its own worktree
+    Parent,
+    /// A random color
+    Random,
+    /// A name derived from the command line, so each command keeps its own worktree
+    CmdHash,
+    /// A new worktree named after the words of the command, e.g. cargo-test
+    Slug,
+}
+
+/// What a run started inside one of arborist's worktrees does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+enum Nested {
+    /// Create a worktree of the main repository, based on the current worktree's commit
+    #[default]
+    Main,
+    /// Run in the current worktree instead of creating another
+    Reuse,
+}
+
+// Longest name --select slug gives a worktree, before any numeric suffix
+const SLUG_MAX_LEN: usize = 40;
+
 // Color palette for random selection
 const COLORS: &[&str] = &[
     "red",
@@ -52,23 +159,713 @@ const COLORS: &[&str] = &[
     "topaz",
 ];
 
+// Exit code when arborist itself fails (as git bisect run uses 125 for "can't test")
+const DEFAULT_ERROR_EXIT_CODE: i32 = 125;
+
+// Exit codes, listed at the end of --help
+const EXIT_CODES_HELP: &str = "Exit status:
+  N        The command's exit status (the first failing step's with --then)
+  128+N    The command was killed by signal N
+  130      Arborist was interrupted and killed the command after a grace period
+  125      Arborist itself failed, e.g. a git error (change with error_exit_code in the user config)
+  2        Invalid command-line arguments";
+
 // CLI argument structure
 #[derive(Parser, Debug)]
 #[command(name = "arborist")]
 #[command(about = "Automatically manage git worktrees and branches for command execution")]
 #[command(version)]
+#[command(subcommand_negates_reqs = true)]
+#[command(after_help = EXIT_CODES_HELP)]
+#[command(
+    subcommand_value_name = "SUBCOMMAND",
+    subcommand_help_heading = "Subcommands"
+)]
+#[command(
+    override_usage = "arborist [OPTIONS] [--] <COMMAND>...\n       arborist [OPTIONS] -c <SCRIPT>\n       arborist [OPTIONS] <SUBCOMMAND>"
+)]
 struct Args {
     /// Enable verbose output
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     verbose: bool,
 
+    /// Don't style output (also disabled when stderr isn't a terminal or NO_COLOR is set)
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Print nothing but errors, e.g. the summary, hints, warnings and the first-run report
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Write the command's output to a log file in arborist's state directory instead of the terminal
+    #[arg(long, global = true)]
+    capture: bool,
+
+    /// Print how long each phase took: repo detection, worktree creation, setup, the command,
+    /// the status check and cleanup
+    #[arg(long, global = true)]
+    timings: bool,
+
+    /// Print the worktree that would be used and the git commands that would run, then exit
+    #[arg(long, conflicts_with_all = ["tmux", "branches"])]
+    dry_run: bool,
+
+    /// Run the command in place, skipping the worktree and all git work (or set ARBORIST_DISABLE=1)
+    #[arg(long, conflicts_with_all = ["tmux", "branches"])]
+    no_worktree: bool,
+
     /// Use random color selection instead of deterministic
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     random: bool,
 
+    /// Use the worktree with this name, creating it (on branch arborist/NAME) if needed,
+    /// instead of choosing one
+    #[arg(long, value_parser = parse_worktree_name, conflicts_with_all = ["random", "select", "branches"])]
+    name: Option<String>,
+
+    /// How the worktree is chosen: parent (one per shell, the default), random, or cmd-hash
+    /// (one per command line, whichever shell runs it)
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "HOW",
+        conflicts_with = "random"
+    )]
+    select: Option<Selection>,
+
+    /// What a run inside one of arborist's worktrees does: main (create a worktree of the main
+    /// repository from the current commit, the default) or reuse (run in the current worktree)
+    #[arg(long, value_enum, value_name = "HOW", conflicts_with_all = ["name", "branches"])]
+    nested: Option<Nested>,
+
+    /// Skip the first-run report for this repository
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    /// Install Git LFS in new worktrees and pull LFS objects
+    #[arg(long, global = true)]
+    lfs: bool,
+
+    /// Check out only these directories (cone-mode sparse-checkout); repeatable or comma-separated
+    #[arg(long, global = true, value_name = "PATHSPEC", value_delimiter = ',')]
+    sparse: Vec<String>,
+
+    /// Post run start/finish messages to the configured announce_url
+    #[arg(long, global = true)]
+    announce: bool,
+
+    /// Notify when the command finishes: POST to the configured notify_url, or a desktop notification
+    #[arg(long, global = true)]
+    notify: bool,
+
+    /// Re-run the command up to N more times when it fails
+    #[arg(long, global = true, value_name = "N", default_value_t = 0)]
+    retries: u32,
+
+    /// Delay between retries (e.g. 500ms, 5s, 1m)
+    #[arg(long, global = true, value_name = "D", value_parser = parse_duration, default_value = "0s")]
+    retry_delay: Duration,
+
+    /// How untracked files count as changes when deciding whether to keep the worktree and in
+    /// the summary: not at all (any still keep the worktree), a new directory once (normal), or
+    /// every file (all)
+    #[arg(long, global = true, value_enum, value_name = "MODE")]
+    untracked: Option<git::UntrackedFiles>,
+
+    /// Only remove a worktree once every process is out of it: arborist always changes back to
+    /// the directory it started in first, and with this a worktree that another process (e.g.
+    /// one the command left running) is still in is kept. Linux only
+    #[arg(long, global = true)]
+    chdir_original: bool,
+
+    /// Keep the last N lines of the command's output for failure reports: the command_finished
+    /// event and the --notify payload. The output still streams as usual
+    #[arg(long, global = true, value_name = "N")]
+    capture_tail: Option<usize>,
+
+    /// Run the command attached to a pseudo-terminal, for tools that behave differently without one
+    #[arg(long, global = true, conflicts_with = "capture")]
+    pty: bool,
+
+    /// Limit the command's memory (e.g. 512M, 4G); a cgroup limit where available, else RLIMIT_AS
+    #[arg(long, global = true, value_name = "SIZE", value_parser = limits::parse_size)]
+    memory_limit: Option<u64>,
+
+    /// Limit the CPU time the command may use (e.g. 90s, 10m)
+    #[arg(long, global = true, value_name = "D", value_parser = parse_duration)]
+    cpu_limit: Option<Duration>,
+
+    /// Limit the number of processes (a cgroup limit where available, else RLIMIT_NPROC)
+    #[arg(long, global = true, value_name = "N")]
+    nproc_limit: Option<u64>,
+
+    /// Only retry on these exit codes (default: any non-zero exit)
+    #[arg(long, global = true, value_name = "CODES", value_delimiter = ',')]
+    retry_on: Vec<i32>,
+
+    /// Shell command to run after the main command succeeds; repeatable
+    #[arg(long, global = true, value_name = "CMD")]
+    then: Vec<String>,
+
+    /// Keep running --then steps after a step fails
+    #[arg(long, global = true)]
+    keep_going: bool,
+
+    /// Set an environment variable for the command; repeatable
+    #[arg(long, global = true, value_name = "KEY=VAL", value_parser = environment::parse_assignment)]
+    env: Vec<(String, String)>,
+
+    /// Load environment variables from a dotenv-style file; repeatable
+    #[arg(long, global = true, value_name = "PATH")]
+    env_file: Vec<PathBuf>,
+
+    /// Start the command from a minimal environment (PATH, HOME, USER, TERM, locale, ...)
+    #[arg(long, global = true)]
+    clean_env: bool,
+
+    /// Run the command in the worktree's Nix environment (flake.nix, shell.nix or default.nix)
+    #[arg(long, global = true)]
+    nix: bool,
+
+    /// Run the command inside a container of this image with the worktree mounted
+    #[arg(long, global = true, value_name = "IMAGE")]
+    container: Option<String>,
+
+    /// Ask what to do with uncommitted changes (keep, discard, commit, stash to your checkout)
+    #[arg(short, long, global = true)]
+    interactive: bool,
+
+    /// Sign commits arborist creates, overriding commit.gpgsign
+    #[arg(long, global = true, overrides_with = "no_sign")]
+    sign: bool,
+
+    /// Don't sign commits arborist creates, overriding commit.gpgsign
+    #[arg(long, global = true, overrides_with = "sign")]
+    no_sign: bool,
+
+    /// Append lifecycle events to this file as JSON lines
+    #[arg(long, global = true, value_name = "PATH", conflicts_with = "events_fd")]
+    events_file: Option<PathBuf>,
+
+    /// Write lifecycle events as JSON lines to this open file descriptor
+    #[arg(long, global = true, value_name = "FD")]
+    events_fd: Option<u32>,
+
+    /// Open the worktree in your editor (see `editor` in the config) while the command runs
+    #[arg(long, global = true)]
+    open: bool,
+
+    /// Run the command in a worktree at the tip of each matching branch (globs or a comma-separated list)
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        value_delimiter = ',',
+        conflicts_with_all = ["tmux", "branch", "from_remote", "update"]
+    )]
+    branches: Vec<String>,
+
+    /// With --branches, run in every branch's worktree at the same time
+    #[arg(long, requires = "branches")]
+    parallel: bool,
+
+    /// Check out a new worktree's commit without creating a branch, for read-only runs
+    #[arg(long, global = true)]
+    detach: bool,
+
+    /// Check out this existing branch (or a remote's branch of that name) in a new worktree
+    /// instead of creating one; it is never deleted
+    #[arg(long, global = true, value_name = "BRANCH", conflicts_with = "detach")]
+    branch: Option<String>,
+
+    /// Fetch this remote branch (e.g. origin/feature-x) and base a new worktree on it, tracking it
+    #[arg(
+        long,
+        global = true,
+        value_name = "REMOTE/BRANCH",
+        conflicts_with_all = ["branch", "detach"]
+    )]
+    from_remote: Option<String>,
+
+    /// Fetch the current branch's upstream and base a new worktree on its tip instead of HEAD
+    #[arg(long, global = true, conflicts_with_all = ["branch", "from_remote"])]
+    update: bool,
+
+    /// Branch the new worktree branch tracks, e.g. origin/main, instead of the branch it was
+    /// created from; "none" leaves it tracking nothing
+    #[arg(long, global = true, value_name = "REF", conflicts_with_all = ["branch", "detach"])]
+    upstream: Option<String>,
+
+    /// Run the command in this directory, relative to the worktree root
+    #[arg(long, global = true, value_name = "PATH")]
+    subdir: Option<PathBuf>,
+
+    /// Open the worktree in a new tmux window or pane and run the command (default: a shell) there
+    #[arg(long, value_name = "WHERE")]
+    tmux: Option<tmux::Target>,
+
+    /// With --tmux, close the window or pane when the command exits successfully
+    #[arg(long, requires = "tmux")]
+    tmux_close: bool,
+
+    #[command(subcommand)]
+    subcommand: Option<Subcommand>,
+
+    /// Run this string through the user's shell ($SHELL -c, or cmd /C on Windows)
+    #[arg(short = 'c', value_name = "SCRIPT", conflicts_with = "command")]
+    shell_command: Option<String>,
+
     /// Command and arguments to execute
-    #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        required_unless_present_any = ["shell_command", "tmux"]
+    )]
+    command: Vec<String>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Subcommand {
+    /// Create a worktree and print its path and branch without running anything
+    Create {
+        /// Print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Create (or reuse) a worktree and print only its path, e.g. for `cd "$(arborist path)"`
+    Path {
+        /// Name (color) of the worktree to reuse or create
+        name: Option<String>,
+
+        /// Write the path to this open file descriptor instead of stdout
+        #[arg(long, value_name = "FD")]
+        fd: Option<u32>,
+    },
+
+    /// Show what a worktree changed since it was created, committed or not
+    Diff {
+        /// Name (color) of the worktree
+        name: String,
+
+        /// Show a diffstat
+        #[arg(long)]
+        stat: bool,
+
+        /// Show the patch (the default; combine with --stat for both)
+        #[arg(long)]
+        patch: bool,
+    },
+
+    /// Write a worktree's commits to a git bundle and its uncommitted changes to a patch next to
+    /// it, e.g. to move them to another machine before removing the worktree
+    Export {
+        /// Name (color) of the worktree
+        name: String,
+
+        /// File to write the commits to; uncommitted changes go to <name>.changes.patch beside it
+        #[arg(long, short, value_name = "PATH")]
+        out: PathBuf,
+
+        /// Write the commits as a series of patches for `git am` instead of a bundle
+        #[arg(long)]
+        patches: bool,
+    },
+
+    /// Remove every arborist worktree, branch, and state record
+    DestroyAll {
+        /// Only destroy worktrees of the current repository (default)
+        #[arg(long, conflicts_with = "everywhere")]
+        repo: bool,
+
+        /// Destroy worktrees of every repository arborist has been used in
+        #[arg(long)]
+        everywhere: bool,
+
+        /// Skip the confirmation prompt
+        #[arg(long)]
+        force: bool,
+
+        /// Also remove locked worktrees
+        #[arg(long)]
+        include_pinned: bool,
+
+        /// Save the work being removed as patches and keep the archive, rather than removing it
+        #[arg(long)]
+        keep_archives: bool,
+    },
+
+    /// Run a tool from the repository's .arborist.toml manifest in a new worktree
+    Run {
+        /// Tool name; lists the available tools when omitted
+        tool: Option<String>,
+
+        /// Extra arguments appended to the tool's command
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Remove a kept arborist worktree and its branch
+    Rm {
+        /// Name (color) of the worktree
+        name: String,
+
+        /// Remove even if it has uncommitted changes or unmerged commits
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Choose an existing arborist worktree from a list and run a command (default: a shell) in it
+    Pick {
+        /// Command and arguments to execute
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+
+    /// Summarize recorded runs: durations, setup times, reuse and cleanup outcomes
+    Stats {
+        /// Report every repository arborist has been used in
+        #[arg(long)]
+        everywhere: bool,
+
+        /// Print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show the runs recorded in a worktree: command, start and end, exit code
+    History {
+        /// Name (color) of the worktree (default: the one the current directory is in, else every
+        /// worktree of the repository)
+        name: Option<String>,
+
+        /// Only the N most recent runs
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+
+        /// Print the runs as JSON, one per line
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List the arborist runs active right now across all repositories
+    Ps {
+        /// Print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Stop an active run, by its id from `arborist ps` or its worktree's name; its worktree is
+    /// then cleaned up as usual
+    Kill {
+        /// Id (process id) of the run, or the name of the worktree it runs in
+        id: String,
+    },
+
+    /// Show the audit log of arborist invocations, optionally filtered
+    Log {
+        /// Only invocations in this repository, or in repositories under this directory
+        #[arg(long, value_name = "PATH")]
+        repo: Option<PathBuf>,
+
+        /// Only invocations that ran in or removed the worktree with this name
+        #[arg(long, value_name = "NAME")]
+        worktree: Option<String>,
+
+        /// Only invocations by this user
+        #[arg(long)]
+        user: Option<String>,
+
+        /// Only invocations at most this long ago (e.g. 30m, 7d)
+        #[arg(long, value_name = "D", value_parser = parse_duration)]
+        since: Option<Duration>,
+
+        /// Only invocations that exited non-zero
+        #[arg(long)]
+        failed: bool,
+
+        /// Only the N most recent matching invocations
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+
+        /// Print the matching records as JSON, one per line
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print a shell prompt segment when inside an arborist worktree (exit 1 otherwise)
+    Prompt {
+        /// Template overriding prompt_template; {name}, {branch} and {path} are substituted
+        #[arg(long, value_name = "TEMPLATE")]
+        format: Option<String>,
+    },
+
+    /// Print one fact about the repository or a worktree, for scripts and editor plugins
+    Query {
+        /// The fact to print
+        #[arg(value_enum)]
+        key: query::Key,
+
+        /// Name (color) of the worktree (default: the one the current directory is in)
+        name: Option<String>,
+    },
+
+    /// Full-screen dashboard of the worktrees of every repository
+    Ui,
+
+    /// Re-run a command in a dedicated worktree whenever files in your checkout change
+    Watch {
+        /// Command and arguments to execute
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+
+    /// Run a command once across worktrees of every repository listed in arborist-workspace.toml
+    #[command(alias = "workspace")]
+    Ws {
+        /// Command and arguments to execute
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+
+    /// Bring a worktree created without arborist under its management
+    Adopt {
+        /// Path of the worktree
+        path: PathBuf,
+
+        /// Name to manage it under (default: its directory's name)
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Rename its branch to arborist/{name}, so it is deleted along with the worktree
+        #[arg(long)]
+        rename_branch: bool,
+    },
+
+    /// Recreate the worktree whose work was last discarded and saved to the archive
+    Undo,
+
+    /// Re-link worktrees after the repository moved or was restored, and fix their metadata
+    Repair,
+
+    /// Create clean worktrees ahead of time and park them for later runs to claim
+    Warmup {
+        /// Worktrees the pool should hold (default: pool_size from the config, or 1)
+        #[arg(long)]
+        count: Option<usize>,
+    },
+
+    /// Keep worktree pools warm and expire, cap and prune worktrees in the background
+    Daemon {
+        /// How often every known repository is maintained (e.g. 5m, 1h)
+        #[arg(long, value_name = "D", value_parser = parse_duration, default_value = "5m")]
+        interval: Duration,
+
+        /// Report whether the daemon is running
+        #[arg(long, conflicts_with = "stop")]
+        status: bool,
+
+        /// Stop the running daemon
+        #[arg(long)]
+        stop: bool,
+    },
+
+    /// Move state and caches kept under /tmp/arborist by earlier versions to the state and cache directories
+    Migrate,
+
+    /// Set the resource limits on this process and exec the command (used internally)
+    #[command(name = "__limits", hide = true)]
+    Limits {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+
+    /// Rebase a kept worktree's branch onto the current tip of the branch it was created from
+    Sync {
+        /// Name (color) of the worktree
+        name: String,
+
+        /// Merge the tip into the worktree's branch instead of rebasing onto it
+        #[arg(long)]
+        merge: bool,
+
+        /// On a conflict, leave the rebase or merge in progress to be resolved in the worktree
+        /// instead of aborting it
+        #[arg(long)]
+        continue_on_conflict: bool,
+
+        /// Use the remote branch as last fetched instead of fetching it
+        #[arg(long)]
+        no_fetch: bool,
+    },
+
+    /// Run a command in worktrees at two refs and compare exit codes and durations
+    Bench {
+        /// Ref the comparison starts from, e.g. main
+        #[arg(long, value_name = "REF")]
+        base: String,
+
+        /// Ref compared against it
+        #[arg(long, value_name = "REF", default_value = "HEAD")]
+        head: String,
+
+        /// Run in both worktrees at the same time
+        #[arg(long)]
+        parallel: bool,
+
+        /// Capture each run's output and show how head's differs from base's
+        #[arg(long)]
+        diff_output: bool,
+
+        /// Command and arguments to execute
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+
+    /// Run a command in an existing arborist worktree
+    Exec {
+        /// Name (color) of the worktree
+        name: String,
+
+        /// Command and arguments to execute
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+}
+
+// Resolved location and branch of the worktree for a run
+#[derive(Debug, Clone)]
+struct WorktreePlan {
+    repo: git::GitRepo,
+    config: config::Config,
+    name: String,
+    path: PathBuf,
+    // The branch checked out in the worktree; None when it is detached
+    branch: Option<String>,
+    // Whether arborist created the branch, and so deletes it with the worktree
+    owns_branch: bool,
+}
+
+impl Args {
+    // Explicit --sign/--no-sign choice; None defers to commit.gpgsign
+    fn signing(&self) -> Option<bool> {
+        if self.sign {
+            Some(true)
+        } else if self.no_sign {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+// What to execute inside the worktree
+#[derive(Debug, Clone, Default)]
+struct Invocation {
     command: Vec<String>,
+    // Applied in order, so later entries win
+    env: Vec<(String, String)>,
+    clean_env: bool,
+    // Enter this Nix environment first
+    nix: Option<nix::NixEnv>,
+    // Load the direnv environment of this directory
+    direnv: Option<PathBuf>,
+    // Run inside this container instead of on the host
+    container: Option<container::Container>,
+    // Resource limits applied to the command
+    limits: limits::Limits,
+    // Start in this directory instead of the current one
+    dir: Option<PathBuf>,
+    // Prefix every line of output with this label, merging stderr into stdout
+    label: Option<String>,
+    // Append stdout and stderr to this file instead
+    capture: Option<PathBuf>,
+    // Remember the last lines of output here
+    tail: Option<tail::OutputTail>,
+}
+
+impl Invocation {
+    fn from_argv(command: &[String]) -> Self {
+        Invocation {
+            command: command.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    // Full argv to spawn, with the Nix environment and container wrapped around the command
+    fn argv(&self) -> Vec<String> {
+        let command = match &self.nix {
+            Some(nix) => nix.wrap(&self.command),
+            None => self.command.clone(),
+        };
+        let command = match &self.direnv {
+            Some(dir) => {
+                let mut argv = vec![
+                    "direnv".to_string(),
+                    "exec".to_string(),
+                    dir.display().to_string(),
+                ];
+                argv.extend(command);
+                argv
+            }
+            None => command,
+        };
+        match &self.container {
+            Some(container) => container.argv(&command, &self.env, &self.limits.container_flags()),
+            None => self.limits.wrap(command),
+        }
+    }
+
+    // Process to spawn, with the environment overrides applied
+    // The command must not be empty
+    fn command(&self) -> process::Command {
+        let command_args = self.argv();
+
+        // Later overrides of a variable win, as they would in a shell
+        let overrides: BTreeMap<_, _> = self.env.iter().cloned().collect();
+
+        let mut command = process::Command::new(&command_args[0]);
+        command.args(&command_args[1..]);
+        if self.clean_env {
+            command.env_clear().envs(environment::clean_base());
+        }
+        command.envs(overrides);
+        if let Some(dir) = &self.dir {
+            command.current_dir(dir);
+        }
+        command
+    }
+
+    // Layer the --env-file, --env and --clean-env options over this invocation
+    fn with_user_env(mut self, args: &Args) -> Result<Self> {
+        for path in &args.env_file {
+            let vars = environment::load_env_file(path)
+                .context(format!("reading env file {}", path.display()))?;
+            self.env.extend(vars);
+        }
+        self.env.extend(args.env.iter().cloned());
+        self.clean_env |= args.clean_env;
+        Ok(self)
+    }
+}
+
+// How the worktree for a run came to exist
+#[derive(Debug, Clone, Copy)]
+struct Provisioning {
+    reused: bool,
+    duration: Duration,
+}
+
+// Machine-readable result of `arborist create`
+#[derive(Serialize)]
+struct CreateOutput<'a> {
+    name: &'a str,
+    path: &'a Path,
+    branch: Option<&'a str>,
+    base_commit: &'a str,
+    repo: &'a Path,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timings: Option<serde_json::Value>,
 }
 
 // Directory guard to restore original directory
@@ -99,143 +896,2130 @@ fn main() {
     let exit_code = match run() {
         Ok(code) => code,
         Err(err) => {
-            eprintln!("Error: {}", err);
-            1
+            eprintln!("{} {}", style::error("Error:"), err.chain());
+            events::emit(
+                "error",
+                json!({ "kind": err.kind(), "message": err.chain() }),
+            );
+            error_exit_code()
         }
     };
 
+    audit::finish(exit_code);
     std::process::exit(exit_code);
 }
 
 fn run() -> Result<i32> {
-    let args = Args::try_parse().unwrap_or_else(|e| e.exit());
+    // First, while arborist is single-threaded: it may change directory and the environment
+    let argv = gitalias::args()?;
+
+    // `arborist <name>` runs an arborist-<name> plugin when one is installed
+    if let Some(exit_code) = plugins::dispatch(&argv)? {
+        return Ok(exit_code);
+    }
+
+    timings::start();
+    let args = Args::try_parse_from(&argv).unwrap_or_else(|e| e.exit());
 
     // Set global verbose flag
     VERBOSE.store(args.verbose, Ordering::Relaxed);
+    QUIET.store(args.quiet, Ordering::Relaxed);
+    style::init(args.no_color);
+    if let Some(untracked) = args.untracked {
+        let _ = UNTRACKED.set(untracked);
+    }
+    if let Some(upstream) = &args.upstream {
+        let _ = UPSTREAM.set(upstream.clone());
+    }
+    cancel::install()?;
 
-    // Step 1: Initialization
-    verbose!("Checking repository...");
-    let repo_info = git::get_repo_info()?;
+    // A passthrough run reads no config and records no state, so nothing arborist keeps can
+    // stop the command from running in place
+    if args.subcommand.is_none() && no_worktree(&args) {
+        open_events(&args)?;
+        return run_command(&args);
+    }
 
-    match repo_info {
-        None => {
-            // Non-git directory, just run command
-            verbose!("Not a git repository, running command directly...");
-            let exit_code = execute_shell_command(&args.command)?;
-            return Ok(exit_code);
+    if let Some(root) = config::load(None)?.worktree_root {
+        let root = config::expand_home(&root);
+        if !root.is_absolute() {
+            return Err(ArboristError::InvalidConfig(format!(
+                "worktree_root must be an absolute path, not {}",
+                root.display()
+            )));
         }
-        Some(repo) => {
-            // Both bare and non-bare repos now use worktrees
-            let is_bare = repo.is_bare;
+        git::set_worktree_root(root);
+    }
 
-            verbose!(
-                "{} repository detected",
-                if is_bare { "Bare" } else { "Normal" }
-            );
-            verbose!("Repository: {}", repo.root.display());
-            verbose!("Current branch: {}", repo.current_branch);
+    // Not worth a warning in prompts or the process setting resource limits
+    let quiet_subcommand = matches!(
+        args.subcommand,
+        Some(
+            Subcommand::Migrate
+                | Subcommand::Prompt { .. }
+                | Subcommand::Query { .. }
+                | Subcommand::Limits { .. }
+        )
+    );
+    if !quiet_subcommand && migrate::is_pending() {
+        warning!(
+            "state from an earlier version is in {}; run `arborist migrate` to move it",
+            state::legacy_state_root().display()
+        );
+    }
 
-            let color = select_color(args.random);
+    state::upgrade_schema()?;
 
-            // Compute worktree path based on repository type
-            let worktree_path = if is_bare {
-                // Bare: {repo_root}/arborist-{color}
-                repo.root.join(format!("arborist-{}", &color))
-            } else {
-                // Non-bare: /tmp/arborist/{sha256}/{color}
-                git::compute_nonbare_worktree_path(&repo.root, &color)?
+    // Every invocation is audited but prompts and queries, which run at every shell prompt or
+    // editor refresh, reading the log, and the process setting resource limits (its run is
+    // already audited by the arborist that started it)
+    if !matches!(
+        args.subcommand,
+        Some(
+            Subcommand::Prompt { .. }
+                | Subcommand::Query { .. }
+                | Subcommand::Log { .. }
+                | Subcommand::Limits { .. }
+        )
+    ) {
+        audit::begin();
+    }
+
+    open_events(&args)?;
+
+    match &args.subcommand {
+        Some(Subcommand::Create { json }) => run_create(&args, *json),
+        Some(Subcommand::Path { name, fd }) => run_path(&args, name.as_deref(), *fd),
+        Some(Subcommand::Exec { name, command }) => run_exec(&args, name, command),
+        Some(Subcommand::Bench {
+            base,
+            head,
+            parallel,
+            diff_output,
+            command,
+        }) => {
+            let options = bench::Options {
+                base,
+                head,
+                parallel: *parallel,
+                diff_output: *diff_output,
             };
+            let invocation = Invocation::from_argv(command).with_user_env(&args)?;
+            bench::run_bench(&args, &options, &invocation)
+        }
+        Some(Subcommand::Sync {
+            name,
+            merge,
+            continue_on_conflict,
+            no_fetch,
+        }) => sync::run_sync(
+            name,
+            *merge,
+            *continue_on_conflict,
+            !*no_fetch,
+            args.signing(),
+        ),
+        Some(Subcommand::Rm { name, force }) => run_rm(name, *force),
+        Some(Subcommand::Diff { name, stat, patch }) => diff::run_diff(name, *stat, *patch),
+        Some(Subcommand::Export { name, out, patches }) => export::run_export(name, out, *patches),
+        Some(Subcommand::Pick { command }) => pick::run_pick(&args, command),
+        Some(Subcommand::Ui) => ui::run_ui(),
+        Some(Subcommand::Migrate) => migrate::run_migrate(),
+        Some(Subcommand::Undo) => archive::run_undo(&args),
+        Some(Subcommand::Repair) => repair::run_repair(),
+        Some(Subcommand::Warmup { count }) => pool::run_warmup(&args, *count),
+        Some(Subcommand::Daemon {
+            interval,
+            status,
+            stop,
+        }) => daemon::run_daemon(&args, *interval, *status, *stop),
+        Some(Subcommand::Adopt {
+            path,
+            name,
+            rename_branch,
+        }) => adopt::run_adopt(path, name.as_deref(), *rename_branch),
+        Some(Subcommand::Limits { command }) => limits::run_limited(&args, command),
+        Some(Subcommand::Watch { command }) => watch::run_watch(&args, command),
+        Some(Subcommand::Ws { command }) => workspace::run_workspace(&args, command),
+        Some(Subcommand::Prompt { format }) => prompt::run_prompt(format.as_deref()),
+        Some(Subcommand::Query { key, name }) => query::run_query(*key, name.as_deref()),
+        Some(Subcommand::Stats { everywhere, json }) => stats::run_stats(*everywhere, *json),
+        Some(Subcommand::History { name, limit, json }) => {
+            history::run_history(name.as_deref(), *limit, *json)
+        }
+        Some(Subcommand::Ps { json }) => ps::run_ps(*json),
+        Some(Subcommand::Kill { id }) => ps::run_kill(id),
+        Some(Subcommand::Log {
+            repo,
+            worktree,
+            user,
+            since,
+            failed,
+            limit,
+            json,
+        }) => audit::run_log(
+            &audit::Filter {
+                repo: repo.clone(),
+                worktree: worktree.clone(),
+                user: user.clone(),
+                since: *since,
+                failed: *failed,
+                limit: *limit,
+            },
+            *json,
+        ),
+        Some(Subcommand::Run {
+            tool,
+            args: tool_args,
+        }) => run_tool(&args, tool.as_deref(), tool_args),
+        Some(Subcommand::DestroyAll {
+            everywhere,
+            force,
+            include_pinned,
+            keep_archives,
+            ..
+        }) => destroy::run_destroy_all(*everywhere, *force, *include_pinned, *keep_archives),
+        None => run_command(&args),
+    }
+}
+
+// Opens the --events-file and --events-fd streams
+fn open_events(args: &Args) -> Result<()> {
+    if let Some(path) = &args.events_file {
+        events::open_file(path)?;
+    }
+    if let Some(fd) = args.events_fd {
+        events::open_fd(fd)?;
+    }
+    Ok(())
+}
+
+// Default mode: create a worktree, run the command in it, then clean up
+fn run_command(args: &Args) -> Result<i32> {
+    let invocation = match &args.shell_command {
+        Some(script) => Invocation::from_argv(&tools::shell_argv(script)),
+        None if args.command.is_empty() => Invocation::from_argv(&[tools::user_shell()]),
+        None => Invocation::from_argv(&args.command),
+    }
+    .with_user_env(args)?;
+
+    if no_worktree(args) {
+        return run_in_place(args, invocation, "worktrees are turned off for this run");
+    }
 
-            verbose!("Preparing worktree at: {}", worktree_path.display());
+    // Step 1: Initialization
+    // An old git fails here with what it lacks, rather than with whichever git command it trips on
+    git::require(git::Feature::Worktrees)?;
+    if let Some(version) = git::version() {
+        verbose!("Using git {}", version);
+    }
+    verbose!("Checking repository...");
+    let repo_info = timings::time(timings::Phase::RepoDetection, git::get_repo_info)?;
+
+    match repo_info {
+        None => run_in_place(args, invocation, "not a git repository"),
+        Some(repo) if !arborist_enabled(&repo)? => {
+            audit::note_repo(&repo.root);
+            run_in_place(args, invocation, "arborist is disabled in this repository")
+        }
+        Some(repo) if repo.unborn => {
+            audit::note_repo(&repo.root);
+            run_unborn(args, invocation, &repo)
+        }
+        Some(_) if !args.branches.is_empty() => {
+            branches::run_branches(args, &invocation, &args.branches)
+        }
+        Some(repo) => {
+            let (repo, outer) = resolve_nested(repo)?;
+            if let Some(outer) = outer
+                && args.nested.unwrap_or_default() == Nested::Reuse
+            {
+                verbose!("Inside worktree {}, running the command there", outer);
+                let plan = plan_worktree(repo, outer)?;
+                if args.dry_run {
+                    dry_run::print_plan(&plan, args, &invocation)?;
+                    return Ok(0);
+                }
+                return run_in_worktree(&plan, args, &invocation, None);
+            }
 
-            // Check if worktree exists
-            if git::worktree_exists(&worktree_path)? {
-                verbose!("Worktree already exists, using existing worktree");
+            // Inside a window opened by --tmux, keep using the worktree that opened it
+            let inner_name = args.tmux.and_then(|_| tmux::inner_name());
+            let color = match &inner_name {
+                Some(name) => name.clone(),
+                None => select_name(args, &invocation.command)?,
+            };
+            let plan = plan_new_worktree(repo, color, args)?;
+            if args.dry_run {
+                dry_run::print_plan(&plan, args, &invocation)?;
+                return Ok(0);
             }
+            let provisioning = create_planned_worktree(&plan, args)?;
 
-            let branch_name = format!("arborist/{}", color);
-            verbose!("Creating worktree with branch '{}'...", branch_name);
-            git::create_worktree(
-                &worktree_path,
-                &branch_name,
-                &repo.current_commit,
-                Some(&repo.current_branch),
-            )?;
-
-            // Change to worktree directory
-            let _prev_path = DirectoryGuard::with_path(&worktree_path)?;
-            verbose!("Changed to worktree directory");
-
-            // Execute user command
-            let exit_code = execute_shell_command(&args.command)?;
-
-            // Cleanup
-            verbose!("Checking worktree status...");
-            let status = git::get_worktree_status()?;
-
-            if status.has_changes {
-                verbose!("Note: Uncommitted changes exist in worktree");
-                verbose!("Keeping worktree at: {}", worktree_path.display());
-            } else if status.commits_ahead > 0 {
-                verbose!("Note: {} unpushed commit(s) exist", status.commits_ahead);
-                verbose!("Keeping worktree at: {}", worktree_path.display());
-            } else {
-                verbose!("No changes detected, removing worktree...");
-                // Return to original directory before removing worktree
-                drop(_prev_path);
-                git::remove_worktree_and_branch(&worktree_path, &branch_name)?;
-                verbose!("Worktree and branch removed");
+            if let Some(target) = args.tmux
+                && inner_name.is_none()
+            {
+                tmux::open(target, &plan)?;
+                return Ok(0);
             }
 
+            let exit_code = run_in_worktree(&plan, args, &invocation, Some(provisioning))?;
+            if args.tmux.is_some() {
+                tmux::finish(exit_code, args.tmux_close)?;
+            }
             Ok(exit_code)
         }
     }
 }
 
-// Execute shell command
-fn execute_shell_command(command_args: &[String]) -> Result<i32> {
-    if command_args.is_empty() {
+// Run the command where arborist was started, without a worktree, because of `reason`: outside a
+// git repository, or in one arborist is disabled in
+fn run_in_place(args: &Args, mut invocation: Invocation, reason: &str) -> Result<i32> {
+    if args.tmux.is_some() || !args.branches.is_empty() {
+        return Err(ArboristError::InvalidConfig(format!(
+            "--tmux and --branches need a worktree, but {}",
+            reason
+        )));
+    }
+    let cwd = env::current_dir()?;
+    if args.dry_run {
+        println!("Would run in {} ({}):", cwd.display(), reason);
+        println!("  {}", tools::shell_join(&invocation.command));
         return Ok(0);
     }
 
-    let program = &command_args[0];
-    let args = &command_args[1..];
+    verbose!("Running command directly: {}", reason);
+    // With worktrees turned off, a config that doesn't load costs only its nix and container
+    // settings
+    let config = match config::load(None) {
+        Err(e) if no_worktree(args) => {
+            verbose!("Not using the config: {}", e);
+            config::Config::default()
+        }
+        config => config?,
+    };
+    invocation.nix = nix_env(&config, args, &cwd);
+    invocation.limits = limits::Limits::from_args(args);
+    if let Some(image) = &args.container {
+        invocation.container = Some(container::Container::new(
+            &config,
+            image,
+            vec![container::Mount::writable(cwd.clone())],
+            cwd.clone(),
+        )?);
+    }
+    if args.capture {
+        let log = state::run_log_path(&cwd, "run", state::unix_now())?;
+        notice!("{} output in {}", style::info("arborist:"), log.display());
+        invocation.capture = Some(log);
+    }
+    let results = timings::time(timings::Phase::Command, || {
+        process::block_on(steps::run_steps(&invocation, args))
+    })?;
+    report_timings(args);
+    Ok(steps::exit_code(&results))
+}
+
+// Run the command in place in a repository whose branch has no commits yet, which a worktree
+// can't be created from
+fn run_unborn(args: &Args, invocation: Invocation, repo: &git::GitRepo) -> Result<i32> {
+    warning!(
+        "{} has no commits yet; running the command in place, without a worktree",
+        repo.current_branch
+    );
+    run_in_place(args, invocation, "the repository has no commits yet")
+}
 
-    let output = cmd(program, args).unchecked().run()?;
+// Whether this run skips the worktree: --no-worktree, or ARBORIST_DISABLE set to anything but
+// "", "0" or "false"
+fn no_worktree(args: &Args) -> bool {
+    args.no_worktree
+        || env::var_os("ARBORIST_DISABLE").is_some_and(|value| {
+            !value.is_empty() && value != "0" && !value.eq_ignore_ascii_case("false")
+        })
+}
 
-    let exit_code = output.status.code().unwrap_or(1);
+// Whether arborist creates worktrees in the repository: `arborist.enabled` in its git config
+// decides, or else the allow_repos and deny_repos lists of the user config
+fn arborist_enabled(repo: &git::GitRepo) -> Result<bool> {
+    if let Some(enabled) = git::repo_enabled()? {
+        return Ok(enabled);
+    }
 
-    Ok(exit_code)
+    let config = config::load(None)?;
+    let listed = |dirs: &[PathBuf]| config::lists_repo(dirs, &repo.root);
+    if let Some(allowed) = &config.allow_repos
+        && !listed(allowed)
+    {
+        verbose!("{} is not in allow_repos", repo.root.display());
+        return Ok(false);
+    }
+    if config.deny_repos.as_deref().is_some_and(listed) {
+        verbose!("{} is in deny_repos", repo.root.display());
+        return Ok(false);
+    }
+    Ok(true)
 }
 
-// Select a color based on mode (random or deterministic)
-fn select_color(use_random: bool) -> String {
-    if use_random {
-        select_color_random()
+// `arborist create`: plan and create the worktree, then report where it is
+fn run_create(args: &Args, json: bool) -> Result<i32> {
+    verbose!("Checking repository...");
+    let repo = timings::time(timings::Phase::RepoDetection, require_repo)?;
+
+    let color = select_name(args, &[])?;
+    let plan = plan_new_worktree(repo, color, args)?;
+    create_planned_worktree(&plan, args)?;
+
+    if json {
+        let output = CreateOutput {
+            name: &plan.name,
+            path: &plan.path,
+            branch: plan.branch.as_deref(),
+            base_commit: &plan.repo.current_commit,
+            repo: &plan.repo.root,
+            timings: args.timings.then(timings::to_json),
+        };
+        println!("{}", serde_json::to_string(&output)?);
     } else {
-        select_color_deterministic()
+        println!("worktree: {}", plan.path.display());
+        println!("branch:   {}", plan.branch_name());
+        if args.timings {
+            timings::print();
+        }
     }
+
+    Ok(0)
 }
 
-// Random color selection (works on all platforms)
-fn select_color_random() -> String {
-    let mut rng = rand::rng();
-    COLORS
-        .choose(&mut rng)
-        .expect("Color palette should not be empty")
-        .to_string()
+// `arborist path`: create or reuse a worktree and print nothing but its path
+fn run_path(args: &Args, name: Option<&str>, fd: Option<u32>) -> Result<i32> {
+    verbose!("Checking repository...");
+    let repo = require_repo()?;
+
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => select_name(args, &[])?,
+    };
+    let plan = plan_new_worktree(repo, name, args)?;
+    create_planned_worktree(&plan, args)?;
+
+    match fd {
+        Some(fd) => {
+            let mut file = open_fd(fd).context(format!("opening file descriptor {}", fd))?;
+            writeln!(file, "{}", plan.path.display())?;
+        }
+        None => println!("{}", plan.path.display()),
+    }
+
+    Ok(0)
 }
 
-// Deterministic color selection based on parent process ID (Unix only)
-#[cfg(unix)]
-fn select_color_deterministic() -> String {
-    let parent_pid = std::os::unix::process::parent_id();
-    let index = (parent_pid as usize) % COLORS.len();
-    COLORS[index].to_string()
+// `arborist exec`: run a command in a worktree created earlier
+fn run_exec(args: &Args, name: &str, command: &[String]) -> Result<i32> {
+    verbose!("Checking repository...");
+    let repo = require_repo()?;
+
+    let plan = plan_worktree(repo, name.to_string())?;
+    if !git::worktree_exists(&plan.path)? {
+        return Err(ArboristError::InvalidPath(format!(
+            "No arborist worktree named '{}' at {}",
+            name,
+            plan.path.display()
+        )));
+    }
+
+    let invocation = Invocation::from_argv(command).with_user_env(args)?;
+    run_in_worktree(&plan, args, &invocation, None)
 }
 
-// Fallback to random selection on non-Unix platforms
-#[cfg(not(unix))]
-fn select_color_deterministic() -> String {
-    select_color_random()
+// `arborist rm`: remove a kept worktree once its work is no longer needed
+fn run_rm(name: &str, force: bool) -> Result<i32> {
+    let repo = require_repo()?;
+    let plan = plan_worktree(repo, name.to_string())?;
+    if !git::worktree_exists(&plan.path)? {
+        return Err(ArboristError::InvalidPath(format!(
+            "No arborist worktree named '{}' at {}",
+            name,
+            plan.path.display()
+        )));
+    }
+
+    if let Some(reason) = in_use_reason(&plan)? {
+        eprintln!("Worktree {} is {}", name, reason);
+        return Ok(1);
+    }
+    let status = {
+        let _guard = DirectoryGuard::with_path(&plan.path)?;
+        worktree_status(&plan, &[])?
+    };
+    if status.has_changes || status.commits_ahead > 0 {
+        if !force {
+            eprintln!(
+                "Worktree {} has uncommitted changes or unmerged commits; use --force to remove it anyway",
+                name
+            );
+            return Ok(1);
+        }
+        if let Some(dir) = archive::archive_worktree(&plan)? {
+            archive::report(&plan.name, &dir);
+        }
+    }
+
+    remove_planned_worktree(&plan, false)
+        .context(format!("removing worktree {}", plan.path.display()))?;
+    state::remove_worktree_meta(&plan.repo.root, &plan.name)?;
+    state::remove_run(&plan.repo.root, &plan.name)?;
+    emit_removed(&plan);
+    match plan.owned_branch() {
+        Some(branch) => notice!(
+            "Removed worktree {} and branch {}",
+            plan.path.display(),
+            branch
+        ),
+        None => notice!("Removed worktree {}", plan.path.display()),
+    }
+
+    Ok(0)
+}
+
+// `arborist run`: resolve a tool from the manifest and run it in a new worktree
+fn run_tool(args: &Args, tool: Option<&str>, tool_args: &[String]) -> Result<i32> {
+    verbose!("Checking repository...");
+    let repo = require_repo()?;
+
+    // Each tool and its arguments hash to their own worktree with --select cmd-hash
+    let command: Vec<String> = tool
+        .into_iter()
+        .map(String::from)
+        .chain(tool_args.iter().cloned())
+        .collect();
+    let color = select_name(args, &command)?;
+    let plan = plan_new_worktree(repo, color, args)?;
+
+    let Some(tool) = tool else {
+        tools::print_tools(&plan.config);
+        return Ok(0);
+    };
+    let invocation = tools::resolve(&plan.config, tool, tool_args)?.with_user_env(args)?;
+    if no_worktree(args) {
+        return run_in_place(args, invocation, "worktrees are turned off for this run");
+    }
+    if !arborist_enabled(&plan.repo)? {
+        return run_in_place(args, invocation, "arborist is disabled in this repository");
+    }
+    if plan.repo.unborn {
+        return run_unborn(args, invocation, &plan.repo);
+    }
+    if args.dry_run {
+        dry_run::print_plan(&plan, args, &invocation)?;
+        return Ok(0);
+    }
+
+    let provisioning = create_planned_worktree(&plan, args)?;
+    run_in_worktree(&plan, args, &invocation, Some(provisioning))
+}
+
+// Opens a file descriptor inherited from the shell (e.g. `3>&1`) for writing
+fn open_fd(fd: u32) -> Result<File> {
+    if cfg!(windows) {
+        return Err(ArboristError::InvalidConfig(
+            "writing to a file descriptor is not supported on Windows".to_string(),
+        ));
+    }
+
+    Ok(OpenOptions::new()
+        .append(true)
+        .open(format!("/dev/fd/{}", fd))?)
+}
+
+fn require_repo() -> Result<git::GitRepo> {
+    git::require(git::Feature::Worktrees)?;
+    let repo = git::get_repo_info()?.ok_or_else(|| ArboristError::NotARepo {
+        dir: env::current_dir().unwrap_or_default(),
+    })?;
+    let (repo, _) = resolve_nested(repo)?;
+    audit::note_repo(&repo.root);
+    if let Err(err) = migrate::identify_repo(&repo) {
+        warning!("couldn't check whether the repository moved: {}", err);
+    }
+    Ok(repo)
+}
+
+// When the current checkout is one of arborist's worktrees, the repository it belongs to and the
+// worktree's name; otherwise worktrees would be nested under, and keyed off, the worktree itself
+// The current commit and branch stay the worktree's, so new worktrees start from them
+fn resolve_nested(repo: git::GitRepo) -> Result<(git::GitRepo, Option<String>)> {
+    // Only linked worktrees have a .git file; worktrees of bare repositories already resolve to it
+    if repo.is_bare || !repo.root.join(".git").is_file() {
+        return Ok((repo, None));
+    }
+
+    let worktrees = git::list_worktrees()?;
+    let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    // The main checkout is listed first
+    let Some(main_root) = worktrees.first().map(|entry| canonical(&entry.path)) else {
+        return Ok((repo, None));
+    };
+    let Some(entry) = worktrees
+        .iter()
+        .find(|entry| canonical(&entry.path) == repo.root)
+        .filter(|_| main_root != repo.root)
+    else {
+        return Ok((repo, None));
+    };
+
+    let main = git::GitRepo {
+        root: main_root,
+        ..repo.clone()
+    };
+    if !is_arborist_worktree(&main, entry)? {
+        return Ok((repo, None));
+    }
+    let name = pick::worktree_name(&main, entry)?;
+    verbose!(
+        "Inside worktree {} of {}",
+        name.as_deref().unwrap_or("(unnamed)"),
+        main.root.display()
+    );
+    Ok((main, name))
+}
+
+// Resolve the worktree path and branch for the given name
+fn plan_worktree(repo: git::GitRepo, name: String) -> Result<WorktreePlan> {
+    // Both bare and non-bare repos use worktrees
+    let is_bare = repo.is_bare;
+
+    verbose!(
+        "{} repository detected",
+        if is_bare { "Bare" } else { "Normal" }
+    );
+    verbose!("Repository: {}", repo.root.display());
+    verbose!("Current branch: {}", repo.base_name());
+
+    // Bare repositories have no checked-out .arborist.toml to read
+    let config = config::load((!is_bare).then_some(repo.root.as_path()))?;
+    // Once per invocation, however many worktrees it plans
+    static IGNORED_WARNED: AtomicBool = AtomicBool::new(false);
+    if !config.ignored_keys.is_empty() && !IGNORED_WARNED.swap(true, Ordering::Relaxed) {
+        warning!(
+            "{} sets {}, which only the user config may; ignoring {}",
+            repo.root.join(".arborist.toml").display(),
+            config.ignored_keys.join(", "),
+            if config.ignored_keys.len() == 1 {
+                "it"
+            } else {
+                "them"
+            }
+        );
+    }
+    if let Some(retries) = config.git_lock_retries {
+        git::set_lock_retries(retries);
+    }
+
+    let meta = state::load_worktree(&repo.root, &name)?;
+    let path = if let Some(path) = meta.as_ref().and_then(|meta| meta.path.clone()) {
+        // Worktrees stay where they were created when the configured location changes
+        path
+    } else {
+        let path = new_worktree_path(&repo, &config, &name)?;
+        // Worktrees from before paths were recorded are where they used to be created
+        let legacy = if is_bare {
+            repo.root.join(format!("arborist-{}", &name))
+        } else {
+            git::default_worktree_root(&repo.root)?.join(&name)
+        };
+        if meta.is_some() && !path.exists() && legacy.exists() {
+            legacy
+        } else {
+            path
+        }
+    };
+
+    // Worktrees created with --detach or --branch have no branch of arborist's to delete
+    let (branch, owns_branch) = match meta {
+        Some(meta) if meta.detached => (None, false),
+        Some(state::WorktreeMeta {
+            branch: Some(branch),
+            ..
+        }) => (Some(branch), false),
+        _ => (Some(format!("arborist/{}", name)), true),
+    };
+
+    Ok(WorktreePlan {
+        repo,
+        config,
+        name,
+        path,
+        branch,
+        owns_branch,
+    })
+}
+
+// Plan a worktree that is created if it doesn't exist yet; with --detach, a new one gets
+// no branch, with --branch it checks out that branch, and with --from-remote or --update it
+// is based on a freshly fetched remote branch
+fn plan_new_worktree(repo: git::GitRepo, name: String, args: &Args) -> Result<WorktreePlan> {
+    // Keyed by an identity rather than its path, the repository's worktrees and state are
+    // still found after it moves
+    if !args.dry_run
+        && let Err(err) = git::ensure_repo_id(&repo.root)
+    {
+        verbose!("Couldn't give the repository an identity: {}", err);
+    }
+    // Leftovers of crashed runs or a cleared /tmp would otherwise look reusable
+    if !args.dry_run {
+        prune::prune_stale(&repo)?;
+    }
+
+    let mut plan = plan_worktree(repo, name)?;
+    if git::worktree_exists(&plan.path)? {
+        // Reused as it is, so it has to be there to run in
+        if !plan.path.is_dir() {
+            return Err(ArboristError::WorktreeMissing {
+                name: plan.name,
+                path: plan.path,
+            });
+        }
+        return Ok(plan);
+    }
+    if plan.path.exists() {
+        return Err(ArboristError::NameCollision {
+            name: plan.name,
+            path: plan.path,
+        });
+    }
+    // A worktree parked by the daemon saves creating and setting up one
+    if pool::can_claim(args)
+        && let Some(claimed) = pool::claim(&plan)?
+    {
+        daemon::request_maintenance(&claimed.repo.root);
+        return Ok(claimed);
+    }
+
+    if args.update {
+        update_base(&mut plan.repo, args.dry_run)?;
+    }
+    if args.detach {
+        plan.branch = None;
+        plan.owns_branch = false;
+    } else if let Some(branch) = &args.branch {
+        plan.repo.current_commit = git::resolve_existing_branch(branch)?;
+        plan.repo.current_branch = branch.clone();
+        plan.repo.detached = false;
+        plan.branch = Some(branch.clone());
+        plan.owns_branch = false;
+    } else if let Some(name) = &args.from_remote {
+        let remote = git::RemoteBranch::parse(name)?;
+        // A dry run shouldn't touch the remote; it plans with what was last fetched
+        plan.repo.current_commit = if args.dry_run {
+            remote.tip()?
+        } else {
+            verbose!("Fetching {}...", name);
+            remote.fetch()?
+        };
+        // The new branch then tracks the remote branch
+        plan.repo.current_branch = name.clone();
+        plan.repo.detached = false;
+    }
+    Ok(plan)
+}
+
+// Base new worktrees on the tip of the current branch's upstream, fetched now, and report
+// how far the checkout is behind it
+fn update_base(repo: &mut git::GitRepo, dry_run: bool) -> Result<()> {
+    let upstream = match repo.upstream() {
+        Some(branch) => git::RemoteBranch::upstream_of(branch)?,
+        None => None,
+    };
+    let Some(upstream) = upstream else {
+        return Err(ArboristError::GitOperationFailed(format!(
+            "--update needs {} to track a remote branch",
+            repo.base_name()
+        )));
+    };
+
+    // A dry run shouldn't touch the remote; it plans with what was last fetched
+    let tip = if dry_run {
+        upstream.tip()?
+    } else {
+        verbose!("Fetching {}...", upstream.name());
+        upstream.fetch()?
+    };
+
+    let behind = git::count_commits_between(&repo.current_commit, &tip)?;
+    let ahead = git::count_commits_between(&tip, &repo.current_commit)?;
+    if behind > 0 || ahead > 0 {
+        let mut message = format!(
+            "{} checkout is {} behind {}",
+            style::info("arborist:"),
+            summary::count(behind, "commit"),
+            upstream.name()
+        );
+        if ahead > 0 {
+            message.push_str(&format!(
+                " and {} ahead, which the worktree won't have",
+                summary::count(ahead, "commit")
+            ));
+        }
+        notice!("{}", message);
+    } else {
+        verbose!("Checkout is up to date with {}", upstream.name());
+    }
+
+    repo.current_commit = tip;
+    // The new branch then tracks the upstream itself rather than the local branch
+    repo.current_branch = upstream.name();
+    Ok(())
+}
+
+impl WorktreePlan {
+    // The worktree's branch for messages, "(detached)" when it has none
+    fn branch_name(&self) -> &str {
+        self.branch.as_deref().unwrap_or("(detached)")
+    }
+
+    // The branch removed along with the worktree, if arborist created it
+    fn owned_branch(&self) -> Option<&str> {
+        self.branch.as_deref().filter(|_| self.owns_branch)
+    }
+
+    // What `git worktree add` checks out for this plan
+    fn worktree_branch(&self) -> git::WorktreeBranch<'_> {
+        match (self.branch.as_deref(), self.owns_branch) {
+            (Some(branch), true) => git::WorktreeBranch::New(branch),
+            (Some(branch), false) => git::WorktreeBranch::Existing(branch),
+            (None, _) => git::WorktreeBranch::Detached,
+        }
+    }
+}
+
+// Remove the planned worktree, once its pre-remove hook allows it, and the branch arborist created
+// for it unless `keep_branch` (it has commits of its own, or is deleted separately). Every path
+// removing a worktree that is still there goes through here, so the hook always runs
+fn remove_planned_worktree(plan: &WorktreePlan, keep_branch: bool) -> Result<()> {
+    lifecycle::run(plan, lifecycle::Hook::PreRemove, &[])?;
+    match plan.owned_branch().filter(|_| !keep_branch) {
+        Some(branch) => git::remove_worktree_and_branch(&plan.path, branch),
+        None => git::remove_worktree(&plan.path),
+    }
+}
+
+// Directory holding only the repository's arborist worktrees; None when they are created
+// inside a bare repository itself
+fn worktrees_dir(repo: &git::GitRepo, config: &config::Config) -> Result<Option<PathBuf>> {
+    let layout = if repo.is_bare {
+        config.bare_worktrees.unwrap_or_default()
+    } else {
+        config::BareWorktrees::Root
+    };
+
+    Ok(match layout {
+        // {worktree root}/{repo_hash}/{color}
+        config::BareWorktrees::Root => Some(git::compute_worktree_root(&repo.root)?),
+        // {parent}/{name}.worktrees/{color}
+        config::BareWorktrees::Sibling => Some(git::sibling_worktree_root(&repo.root)),
+        // {repo_root}/arborist-{color}
+        config::BareWorktrees::Inside => None,
+    })
+}
+
+// Where a new worktree with this name is created
+fn new_worktree_path(repo: &git::GitRepo, config: &config::Config, name: &str) -> Result<PathBuf> {
+    Ok(match worktrees_dir(repo, config)? {
+        Some(dir) => dir.join(name),
+        None => repo.root.join(format!("arborist-{}", name)),
+    })
+}
+
+// Whether a registered worktree was created by arborist, wherever it was configured to go
+fn is_arborist_worktree(repo: &git::GitRepo, entry: &git::WorktreeEntry) -> Result<bool> {
+    if entry
+        .branch
+        .as_deref()
+        .is_some_and(|branch| branch.starts_with("arborist/"))
+    {
+        return Ok(true);
+    }
+
+    let path = &entry.path;
+    if path.starts_with(git::compute_worktree_root(&repo.root)?)
+        || path.starts_with(git::worktree_root().join(git::path_hash(&repo.root)))
+        || path.starts_with(git::default_worktree_root(&repo.root)?)
+    {
+        return Ok(true);
+    }
+
+    // Adopted worktrees are wherever they were made
+    if state::list_worktree_meta(&state::repo_state_dir(&repo.root)?)?
+        .iter()
+        .any(|(_, meta)| meta.path.as_ref() == Some(path))
+    {
+        return Ok(true);
+    }
+
+    Ok(repo.is_bare
+        && (path.starts_with(git::sibling_worktree_root(&repo.root))
+            || path.parent() == Some(repo.root.as_path())
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with("arborist-"))))
+}
+
+// Create the planned worktree (or reuse it if it already exists)
+fn create_planned_worktree(plan: &WorktreePlan, args: &Args) -> Result<Provisioning> {
+    let started = Instant::now();
+    if plan.repo.unborn {
+        return Err(ArboristError::UnbornBranch {
+            branch: plan.repo.current_branch.clone(),
+        });
+    }
+    audit::note_worktree(plan);
+    state::record_repo(&plan.repo.root)?;
+
+    // Explain what arborist does the first time it runs in a repository
+    if !state::is_acknowledged(&plan.repo.root)? {
+        if !args.yes && !args.quiet {
+            print_first_run_report(&plan.repo, &plan.path, plan.branch_name());
+        }
+        state::record_acknowledgment(&plan.repo.root)?;
+    }
+
+    // Reject bad config before anything is created
+    for dirs in [&plan.config.link_artifacts, &plan.config.copy_cow]
+        .into_iter()
+        .flatten()
+    {
+        artifacts::validate_artifact_dirs(dirs)?;
+    }
+    templates::validate(plan)?;
+    if plan.config.worktree_config.is_some() || plan.config.inherit_config.is_some() {
+        git::require(git::Feature::WorktreeConfig)?;
+    }
+
+    verbose!("Preparing worktree at: {}", plan.path.display());
+
+    // Check if worktree exists
+    let existed = git::worktree_exists(&plan.path)?;
+    if existed {
+        verbose!("Worktree already exists, using existing worktree");
+    }
+    // A missing upstream would otherwise only fail once the worktree exists
+    if !existed
+        && matches!(plan.worktree_branch(), git::WorktreeBranch::New(_))
+        && let Some(upstream) = worktree_upstream(plan)
+        && !git::is_branch(upstream)?
+    {
+        return Err(ArboristError::UpstreamMissing {
+            upstream: upstream.to_string(),
+        });
+    }
+
+    let partial = if existed { None } else { git::partial_clone()? };
+    if let Some(partial) = &partial {
+        verbose!(
+            "Partial clone: missing blobs are fetched from {} (filter {})",
+            partial.remote,
+            partial.filter.as_deref().unwrap_or("unknown")
+        );
+    }
+    let sparse = worktree_sparse(plan, args, partial.is_some())?;
+
+    match plan.worktree_branch() {
+        git::WorktreeBranch::New(branch) => {
+            verbose!("Creating worktree with branch '{}'...", branch)
+        }
+        git::WorktreeBranch::Existing(branch) => {
+            verbose!("Creating worktree of existing branch '{}'...", branch)
+        }
+        git::WorktreeBranch::Detached => verbose!("Creating detached worktree..."),
+    }
+    if !sparse.is_empty() {
+        verbose!("Using sparse checkout of: {}", sparse.join(", "));
+    }
+    timings::time(timings::Phase::WorktreeCreation, || {
+        let _spinner = (!existed).then(|| {
+            progress::Spinner::start(match &partial {
+                Some(partial) => format!(
+                    "creating worktree {} (fetching missing blobs from {})",
+                    plan.name, partial.remote
+                ),
+                None => format!("creating worktree {}", plan.name),
+            })
+        });
+        git::create_worktree(
+            &plan.path,
+            plan.worktree_branch(),
+            &plan.repo.current_commit,
+            worktree_upstream(plan),
+            &sparse,
+        )
+    })
+    .context(format!("creating worktree {}", plan.path.display()))?;
+
+    // Remember the base so cleanup can tell whether new commits would be lost
+    if !existed {
+        let meta = state::WorktreeMeta {
+            base_commit: plan.repo.current_commit.clone(),
+            base_branch: plan.repo.current_branch.clone(),
+            detached: plan.branch.is_none(),
+            branch: plan.branch.clone().filter(|_| !plan.owns_branch),
+            path: Some(plan.path.clone()),
+            created_at: state::unix_now(),
+            parked: false,
+        };
+        state::record_worktree(&plan.repo.root, &plan.name, &meta)?;
+    }
+
+    let setup = timings::time(timings::Phase::Setup, || -> Result<()> {
+        let _spinner = progress::Spinner::start(format!("setting up worktree {}", plan.name));
+        setup_worktree(plan, args)?;
+        if !existed {
+            templates::write(plan).context("writing templates")?;
+            lifecycle::run(plan, lifecycle::Hook::PostCreate, &[])?;
+        }
+        Ok(())
+    })
+    .context(format!("setting up worktree {}", plan.path.display()));
+    // A new worktree that couldn't be set up (its post-create hook failed, say) goes again rather
+    // than being left for later runs to reuse half set up
+    if let Err(err) = setup {
+        if !existed {
+            match remove_planned_worktree(plan, false) {
+                Ok(()) => state::remove_worktree_meta(&plan.repo.root, &plan.name)?,
+                Err(remove_err) => warning!(
+                    "couldn't remove worktree {}: {}",
+                    plan.path.display(),
+                    remove_err.chain()
+                ),
+            }
+        }
+        return Err(err);
+    }
+
+    let provisioning = Provisioning {
+        reused: existed,
+        duration: started.elapsed(),
+    };
+    if !existed {
+        events::emit(
+            "worktree_created",
+            json!({
+                "name": plan.name,
+                "path": plan.path.to_string_lossy(),
+                "branch": plan.branch,
+                "base_commit": plan.repo.current_commit,
+                "detached": plan.repo.detached,
+                "repo": plan.repo.root.to_string_lossy(),
+                "duration_ms": provisioning.duration.as_millis() as u64,
+            }),
+        );
+    }
+    Ok(provisioning)
+}
+
+// Directories a new worktree checks out with sparse-checkout, empty for a full checkout: those from
+// the command line, else from config, else in a partial clone the main checkout's, so creating the
+// worktree doesn't download blobs the checkout has never needed (newer git versions copy them
+// itself, but only with extensions.worktreeConfig)
+fn worktree_sparse(plan: &WorktreePlan, args: &Args, partial: bool) -> Result<Vec<String>> {
+    if !args.sparse.is_empty() {
+        return Ok(args.sparse.clone());
+    }
+    if let Some(sparse) = &plan.config.sparse {
+        return Ok(sparse.clone());
+    }
+    if !partial || plan.repo.is_bare {
+        return Ok(Vec::new());
+    }
+
+    let sparse = git::sparse_checkout_dirs(&plan.repo.root)?;
+    if !sparse.is_empty() {
+        verbose!("Inheriting the main checkout's sparse-checkout directories");
+    }
+    Ok(sparse)
+}
+
+// Copies the values of `keys` as the checkout sees them into the worktree's own config, for those
+// the worktree sees otherwise: conditional includes match on the repository's location, and the
+// worktree lives somewhere else
+fn inherit_config(plan: &WorktreePlan, keys: &[String]) -> Result<()> {
+    let mut values = BTreeMap::new();
+    for key in keys {
+        let Some(value) = git::get_config_at(&plan.repo.root, key)? else {
+            continue;
+        };
+        if git::get_config_at(&plan.path, key)?.as_ref() != Some(&value) {
+            values.insert(key.clone(), value);
+        }
+    }
+    if !values.is_empty() {
+        verbose!(
+            "Inheriting git config from the checkout: {}",
+            values.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+    }
+    git::set_worktree_config(&plan.path, &values)
+}
+
+// Prepare a freshly created or reused worktree before anything runs in it
+fn setup_worktree(plan: &WorktreePlan, args: &Args) -> Result<()> {
+    // Carry over skip-worktree/assume-unchanged bits from the main checkout
+    if !plan.repo.is_bare {
+        let flags = git::get_index_flags(&plan.repo.root)?;
+        if !flags.is_empty() {
+            verbose!(
+                "Inheriting {} skip-worktree and {} assume-unchanged path(s)...",
+                flags.skip_worktree.len(),
+                flags.assume_unchanged.len()
+            );
+            git::apply_index_flags(&plan.path, &flags)?;
+        }
+    }
+
+    githooks::copy_hooks(plan)?;
+
+    // Before worktree_config, which wins where both set a key
+    if let Some(keys) = &plan.config.inherit_config {
+        inherit_config(plan, keys).context("inheriting git config")?;
+    }
+
+    if let Some(values) = &plan.config.worktree_config {
+        verbose!(
+            "Setting worktree config: {}",
+            values.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+        git::set_worktree_config(&plan.path, values).context("setting worktree config")?;
+    }
+
+    if plan.config.direnv.unwrap_or(false) && environment::direnv_allow(&plan.path)? {
+        verbose!("Allowed .envrc for direnv");
+    }
+
+    if args.lfs {
+        verbose!("Pulling Git LFS objects...");
+        git::lfs_pull(&plan.path)?;
+    }
+
+    if let Some(dirs) = &plan.config.copy_cow
+        && !plan.repo.is_bare
+    {
+        artifacts::copy_artifacts_cow(plan, dirs)?;
+    }
+
+    if let Some(dirs) = &plan.config.link_artifacts {
+        artifacts::link_artifacts(plan, dirs)?;
+    }
+
+    Ok(())
+}
+
+// Run the command inside the worktree, then keep or remove it based on its state
+// `provisioning` is None when running in a worktree created by an earlier command
+fn run_in_worktree(
+    plan: &WorktreePlan,
+    args: &Args,
+    invocation: &Invocation,
+    provisioning: Option<Provisioning>,
+) -> Result<i32> {
+    audit::note_worktree(plan);
+    // Worktree details are injected last so the command can rely on them
+    let mut invocation = invocation.clone();
+    invocation.env.extend(githooks::shared_hooks_env(plan)?);
+    invocation.env.extend(environment::worktree_vars(plan));
+
+    // Change to worktree directory
+    let mut context = RunContext::enter(&plan.path, &plan.repo.root)?;
+    verbose!("Changed to worktree directory");
+    // A signal from here on cancels the run instead of killing arborist, so it is still torn down
+    let supervising = cancel::Supervising::new();
+    context.on_release(move || {
+        drop(supervising);
+        Ok(())
+    });
+
+    let prepared = resolve_subdir(plan, args.subdir.as_deref()).and_then(|run_dir| {
+        let workdir = run_dir.clone().unwrap_or_else(|| plan.path.clone());
+        wrap_invocation(plan, args, &mut invocation, workdir)?;
+        Ok(run_dir)
+    });
+    let run_dir = match prepared {
+        Ok(dir) => dir,
+        Err(e) => {
+            cleanup_worktree(plan, context, args, None)?;
+            return Err(e);
+        }
+    };
+
+    let announcer = if args.announce {
+        match plan.config.announce_url.as_deref() {
+            Some(url) => Some(announce::Announcer::new(url, plan, &invocation.command)),
+            None => {
+                warning!("--announce given but no announce_url is configured");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // The editor is only a viewer; failing to start it shouldn't stop the run
+    if args.open
+        && let Err(err) = editor::open(&plan.config, &plan.path)
+    {
+        warning!("{}", err.chain());
+    }
+
+    if let Some(announcer) = &announcer {
+        announcer.started();
+    }
+
+    // Execute user command, recording it so other arborist commands can see it running
+    let run = state::RunInfo {
+        pid: std::process::id(),
+        command: invocation.command.join(" "),
+        started_at: state::unix_now(),
+        heartbeat_at: 0,
+        host: heartbeat::host(),
+    };
+    let heartbeat = heartbeat::Heartbeat::start(&plan.repo.root, &plan.name, &run)?;
+    let (repo_root, name) = (plan.repo.root.clone(), plan.name.clone());
+    context.on_release(move || {
+        drop(heartbeat);
+        state::remove_run(&repo_root, &name)
+    });
+    if args.capture {
+        invocation.capture = Some(state::run_log_path(
+            &plan.repo.root,
+            &plan.name,
+            run.started_at,
+        )?);
+    }
+    invocation.tail = args.capture_tail.map(tail::OutputTail::new);
+    let started = Instant::now();
+    let results = lock::RunLock::acquire(&plan.path).and_then(|lock| {
+        context.on_release(move || {
+            drop(lock);
+            Ok(())
+        });
+        let _run_dir = run_dir.map(DirectoryGuard::with_path).transpose()?;
+        timings::time(timings::Phase::Command, || {
+            process::block_on(lifecycle::run_steps(&[plan], &invocation, args))
+        })
+    });
+    // Until cleanup the worktree is still the run's, held by its lock and heartbeat
+    let results = results?;
+    let exit_code = steps::exit_code(&results);
+    let attempts = steps::attempts(&results);
+    let duration = started.elapsed();
+    let finished_at = state::unix_now();
+
+    // Looked up and measured before cleanup, which may remove the worktree
+    let head = git::head_commit(&plan.path)
+        .inspect_err(|err| {
+            verbose!(
+                "Warning: failed to read the worktree's HEAD: {}",
+                err.chain()
+            )
+        })
+        .ok();
+    let changes = if args.quiet {
+        None
+    } else {
+        timings::time(timings::Phase::StatusCheck, || summary::measure(plan))
+            .inspect_err(|err| verbose!("Warning: failed to measure changes: {}", err.chain()))
+            .ok()
+    };
+
+    let outcome = cleanup_worktree(plan, context, args, Some(exit_code))?;
+
+    let record = state::RunRecord {
+        name: plan.name.clone(),
+        command: run.command,
+        started_at: run.started_at,
+        duration_ms: duration.as_millis() as u64,
+        finished_at: Some(finished_at),
+        exit_code,
+        attempts,
+        head,
+        reused: provisioning.is_none_or(|provisioning| provisioning.reused),
+        setup_ms: provisioning
+            .filter(|provisioning| !provisioning.reused)
+            .map(|provisioning| provisioning.duration.as_millis() as u64),
+        kept: outcome.kept(),
+        log: invocation.capture.clone(),
+    };
+    // History is informational; a failure to write it shouldn't fail the run
+    if let Err(err) = state::append_history(&plan.repo.root, &record) {
+        verbose!("Warning: failed to record run history: {}", err.chain());
+    }
+
+    if !args.quiet {
+        summary::print_summary(
+            plan,
+            &record,
+            duration,
+            changes.as_ref(),
+            &outcome,
+            invocation.limits.violation(exit_code),
+        );
+    }
+
+    if let CleanupOutcome::Kept(status) = &outcome
+        && plan.config.hints.unwrap_or(true)
+        && !args.quiet
+    {
+        hints::print_hints(plan, status);
+    }
+
+    report_timings(args);
+
+    if let Some(announcer) = &announcer {
+        announcer.finished(exit_code, outcome.kept());
+    }
+
+    if args.notify {
+        notify::finished(
+            plan,
+            &invocation.command,
+            exit_code,
+            duration,
+            outcome.kept(),
+            invocation.tail.as_ref().filter(|_| exit_code != 0),
+        );
+    }
+
+    Ok(exit_code)
+}
+
+// Print the time spent in each phase and send it as an event, with --timings
+fn report_timings(args: &Args) {
+    if args.timings {
+        events::emit("timings", timings::to_json());
+        if !args.quiet {
+            timings::print();
+        }
+    }
+}
+
+// Set up the Nix, direnv and container environments the command runs in
+// Runs from inside the worktree
+fn wrap_invocation(
+    plan: &WorktreePlan,
+    args: &Args,
+    invocation: &mut Invocation,
+    workdir: PathBuf,
+) -> Result<()> {
+    invocation.nix = nix_env(&plan.config, args, &plan.path);
+    if plan.config.direnv.unwrap_or(false) && plan.path.join(".envrc").is_file() {
+        invocation.direnv = Some(plan.path.clone());
+    }
+    invocation.container = worktree_container(plan, args, workdir)?;
+    invocation.limits = limits::Limits::from_args(args);
+    Ok(())
+}
+
+// Nix environment for --nix (or `nix = true`), looked up at the worktree root
+fn nix_env(config: &config::Config, args: &Args, root: &Path) -> Option<nix::NixEnv> {
+    if !args.nix && !config.nix.unwrap_or(false) {
+        return None;
+    }
+
+    let env = nix::NixEnv::detect(root);
+    match &env {
+        Some(env) => verbose!("Using Nix environment: {:?}", env),
+        None => warning!(
+            "Nix requested but no flake.nix, shell.nix or default.nix in {}",
+            root.display()
+        ),
+    }
+    env
+}
+
+// Container for --container, mounting the worktree and as much of the repository's git directory
+// as git needs to work inside it. Nothing the host's git runs or obeys is writable from inside:
+// hooks aren't mounted, and the config files and the worktree's .git file are read-only, so the
+// command can't plant a hook or a core.fsmonitor for the next git call on the host
+fn worktree_container(
+    plan: &WorktreePlan,
+    args: &Args,
+    workdir: PathBuf,
+) -> Result<Option<container::Container>> {
+    use container::Mount;
+
+    let Some(image) = &args.container else {
+        return Ok(None);
+    };
+
+    let common_dir = git::get_common_dir()?;
+    let admin_dir = git::git_dir_of(&plan.path)?;
+    let mut mounts = vec![
+        Mount::writable(plan.path.clone()),
+        Mount::read_only(plan.path.join(".git")),
+        Mount::writable(admin_dir.clone()),
+    ];
+    // Read once extensions.worktreeConfig is set, so it must exist to be mounted read-only
+    let worktree_config = admin_dir.join("config.worktree");
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&worktree_config)?;
+    mounts.push(Mount::read_only(worktree_config));
+    for (entry, writable) in [
+        ("objects", true),
+        ("refs", true),
+        ("logs", true),
+        ("lfs", true),
+        ("config", false),
+        ("HEAD", false),
+        ("packed-refs", false),
+        ("info", false),
+        ("modules", false),
+    ] {
+        let path = common_dir.join(entry);
+        if path.exists() {
+            mounts.push(if writable {
+                Mount::writable(path)
+            } else {
+                Mount::read_only(path)
+            });
+        }
+    }
+    // Symlinked artifact directories point into the shared cache
+    if plan.config.link_artifacts.is_some() {
+        mounts.push(Mount::writable(state::repo_cache_dir(&plan.repo.root)?));
+    }
+
+    verbose!("Running in container image {}", image);
+    container::Container::new(&plan.config, image, mounts, workdir).map(Some)
+}
+
+// Resolve --subdir against the worktree, requiring it to exist after checkout
+fn resolve_subdir(plan: &WorktreePlan, subdir: Option<&Path>) -> Result<Option<PathBuf>> {
+    let Some(subdir) = subdir else {
+        return Ok(None);
+    };
+
+    let escapes = subdir
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+    if escapes {
+        return Err(ArboristError::InvalidPath(format!(
+            "--subdir must be a relative path inside the worktree: {}",
+            subdir.display()
+        )));
+    }
+
+    let dir = plan.path.join(subdir);
+    if !dir.is_dir() {
+        return Err(ArboristError::InvalidPath(format!(
+            "--subdir {} does not exist in the worktree",
+            subdir.display()
+        )));
+    }
+
+    verbose!("Running in subdirectory: {}", subdir.display());
+    Ok(Some(dir))
+}
+
+// What happened to the worktree once the command finished
+enum CleanupOutcome {
+    Kept(git::WorktreeStatus),
+    Removed,
+}
+
+impl CleanupOutcome {
+    fn kept(&self) -> bool {
+        matches!(self, CleanupOutcome::Kept(_))
+    }
+}
+
+// Keep or remove the worktree as the config's policy says for how the command exited
+// (None when it never ran), never removing uncommitted changes or commits it doesn't say to
+// Expects the current directory to be the worktree root, which `context` entered; the context
+// is finished here, leaving the original directory current whatever the outcome
+fn cleanup_worktree(
+    plan: &WorktreePlan,
+    mut context: RunContext,
+    args: &Args,
+    exit_code: Option<i32>,
+) -> Result<CleanupOutcome> {
+    // The run's own lock and heartbeat would otherwise keep the worktree as in use
+    context.release()?;
+    if args.chdir_original {
+        context.check_processes();
+    }
+    verbose!(
+        "Checking worktree status ({})...",
+        status_mode(plan)?.describe()
+    );
+    let excluded = excluded_paths(plan)?;
+    let status = timings::time(timings::Phase::StatusCheck, || {
+        if let Some(patterns) = &plan.config.autoclean
+            && !patterns.is_empty()
+        {
+            verbose!("Deleting untracked files matching: {}", patterns.join(", "));
+            git::clean_paths(patterns).context("deleting untracked files (autoclean)")?;
+        }
+        worktree_status(plan, &excluded).context("checking worktree status")
+    })?;
+    let outcome = timings::time(timings::Phase::Cleanup, || {
+        apply_cleanup_policy(plan, context, args, exit_code, &excluded, status)
+    })?;
+    if outcome.kept() {
+        audit::note_cleanup(plan, audit::Cleanup::Kept);
+    }
+    Ok(outcome)
+}
+
+// Keep or remove the worktree according to its status and the cleanup policy for the command's
+// exit code
+fn apply_cleanup_policy(
+    plan: &WorktreePlan,
+    context: RunContext,
+    args: &Args,
+    exit_code: Option<i32>,
+    excluded: &[String],
+    mut status: git::WorktreeStatus,
+) -> Result<CleanupOutcome> {
+    let run_action = match exit_code {
+        Some(0) => plan.config.on_success,
+        _ => plan.config.on_failure,
+    }
+    .unwrap_or_default();
+    // Commits that are safe elsewhere, or that the policy says to drop
+    let mut drop_commits = false;
+    if run_action == config::RunAction::Clean {
+        let on_changes = if args.interactive {
+            config::ChangesAction::Prompt
+        } else {
+            plan.config.on_changes.unwrap_or_default()
+        };
+        if status.has_changes {
+            match on_changes {
+                config::ChangesAction::Keep => {}
+                // Let the user decide what happens to the changes; without a terminal they are kept
+                config::ChangesAction::Prompt if interactive::is_available() => {
+                    if let Err(err) = interactive::resolve_changes(plan, excluded, args.signing()) {
+                        warning!("{}; keeping the worktree", err.chain());
+                    }
+                    status = worktree_status(plan, excluded).context("checking worktree status")?;
+                }
+                config::ChangesAction::Prompt => {}
+                config::ChangesAction::Clean => {
+                    verbose!("Discarding uncommitted changes (on_changes = \"clean\")");
+                    if let Some(dir) = archive::archive_worktree(plan)? {
+                        archive::report(&plan.name, &dir);
+                    }
+                    git::discard_changes(excluded)?;
+                    status = worktree_status(plan, excluded).context("checking worktree status")?;
+                }
+            }
+        }
+
+        if status.commits_ahead > 0 && !status.has_changes {
+            drop_commits = match plan.config.on_commits.unwrap_or_default() {
+                config::CommitsAction::Keep => false,
+                config::CommitsAction::Clean => {
+                    verbose!(
+                        "Dropping {} commit(s) (on_commits = \"clean\")",
+                        status.commits_ahead
+                    );
+                    if let Some(dir) = archive::archive_worktree(plan)? {
+                        archive::report(&plan.name, &dir);
+                    }
+                    true
+                }
+                config::CommitsAction::MergeBack => merge_back(plan, args.signing()),
+            };
+        }
+    }
+
+    let keep = run_action == config::RunAction::Keep
+        || status.has_changes
+        || status.commits_ahead > 0 && !drop_commits;
+    events::emit(
+        "cleanup_decision",
+        json!({
+            "name": plan.name,
+            "decision": if keep { "keep" } else { "remove" },
+            "has_changes": status.has_changes,
+            "commits_ahead": status.commits_ahead,
+            "status_mode": status_mode(plan)?.describe(),
+        }),
+    );
+
+    if run_action == config::RunAction::Keep {
+        verbose!(
+            "Keeping worktree at: {} (policy for a {} command)",
+            plan.path.display(),
+            if exit_code == Some(0) {
+                "successful"
+            } else {
+                "failed"
+            }
+        );
+        Ok(CleanupOutcome::Kept(status))
+    } else if status.has_changes {
+        verbose!("Note: Uncommitted changes exist in worktree");
+        verbose!("Keeping worktree at: {}", plan.path.display());
+        Ok(CleanupOutcome::Kept(status))
+    } else if status.commits_ahead > 0 && !drop_commits {
+        verbose!("Note: {} unpushed commit(s) exist", status.commits_ahead);
+        verbose!("Keeping worktree at: {}", plan.path.display());
+        Ok(CleanupOutcome::Kept(status))
+    } else if let Some(reason) = in_use_reason(plan)? {
+        // Another run is still using it, or the user pinned it
+        notice!("Keeping worktree {}: {}", plan.name, reason);
+        Ok(CleanupOutcome::Kept(status))
+    } else {
+        verbose!("No changes detected, removing worktree...");
+        // Back in the original directory, with the run's holds released, before it goes
+        context
+            .finish_and_remove(|_| remove_planned_worktree(plan, false))
+            .context(format!("removing worktree {}", plan.path.display()))?;
+        state::remove_worktree_meta(&plan.repo.root, &plan.name)?;
+        emit_removed(plan);
+        verbose!("Worktree removed");
+        Ok(CleanupOutcome::Removed)
+    }
+}
+
+// Merge the worktree's branch into the branch checked out in the main checkout
+// (on_commits = "merge-back"); on failure the worktree is kept, so nothing is lost
+// Returns: whether the commits were merged
+fn merge_back(plan: &WorktreePlan, sign: Option<bool>) -> bool {
+    let Some(branch) = plan.owned_branch() else {
+        return false;
+    };
+    if plan.repo.is_bare {
+        warning!(
+            "can't merge {} back into a bare repository; keeping the worktree",
+            branch
+        );
+        return false;
+    }
+
+    let merged = git::checked_out_branch(&plan.repo.root).and_then(|target| match target {
+        Some(target) => {
+            Ok(
+                git::merge_into(&plan.repo.root, branch, &plan.config.identity(), sign)?
+                    .then_some(target),
+            )
+        }
+        None => Err(ArboristError::GitOperationFailed(format!(
+            "{} has a detached HEAD",
+            plan.repo.root.display()
+        ))),
+    });
+    match merged {
+        Ok(Some(target)) => {
+            notice!(
+                "{} merged {} into {}",
+                style::info("arborist:"),
+                branch,
+                target
+            );
+            true
+        }
+        Ok(None) => {
+            warning!(
+                "merging {} back conflicted and was aborted; keeping the worktree",
+                branch
+            );
+            false
+        }
+        Err(err) => {
+            warning!(
+                "couldn't merge {} back: {}; keeping the worktree",
+                branch,
+                err.chain()
+            );
+            false
+        }
+    }
+}
+
+// Why a worktree mustn't be removed: a command running in it, or a lock (other than one
+// left by a run that died, which is released)
+fn in_use_reason(plan: &WorktreePlan) -> Result<Option<String>> {
+    if let Some(run) = heartbeat::active_run(&plan.repo.root, &plan.name)? {
+        return Ok(Some(format!(
+            "in use by the arborist run in process {}",
+            run.pid
+        )));
+    }
+
+    let mut worktrees = git::list_worktrees()?;
+    worktrees.retain(|entry| entry.path == plan.path);
+    lock::release_stale(&plan.repo, &mut worktrees)?;
+    Ok(worktrees
+        .into_iter()
+        .find(|entry| entry.locked)
+        .map(|entry| match lock::active_run(&entry) {
+            Some(pid) => format!("in use by the arborist run in process {}", pid),
+            None => format!(
+                "locked ({})",
+                entry.lock_reason.as_deref().unwrap_or("no reason given")
+            ),
+        }))
+}
+
+// Report a removed worktree in a worktree_removed event and in the audit log
+fn emit_removed(plan: &WorktreePlan) {
+    audit::note_cleanup(plan, audit::Cleanup::Removed);
+    events::emit(
+        "worktree_removed",
+        json!({ "name": plan.name, "path": plan.path.to_string_lossy(), "branch": plan.branch }),
+    );
+}
+
+// --untracked, which wins over the config's untracked for every worktree of the invocation
+static UNTRACKED: OnceLock<git::UntrackedFiles> = OnceLock::new();
+
+// --upstream, which wins over the config's upstream in the same way
+static UPSTREAM: OnceLock<String> = OnceLock::new();
+
+// How the worktree is checked for changes, as fast_status says
+fn status_mode(plan: &WorktreePlan) -> Result<git::StatusMode> {
+    git::StatusMode::detect(
+        plan.config.fast_status.unwrap_or(false),
+        untracked_files(plan),
+    )
+}
+
+// How untracked files count: as --untracked or the config's untracked says, else not at all with
+// fast_status
+fn untracked_files(plan: &WorktreePlan) -> git::UntrackedFiles {
+    UNTRACKED
+        .get()
+        .copied()
+        .or(plan.config.untracked)
+        .unwrap_or(if plan.config.fast_status.unwrap_or(false) {
+            git::UntrackedFiles::No
+        } else {
+            git::UntrackedFiles::Normal
+        })
+}
+
+// The branch a new worktree branch tracks: as --upstream or the config's upstream says, else the
+// branch it was created from. None when it tracks nothing, either because "none" was asked for or
+// because it came from a detached HEAD
+fn worktree_upstream(plan: &WorktreePlan) -> Option<&str> {
+    match UPSTREAM
+        .get()
+        .map(String::as_str)
+        .or(plan.config.upstream.as_deref())
+    {
+        Some("none") => None,
+        Some(upstream) => Some(upstream),
+        None => plan.repo.upstream(),
+    }
+}
+
+// Paths that never count as changes: linked and copied artifact directories, copied hooks, and
+// those the config says to ignore
+fn excluded_paths(plan: &WorktreePlan) -> Result<Vec<String>> {
+    let mut excluded: Vec<String> = [
+        &plan.config.link_artifacts,
+        &plan.config.copy_cow,
+        &plan.config.ignore_changes,
+    ]
+    .into_iter()
+    .flatten()
+    .flatten()
+    .cloned()
+    .collect();
+    excluded.extend(githooks::copied_hooks_dir(plan)?);
+    excluded.extend(templates::destinations(plan));
+    Ok(excluded)
+}
+
+// Status of the worktree in the current directory. Commits are counted against the
+// recorded base as well as the upstream, since the upstream may be unset or point elsewhere
+fn worktree_status(plan: &WorktreePlan, excluded: &[String]) -> Result<git::WorktreeStatus> {
+    let mut status = git::get_worktree_status(excluded, status_mode(plan)?)?;
+    // Only uncommitted changes keep a worktree whose branch isn't arborist's: an existing
+    // branch holds on to its commits without the worktree, and detached worktrees are for
+    // read-only runs
+    if !plan.owns_branch {
+        status.commits_ahead = 0;
+        return Ok(status);
+    }
+
+    if let Some(meta) = state::load_worktree(&plan.repo.root, &plan.name)? {
+        // Commits already on the branch the worktree came from are safe to drop
+        let base = if meta.base_branch != "HEAD" && git::branch_exists(&meta.base_branch)? {
+            meta.base_branch
+        } else {
+            meta.base_commit
+        };
+        let unmerged = git::count_commits_not_in(&base)?;
+        status.commits_ahead = status.commits_ahead.max(unmerged);
+    }
+
+    Ok(status)
+}
+
+// Print a one-time summary of what arborist creates and deletes in this repository
+fn print_first_run_report(repo: &git::GitRepo, worktree_path: &Path, branch_name: &str) {
+    let worktree_root = worktree_path.parent().unwrap_or(worktree_path);
+
+    eprintln!(
+        "{} first run in {}",
+        style::info("arborist:"),
+        repo.root.display()
+    );
+    eprintln!("  Worktrees are created under: {}", worktree_root.display());
+    eprintln!(
+        "  Branches are created in the 'arborist/' namespace (this run: {})",
+        branch_name
+    );
+    eprintln!("  When the command exits, a worktree with no uncommitted changes and no");
+    eprintln!("  unpushed commits is force-removed and its branch deleted (git branch -D).");
+    eprintln!("  Worktrees with changes or commits are kept.");
+    eprintln!("This report is shown once per repository; pass --yes to skip it.");
+}
+
+// Run the command, re-running it on retryable failures
+// Returns: (final exit code, number of attempts)
+async fn execute_with_retries(invocation: &Invocation, args: &Args) -> Result<(i32, u32)> {
+    let mut attempts = 1;
+    loop {
+        let exit_code = execute_shell_command(invocation, args).await?;
+
+        let retryable = exit_code != 0
+            && !cancel::is_cancelled()
+            && (args.retry_on.is_empty() || args.retry_on.contains(&exit_code));
+        if !retryable || attempts > args.retries {
+            return Ok((exit_code, attempts));
+        }
+
+        eprintln!(
+            "arborist: command exited with {}, retrying ({}/{})...",
+            exit_code, attempts, args.retries
+        );
+        async_io::Timer::after(args.retry_delay).await;
+        attempts += 1;
+    }
+}
+
+// Check a worktree name given with --name, which becomes a directory and part of a branch
+fn parse_worktree_name(value: &str) -> std::result::Result<String, String> {
+    let valid = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && !value.is_empty()
+        && !value.starts_with(['-', '.'])
+        && !value.ends_with(".lock")
+        && !value.contains("..");
+    if valid {
+        Ok(value.to_string())
+    } else {
+        Err(format!(
+            "invalid worktree name '{}': it must start with a letter or digit and contain only letters, digits, '-', '_' and '.'",
+            value
+        ))
+    }
+}
+
+// Parse durations like "500ms", "5s", "2m", "1h" (plain numbers are seconds)
+fn parse_duration(value: &str) -> std::result::Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", value))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        "d" => number * 86400.0,
+        _ => return Err(format!("invalid duration unit '{}' in '{}'", unit, value)),
+    };
+
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| format!("duration '{}' is out of range", value))
+}
+
+// Execute shell command, killing it when it outlives an interrupt's grace period
+async fn execute_shell_command(invocation: &Invocation, args: &Args) -> Result<i32> {
+    if invocation.command.is_empty() {
+        return Ok(0);
+    }
+    if args.pty {
+        return pty::run(invocation).await;
+    }
+
+    let mut command = invocation.command();
+    // Where this command's output starts in the log, for the tail to read it from there
+    let mut log_offset = 0;
+    if let Some(log) = &invocation.capture {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log)
+            .context(format!("opening log file {}", log.display()))?;
+        log_offset = file.metadata()?.len();
+        command.stdout(file.try_clone()?).stderr(file);
+    }
+
+    match (&invocation.capture, &invocation.label, &invocation.tail) {
+        (None, Some(label), tail) => {
+            // Both streams share one pipe, so lines keep the order they were written in
+            let (reader, writer) = std::io::pipe()?;
+            command.stdout(writer.try_clone()?).stderr(writer);
+            let mut child = command.spawn()?;
+            // Only the command keeps the write end once it has started, so reading ends when it
+            // exits
+            drop(command);
+            let labelled = copy_labelled(reader, label, tail.as_ref());
+            let (exit_code, copied) =
+                futures_lite::future::zip(cancel::wait(&mut child), labelled).await;
+            copied?;
+            exit_code
+        }
+        (None, None, Some(tail)) => tail::run(command, tail).await,
+        (Some(log), _, Some(tail)) => {
+            let exit_code = cancel::wait(&mut command.spawn()?).await;
+            tail::read_log(tail, log, log_offset)?;
+            exit_code
+        }
+        _ => cancel::wait(&mut command.spawn()?).await,
+    }
+}
+
+// Exit code for failures of arborist itself, kept apart from the command's own exit codes
+// Read from the user config only, since the repository may be what failed to load
+fn error_exit_code() -> i32 {
+    config::load(None)
+        .ok()
+        .and_then(|config| config.error_exit_code)
+        .unwrap_or(DEFAULT_ERROR_EXIT_CODE)
+}
+
+// Copy a running command's output line by line behind a label until it exits
+async fn copy_labelled(
+    reader: std::io::PipeReader,
+    label: &str,
+    tail: Option<&tail::OutputTail>,
+) -> Result<()> {
+    use futures_lite::AsyncBufReadExt;
+
+    let mut reader = futures_lite::io::BufReader::new(async_io::Async::new(reader)?);
+    let mut stdout = std::io::stdout();
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line).await? > 0 {
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        writeln!(stdout, "{} {}", label, String::from_utf8_lossy(text))?;
+        if let Some(tail) = tail {
+            tail.push(text);
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+// Select the worktree name for a run of `command` based on --select (or --random)
+fn select_name(args: &Args, command: &[String]) -> Result<String> {
+    if let Some(name) = &args.name {
+        return Ok(name.clone());
+    }
+    let selection = if args.random {
+        Selection::Random
+    } else {
+        args.select.unwrap_or(Selection::Parent)
+    };
+    Ok(match selection {
+        Selection::Parent => select_color_deterministic(),
+        Selection::Random => select_color_random(),
+        Selection::CmdHash => command_name(command),
+        Selection::Slug => unused_name(&command_slug(args, command))?,
+    })
+}
+
+// Words of the command as a name that is valid in a branch, e.g. "claude-fix-auth" for
+// `claude "fix auth"`; a -c script is named after the script rather than the shell
+fn command_slug(args: &Args, command: &[String]) -> String {
+    let words: Vec<String> = match &args.shell_command {
+        Some(script) => vec![script.clone()],
+        None => command
+            .iter()
+            .enumerate()
+            .filter(|(i, arg)| *i == 0 || !arg.starts_with('-'))
+            .map(|(i, arg)| match Path::new(arg).file_name() {
+                Some(program) if i == 0 => program.to_string_lossy().into_owned(),
+                _ => arg.clone(),
+            })
+            .collect(),
+    };
+
+    let mut slug = String::new();
+    for c in words.join(" ").chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    if slug.len() > SLUG_MAX_LEN {
+        // Cut at a word boundary when there is one
+        slug.truncate(SLUG_MAX_LEN);
+        if let Some(end) = slug.rfind('-') {
+            slug.truncate(end);
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "shell".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+// `name`, or with the first numeric suffix (name-2, name-3, ...) not used by a worktree or
+// arborist branch
+fn unused_name(name: &str) -> Result<String> {
+    let repo_root = git::get_repo_info()?.map(|repo| repo.root);
+    first_unused(name, |candidate| {
+        let recorded = match &repo_root {
+            Some(root) => state::load_worktree(root, candidate)?.is_some(),
+            None => false,
+        };
+        Ok(recorded || git::branch_exists(&format!("arborist/{}", candidate))?)
+    })
+}
+
+// `name`, or the first of name-2, name-3, ... that isn't `used`
+fn first_unused(name: &str, mut used: impl FnMut(&str) -> Result<bool>) -> Result<String> {
+    for number in 1.. {
+        let candidate = match number {
+            1 => name.to_string(),
+            _ => format!("{}-{}", name, number),
+        };
+        if !used(&candidate)? {
+            return Ok(candidate);
+        }
+    }
+    unreachable!("some numeric suffix is always unused")
+}
+
+// Name for a command line's own worktree: the program and a hash of the whole argv,
+// e.g. "cargo-3f9a1c2e"
+fn command_name(command: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    for arg in command {
+        hasher.update(arg.as_bytes());
+        hasher.update([0]);
+    }
+    let hash = format!("{:x}", hasher.finalize());
+
+    let program: String = command
+        .first()
+        .and_then(|program| Path::new(program).file_name())
+        .map(|program| program.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "shell".to_string())
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("{}-{}", program.trim_matches('-'), &hash[..8])
+}
+
+// Random color selection (works on all platforms)
+fn select_color_random() -> String {
+    let mut rng = rand::rng();
+    COLORS
+        .choose(&mut rng)
+        .expect("Color palette should not be empty")
+        .to_string()
+}
+
+// Deterministic color selection based on parent process ID (Unix only)
+#[cfg(unix)]
+fn select_color_deterministic() -> String {
+    let parent_pid = std::os::unix::process::parent_id();
+    let index = (parent_pid as usize) % COLORS.len();
+    COLORS[index].to_string()
+}
+
+// Fallback to random selection on non-Unix platforms
+#[cfg(not(unix))]
+fn select_color_deterministic() -> String {
+    select_color_random()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slug(argv: &[&str]) -> String {
+        let args = Args::try_parse_from([&["arborist"], argv].concat()).unwrap();
+        command_slug(&args, &args.command)
+    }
+
+    #[test]
+    fn slugs_name_commands_by_program_and_words() {
+        assert_eq!(slug(&["claude", "fix auth"]), "claude-fix-auth");
+        assert_eq!(slug(&["/usr/bin/cargo", "--release", "test"]), "cargo-test");
+        assert_eq!(slug(&["-c", "make check && ./deploy"]), "make-check-deploy");
+        assert_eq!(slug(&["-c", "!!"]), "shell");
+    }
+
+    #[test]
+    fn long_slugs_are_cut_at_a_word() {
+        let long = slug(&["claude", &"refactor the parser ".repeat(5)]);
+        assert!(long.len() <= SLUG_MAX_LEN);
+        assert!(long.starts_with("claude-refactor-the-parser"));
+        assert!(!long.ends_with('-'));
+        assert!(
+            ["refactor", "the", "parser"]
+                .iter()
+                .any(|word| long.ends_with(word))
+        );
+    }
+
+    #[test]
+    fn numbers_names_already_in_use() {
+        let used = ["fix", "fix-2", "fix-auth"];
+        let unused = |name| first_unused(name, |candidate| Ok(used.contains(&candidate)));
+        assert_eq!(unused("fix").unwrap(), "fix-3");
+        assert_eq!(unused("fix-auth").unwrap(), "fix-auth-2");
+        assert_eq!(unused("fi").unwrap(), "fi");
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration(" 2m "), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 86400)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5 minutes").is_err());
+        assert!(parse_duration("1e400").is_err());
+        assert!(parse_duration("99999999999999999999d").is_err());
+    }
+
+    #[test]
+    fn worktree_names_must_be_safe_in_paths_and_branches() {
+        assert!(parse_worktree_name("fix-auth_2.0").is_ok());
+        for name in ["", "-x", ".hidden", "a..b", "x.lock", "a/b", "tab\tname"] {
+            assert!(
+                parse_worktree_name(name).is_err(),
+                "{:?} was accepted",
+                name
+            );
+        }
+    }
 }
@@ -0,0 +1,175 @@
+use crate::{
+    Args, DirectoryGuard, WorktreePlan, emit_removed, excluded_paths, heartbeat,
+    is_arborist_worktree, parse_duration, pick, plan_worktree, pool, prune,
+    remove_planned_worktree, worktree_status,
+};
+use arborist::error::{ArboristError, Result};
+use arborist::{config, git, state};
+use std::collections::BTreeMap;
+
+// A worktree nothing is using, which maintenance may remove
+struct Idle {
+    plan: WorktreePlan,
+    status: git::WorktreeStatus,
+    // When it was created or last finished a run, in seconds since the Unix epoch
+    last_used: u64,
+}
+
+/// Housekeeping of one repository's worktrees, as the daemon does it: forgetting missing
+/// worktrees, removing idle ones unused for longer than worktree_ttl or beyond max_worktrees,
+/// and parking worktrees until the pool holds pool_size
+/// Worktrees with uncommitted changes, locked ones and those in use are never removed, and
+/// branches with commits outlive their worktrees
+/// Expects the current directory to be in the repository
+pub fn maintain(repo: &git::GitRepo, args: &Args) -> Result<()> {
+    let config = config::load((!repo.is_bare).then_some(repo.root.as_path()))?;
+    prune::prune_stale(repo)?;
+
+    let ttl = config
+        .worktree_ttl
+        .as_deref()
+        .map(parse_duration)
+        .transpose()
+        .map_err(|err| ArboristError::InvalidConfig(format!("worktree_ttl: {}", err)))?;
+    let mut idle = idle_worktrees(repo)?;
+    if let Some(ttl) = ttl {
+        let cutoff = state::unix_now().saturating_sub(ttl.as_secs());
+        let (expired, fresh) = idle.into_iter().partition(|idle| idle.last_used < cutoff);
+        idle = fresh;
+        for worktree in expired {
+            retire(&worktree, "unused for longer than worktree_ttl")?;
+        }
+    }
+
+    let mut parked = pool::parked(repo)?;
+    let mut count = arborist_worktrees(repo)?;
+    if let Some(max) = config.max_worktrees {
+        // The pool goes first, as it is only there to save time
+        while count > max && !parked.is_empty() {
+            let plan = parked.remove(0);
+            // Claimed by a run since the pool was listed
+            if !pool::take(&plan)? {
+                continue;
+            }
+            verbose!("Removing parked worktree {} (max_worktrees)", plan.name);
+            git::unlock_worktree(&plan.path)?;
+            if let Err(err) = remove_planned_worktree(&plan, false) {
+                pool::put_back(&plan)?;
+                return Err(err);
+            }
+            state::remove_worktree_meta(&plan.repo.root, &plan.name)?;
+            emit_removed(&plan);
+            count -= 1;
+        }
+        while count > max && !idle.is_empty() {
+            retire(&idle.remove(0), "beyond max_worktrees")?;
+            count -= 1;
+        }
+    }
+
+    // Until arborist has run in the repository once, its first-run report is still due
+    if let Some(size) = config.pool_size
+        && state::is_acknowledged(&repo.root)?
+    {
+        // Parked worktrees only take room left over by those in use
+        let size = match config.max_worktrees {
+            Some(max) => size.min(max.saturating_sub(count - parked.len())),
+            None => size,
+        };
+        let created = pool::warm_up(repo, args, size)?;
+        if created > 0 {
+            verbose!("Parked {} worktree(s) in the pool", created);
+        }
+    }
+
+    Ok(())
+}
+
+// How many worktrees arborist has in the repository, parked ones included
+fn arborist_worktrees(repo: &git::GitRepo) -> Result<usize> {
+    let mut count = 0;
+    for entry in git::list_worktrees()? {
+        if entry.path != repo.root && is_arborist_worktree(repo, &entry)? {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+// Worktrees that could be removed without losing anything, least recently used first
+fn idle_worktrees(repo: &git::GitRepo) -> Result<Vec<Idle>> {
+    // When each worktree last finished a run
+    let mut finished = BTreeMap::new();
+    for record in state::load_history(&state::repo_state_dir(&repo.root)?)? {
+        let end = record.finished_at();
+        let latest = finished.entry(record.name).or_insert(0);
+        *latest = end.max(*latest);
+    }
+
+    let mut idle = Vec::new();
+    for entry in git::list_worktrees()? {
+        if entry.path == repo.root
+            || entry.locked
+            || !entry.path.is_dir()
+            || !is_arborist_worktree(repo, &entry)?
+        {
+            continue;
+        }
+        let Some(name) = pick::worktree_name(repo, &entry)? else {
+            continue;
+        };
+        if heartbeat::active_run(&repo.root, &name)?.is_some() {
+            continue;
+        }
+        let meta = state::load_worktree(&repo.root, &name)?;
+        if meta.as_ref().is_some_and(|meta| meta.parked) {
+            continue;
+        }
+
+        let plan = plan_worktree(repo.clone(), name)?;
+        let status = {
+            let _guard = DirectoryGuard::with_path(&plan.path)?;
+            worktree_status(&plan, &excluded_paths(&plan)?)?
+        };
+        if status.has_changes {
+            continue;
+        }
+
+        let created = meta.map_or(0, |meta| meta.created_at);
+        let last_used = finished.get(&plan.name).copied().unwrap_or(0).max(created);
+        idle.push(Idle {
+            plan,
+            status,
+            last_used,
+        });
+    }
+    idle.sort_by_key(|idle| idle.last_used);
+
+    Ok(idle)
+}
+
+// Remove an idle worktree, keeping its branch when that has commits of its own
+fn retire(idle: &Idle, reason: &str) -> Result<()> {
+    let plan = &idle.plan;
+    // It may have started a run since it was found idle
+    if heartbeat::active_run(&plan.repo.root, &plan.name)?.is_some() {
+        return Ok(());
+    }
+
+    if idle.status.commits_ahead > 0 {
+        remove_planned_worktree(plan, true)?;
+        notice!(
+            "Removed worktree {} ({}), keeping branch {}",
+            plan.name,
+            reason,
+            plan.branch_name()
+        );
+    } else {
+        remove_planned_worktree(plan, false)?;
+        notice!("Removed worktree {} ({})", plan.name, reason);
+    }
+    state::remove_worktree_meta(&plan.repo.root, &plan.name)?;
+    state::remove_run(&plan.repo.root, &plan.name)?;
+    emit_removed(plan);
+    Ok(())
+}
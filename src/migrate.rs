@@ -0,0 +1,277 @@
+use crate::artifacts;
+use arborist::error::{ArboristError, Result};
+use arborist::{git, state};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// `arborist migrate`: move state and caches kept under /tmp/arborist by earlier versions
+// into the state and cache directories
+pub fn run_migrate() -> Result<i32> {
+    let mut moved = 0;
+    // Worktrees may link into the cache, so moved caches leave a link behind
+    for (from, to, link_back) in [
+        (state::legacy_state_root(), state::state_root(), false),
+        (state::legacy_cache_root(), state::cache_root(), true),
+    ] {
+        if from == to || !from.is_dir() {
+            continue;
+        }
+        fs::create_dir_all(&to)?;
+
+        for entry in fs::read_dir(&from)? {
+            let entry = entry?;
+            // Left behind by an earlier migration
+            if entry.file_type()?.is_symlink() {
+                continue;
+            }
+
+            let source = entry.path();
+            let target = to.join(entry.file_name());
+            if target.symlink_metadata().is_ok() {
+                if !source.is_dir() || !target.is_dir() {
+                    warning!(
+                        "{} already exists; leaving {} in place",
+                        target.display(),
+                        source.display()
+                    );
+                    continue;
+                }
+                // Arborist already ran in the repository since upgrading
+                merge_dir(&source, &target)?;
+                notice!("Merged {} into {}", source.display(), target.display());
+            } else {
+                move_path(&source, &target)?;
+                notice!("Moved {} to {}", source.display(), target.display());
+            }
+            if link_back && !source.exists() {
+                artifacts::symlink_dir(&target, &source)?;
+            }
+            moved += 1;
+        }
+    }
+
+    if moved == 0 {
+        notice!("Nothing to migrate");
+    }
+    Ok(0)
+}
+
+/// Whether state from an earlier version is waiting to be migrated
+pub fn is_pending() -> bool {
+    let legacy = state::legacy_state_root();
+    legacy != state::state_root()
+        && fs::read_dir(&legacy).is_ok_and(|mut entries| entries.next().is_some())
+}
+
+/// Keeps arborist's record of a repository pointing at it
+/// State kept under the hash of the repository's root by earlier versions, or left under the
+/// hash of where it used to be, is moved under a new identity for the repository; and when a
+/// repository that has one has moved since arborist last saw it, its worktrees are repaired
+pub fn identify_repo(repo: &git::GitRepo) -> Result<()> {
+    let Some(id) = git::repo_id(&repo.root)? else {
+        // Repositories arborist has no state for get an identity with their first worktree
+        if let Some((state_dir, old_root)) = find_legacy_state(repo)? {
+            adopt_legacy_state(repo, &state_dir, &old_root)?;
+        }
+        return Ok(());
+    };
+
+    let Some(recorded) = state::recorded_root(&repo.root)? else {
+        return Ok(());
+    };
+    if recorded == repo.root {
+        return Ok(());
+    }
+
+    if recorded.exists() {
+        // Run from one of the repository's own checkouts, or from a copy of it, which
+        // carried the identity along with its config and needs one of its own
+        if git::repo_id(&recorded)?.as_deref() == Some(id.as_str())
+            && !same_repository(&recorded, &repo.root)?
+        {
+            verbose!(
+                "{} is a copy of {}; giving it an identity of its own",
+                repo.root.display(),
+                recorded.display()
+            );
+            git::create_repo_id(&repo.root)?;
+        }
+        return Ok(());
+    }
+    // A checkout of the repository that has since been removed
+    if git::list_worktrees()?
+        .iter()
+        .any(|entry| entry.path == recorded)
+    {
+        return Ok(());
+    }
+
+    follow_move(repo, &recorded)
+}
+
+// The state directory of a repository without an identity: the one under the hash of its
+// root, or for a repository that moved, the one whose worktrees are registered with it
+// Returns: (state directory, root the repository had when the state was recorded)
+fn find_legacy_state(repo: &git::GitRepo) -> Result<Option<(PathBuf, PathBuf)>> {
+    let state_dir = state::state_root().join(git::path_hash(&repo.root));
+    if state_dir.is_dir() {
+        return Ok(Some((state_dir, repo.root.clone())));
+    }
+
+    let mut worktrees = None;
+    for (state_dir, old_root) in state::known_repos()? {
+        if old_root.exists() {
+            continue;
+        }
+        let Some(hash) = state_dir.file_name() else {
+            continue;
+        };
+        let roots = [
+            PathBuf::from(git::DEFAULT_WORKTREE_ROOT).join(hash),
+            git::worktree_root().join(hash),
+            old_root.clone(),
+        ];
+        let recorded: Vec<PathBuf> = state::list_worktree_meta(&state_dir)?
+            .into_iter()
+            .filter_map(|(_, meta)| meta.path)
+            .collect();
+
+        // The main checkout comes first and is where the repository is now
+        let worktrees = match &mut worktrees {
+            Some(worktrees) => worktrees,
+            None => worktrees.insert(git::list_worktrees()?),
+        };
+        if worktrees.iter().skip(1).any(|entry| {
+            roots.iter().any(|root| entry.path.starts_with(root)) || recorded.contains(&entry.path)
+        }) {
+            return Ok(Some((state_dir, old_root)));
+        }
+    }
+
+    Ok(None)
+}
+
+// Move state from under the hash of a repository's old root to under a new identity
+fn adopt_legacy_state(repo: &git::GitRepo, state_dir: &Path, old_root: &Path) -> Result<()> {
+    let Some(hash) = state_dir.file_name() else {
+        return Ok(());
+    };
+    let id = git::create_repo_id(&repo.root)?;
+    verbose!(
+        "Moving state of {} from {} under its identity {}",
+        repo.root.display(),
+        state_dir.display(),
+        id
+    );
+
+    move_path(state_dir, &state::state_root().join(&id))?;
+    // Worktrees may link into the cache, so it leaves a link behind
+    let cache_dir = state::cache_root().join(hash);
+    let new_cache_dir = state::cache_root().join(&id);
+    if cache_dir.is_dir() && !new_cache_dir.exists() {
+        move_path(&cache_dir, &new_cache_dir)?;
+        artifacts::symlink_dir(&new_cache_dir, &cache_dir)?;
+    }
+
+    // Worktrees from before paths were recorded are where they used to be created, which
+    // no longer follows from the repository's identity
+    let state_dir = state::repo_state_dir(&repo.root)?;
+    for (name, mut meta) in state::list_worktree_meta(&state_dir)? {
+        if meta.path.is_none() {
+            meta.path = Some(if repo.is_bare {
+                old_root.join(format!("arborist-{}", name))
+            } else {
+                PathBuf::from(git::DEFAULT_WORKTREE_ROOT)
+                    .join(hash)
+                    .join(&name)
+            });
+            state::record_worktree(&repo.root, &name, &meta)?;
+        }
+    }
+
+    if old_root != repo.root {
+        follow_move(repo, old_root)?;
+    }
+    Ok(())
+}
+
+// Re-link a repository that moved from old_root with its worktrees, moving the recorded
+// paths of worktrees that were inside it along with it
+fn follow_move(repo: &git::GitRepo, old_root: &Path) -> Result<()> {
+    notice!(
+        "Repository moved from {}; repairing its worktrees",
+        old_root.display()
+    );
+
+    let state_dir = state::repo_state_dir(&repo.root)?;
+    let mut moved = Vec::new();
+    for (name, mut meta) in state::list_worktree_meta(&state_dir)? {
+        let Some(path) = meta
+            .path
+            .as_ref()
+            .and_then(|path| path.strip_prefix(old_root).ok())
+            .map(|rest| repo.root.join(rest))
+        else {
+            continue;
+        };
+        meta.path = Some(path.clone());
+        state::record_worktree(&repo.root, &name, &meta)?;
+        if path.exists() {
+            moved.push(path);
+        }
+    }
+
+    git::repair_worktrees(&repo.root, &moved)?;
+    state::record_repo(&repo.root)
+}
+
+// Whether two checkouts belong to the same repository
+fn same_repository(a: &Path, b: &Path) -> Result<bool> {
+    Ok(git::common_dir_of(a)? == git::common_dir_of(b)?)
+}
+
+// Move everything in source into the existing directory target, appending to the run history
+// and keeping target's copy of any other file both have
+fn merge_dir(source: &Path, target: &Path) -> Result<()> {
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let from = entry.path();
+        let to = target.join(entry.file_name());
+        if to.symlink_metadata().is_err() {
+            move_path(&from, &to)?;
+        } else if from.is_dir() && to.is_dir() {
+            merge_dir(&from, &to)?;
+        } else if entry.file_name() == state::HISTORY_FILE {
+            // Earlier runs go first, keeping the history in order
+            let mut history = fs::read(&from)?;
+            history.extend(fs::read(&to)?);
+            fs::write(&to, history)?;
+            fs::remove_file(&from)?;
+        } else {
+            fs::remove_file(&from)?;
+        }
+    }
+
+    fs::remove_dir(source)?;
+    Ok(())
+}
+
+// Rename, or copy and delete when the directories are on different filesystems (as /tmp
+// often is)
+fn move_path(source: &Path, target: &Path) -> Result<()> {
+    match fs::rename(source, target) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            if source.is_dir() {
+                artifacts::clone_tree(source, target)?;
+                fs::remove_dir_all(source)?;
+            } else {
+                fs::copy(source, target)?;
+                fs::remove_file(source)?;
+            }
+            Ok(())
+        }
+        Err(err) => Err(ArboristError::IoError(err)),
+    }
+}
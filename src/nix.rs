@@ -0,0 +1,52 @@
+use crate::tools;
+use std::path::{Path, PathBuf};
+
+/// A Nix development environment found at the root of a worktree
+#[derive(Debug, Clone)]
+pub enum NixEnv {
+    /// flake.nix, entered with `nix develop`
+    Flake(PathBuf),
+    /// shell.nix or default.nix, entered with `nix-shell`
+    Shell(PathBuf),
+}
+
+impl NixEnv {
+    /// Looks for flake.nix, then shell.nix, then default.nix in `root`
+    pub fn detect(root: &Path) -> Option<Self> {
+        if root.join("flake.nix").is_file() {
+            return Some(NixEnv::Flake(root.to_path_buf()));
+        }
+
+        ["shell.nix", "default.nix"]
+            .iter()
+            .map(|file| root.join(file))
+            .find(|path| path.is_file())
+            .map(NixEnv::Shell)
+    }
+
+    /// Argv running `command` inside the development environment
+    pub fn wrap(&self, command: &[String]) -> Vec<String> {
+        match self {
+            NixEnv::Flake(root) => {
+                let mut argv = vec![
+                    "nix".to_string(),
+                    "develop".to_string(),
+                    root.display().to_string(),
+                    "--command".to_string(),
+                ];
+                argv.extend(command.iter().cloned());
+                argv
+            }
+            // nix-shell only takes a shell string
+            NixEnv::Shell(file) => {
+                let script = tools::shell_join(command);
+                vec![
+                    "nix-shell".to_string(),
+                    file.display().to_string(),
+                    "--run".to_string(),
+                    script,
+                ]
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::WorktreePlan;
+use crate::tail::OutputTail;
+use arborist::webhook;
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+// Payload posted to notify_url when a run finishes
+#[derive(Serialize)]
+struct Notification<'a> {
+    worktree: &'a str,
+    branch: Option<&'a str>,
+    path: &'a Path,
+    repo: &'a Path,
+    command: &'a [String],
+    exit_code: i32,
+    duration_ms: u64,
+    kept: bool,
+    // The last lines of output of a failed run, with --capture-tail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output_tail: Option<Vec<String>>,
+}
+
+/// Reports a finished run to notify_url, or as a desktop notification when no URL is configured
+/// Failures only warn; the run itself has already finished
+pub fn finished(
+    plan: &WorktreePlan,
+    command: &[String],
+    exit_code: i32,
+    duration: Duration,
+    kept: bool,
+    tail: Option<&OutputTail>,
+) {
+    let Some(url) = plan.config.notify_url.as_deref() else {
+        desktop(plan, command, exit_code, duration, kept);
+        return;
+    };
+
+    let notification = Notification {
+        worktree: &plan.name,
+        branch: plan.branch.as_deref(),
+        path: &plan.path,
+        repo: &plan.repo.root,
+        command,
+        exit_code,
+        duration_ms: duration.as_millis() as u64,
+        kept,
+        output_tail: tail.map(OutputTail::lines),
+    };
+    if let Err(e) = webhook::post_json(url, &notification) {
+        warning!("Failed to send notification: {}", e);
+    }
+}
+
+fn desktop(
+    plan: &WorktreePlan,
+    command: &[String],
+    exit_code: i32,
+    duration: Duration,
+    kept: bool,
+) {
+    let summary = if exit_code == 0 {
+        format!("arborist: {} succeeded", plan.name)
+    } else {
+        format!("arborist: {} failed ({})", plan.name, exit_code)
+    };
+    let body = format!(
+        "{}\nran for {}s; worktree {}",
+        command.join(" "),
+        duration.as_secs(),
+        if kept { "kept" } else { "removed" }
+    );
+
+    if let Err(e) = notify_rust::Notification::new()
+        .summary(&summary)
+        .body(&body)
+        .appname("arborist")
+        .show()
+    {
+        warning!("Failed to show desktop notification: {}", e);
+    }
+}
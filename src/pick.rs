@@ -0,0 +1,217 @@
+use crate::{
+    Args, DirectoryGuard, Invocation, WorktreePlan, excluded_paths, interactive,
+    is_arborist_worktree, plan_worktree, require_repo, run_in_worktree, tools, worktree_status,
+};
+use arborist::error::Result;
+use arborist::{git, state};
+use duct::cmd;
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// An existing arborist worktree with its current state
+pub struct Candidate {
+    pub plan: WorktreePlan,
+    pub age: Option<Duration>,
+    pub status: git::WorktreeStatus,
+}
+
+impl Candidate {
+    /// Short description of the worktree's state: dirty, commits ahead, or clean
+    pub fn state(&self) -> String {
+        if self.status.has_changes {
+            "dirty".to_string()
+        } else if self.status.commits_ahead > 0 {
+            format!("{} ahead", self.status.commits_ahead)
+        } else {
+            "clean".to_string()
+        }
+    }
+
+    /// Time since creation, e.g. "5m" or "2d"
+    pub fn age(&self) -> String {
+        self.age.map(format_age).unwrap_or_else(|| "?".to_string())
+    }
+
+    fn line(&self) -> String {
+        format!(
+            "{:<12} {:<24} {:>5}  {}",
+            self.plan.name,
+            self.plan.branch_name(),
+            self.age(),
+            self.state()
+        )
+    }
+}
+
+// `arborist pick`: choose an existing worktree, then run a command (or a shell) in it
+pub fn run_pick(args: &Args, command: &[String]) -> Result<i32> {
+    let repo = require_repo()?;
+    let candidates = collect_candidates(&repo)?;
+    if candidates.is_empty() {
+        eprintln!("No arborist worktrees to pick from");
+        return Ok(1);
+    }
+
+    if !interactive::is_available() {
+        eprintln!("arborist pick needs a terminal");
+        return Ok(1);
+    }
+
+    let Some(chosen) = choose(&candidates)? else {
+        eprintln!("No worktree selected");
+        return Ok(1);
+    };
+    let plan = &candidates[chosen].plan;
+
+    let command = if command.is_empty() {
+        vec![tools::user_shell()]
+    } else {
+        command.to_vec()
+    };
+    let invocation = Invocation::from_argv(&command).with_user_env(args)?;
+    run_in_worktree(plan, args, &invocation, None)
+}
+
+/// Lists the arborist worktrees of a repository (other than its main checkout)
+pub fn collect_candidates(repo: &git::GitRepo) -> Result<Vec<Candidate>> {
+    let mut candidates = Vec::new();
+    for entry in git::list_worktrees()? {
+        if entry.path == repo.root || !entry.path.is_dir() || !is_arborist_worktree(repo, &entry)? {
+            continue;
+        }
+        let Some(name) = worktree_name(repo, &entry)? else {
+            continue;
+        };
+
+        let plan = plan_worktree(repo.clone(), name)?;
+        let status = {
+            let _guard = DirectoryGuard::with_path(&plan.path)?;
+            worktree_status(&plan, &excluded_paths(&plan)?)?
+        };
+        let age = worktree_age(&plan)?;
+        candidates.push(Candidate { plan, age, status });
+    }
+
+    Ok(candidates)
+}
+
+// Name (color) of a worktree, from its metadata, its branch or else its directory
+pub fn worktree_name(repo: &git::GitRepo, entry: &git::WorktreeEntry) -> Result<Option<String>> {
+    // Adopted worktrees keep whatever name they were given
+    if let Some((name, _)) = state::list_worktree_meta(&state::repo_state_dir(&repo.root)?)?
+        .into_iter()
+        .find(|(_, meta)| meta.path.as_ref() == Some(&entry.path))
+    {
+        return Ok(Some(name));
+    }
+
+    if let Some(name) = entry
+        .branch
+        .as_deref()
+        .and_then(|branch| branch.strip_prefix("arborist/"))
+    {
+        return Ok(Some(name.to_string()));
+    }
+
+    let Some(dir) = entry.path.file_name() else {
+        return Ok(None);
+    };
+    let dir = dir.to_string_lossy();
+    Ok(Some(
+        dir.strip_prefix("arborist-").unwrap_or(&dir).to_string(),
+    ))
+}
+
+// Time since creation, from the recorded metadata or else the directory itself
+fn worktree_age(plan: &WorktreePlan) -> Result<Option<Duration>> {
+    let created = match state::load_worktree(&plan.repo.root, &plan.name)? {
+        Some(meta) if meta.created_at > 0 => {
+            Some(UNIX_EPOCH + Duration::from_secs(meta.created_at))
+        }
+        _ => plan.path.metadata().and_then(|m| m.modified()).ok(),
+    };
+    Ok(created.and_then(|time| SystemTime::now().duration_since(time).ok()))
+}
+
+/// A duration at its coarsest unit, e.g. "5m" or "2d"
+pub fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+// Index of the chosen candidate, using fzf when it is installed
+fn choose(candidates: &[Candidate]) -> Result<Option<usize>> {
+    let lines: Vec<String> = candidates.iter().map(Candidate::line).collect();
+    if has_fzf() {
+        return choose_with_fzf(&lines);
+    }
+
+    let mut shown: Vec<usize> = (0..lines.len()).collect();
+    loop {
+        for (number, &index) in shown.iter().enumerate() {
+            eprintln!("{:>3}) {}", number + 1, lines[index]);
+        }
+        eprint!("Pick a number, or type to filter: ");
+        io::stderr().flush()?;
+
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            eprintln!();
+            return Ok(None);
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(None);
+        }
+
+        if let Ok(number) = answer.parse::<usize>()
+            && (1..=shown.len()).contains(&number)
+        {
+            return Ok(Some(shown[number - 1]));
+        }
+
+        let filtered: Vec<usize> = (0..lines.len())
+            .filter(|&index| fuzzy_match(answer, &lines[index]))
+            .collect();
+        match filtered.as_slice() {
+            [only] => return Ok(Some(*only)),
+            [] => eprintln!("Nothing matches '{}'", answer),
+            _ => shown = filtered,
+        }
+    }
+}
+
+fn choose_with_fzf(lines: &[String]) -> Result<Option<usize>> {
+    let output = cmd!("fzf", "--prompt", "worktree> ", "--no-sort")
+        .stdin_bytes(lines.join("\n"))
+        .stdout_capture()
+        .unchecked()
+        .run()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let selected = String::from_utf8_lossy(&output.stdout);
+    let selected = selected.trim_end_matches('\n');
+    Ok(lines.iter().position(|line| line == selected))
+}
+
+fn has_fzf() -> bool {
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join("fzf").is_file()))
+}
+
+// Whether every character of the query appears in order in the text (case-insensitive)
+fn fuzzy_match(query: &str, text: &str) -> bool {
+    let mut text = text.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|wanted| text.any(|c| c == wanted))
+}
@@ -0,0 +1,113 @@
+use crate::Args;
+use arborist::error::{Result, ResultExt};
+use arborist::{git, process, state};
+use clap::CommandFactory;
+use duct::cmd;
+use serde::Serialize;
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+// Version of the ARBORIST_CONTEXT format handed to plugins
+const PROTOCOL_VERSION: u32 = 1;
+
+// Context handed to a plugin as JSON in ARBORIST_CONTEXT
+#[derive(Serialize)]
+struct PluginContext {
+    protocol: u32,
+    version: &'static str,
+    // Path of the arborist binary, for plugins that call back into it
+    arborist: Option<PathBuf>,
+    cwd: PathBuf,
+    repo: Option<PathBuf>,
+    state_dir: Option<PathBuf>,
+    // Set when the current directory is inside an arborist worktree
+    worktree: Option<WorktreeContext>,
+}
+
+#[derive(Serialize)]
+struct WorktreeContext {
+    name: String,
+    path: PathBuf,
+    branch: String,
+}
+
+/// Runs `arborist-<name>` from PATH when the first argument names no built-in subcommand
+/// Returns: the plugin's exit code, or None when arborist should handle the arguments itself
+pub fn dispatch(argv: &[OsString]) -> Result<Option<i32>> {
+    let mut args = argv.iter().skip(1);
+    let Some(name) = args.next().and_then(|arg| arg.to_str()) else {
+        return Ok(None);
+    };
+    if name.starts_with('-') || Args::command().find_subcommand(name).is_some() {
+        return Ok(None);
+    }
+    let Some(plugin) = find_plugin(name) else {
+        return Ok(None);
+    };
+
+    let context = context()?;
+    let mut expression = cmd(&plugin, args)
+        .env("ARBORIST_CONTEXT", serde_json::to_string(&context)?)
+        .unchecked();
+    if let Some(repo) = &context.repo {
+        expression = expression.env("ARBORIST_REPO", repo);
+    }
+    if let Some(worktree) = &context.worktree {
+        expression = expression
+            .env("ARBORIST_NAME", &worktree.name)
+            .env("ARBORIST_BRANCH", &worktree.branch)
+            .env("ARBORIST_WORKTREE", &worktree.path);
+    }
+
+    let output = expression
+        .run()
+        .context(format!("running plugin {}", plugin.display()))?;
+    Ok(Some(process::exit_code(&output.status)))
+}
+
+// Executable named arborist-<name> in a PATH directory
+fn find_plugin(name: &str) -> Option<PathBuf> {
+    let file = format!("arborist-{}{}", name, env::consts::EXE_SUFFIX);
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(&file))
+        .find(|candidate| candidate.is_file())
+}
+
+fn context() -> Result<PluginContext> {
+    let repo = git::get_repo_info()?;
+    let worktree = repo.as_ref().and_then(|repo| {
+        let name = repo.current_branch.strip_prefix("arborist/")?;
+        Some(WorktreeContext {
+            name: name.to_string(),
+            path: repo.root.clone(),
+            branch: repo.current_branch.clone(),
+        })
+    });
+
+    // Report the main checkout (listed first by git) even from inside a worktree
+    let repo_root = match repo {
+        Some(repo) => Some(
+            git::list_worktrees()?
+                .into_iter()
+                .next()
+                .map_or(repo.root, |main| main.path),
+        ),
+        None => None,
+    };
+    let state_dir = repo_root
+        .as_deref()
+        .map(state::repo_state_dir)
+        .transpose()?;
+
+    Ok(PluginContext {
+        protocol: PROTOCOL_VERSION,
+        version: env!("CARGO_PKG_VERSION"),
+        arborist: env::current_exe().ok(),
+        cwd: env::current_dir()?,
+        repo: repo_root,
+        state_dir,
+        worktree,
+    })
+}
@@ -0,0 +1,180 @@
+use crate::{
+    Args, DirectoryGuard, WorktreePlan, create_planned_worktree, excluded_paths, lock,
+    plan_worktree, prune, require_repo, status_mode, templates, worktree_upstream,
+};
+use arborist::error::Result;
+use arborist::{config, git, state};
+
+// Names of parked worktrees start with this, followed by a number
+const PARKED_PREFIX: &str = "parked-";
+
+// `arborist warmup`: fill the current repository's pool without a daemon, e.g. from cron
+pub fn run_warmup(args: &Args, count: Option<usize>) -> Result<i32> {
+    let repo = require_repo()?;
+    let count = match count {
+        Some(count) => count,
+        None => config::load((!repo.is_bare).then_some(repo.root.as_path()))?
+            .pool_size
+            .unwrap_or(1),
+    };
+
+    if let Err(err) = git::ensure_repo_id(&repo.root) {
+        verbose!("Couldn't give the repository an identity: {}", err);
+    }
+    prune::prune_stale(&repo)?;
+    let created = warm_up(&repo, args, count)?;
+    notice!(
+        "Parked {} new worktree(s); {} in the pool",
+        created,
+        parked(&repo)?.len()
+    );
+    Ok(0)
+}
+
+/// Whether a run with these options can take a worktree from the pool, which holds
+/// worktrees of a new arborist branch at the base commit with nothing else to set up
+pub fn can_claim(args: &Args) -> bool {
+    !args.dry_run
+        && !args.detach
+        && args.branch.is_none()
+        && args.from_remote.is_none()
+        && !args.update
+        && args.sparse.is_empty()
+}
+
+/// Parked worktrees of a repository, oldest first
+pub fn parked(repo: &git::GitRepo) -> Result<Vec<WorktreePlan>> {
+    let mut parked = Vec::new();
+    let mut metas = state::list_worktree_meta(&state::repo_state_dir(&repo.root)?)?;
+    metas.sort_by_key(|(_, meta)| meta.created_at);
+    for (name, meta) in metas {
+        if !meta.parked {
+            continue;
+        }
+        let plan = plan_worktree(repo.clone(), name)?;
+        if git::worktree_exists(&plan.path)? {
+            parked.push(plan);
+        }
+    }
+    Ok(parked)
+}
+
+/// Creates a worktree and parks it in the pool, set up and locked until a run claims it
+pub fn park(repo: &git::GitRepo, args: &Args) -> Result<WorktreePlan> {
+    let metas = state::list_worktree_meta(&state::repo_state_dir(&repo.root)?)?;
+    let mut number = 1;
+    let plan = loop {
+        let name = format!("{}{}", PARKED_PREFIX, number);
+        let taken = metas.iter().any(|(taken, _)| *taken == name);
+        if !taken {
+            let plan = plan_worktree(repo.clone(), name)?;
+            if !plan.path.exists() && !git::branch_exists(&format!("arborist/{}", plan.name))? {
+                break plan;
+            }
+        }
+        number += 1;
+    };
+
+    create_planned_worktree(&plan, args)?;
+    state::update_worktree(&repo.root, &plan.name, |meta| meta.parked = true)?;
+    lock::park(&plan.path)?;
+    verbose!("Parked worktree {} in the pool", plan.path.display());
+    Ok(plan)
+}
+
+/// Takes a parked worktree for a run planned to create `plan`, moving it to the planned path
+/// under the planned name and resetting it to the current commit
+/// Returns: None when nothing is parked that can be used
+pub fn claim(plan: &WorktreePlan) -> Result<Option<WorktreePlan>> {
+    let Some(branch) = plan.owned_branch() else {
+        return Ok(None);
+    };
+
+    for parked in parked(&plan.repo)? {
+        // Something wrote into it while it sat in the pool; leave it for the user to look at
+        let clean = {
+            let _guard = DirectoryGuard::with_path(&parked.path)?;
+            !git::get_worktree_status(&excluded_paths(&parked)?, status_mode(&parked)?)?.has_changes
+        };
+        let Some(parked_branch) = parked.owned_branch().filter(|_| clean) else {
+            continue;
+        };
+        // Another run, or the daemon trimming the pool, got to it first
+        if !take(&parked)? {
+            continue;
+        }
+
+        verbose!(
+            "Claiming parked worktree {} as {}",
+            parked.path.display(),
+            plan.name
+        );
+        let moved = git::unlock_worktree(&parked.path)
+            .and_then(|()| git::move_worktree(&parked.path, &plan.path));
+        if let Err(err) = moved {
+            verbose!(
+                "Couldn't claim parked worktree {}: {}",
+                parked.path.display(),
+                err
+            );
+            // Still where it was parked, so it goes back into the pool
+            if git::worktree_exists(&parked.path)? {
+                put_back(&parked)?;
+            }
+            continue;
+        }
+        git::rename_branch(parked_branch, branch)?;
+        git::reset_worktree(&plan.path, &plan.repo.current_commit)?;
+        // Parked under another name, its templates are filled in again for this one
+        templates::write(plan)?;
+        match worktree_upstream(plan) {
+            Some(upstream) => git::set_branch_upstream(branch, upstream)?,
+            // The parked branch may track what the pool was warmed from
+            None => git::unset_branch_upstream(branch)?,
+        }
+
+        let meta = state::WorktreeMeta {
+            base_commit: plan.repo.current_commit.clone(),
+            base_branch: plan.repo.current_branch.clone(),
+            detached: false,
+            branch: None,
+            path: Some(plan.path.clone()),
+            created_at: state::unix_now(),
+            parked: false,
+        };
+        state::record_worktree(&plan.repo.root, &plan.name, &meta)?;
+        state::remove_worktree_meta(&plan.repo.root, &parked.name)?;
+        return Ok(Some(plan.clone()));
+    }
+
+    Ok(None)
+}
+
+/// Takes a parked worktree out of the pool under the state lock, so that only one run (or pool
+/// trim) can have it
+/// Returns: false when it was no longer parked
+pub fn take(parked: &WorktreePlan) -> Result<bool> {
+    let mut taken = false;
+    state::update_worktree(&parked.repo.root, &parked.name, |meta| {
+        taken = meta.parked;
+        meta.parked = false;
+    })?;
+    Ok(taken)
+}
+
+/// Returns a worktree taken out of the pool to it, locking it again unless it still is
+pub fn put_back(parked: &WorktreePlan) -> Result<()> {
+    let _ = lock::park(&parked.path);
+    state::update_worktree(&parked.repo.root, &parked.name, |meta| meta.parked = true)?;
+    Ok(())
+}
+
+/// Parks worktrees until the pool holds `count`
+/// Returns: how many were created
+pub fn warm_up(repo: &git::GitRepo, args: &Args, count: usize) -> Result<usize> {
+    let parked = parked(repo)?.len();
+    for _ in parked..count {
+        park(repo, args)?;
+    }
+    Ok(count.saturating_sub(parked))
+}
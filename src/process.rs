@@ -0,0 +1,243 @@
+use crate::error::Result;
+use async_executor::LocalExecutor;
+use async_io::Timer;
+use event_listener::Event;
+use futures_lite::future;
+use std::future::Future;
+use std::io;
+use std::pin::pin;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+pub use async_process::{Child, Command, Stdio};
+pub use blocking::unblock;
+
+/// Runs `future` to completion on the calling thread, which drives every command, pipe and timer
+/// it waits on; git and the commands of a run all go through here rather than a thread each
+pub fn block_on<T>(future: impl Future<Output = T>) -> T {
+    async_io::block_on(future)
+}
+
+/// Runs the futures concurrently on the scheduler of the thread awaiting this, e.g. the same
+/// command in several worktrees at once
+/// Returns: their outputs, in the order given
+pub async fn join_all<'a, T: 'a>(
+    futures: impl IntoIterator<Item: Future<Output = T> + 'a>,
+) -> Vec<T> {
+    let executor = LocalExecutor::new();
+    let tasks: Vec<_> = futures
+        .into_iter()
+        .map(|future| executor.spawn(future))
+        .collect();
+    executor
+        .run(async {
+            let mut outputs = Vec::with_capacity(tasks.len());
+            for task in tasks {
+                outputs.push(task.await);
+            }
+            outputs
+        })
+        .await
+}
+
+/// A request to stop the commands supervised with it, e.g. from a signal handler; once made it
+/// stays made
+pub struct Cancellation {
+    cancelled: AtomicBool,
+    event: Event,
+}
+
+impl Cancellation {
+    pub const fn new() -> Self {
+        Cancellation {
+            cancelled: AtomicBool::new(false),
+            event: Event::new(),
+        }
+    }
+
+    /// Cancels everything supervised with this, waking it wherever it waits
+    /// Returns: whether it had already been cancelled
+    pub fn cancel(&self) -> bool {
+        let already = self.cancelled.swap(true, Ordering::SeqCst);
+        self.event.notify(usize::MAX);
+        already
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Completes once this is cancelled
+    pub async fn cancelled(&self) {
+        loop {
+            if self.is_cancelled() {
+                return;
+            }
+            let listener = self.event.listen();
+            // Cancelled between the check and listening, which wouldn't be notified again
+            if self.is_cancelled() {
+                return;
+            }
+            listener.await;
+        }
+    }
+}
+
+impl Default for Cancellation {
+    fn default() -> Self {
+        Cancellation::new()
+    }
+}
+
+/// How a supervised command ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// It exited by itself, with this exit code
+    Exited(i32),
+    /// It ran past its timeout and was killed
+    TimedOut,
+    /// It was still running `grace` after being cancelled and was killed
+    Cancelled,
+}
+
+/// Waits for a started command, killing it when it runs past `timeout` or is still running
+/// `grace` after `cancellation` is cancelled
+/// `exited` completes with the command's exit code once it exits; `kill` stops it and must make
+/// `exited` complete, which is then waited for so nothing outlives the supervision
+pub async fn supervise(
+    exited: impl Future<Output = Result<i32>>,
+    kill: impl FnOnce() -> io::Result<()>,
+    timeout: Option<Duration>,
+    cancellation: &Cancellation,
+    grace: Duration,
+) -> Result<Outcome> {
+    let mut exited = pin!(exited);
+    let stopped = future::or(
+        async {
+            match timeout {
+                Some(timeout) => Timer::after(timeout).await,
+                None => future::pending().await,
+            };
+            Outcome::TimedOut
+        },
+        async {
+            cancellation.cancelled().await;
+            Timer::after(grace).await;
+            Outcome::Cancelled
+        },
+    );
+    let ended = future::or(async { Ok(exited.as_mut().await) }, async {
+        Err(stopped.await)
+    });
+
+    let outcome = match ended.await {
+        Ok(exit_code) => return Ok(Outcome::Exited(exit_code?)),
+        Err(outcome) => outcome,
+    };
+    kill()?;
+    exited.await?;
+    Ok(outcome)
+}
+
+/// Waits for a child process as `supervise` does, killing it on timeout or cancellation
+pub async fn wait(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    cancellation: &Cancellation,
+    grace: Duration,
+) -> Result<Outcome> {
+    let status = child.status();
+    supervise(
+        async { Ok(exit_code(&status.await?)) },
+        || child.kill(),
+        timeout,
+        cancellation,
+        grace,
+    )
+    .await
+}
+
+/// Exit code reporting how a process ended: its own exit code, or 128+N when killed by signal N
+pub fn exit_code(status: &ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn sh(script: &str) -> Child {
+        Command::new("sh").args(["-c", script]).spawn().unwrap()
+    }
+
+    #[test]
+    fn joins_in_order_while_running_together() {
+        let started = Instant::now();
+        let outputs = block_on(join_all([300, 100, 200].map(|millis| async move {
+            Timer::after(Duration::from_millis(millis)).await;
+            millis
+        })));
+        assert_eq!(outputs, [300, 100, 200]);
+        assert!(started.elapsed() < Duration::from_millis(600));
+    }
+
+    #[test]
+    fn reports_the_exit_code() {
+        let cancellation = Cancellation::new();
+        let outcome = block_on(wait(&mut sh("exit 3"), None, &cancellation, Duration::ZERO));
+        assert_eq!(outcome.unwrap(), Outcome::Exited(3));
+    }
+
+    #[test]
+    fn kills_a_command_past_its_timeout() {
+        let cancellation = Cancellation::new();
+        let started = Instant::now();
+        let timeout = Some(Duration::from_millis(100));
+        let outcome = block_on(wait(
+            &mut sh("sleep 10"),
+            timeout,
+            &cancellation,
+            Duration::ZERO,
+        ));
+        assert_eq!(outcome.unwrap(), Outcome::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn kills_a_cancelled_command_after_its_grace_period() {
+        let cancellation = Cancellation::new();
+        let mut child = sh("sleep 10");
+        let outcome = block_on(future::zip(
+            wait(&mut child, None, &cancellation, Duration::from_millis(50)),
+            async {
+                Timer::after(Duration::from_millis(50)).await;
+                assert!(!cancellation.cancel());
+            },
+        ));
+        assert_eq!(outcome.0.unwrap(), Outcome::Cancelled);
+        assert!(cancellation.cancel());
+    }
+
+    #[test]
+    fn a_command_exiting_within_its_grace_period_is_not_killed() {
+        let cancellation = Cancellation::new();
+        cancellation.cancel();
+        let grace = Duration::from_secs(5);
+        let outcome = block_on(wait(
+            &mut sh("sleep 0.1; exit 4"),
+            None,
+            &cancellation,
+            grace,
+        ));
+        assert_eq!(outcome.unwrap(), Outcome::Exited(4));
+    }
+}
@@ -0,0 +1,73 @@
+use crate::style;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Steps quicker than this finish before the spinner shows, so they print nothing
+const DELAY: Duration = Duration::from_millis(500);
+const TICK: Duration = Duration::from_millis(100);
+const FRAMES: &[char] = &['|', '/', '-', '\\'];
+
+/// A spinner with the elapsed time on stderr while a slow step runs, erased when dropped
+/// git only shows its own checkout progress on a terminal, and arborist captures its output
+pub struct Spinner {
+    // Dropped to stop the spinner at once rather than at its next tick
+    done: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    /// Shows `message` with a spinner once the step has taken a moment; nothing is shown when
+    /// stderr isn't a terminal, with --quiet, or with --verbose, whose messages would interleave
+    pub fn start(message: String) -> Spinner {
+        let (done, stopped) = mpsc::channel();
+        let silent = crate::QUIET.load(Ordering::Relaxed) || crate::VERBOSE.load(Ordering::Relaxed);
+        let thread = (!silent && io::stderr().is_terminal())
+            .then(|| thread::spawn(move || spin(&message, &stopped)));
+        Spinner {
+            done: Some(done),
+            thread,
+        }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.done.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn spin(message: &str, stopped: &Receiver<()>) {
+    let started = Instant::now();
+    let mut shown = false;
+    let mut frame = 0;
+    while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(TICK) {
+        if started.elapsed() < DELAY {
+            continue;
+        }
+
+        let mut stderr = io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r\x1b[2K{} {} {} ({}s)",
+            style::info("arborist:"),
+            FRAMES[frame % FRAMES.len()],
+            message,
+            started.elapsed().as_secs()
+        );
+        let _ = stderr.flush();
+        shown = true;
+        frame += 1;
+    }
+
+    if shown {
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K");
+        let _ = stderr.flush();
+    }
+}
@@ -0,0 +1,60 @@
+use arborist::config;
+use arborist::error::Result;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Segment printed when no prompt_template is configured
+const DEFAULT_TEMPLATE: &str = "arborist:{name}";
+
+// `arborist prompt`: print a prompt segment when inside an arborist worktree
+// Reads .git and HEAD directly instead of running git so it stays fast enough for PS1
+pub fn run_prompt(format: Option<&str>) -> Result<i32> {
+    let cwd = env::current_dir()?;
+    let Some((root, branch)) = current_branch(&cwd) else {
+        return Ok(1);
+    };
+    let Some(name) = branch.strip_prefix("arborist/") else {
+        return Ok(1);
+    };
+
+    let template = match format {
+        Some(format) => format.to_string(),
+        None => config::load(Some(&root))?
+            .prompt_template
+            .unwrap_or_else(|| DEFAULT_TEMPLATE.to_string()),
+    };
+
+    println!(
+        "{}",
+        template
+            .replace("{name}", name)
+            .replace("{branch}", &branch)
+            .replace("{path}", &root.display().to_string())
+    );
+    Ok(0)
+}
+
+// Checkout root and checked-out branch of the repository containing dir
+fn current_branch(dir: &Path) -> Option<(PathBuf, String)> {
+    let (root, git_dir) = find_git_dir(dir)?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let branch = head.trim().strip_prefix("ref: refs/heads/")?;
+    Some((root, branch.to_string()))
+}
+
+// Walk up to the nearest .git, following the `gitdir:` pointer worktrees use
+fn find_git_dir(dir: &Path) -> Option<(PathBuf, PathBuf)> {
+    for ancestor in dir.ancestors() {
+        let dot_git = ancestor.join(".git");
+        if dot_git.is_dir() {
+            return Some((ancestor.to_path_buf(), dot_git));
+        }
+        if dot_git.is_file() {
+            let contents = fs::read_to_string(&dot_git).ok()?;
+            let git_dir = contents.trim().strip_prefix("gitdir:")?.trim();
+            return Some((ancestor.to_path_buf(), ancestor.join(git_dir)));
+        }
+    }
+    None
+}
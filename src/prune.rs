@@ -0,0 +1,68 @@
+use crate::{heartbeat, is_arborist_worktree, lock, pick};
+use arborist::error::Result;
+use arborist::{git, state};
+use std::path::PathBuf;
+
+/// Forgets arborist worktrees whose directories are gone, e.g. after a crashed run or /tmp
+/// being cleared, so new worktrees can take their place
+/// Unregisters them as `git worktree prune` would, sparing locked worktrees and those that
+/// aren't arborist's, and drops the metadata of worktrees that no longer exist along with
+/// their branches, unless those have commits that would be lost
+/// Returns: the paths of the worktrees forgotten
+pub fn prune_stale(repo: &git::GitRepo) -> Result<Vec<PathBuf>> {
+    let mut pruned = Vec::new();
+    let mut worktrees = git::list_worktrees()?;
+    lock::release_stale(repo, &mut worktrees)?;
+    for entry in &worktrees {
+        if !entry.prunable || entry.locked || !is_arborist_worktree(repo, entry)? {
+            continue;
+        }
+        // Its directory may only be out of reach for now, e.g. on an unmounted volume
+        if let Some(name) = pick::worktree_name(repo, entry)?
+            && heartbeat::active_run(&repo.root, &name)?.is_some()
+        {
+            continue;
+        }
+        verbose!("Unregistering missing worktree {}", entry.path.display());
+        git::remove_worktree(&entry.path)?;
+        pruned.push(entry.path.clone());
+    }
+
+    let state_dir = state::repo_state_dir(&repo.root)?;
+    for (name, meta) in state::list_worktree_meta(&state_dir)? {
+        // Worktrees from before paths were recorded are left to `arborist destroy-all`
+        let Some(path) = &meta.path else {
+            continue;
+        };
+        if path.exists()
+            || heartbeat::active_run(&repo.root, &name)?.is_some()
+            || worktrees
+                .iter()
+                .any(|entry| entry.path == *path && entry.locked)
+        {
+            continue;
+        }
+
+        verbose!("Forgetting missing worktree {} at {}", name, path.display());
+        state::remove_worktree_meta(&repo.root, &name)?;
+        state::remove_run(&repo.root, &name)?;
+        if !pruned.contains(path) {
+            pruned.push(path.clone());
+        }
+
+        let branch = format!("arborist/{}", name);
+        if !meta.detached && meta.branch.is_none() && git::branch_exists(&branch)? {
+            if git::count_commits_between(&meta.base_commit, &branch)? == 0 {
+                git::delete_branch(&branch)?;
+            } else {
+                warning!(
+                    "worktree {} is gone but its branch {} has commits; keeping the branch",
+                    name,
+                    branch
+                );
+            }
+        }
+    }
+
+    Ok(pruned)
+}
@@ -0,0 +1,152 @@
+use crate::{heartbeat, lock, pick};
+use arborist::error::{ArboristError, Result};
+use arborist::state;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// A command arborist is running right now, in any repository
+#[derive(Serialize)]
+struct ActiveRun {
+    pid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    repo: PathBuf,
+    worktree: String,
+    command: String,
+    started_at: u64,
+    elapsed_secs: u64,
+}
+
+impl ActiveRun {
+    // Whether the run is on this machine, where its pid can be signalled
+    fn is_local(&self) -> bool {
+        self.host.is_none() || self.host == heartbeat::host()
+    }
+}
+
+// Runs with a fresh heartbeat across every repository arborist has been used in, oldest first
+fn active_runs() -> Result<Vec<ActiveRun>> {
+    let now = state::unix_now();
+    let mut runs = Vec::new();
+    for (state_dir, repo) in state::known_repos()? {
+        for (worktree, run) in heartbeat::active_runs(&state_dir)? {
+            runs.push(ActiveRun {
+                pid: run.pid,
+                host: run.host,
+                repo: repo.clone(),
+                worktree,
+                command: run.command,
+                started_at: run.started_at,
+                elapsed_secs: now.saturating_sub(run.started_at),
+            });
+        }
+    }
+    runs.sort_by_key(|run| run.started_at);
+    Ok(runs)
+}
+
+// `arborist ps`: list the arborist runs active right now, across all repositories
+pub fn run_ps(json: bool) -> Result<i32> {
+    let runs = active_runs()?;
+    if json {
+        println!("{}", serde_json::to_string(&runs)?);
+        return Ok(0);
+    }
+    if runs.is_empty() {
+        eprintln!("No arborist runs are active");
+        return Ok(0);
+    }
+
+    println!(
+        "{:<8} {:<12} {:>7}  {:<40} COMMAND",
+        "ID", "WORKTREE", "ELAPSED", "REPO"
+    );
+    for run in &runs {
+        // Runs elsewhere can be seen through shared state, but not signalled from here
+        let id = if run.is_local() {
+            run.pid.to_string()
+        } else {
+            format!("{}@{}", run.pid, run.host.as_deref().unwrap_or("?"))
+        };
+        println!(
+            "{:<8} {:<12} {:>7}  {:<40} {}",
+            id,
+            run.worktree,
+            pick::format_age(Duration::from_secs(run.elapsed_secs)),
+            run.repo.display(),
+            run.command
+        );
+    }
+    Ok(0)
+}
+
+// `arborist kill`: stop an active run, by the id `arborist ps` shows or its worktree's name
+// The run treats the signal as an interrupt: its command is killed after the grace period and the
+// worktree is cleaned up as after any other run
+pub fn run_kill(id: &str) -> Result<i32> {
+    let runs = active_runs()?;
+    let matches: Vec<&ActiveRun> = match id.parse::<u32>() {
+        Ok(pid) => runs
+            .iter()
+            .filter(|run| run.pid == pid && run.is_local())
+            .collect(),
+        Err(_) => runs.iter().filter(|run| run.worktree == id).collect(),
+    };
+    let run = match matches.as_slice() {
+        [] => {
+            return Err(ArboristError::InvalidPath(format!(
+                "No active arborist run on this machine matches '{}'; see `arborist ps`",
+                id
+            )));
+        }
+        [run] => *run,
+        // A workspace run is one process running in a worktree of each of its repositories
+        [run, rest @ ..] if rest.iter().all(|other| other.pid == run.pid) => *run,
+        _ => {
+            return Err(ArboristError::InvalidPath(format!(
+                "Several active runs are in worktrees named '{}'; kill one by its id from `arborist ps`",
+                id
+            )));
+        }
+    };
+    if !run.is_local() || !lock::is_alive(run.pid) {
+        return Err(ArboristError::InvalidPath(format!(
+            "The run in worktree {} isn't on this machine, so it can't be signalled from here",
+            run.worktree
+        )));
+    }
+
+    terminate(run.pid)?;
+    notice!(
+        "Stopping the arborist run in process {} (worktree {}): its command is killed after a grace period, then the worktree is cleaned up",
+        run.pid,
+        run.worktree
+    );
+    Ok(0)
+}
+
+// Sends SIGTERM, which arborist turns into cancelling its command rather than exiting at once
+#[cfg(unix)]
+fn terminate(pid: u32) -> Result<()> {
+    let output = duct::cmd!("kill", "-TERM", pid.to_string())
+        .stderr_capture()
+        .unchecked()
+        .run()?;
+    if !output.status.success() {
+        return Err(ArboristError::IoError(std::io::Error::other(format!(
+            "failed to signal process {}: {}",
+            pid,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn terminate(pid: u32) -> Result<()> {
+    Err(ArboristError::IoError(std::io::Error::other(format!(
+        "stopping process {} is only supported on Unix",
+        pid
+    ))))
+}
@@ -0,0 +1,148 @@
+use crate::{Invocation, cancel, environment};
+use arborist::error::{ArboristError, Result};
+use arborist::process;
+use async_io::Timer;
+use crossterm::terminal;
+use futures_lite::future;
+use portable_pty::{CommandBuilder, PtySize, native_pty_system};
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::time::Duration;
+
+// How often the terminal is checked for having been resized
+const RESIZE_POLL: Duration = Duration::from_millis(250);
+
+// How long output still buffered in the terminal gets to drain after the command exits
+const DRAIN: Duration = Duration::from_millis(500);
+
+/// Runs the command attached to a pseudo-terminal of the same size as ours, passing our
+/// input through and its output to stdout, so it behaves as if run directly in a terminal
+/// Returns: the command's exit code, or INTERRUPTED when it was killed
+pub async fn run(invocation: &Invocation) -> Result<i32> {
+    let pair = native_pty_system()
+        .openpty(terminal_size())
+        .map_err(pty_failed)?;
+    let mut child = pair
+        .slave
+        .spawn_command(command(invocation)?)
+        .map_err(pty_failed)?;
+    // Only the child holds the terminal open, so reading ends when it exits
+    drop(pair.slave);
+
+    let mut reader = pair.master.try_clone_reader().map_err(pty_failed)?;
+    let mut writer = pair.master.take_writer().map_err(pty_failed)?;
+    let mut killer = child.clone_killer();
+
+    // Keys go straight to the command, which does its own line editing and echo
+    let _raw_mode = RawMode::enable()?;
+
+    // The terminal's end only offers blocking reads and writes, which run on the scheduler's
+    // blocking pool
+    let tail = invocation.tail.clone();
+    let output = process::unblock(move || {
+        let mut stdout = io::stdout();
+        let _ = match tail {
+            Some(tail) => io::copy(&mut reader, &mut tail.tee(&mut stdout)),
+            None => io::copy(&mut reader, &mut stdout),
+        };
+        let _ = stdout.flush();
+    });
+    // Blocks on our stdin for as long as arborist runs; the command exiting doesn't wake it
+    process::unblock(move || {
+        let _ = io::copy(&mut io::stdin(), &mut writer);
+    })
+    .detach();
+
+    let master = pair.master;
+    let exited = process::unblock(move || Ok(child.wait()?.exit_code() as i32));
+    let exit_code = future::or(cancel::supervise(exited, || killer.kill()), async {
+        let mut size = terminal_size();
+        loop {
+            Timer::after(RESIZE_POLL).await;
+            let current = terminal_size();
+            if (current.rows, current.cols) != (size.rows, size.cols) {
+                if let Err(err) = master.resize(current) {
+                    verbose!("Warning: failed to resize terminal: {}", err);
+                }
+                size = current;
+            }
+        }
+    })
+    .await;
+
+    // Output still buffered in the terminal drains while it's held open
+    future::or(output, async {
+        Timer::after(DRAIN).await;
+    })
+    .await;
+    drop(master);
+    exit_code
+}
+
+// The invocation as a command for the pseudo-terminal, with the same argv, environment and
+// directory it would get when run directly
+fn command(invocation: &Invocation) -> Result<CommandBuilder> {
+    let argv = invocation.argv();
+    let mut command = CommandBuilder::new(&argv[0]);
+    command.args(&argv[1..]);
+
+    let overrides: BTreeMap<_, _> = invocation.env.iter().cloned().collect();
+    if invocation.clean_env {
+        command.env_clear();
+        for (key, value) in environment::clean_base() {
+            command.env(key, value);
+        }
+    }
+    for (key, value) in overrides {
+        command.env(key, value);
+    }
+
+    // Without a directory the command would start in $HOME
+    let dir = match &invocation.dir {
+        Some(dir) => dir.clone(),
+        None => env::current_dir()?,
+    };
+    command.cwd(dir);
+    Ok(command)
+}
+
+fn terminal_size() -> PtySize {
+    let (cols, rows) = terminal::size().unwrap_or((80, 24));
+    PtySize {
+        rows,
+        cols,
+        pixel_width: 0,
+        pixel_height: 0,
+    }
+}
+
+fn pty_failed(err: impl std::fmt::Display) -> ArboristError {
+    ArboristError::IoError(io::Error::other(format!(
+        "failed to set up a pseudo-terminal: {}",
+        err
+    )))
+}
+
+// Raw mode on our terminal while the command runs, restored however the run ends
+struct RawMode {
+    enabled: bool,
+}
+
+impl RawMode {
+    fn enable() -> Result<Self> {
+        let enabled = io::stdin().is_terminal();
+        if enabled {
+            terminal::enable_raw_mode()?;
+        }
+        Ok(RawMode { enabled })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if self.enabled {
+            let _ = terminal::disable_raw_mode();
+        }
+    }
+}
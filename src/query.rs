@@ -0,0 +1,99 @@
+use crate::{plan_worktree, resolve_nested};
+use arborist::error::{ArboristError, Result};
+use arborist::{git, state};
+use std::env;
+
+/// A fact `arborist query` prints; the names and what they print are kept stable for scripts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Key {
+    /// Root of the repository's main checkout, also from inside one of its worktrees
+    RepoRoot,
+    /// Identifier arborist keeps the repository's state and worktrees under
+    RepoHash,
+    /// "true" inside an arborist worktree (or when the named one exists), else "false" with exit 1
+    IsArboristWorktree,
+    /// The worktree's name
+    WorktreeName,
+    /// The worktree's path
+    WorktreePath,
+    /// The branch checked out in the worktree; nothing for a detached one
+    Branch,
+    /// The commit the worktree was created from
+    BaseCommit,
+    /// The branch the worktree was created from; nothing when that was a detached HEAD
+    BaseBranch,
+}
+
+// `arborist query`: print one fact about the repository or a worktree, nothing else, for scripts
+// and editor plugins. Facts about a worktree are of the one named, else of the one the current
+// directory is in. A fact that has no value prints nothing and exits 1
+pub fn run_query(key: Key, name: Option<&str>) -> Result<i32> {
+    let Some(repo) = git::get_repo_info()? else {
+        if key == Key::IsArboristWorktree {
+            println!("false");
+            return Ok(1);
+        }
+        return Err(ArboristError::NotARepo {
+            dir: env::current_dir().unwrap_or_default(),
+        });
+    };
+    let (repo, current) = resolve_nested(repo)?;
+
+    match key {
+        Key::RepoRoot => {
+            println!("{}", repo.root.display());
+            return Ok(0);
+        }
+        Key::RepoHash => {
+            println!("{}", git::repo_hash(&repo.root)?);
+            return Ok(0);
+        }
+        _ => {}
+    }
+
+    let name = match (name, current) {
+        (Some(name), _) => name.to_string(),
+        (None, Some(current)) => current,
+        (None, None) if key == Key::IsArboristWorktree => {
+            println!("false");
+            return Ok(1);
+        }
+        (None, None) => {
+            return Err(ArboristError::InvalidPath(
+                "Not inside an arborist worktree; name the worktree to query".to_string(),
+            ));
+        }
+    };
+    let plan = plan_worktree(repo, name)?;
+    let exists = git::worktree_exists(&plan.path)?;
+    if key == Key::IsArboristWorktree {
+        println!("{}", exists);
+        return Ok(if exists { 0 } else { 1 });
+    }
+    if !exists {
+        return Err(ArboristError::InvalidPath(format!(
+            "No arborist worktree named '{}' at {}",
+            plan.name,
+            plan.path.display()
+        )));
+    }
+
+    let meta = state::load_worktree(&plan.repo.root, &plan.name)?;
+    let value = match key {
+        Key::WorktreeName => Some(plan.name.clone()),
+        Key::WorktreePath => Some(plan.path.display().to_string()),
+        Key::Branch => plan.branch.clone(),
+        Key::BaseCommit => meta.map(|meta| meta.base_commit),
+        Key::BaseBranch => meta
+            .map(|meta| meta.base_branch)
+            .filter(|branch| branch != "HEAD"),
+        Key::RepoRoot | Key::RepoHash | Key::IsArboristWorktree => unreachable!("answered above"),
+    };
+    match value {
+        Some(value) => {
+            println!("{}", value);
+            Ok(0)
+        }
+        None => Ok(1),
+    }
+}
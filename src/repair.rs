@@ -0,0 +1,98 @@
+use crate::{is_arborist_worktree, pick, plan_worktree, prune, require_repo};
+use arborist::error::Result;
+use arborist::{git, state};
+
+// `arborist repair`: re-link worktrees whose links to the repository broke (e.g. after it was
+// moved or restored from a backup), bring their metadata up to date and report what's left
+pub fn run_repair() -> Result<i32> {
+    // Finding the repository already follows it when it moved since arborist last saw it
+    let repo = require_repo()?;
+    let mut repaired = 0;
+    let mut problems = Vec::new();
+
+    for path in prune::prune_stale(&repo)? {
+        notice!("Forgot {}, which no longer exists", path.display());
+        repaired += 1;
+    }
+
+    for entry in git::list_worktrees()? {
+        if entry.path == repo.root || !is_arborist_worktree(&repo, &entry)? {
+            continue;
+        }
+        let Some(name) = pick::worktree_name(&repo, &entry)? else {
+            continue;
+        };
+
+        match git::repair_worktrees(&repo.root, std::slice::from_ref(&entry.path)) {
+            Ok(fixed) => {
+                for fix in &fixed {
+                    notice!("Repaired {}", fix);
+                }
+                repaired += fixed.len();
+            }
+            Err(err) => {
+                problems.push(format!("{}: {}", entry.path.display(), err));
+                continue;
+            }
+        }
+
+        // Metadata goes missing with a state directory that was lost or never migrated
+        let meta = match state::load_worktree(&repo.root, &name)? {
+            Some(mut meta) => {
+                if meta.path.as_ref() == Some(&entry.path) {
+                    meta
+                } else {
+                    meta.path = Some(entry.path.clone());
+                    state::record_worktree(&repo.root, &name, &meta)?;
+                    notice!("Recorded the location of {}", name);
+                    repaired += 1;
+                    meta
+                }
+            }
+            None => {
+                let head = git::head_commit(&entry.path)?;
+                let owned = entry
+                    .branch
+                    .as_deref()
+                    .is_some_and(|branch| branch == format!("arborist/{}", name));
+                let meta = state::WorktreeMeta {
+                    base_commit: git::merge_base(&head, &repo.current_commit)?.unwrap_or(head),
+                    base_branch: repo.current_branch.clone(),
+                    detached: entry.branch.is_none(),
+                    branch: entry.branch.clone().filter(|_| !owned),
+                    path: Some(entry.path.clone()),
+                    created_at: state::unix_now(),
+                    parked: false,
+                };
+                state::record_worktree(&repo.root, &name, &meta)?;
+                notice!("Recorded missing metadata of {}", name);
+                repaired += 1;
+                meta
+            }
+        };
+
+        // Arborist's branches track the branch they were created from
+        let plan = plan_worktree(repo.clone(), name.clone())?;
+        let Some(branch) = plan.owned_branch() else {
+            continue;
+        };
+        if meta.base_branch == "HEAD" || git::branch_upstream(branch)?.is_some() {
+            continue;
+        }
+        match git::set_branch_upstream(branch, &meta.base_branch) {
+            Ok(()) => {
+                notice!("Set the upstream of {} to {}", branch, meta.base_branch);
+                repaired += 1;
+            }
+            Err(err) => problems.push(format!("{}: {}", branch, err)),
+        }
+    }
+
+    for problem in &problems {
+        warning!("couldn't repair {}", problem);
+    }
+    if repaired == 0 && problems.is_empty() {
+        notice!("Nothing to repair");
+    }
+    Ok(if problems.is_empty() { 0 } else { 1 })
+}
@@ -0,0 +1,601 @@
+use crate::error::{ArboristError, Result};
+use crate::git;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Marker file recording that the first-run report was shown for a repository
+const ACK_FILE: &str = "acknowledged";
+
+// File recording the repository root a state directory belongs to
+const REPO_FILE: &str = "repo";
+
+/// Append-only log of finished runs, one JSON record per line
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+// Directory of output captured with --capture, one {worktree name}-{start time}.log per run
+const LOGS_DIR: &str = "logs";
+
+// Directory of per-worktree state files, named {worktree name}.json and {worktree name}.run.json
+const WORKTREES_DIR: &str = "worktrees";
+
+// Directory under the state root holding patches of discarded work, by repository
+const ARCHIVE_DIR: &str = "archive";
+
+// Append-only log under the state root of every arborist invocation, one JSON record per line
+const AUDIT_FILE: &str = "audit.jsonl";
+
+// File under the state root recording the schema version the state was last written in
+const SCHEMA_FILE: &str = "schema";
+
+// Lock file taken by arborist processes writing to the directory holding it: the state root for
+// the schema and audit log, a repository's state directory for everything in it
+const LOCK_FILE: &str = ".lock";
+
+/// Version of the layout and records of the state directory, raised whenever a change needs
+/// existing state rewritten; unversioned state from before it was recorded is version 0
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// What a worktree was created from, recorded when arborist creates it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorktreeMeta {
+    pub base_commit: String,
+    /// Branch checked out when the worktree was created ("HEAD" when detached)
+    pub base_branch: String,
+    /// Whether the worktree was created without a branch of its own (--detach)
+    #[serde(default)]
+    pub detached: bool,
+    /// Existing branch checked out with --branch, which arborist never deletes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Where the worktree was created, so it is still found after the worktree root changes
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "raw_path")]
+    pub path: Option<PathBuf>,
+    /// Creation time in seconds since the Unix epoch
+    #[serde(default)]
+    pub created_at: u64,
+    /// Whether the worktree is parked in the pool, set up and waiting for a run to claim it
+    #[serde(default)]
+    pub parked: bool,
+}
+
+/// A command arborist is running in a worktree, recorded for the duration of the run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunInfo {
+    /// Process id of the arborist process running the command
+    pub pid: u32,
+    pub command: String,
+    /// Start time in seconds since the Unix epoch
+    pub started_at: u64,
+    /// When the run last showed it was alive, in seconds since the Unix epoch; renewed
+    /// periodically for as long as it runs
+    #[serde(default)]
+    pub heartbeat_at: u64,
+    /// Host name of the machine (or container) the run is on, where alone its pid means anything
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+}
+
+/// A finished run, appended to the repository's history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// Worktree name (color)
+    pub name: String,
+    pub command: String,
+    /// Start time in seconds since the Unix epoch
+    pub started_at: u64,
+    /// Time spent running the command and its --then steps
+    pub duration_ms: u64,
+    /// Time the command and its steps finished in seconds since the Unix epoch; None in records
+    /// from before it was kept, see `finished_at()`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    pub exit_code: i32,
+    /// Times the command was run, retries with --retries included; with --then steps, the most
+    /// any one step took. 0 when it couldn't be started, 1 in records from before it was kept
+    #[serde(default = "single_attempt")]
+    pub attempts: u32,
+    /// Commit the worktree's HEAD was at when the command finished, when it was looked up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head: Option<String>,
+    /// Whether the run used a worktree that already existed
+    pub reused: bool,
+    /// Time spent creating and setting up the worktree; None when it was reused
+    pub setup_ms: Option<u64>,
+    /// Whether the worktree was kept afterwards
+    pub kept: bool,
+    /// File holding the command's output when it was captured
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "raw_path")]
+    pub log: Option<PathBuf>,
+}
+
+fn single_attempt() -> u32 {
+    1
+}
+
+impl RunRecord {
+    /// When the command and its steps finished, in seconds since the Unix epoch
+    pub fn finished_at(&self) -> u64 {
+        self.finished_at
+            .unwrap_or(self.started_at + self.duration_ms / 1000)
+    }
+}
+
+/// An arborist invocation, appended to the user's audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Time the invocation finished in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Login name of the user who ran it, and their user id
+    pub user: String,
+    pub uid: u32,
+    /// Arguments arborist was run with, its own name included
+    pub argv: Vec<String>,
+    /// Directory it was run in
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "raw_path")]
+    pub cwd: Option<PathBuf>,
+    /// Root of the repository it operated on, if any
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "raw_path")]
+    pub repo: Option<PathBuf>,
+    /// Worktrees it ran in, created or removed
+    #[serde(default)]
+    pub worktrees: Vec<AuditWorktree>,
+    pub exit_code: i32,
+}
+
+/// A worktree an audited invocation touched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditWorktree {
+    /// Worktree name (color)
+    pub name: String,
+    /// None when the worktree is detached
+    pub branch: Option<String>,
+    /// What happened to the worktree afterwards: "kept" or "removed"; None when it was left alone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cleanup: Option<String>,
+}
+
+// Paths in state files: a string, or for a path that isn't UTF-8 (which JSON strings can't hold)
+// an array of its bytes
+mod raw_path {
+    use crate::git;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::path::PathBuf;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Text(String),
+        Bytes(Vec<u8>),
+    }
+
+    pub fn serialize<S: Serializer>(
+        path: &Option<PathBuf>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        path.as_ref()
+            .map(|path| match path.to_str() {
+                Some(text) => Raw::Text(text.to_string()),
+                None => Raw::Bytes(path.as_os_str().as_encoded_bytes().to_vec()),
+            })
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<PathBuf>, D::Error> {
+        Ok(
+            Option::<Raw>::deserialize(deserializer)?.map(|raw| match raw {
+                Raw::Text(text) => PathBuf::from(text),
+                Raw::Bytes(bytes) => git::path_from_bytes(&bytes),
+            }),
+        )
+    }
+}
+
+/// Current time in seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Root directory for state shared across arborist runs
+/// Returns: $XDG_STATE_HOME/arborist (~/.local/state/arborist), the platform's local data
+/// directory where it has no state directory, or /tmp/arborist/state without a home directory
+pub fn state_root() -> PathBuf {
+    match ProjectDirs::from("", "", "arborist") {
+        Some(dirs) => dirs
+            .state_dir()
+            .unwrap_or_else(|| dirs.data_local_dir())
+            .to_path_buf(),
+        None => legacy_state_root(),
+    }
+}
+
+/// Root directory for caches shared across arborist runs
+/// Returns: $XDG_CACHE_HOME/arborist (~/.cache/arborist) or the platform's cache directory,
+/// or /tmp/arborist/cache without a home directory
+pub fn cache_root() -> PathBuf {
+    match ProjectDirs::from("", "", "arborist") {
+        Some(dirs) => dirs.cache_dir().to_path_buf(),
+        None => legacy_cache_root(),
+    }
+}
+
+/// Where state was kept before it moved to the state directory
+/// Returns: /tmp/arborist/state
+pub fn legacy_state_root() -> PathBuf {
+    PathBuf::from("/tmp").join("arborist").join("state")
+}
+
+/// Where caches were kept before they moved to the cache directory
+/// Returns: /tmp/arborist/cache
+pub fn legacy_cache_root() -> PathBuf {
+    PathBuf::from("/tmp").join("arborist").join("cache")
+}
+
+/// Directory holding state for a single repository
+/// Returns: {state root}/{repo_hash}
+pub fn repo_state_dir(repo_root: &Path) -> Result<PathBuf> {
+    Ok(state_root().join(git::repo_hash(repo_root)?))
+}
+
+/// Directory holding patches of work discarded from a repository's worktrees
+/// Kept apart from the repository's state, so `destroy-all --keep-archives` can leave it behind
+/// Returns: {state root}/archive/{repo_hash}
+pub fn archive_dir(repo_root: &Path) -> Result<PathBuf> {
+    Ok(state_root()
+        .join(ARCHIVE_DIR)
+        .join(git::repo_hash(repo_root)?))
+}
+
+/// Directory holding shared build artifacts for a single repository
+/// Returns: {cache root}/{repo_hash}
+pub fn repo_cache_dir(repo_root: &Path) -> Result<PathBuf> {
+    Ok(cache_root().join(git::repo_hash(repo_root)?))
+}
+
+pub fn is_acknowledged(repo_root: &Path) -> Result<bool> {
+    Ok(repo_state_dir(repo_root)?.join(ACK_FILE).exists())
+}
+
+pub fn record_acknowledgment(repo_root: &Path) -> Result<()> {
+    let dir = repo_state_dir(repo_root)?;
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join(ACK_FILE), "")?;
+    Ok(())
+}
+
+/// Records the repository root so user-wide commands can find it later
+pub fn record_repo(repo_root: &Path) -> Result<()> {
+    let dir = repo_state_dir(repo_root)?;
+    fs::create_dir_all(&dir)?;
+    fs::write(
+        dir.join(REPO_FILE),
+        repo_root.as_os_str().as_encoded_bytes(),
+    )?;
+    Ok(())
+}
+
+/// The repository root recorded in a repository's state directory, which differs from
+/// `repo_root` when the repository has moved since
+pub fn recorded_root(repo_root: &Path) -> Result<Option<PathBuf>> {
+    let path = repo_state_dir(repo_root)?.join(REPO_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(git::path_from_bytes(&fs::read(path)?)))
+}
+
+/// Records what a newly created worktree was based on
+pub fn record_worktree(repo_root: &Path, name: &str, meta: &WorktreeMeta) -> Result<()> {
+    let _lock = lock(&repo_state_dir(repo_root)?)?;
+    write_json(&worktree_file(repo_root, name, "json")?, meta)
+}
+
+/// Changes a worktree's recorded metadata in place, with no other arborist process writing it in
+/// between, as `load_worktree` then `record_worktree` would allow
+/// Returns: false when the worktree has no metadata to change
+pub fn update_worktree(
+    repo_root: &Path,
+    name: &str,
+    update: impl FnOnce(&mut WorktreeMeta),
+) -> Result<bool> {
+    let _lock = lock(&repo_state_dir(repo_root)?)?;
+    let path = worktree_file(repo_root, name, "json")?;
+    let Some(mut meta) = read_json(&path)? else {
+        return Ok(false);
+    };
+    update(&mut meta);
+    write_json(&path, &meta)?;
+    Ok(true)
+}
+
+/// Reads a worktree's metadata; None for worktrees created before it was recorded
+pub fn load_worktree(repo_root: &Path, name: &str) -> Result<Option<WorktreeMeta>> {
+    read_json(&worktree_file(repo_root, name, "json")?)
+}
+
+/// Reads the metadata of every worktree recorded in a repository's state directory
+/// Returns: (worktree name, metadata) pairs
+pub fn list_worktree_meta(state_dir: &Path) -> Result<Vec<(String, WorktreeMeta)>> {
+    let dir = state_dir.join(WORKTREES_DIR);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut worktrees = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".json"))
+            .filter(|name| !name.ends_with(".run"))
+        else {
+            continue;
+        };
+        if let Some(meta) = read_json(&path)? {
+            worktrees.push((name.to_string(), meta));
+        }
+    }
+    worktrees.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(worktrees)
+}
+
+/// Forgets a worktree's metadata once the worktree is removed
+pub fn remove_worktree_meta(repo_root: &Path, name: &str) -> Result<()> {
+    let _lock = lock(&repo_state_dir(repo_root)?)?;
+    remove_file(&worktree_file(repo_root, name, "json")?)
+}
+
+/// Marks a command as running in a worktree
+pub fn record_run(repo_root: &Path, name: &str, run: &RunInfo) -> Result<()> {
+    let _lock = lock(&repo_state_dir(repo_root)?)?;
+    write_json(&worktree_file(repo_root, name, "run.json")?, run)
+}
+
+/// The command last recorded as running in a worktree; its process may have died since
+pub fn load_run(repo_root: &Path, name: &str) -> Result<Option<RunInfo>> {
+    read_json(&worktree_file(repo_root, name, "run.json")?)
+}
+
+/// The commands last recorded as running in a repository's worktrees, by worktree name; their
+/// processes may have died since
+pub fn list_runs(state_dir: &Path) -> Result<Vec<(String, RunInfo)>> {
+    let dir = state_dir.join(WORKTREES_DIR);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut runs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".run.json"))
+        else {
+            continue;
+        };
+        if let Some(run) = read_json(&path)? {
+            runs.push((name.to_string(), run));
+        }
+    }
+    runs.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(runs)
+}
+
+/// Clears the running command once it has finished
+pub fn remove_run(repo_root: &Path, name: &str) -> Result<()> {
+    let _lock = lock(&repo_state_dir(repo_root)?)?;
+    remove_file(&worktree_file(repo_root, name, "run.json")?)
+}
+
+// Per-worktree state file: {state dir}/worktrees/{name}.{extension}
+fn worktree_file(repo_root: &Path, name: &str, extension: &str) -> Result<PathBuf> {
+    Ok(repo_state_dir(repo_root)?
+        .join(WORKTREES_DIR)
+        .join(format!("{}.{}", name, extension)))
+}
+
+// Written to a temporary file renamed over the old one, so a reader (which takes no lock) sees
+// either the old record or the new one, never half of one
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", std::process::id()));
+    fs::write(&temp, serde_json::to_vec(value)?)?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+// A file removed by another process since it was listed reads as missing
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<Option<T>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// Holds the lock on `dir` until the returned file is dropped, waiting for any other arborist
+// process holding it. Locks are advisory: they keep arborist processes from interleaving their
+// writes, and are released by the system if a process dies holding one
+fn lock(dir: &Path) -> Result<File> {
+    fs::create_dir_all(dir)?;
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(LOCK_FILE))?;
+    file.lock()?;
+    Ok(file)
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// File receiving the captured output of the run started at `started_at` in worktree `name`
+pub fn run_log_path(repo_root: &Path, name: &str, started_at: u64) -> Result<PathBuf> {
+    let dir = repo_state_dir(repo_root)?.join(LOGS_DIR);
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}-{}.log", name, started_at)))
+}
+
+/// Appends a finished run to the repository's history
+pub fn append_history(repo_root: &Path, record: &RunRecord) -> Result<()> {
+    let dir = repo_state_dir(repo_root)?;
+    let _lock = lock(&dir)?;
+
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(HISTORY_FILE))?
+        .write_all(&line)?;
+    Ok(())
+}
+
+/// Reads the run history from a repository's state directory, oldest first
+/// Lines that can't be parsed (e.g. from a run cut off mid-write) are skipped
+pub fn load_history(state_dir: &Path) -> Result<Vec<RunRecord>> {
+    let path = state_dir.join(HISTORY_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// The user's audit log of arborist invocations across all repositories
+/// Returns: {state root}/audit.jsonl
+pub fn audit_log_path() -> PathBuf {
+    state_root().join(AUDIT_FILE)
+}
+
+/// Appends an invocation to the user's audit log
+pub fn append_audit(record: &AuditRecord) -> Result<()> {
+    let path = audit_log_path();
+    let _lock = lock(&state_root())?;
+
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    let mut options = fs::OpenOptions::new();
+    options.create(true).append(true);
+    // Command lines and working directories are for the user's eyes only
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(&line)?;
+    Ok(())
+}
+
+/// Reads the user's audit log, oldest first
+/// Lines that can't be parsed (e.g. from an invocation cut off mid-write) are skipped
+pub fn load_audit() -> Result<Vec<AuditRecord>> {
+    let path = audit_log_path();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Lists every repository arborist has created worktrees for
+/// Returns: (state directory, recorded repository root) pairs
+pub fn known_repos() -> Result<Vec<(PathBuf, PathBuf)>> {
+    let root = state_root();
+    if !root.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut repos = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let dir = entry?.path();
+        if let Ok(repo_root) = fs::read(dir.join(REPO_FILE)) {
+            repos.push((dir, git::path_from_bytes(&repo_root)));
+        }
+    }
+    repos.sort();
+
+    Ok(repos)
+}
+
+/// Brings state written by an earlier version up to the current schema, once per state directory
+/// Returns: Err when a newer arborist has written the state, which this one mustn't touch
+pub fn upgrade_schema() -> Result<()> {
+    let root = state_root();
+    let path = root.join(SCHEMA_FILE);
+    // Checked without the lock first, as it nearly always is current
+    if read_schema(&path)? == Some(SCHEMA_VERSION) {
+        return Ok(());
+    }
+
+    let _lock = lock(&root)?;
+    let found = read_schema(&path)?.unwrap_or(0);
+    if found > SCHEMA_VERSION {
+        return Err(ArboristError::StateTooNew {
+            dir: root,
+            found,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    for version in found..SCHEMA_VERSION {
+        migrate_schema(&root, version)?;
+    }
+    fs::write(&path, SCHEMA_VERSION.to_string())?;
+    Ok(())
+}
+
+fn read_schema(path: &Path) -> Result<Option<u32>> {
+    match fs::read_to_string(path) {
+        Ok(text) => text.trim().parse().map(Some).map_err(|_| {
+            ArboristError::InvalidConfig(format!(
+                "{} should hold a schema version, not {:?}",
+                path.display(),
+                text.trim()
+            ))
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// Rewrites the state under `root` from schema `from` to the one after it
+fn migrate_schema(_root: &Path, from: u32) -> Result<()> {
+    match from {
+        // Unversioned state is already laid out as version 1; fields added since are optional
+        0 => Ok(()),
+        _ => unreachable!("no schema after {}", SCHEMA_VERSION),
+    }
+}
+
+/// Deletes all recorded state for a repository
+pub fn remove_repo_state(state_dir: &Path) -> Result<()> {
+    if state_dir.exists() {
+        fs::remove_dir_all(state_dir)?;
+    }
+    Ok(())
+}
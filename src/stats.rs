@@ -0,0 +1,136 @@
+use arborist::error::Result;
+use arborist::state;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Aggregated run history of one repository
+#[derive(Serialize)]
+struct RepoStats {
+    repo: PathBuf,
+    runs: usize,
+    succeeded: usize,
+    failed: usize,
+    run_time: Option<Timing>,
+    // Only runs that created their worktree contribute setup times
+    created: usize,
+    reused: usize,
+    setup_time: Option<Timing>,
+    kept: usize,
+    removed: usize,
+}
+
+#[derive(Serialize)]
+struct Timing {
+    mean_ms: u64,
+    median_ms: u64,
+    max_ms: u64,
+}
+
+impl Timing {
+    fn from_millis(mut samples: Vec<u64>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        Some(Timing {
+            mean_ms: samples.iter().sum::<u64>() / samples.len() as u64,
+            median_ms: samples[samples.len() / 2],
+            max_ms: samples[samples.len() - 1],
+        })
+    }
+}
+
+// `arborist stats`: summarize recorded runs for this repository (or all of them)
+pub fn run_stats(everywhere: bool, json: bool) -> Result<i32> {
+    let repos = if everywhere {
+        state::known_repos()?
+    } else {
+        let repo = crate::require_repo()?;
+        vec![(state::repo_state_dir(&repo.root)?, repo.root)]
+    };
+
+    let mut stats = Vec::new();
+    for (state_dir, repo_root) in repos {
+        let history = state::load_history(&state_dir)?;
+        if !history.is_empty() {
+            stats.push(summarize(repo_root, &history));
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string(&stats)?);
+    } else if stats.is_empty() {
+        eprintln!("No runs recorded yet");
+    } else {
+        for (index, repo) in stats.iter().enumerate() {
+            if index > 0 {
+                println!();
+            }
+            print_stats(repo);
+        }
+    }
+
+    Ok(0)
+}
+
+fn summarize(repo: PathBuf, history: &[state::RunRecord]) -> RepoStats {
+    let succeeded = history.iter().filter(|run| run.exit_code == 0).count();
+    let reused = history.iter().filter(|run| run.reused).count();
+    let kept = history.iter().filter(|run| run.kept).count();
+    let setup_times: Vec<u64> = history.iter().filter_map(|run| run.setup_ms).collect();
+
+    RepoStats {
+        repo,
+        runs: history.len(),
+        succeeded,
+        failed: history.len() - succeeded,
+        run_time: Timing::from_millis(history.iter().map(|run| run.duration_ms).collect()),
+        created: setup_times.len(),
+        reused,
+        setup_time: Timing::from_millis(setup_times),
+        kept,
+        removed: history.len() - kept,
+    }
+}
+
+fn print_stats(stats: &RepoStats) {
+    println!("{}", stats.repo.display());
+    println!(
+        "  runs:       {} ({} succeeded, {} failed)",
+        stats.runs, stats.succeeded, stats.failed
+    );
+    if let Some(timing) = &stats.run_time {
+        println!("  run time:   {}", format_timing(timing));
+    }
+    if let Some(timing) = &stats.setup_time {
+        println!(
+            "  setup time: {} over {} created worktree(s)",
+            format_timing(timing),
+            stats.created
+        );
+    }
+    println!(
+        "  reuse:      {} of {} run(s) used an existing worktree ({:.0}%)",
+        stats.reused,
+        stats.runs,
+        stats.reused as f64 * 100.0 / stats.runs as f64
+    );
+    println!(
+        "  cleanup:    {} removed, {} kept",
+        stats.removed, stats.kept
+    );
+}
+
+fn format_timing(timing: &Timing) -> String {
+    let format = |ms: u64| match ms {
+        0..1000 => format!("{}ms", ms),
+        _ => format!("{:.1?}", Duration::from_millis(ms)),
+    };
+    format!(
+        "mean {}, median {}, max {}",
+        format(timing.mean_ms),
+        format(timing.median_ms),
+        format(timing.max_ms)
+    )
+}
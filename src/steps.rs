@@ -0,0 +1,130 @@
+use crate::{Args, Invocation, cancel, events, execute_with_retries, style, tools};
+use arborist::error::Result;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+/// Result of one command in a pipeline
+#[derive(Debug, Clone)]
+pub struct StepResult {
+    pub label: String,
+    // None when the step was skipped after an earlier failure
+    pub exit_code: Option<i32>,
+    pub duration: Duration,
+    pub attempts: u32,
+}
+
+/// Runs the main command followed by every --then step
+/// Stops at the first failure unless --keep-going is given
+pub async fn run_steps(invocation: &Invocation, args: &Args) -> Result<Vec<StepResult>> {
+    let mut steps = vec![(invocation.command.join(" "), invocation.clone())];
+    for script in &args.then {
+        let step = Invocation {
+            command: tools::shell_argv(script),
+            ..invocation.clone()
+        };
+        steps.push((script.clone(), step));
+    }
+
+    let mut results = Vec::new();
+    let mut failed = false;
+    for (index, (label, step)) in steps.into_iter().enumerate() {
+        // An interrupt stops the pipeline even with --keep-going
+        if failed && !args.keep_going || cancel::is_cancelled() {
+            results.push(StepResult {
+                label,
+                exit_code: None,
+                duration: Duration::ZERO,
+                attempts: 0,
+            });
+            continue;
+        }
+
+        verbose!("Running step: {}", label);
+        events::emit(
+            "command_started",
+            json!({ "step": index, "command": label }),
+        );
+        let started = Instant::now();
+        let (exit_code, attempts) = execute_with_retries(&step, args).await?;
+        let mut finished = json!({
+            "step": index,
+            "command": label,
+            "exit_code": exit_code,
+            "duration_ms": started.elapsed().as_millis() as u64,
+            "attempts": attempts,
+        });
+        // What the command printed last usually says why it failed
+        if let Some(tail) = step.tail.as_ref().filter(|_| exit_code != 0) {
+            finished["output_tail"] = json!(tail.lines());
+        }
+        events::emit("command_finished", finished);
+        failed |= exit_code != 0;
+        if attempts > 1 {
+            verbose!("Step finished after {} attempt(s)", attempts);
+        }
+
+        results.push(StepResult {
+            label,
+            exit_code: Some(exit_code),
+            duration: started.elapsed(),
+            attempts,
+        });
+    }
+
+    if results.len() > 1 {
+        print_steps(&results);
+    }
+
+    Ok(results)
+}
+
+/// Times a pipeline's commands were run: the most attempts any one step took
+pub fn attempts(results: &[StepResult]) -> u32 {
+    results.iter().map(|step| step.attempts).max().unwrap_or(0)
+}
+
+/// Exit code of a pipeline: the first failing step's code, or 0
+pub fn exit_code(results: &[StepResult]) -> i32 {
+    results
+        .iter()
+        .filter_map(|step| step.exit_code)
+        .find(|code| *code != 0)
+        .unwrap_or(0)
+}
+
+fn print_steps(results: &[StepResult]) {
+    notice!("{} steps", style::info("arborist:"));
+    for step in results {
+        let Some(code) = step.exit_code else {
+            notice!("  skipped {:>8}  {}", "", step.label);
+            continue;
+        };
+
+        let mut details = Vec::new();
+        if code != 0 {
+            details.push(format!("exit {}", code));
+        }
+        if step.attempts > 1 {
+            details.push(format!("{} attempts", step.attempts));
+        }
+        let details = if details.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", details.join(", "))
+        };
+
+        // Padded before styling so escape codes don't upset the alignment
+        let outcome = if code == 0 {
+            style::success(&format!("{:<7}", "ok"))
+        } else {
+            style::failure(&format!("{:<7}", "failed"))
+        };
+        notice!(
+            "  {} {:>8.2?}  {}{}",
+            outcome,
+            step.duration,
+            step.label,
+            details
+        );
+    }
+}
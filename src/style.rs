@@ -0,0 +1,96 @@
+use crossterm::style::{Color, Stylize};
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Whether arborist's messages are styled, decided once at startup
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Colors cycled through for worktrees whose name isn't a color
+const PALETTE: &[Color] = &[
+    Color::Cyan,
+    Color::Magenta,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Red,
+];
+
+/// Styles messages when stderr is a terminal, unless --no-color is given or NO_COLOR is set
+pub fn init(no_color: bool) {
+    let no_color_env = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let enabled = !no_color && !no_color_env && io::stderr().is_terminal();
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Label of an error message
+pub fn error(text: &str) -> String {
+    paint(text, |text| text.red().bold().to_string())
+}
+
+/// Label of a warning
+pub fn warning(text: &str) -> String {
+    paint(text, |text| text.yellow().bold().to_string())
+}
+
+/// Label of a hint
+pub fn hint(text: &str) -> String {
+    paint(text, |text| text.cyan().to_string())
+}
+
+/// Label of arborist's own progress and summary lines
+pub fn info(text: &str) -> String {
+    paint(text, |text| text.bold().to_string())
+}
+
+/// Something that went well, e.g. a zero exit code
+pub fn success(text: &str) -> String {
+    paint(text, |text| text.green().to_string())
+}
+
+/// Something that went wrong, e.g. a non-zero exit code
+pub fn failure(text: &str) -> String {
+    paint(text, |text| text.red().to_string())
+}
+
+/// Detail that matters less than the surrounding output, such as --verbose messages
+pub fn dim(text: &str) -> String {
+    paint(text, |text| text.dim().to_string())
+}
+
+/// Text in the color a worktree is named after, or a stable palette color for other names
+pub fn worktree(name: &str, text: &str) -> String {
+    let color = worktree_color(name);
+    paint(text, |text| text.with(color).to_string())
+}
+
+fn paint(text: &str, style: impl FnOnce(&str) -> String) -> String {
+    if is_enabled() {
+        style(text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn worktree_color(name: &str) -> Color {
+    match name {
+        "red" | "crimson" | "maroon" | "ruby" => Color::Red,
+        "blue" | "navy" | "indigo" | "sapphire" => Color::Blue,
+        "green" | "lime" | "emerald" | "mint" => Color::Green,
+        "yellow" | "amber" | "topaz" => Color::Yellow,
+        "cyan" | "teal" | "turquoise" => Color::Cyan,
+        "magenta" | "purple" | "pink" | "violet" | "lavender" => Color::Magenta,
+        "orange" | "coral" | "peach" => Color::AnsiValue(208),
+        "slate" => Color::Grey,
+        _ => {
+            let hash = name.bytes().fold(0usize, |hash, byte| {
+                hash.wrapping_mul(31).wrapping_add(byte as usize)
+            });
+            PALETTE[hash % PALETTE.len()]
+        }
+    }
+}
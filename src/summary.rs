@@ -0,0 +1,93 @@
+use crate::{CleanupOutcome, WorktreePlan, excluded_paths, style, untracked_files};
+use arborist::error::Result;
+use arborist::{git, state};
+use std::time::Duration;
+
+/// What a run changed in its worktree since the worktree was created
+pub struct Changes {
+    pub files: usize,
+    pub commits: usize,
+}
+
+/// Measures the changes of the worktree in the current directory against its base commit
+pub fn measure(plan: &WorktreePlan) -> Result<Changes> {
+    let base = match state::load_worktree(&plan.repo.root, &plan.name)? {
+        Some(meta) => meta.base_commit,
+        None => plan.repo.current_commit.clone(),
+    };
+    let excluded = excluded_paths(plan)?;
+
+    Ok(Changes {
+        files: git::count_changed_files(&plan.path, &base, &excluded, untracked_files(plan))?,
+        commits: git::count_commits_not_in(&base)?,
+    })
+}
+
+/// Prints a one-line account of a finished run, as recorded in the history, and what happened to
+/// its worktree
+pub fn print_summary(
+    plan: &WorktreePlan,
+    record: &state::RunRecord,
+    duration: Duration,
+    changes: Option<&Changes>,
+    outcome: &CleanupOutcome,
+    limit: Option<&str>,
+) {
+    let exit_code = record.exit_code;
+    let code = match limit {
+        Some(limit) => format!("exit code {} (exceeded {})", exit_code, limit),
+        None => format!("exit code {}", exit_code),
+    };
+    let code = if exit_code == 0 {
+        style::success(&code)
+    } else {
+        style::failure(&code)
+    };
+    let mut parts = vec![format!("{} after {:.2?}", code, duration)];
+    if record.attempts > 1 {
+        parts.push(format!("{} attempts", record.attempts));
+    }
+    // Without a branch to go back to, the commit is all that says where the run started
+    if plan.repo.detached {
+        parts.push(format!("from {}", plan.repo.base_name()));
+    }
+    if let Some(changes) = changes {
+        parts.push(format!(
+            "{} changed, {} created",
+            count(changes.files, "file"),
+            count(changes.commits, "commit")
+        ));
+    }
+    // Untracked files could have been left out of the decision below
+    if untracked_files(plan) == git::UntrackedFiles::No {
+        parts.push("untracked files skipped".to_string());
+    }
+    parts.push(match outcome {
+        CleanupOutcome::Kept(_) => {
+            format!(
+                "worktree {} kept at {}",
+                style::worktree(&plan.name, &plan.name),
+                plan.path.display()
+            )
+        }
+        CleanupOutcome::Removed => format!(
+            "worktree {} removed",
+            style::worktree(&plan.name, &plan.name)
+        ),
+    });
+
+    if let Some(log) = &record.log {
+        parts.push(format!("output in {}", log.display()));
+    }
+
+    eprintln!("{} {}", style::info("arborist:"), parts.join("; "));
+}
+
+/// "1 file", "2 files"
+pub fn count(n: usize, noun: &str) -> String {
+    if n == 1 {
+        format!("1 {}", noun)
+    } else {
+        format!("{} {}s", n, noun)
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    DirectoryGuard, excluded_paths, heartbeat, plan_worktree, require_repo, style, summary,
+    worktree_status,
+};
+use arborist::error::{ArboristError, Result};
+use arborist::{git, state};
+
+// `arborist sync`: bring a kept worktree's branch up to date with the branch it was created
+// from, by rebasing it onto that branch's tip (or merging the tip in), fetching it first when it
+// tracks a remote branch. The commits it makes are signed as `sign` says (see git::commit_all)
+pub fn run_sync(
+    name: &str,
+    merge: bool,
+    continue_on_conflict: bool,
+    fetch: bool,
+    sign: Option<bool>,
+) -> Result<i32> {
+    let repo = require_repo()?;
+    let plan = plan_worktree(repo, name.to_string())?;
+    if !git::worktree_exists(&plan.path)? {
+        return Err(ArboristError::InvalidPath(format!(
+            "No arborist worktree named '{}' at {}",
+            name,
+            plan.path.display()
+        )));
+    }
+    let Some(branch) = plan.branch.clone() else {
+        return Err(ArboristError::InvalidPath(format!(
+            "Worktree {} is detached, so it has no branch to sync",
+            name
+        )));
+    };
+    let Some(meta) = state::load_worktree(&plan.repo.root, &plan.name)? else {
+        return Err(ArboristError::InvalidPath(format!(
+            "Worktree {} has no record of the branch it was created from",
+            name
+        )));
+    };
+    if meta.base_branch == "HEAD" {
+        return Err(ArboristError::InvalidPath(format!(
+            "Worktree {} was created from a detached HEAD, so there is no branch to sync with",
+            name
+        )));
+    }
+
+    if let Some(run) = heartbeat::active_run(&plan.repo.root, &plan.name)? {
+        eprintln!(
+            "Worktree {} is in use by the arborist run in process {}",
+            name, run.pid
+        );
+        return Ok(1);
+    }
+    let status = {
+        let _guard = DirectoryGuard::with_path(&plan.path)?;
+        worktree_status(&plan, &excluded_paths(&plan)?)?
+    };
+    if status.has_changes {
+        eprintln!(
+            "Worktree {} has uncommitted changes; commit or discard them before syncing",
+            name
+        );
+        return Ok(1);
+    }
+
+    // The remote branch the base tracks has the newest work; a local-only base is used as it is
+    let (target_name, target) = match git::RemoteBranch::upstream_of(&meta.base_branch)? {
+        Some(upstream) if fetch => {
+            verbose!("Fetching {}...", upstream.name());
+            let tip = upstream.fetch()?;
+            (upstream.name(), tip)
+        }
+        Some(upstream) => {
+            let tip = upstream.tip()?;
+            (upstream.name(), tip)
+        }
+        None => (
+            meta.base_branch.clone(),
+            git::branch_tip(&meta.base_branch)?,
+        ),
+    };
+
+    let head = git::head_commit(&plan.path)?;
+    let behind = git::count_commits_between(&head, &target)?;
+    if behind == 0 {
+        notice!(
+            "{} {} is up to date with {}",
+            style::info("arborist:"),
+            branch,
+            target_name
+        );
+        return Ok(0);
+    }
+
+    let verb = if merge { "merge" } else { "rebase" };
+    let synced = if merge {
+        verbose!("Merging {} into {}...", target_name, branch);
+        git::merge(&plan.path, &target_name, &plan.config.identity(), sign)?
+    } else {
+        verbose!("Rebasing {} onto {}...", branch, target_name);
+        git::rebase(&plan.path, &target, &plan.config.identity(), sign)?
+    };
+
+    if !synced {
+        eprintln!(
+            "{} the {} of {} with {} conflicted in: {}",
+            style::error("Conflict:"),
+            verb,
+            branch,
+            target_name,
+            git::conflicted_files(&plan.path)?.join(", ")
+        );
+        if continue_on_conflict {
+            eprintln!(
+                "Resolve the conflicts in {}, then run `git {} --continue` there (or `git {} --abort`)",
+                plan.path.display(),
+                verb,
+                verb
+            );
+        } else {
+            if merge {
+                git::abort_merge(&plan.path)?;
+            } else {
+                git::abort_rebase(&plan.path)?;
+            }
+            eprintln!(
+                "The {} was aborted, leaving {} as it was; use --continue-on-conflict to resolve the conflicts yourself",
+                verb, branch
+            );
+        }
+        return Ok(1);
+    }
+
+    // Only what the branch has over the new base counts as the worktree's work from now on
+    state::update_worktree(&plan.repo.root, &plan.name, |meta| {
+        meta.base_commit = target
+    })?;
+
+    let synced = if merge {
+        format!("merged {} into {}", target_name, branch)
+    } else {
+        format!("rebased {} onto {}", branch, target_name)
+    };
+    notice!(
+        "{} {}, picking up {}",
+        style::info("arborist:"),
+        synced,
+        summary::count(behind, "new commit")
+    );
+    Ok(0)
+}
@@ -0,0 +1,161 @@
+use crate::cancel;
+use arborist::error::Result;
+use arborist::process::{self, Stdio};
+use async_io::Timer;
+use futures_lite::{AsyncRead, AsyncReadExt, future};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// How long output still in the pipes gets to drain after the command exits; a background process
+// it left holding them open mustn't keep arborist waiting
+const DRAIN: Duration = Duration::from_millis(500);
+
+/// The last lines a command printed, stdout and stderr together in the order they arrived,
+/// collected with `--capture-tail` while its output still streams to the terminal
+#[derive(Debug, Clone)]
+pub struct OutputTail {
+    capacity: usize,
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl OutputTail {
+    pub fn new(capacity: usize) -> Self {
+        OutputTail {
+            capacity,
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Remembers one line of output, forgetting the oldest beyond the capacity
+    pub fn push(&self, line: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lines = self.lock();
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(
+            String::from_utf8_lossy(line)
+                .trim_end_matches('\r')
+                .to_string(),
+        );
+    }
+
+    /// The remembered lines, oldest first
+    pub fn lines(&self) -> Vec<String> {
+        self.lock().iter().cloned().collect()
+    }
+
+    /// A writer passing everything through to `inner` as it comes, remembering complete lines
+    pub fn tee<W: Write>(&self, inner: W) -> Tee<W> {
+        Tee {
+            inner,
+            tail: self.clone(),
+            partial: Vec::new(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<String>> {
+        self.lines
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Writes through to another writer unchanged, so progress bars and prompts without a newline
+/// still show at once, while the tail gets whole lines
+pub struct Tee<W: Write> {
+    inner: W,
+    tail: OutputTail,
+    // The line written so far, until its newline arrives
+    partial: Vec<u8>,
+}
+
+impl<W: Write> Write for Tee<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        // Flushed at once, as the command's own output would have been
+        self.inner.flush()?;
+
+        let mut rest = &buf[..written];
+        while let Some(newline) = rest.iter().position(|&byte| byte == b'\n') {
+            self.partial.extend_from_slice(&rest[..newline]);
+            self.tail.push(&self.partial);
+            self.partial.clear();
+            rest = &rest[newline + 1..];
+        }
+        self.partial.extend_from_slice(rest);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for Tee<W> {
+    // Output that ended without a newline is still the command's last line
+    fn drop(&mut self) {
+        if !self.partial.is_empty() {
+            self.tail.push(&self.partial);
+        }
+    }
+}
+
+/// Runs the command with its stdout and stderr passing through pipes on their way to ours, both
+/// copied on the scheduler as the command runs, so `tail` sees every line without the command's
+/// output being held back
+/// Returns: the command's exit code, or INTERRUPTED when it was killed
+pub async fn run(mut command: process::Command, tail: &OutputTail) -> Result<i32> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut copying = pin!(future::zip(
+        copy(child.stdout.take(), tail.tee(io::stdout())),
+        copy(child.stderr.take(), tail.tee(io::stderr())),
+    ));
+
+    let mut copied = false;
+    let exit_code = future::or(cancel::wait(&mut child), async {
+        copying.as_mut().await;
+        copied = true;
+        future::pending().await
+    })
+    .await;
+    if !copied {
+        future::or(copying, async {
+            Timer::after(DRAIN).await;
+            ((), ())
+        })
+        .await;
+    }
+    exit_code
+}
+
+async fn copy(reader: Option<impl AsyncRead + Unpin>, mut tee: Tee<impl Write>) {
+    let Some(mut reader) = reader else {
+        return;
+    };
+    let mut buffer = [0; 8192];
+    while let Ok(read @ 1..) = reader.read(&mut buffer).await {
+        if tee.write_all(&buffer[..read]).is_err() {
+            return;
+        }
+    }
+}
+
+/// Adds the lines a command appended to `log` past `offset` to the tail, for output written to a
+/// log file rather than through arborist
+pub fn read_log(tail: &OutputTail, log: &Path, offset: u64) -> Result<()> {
+    let mut file = File::open(log)?;
+    file.seek(SeekFrom::Start(offset))?;
+    io::copy(&mut file, &mut tail.tee(io::sink()))?;
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use crate::WorktreePlan;
+use arborist::config;
+use arborist::error::{ArboristError, Result, ResultExt};
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Writes every configured template into the worktree, with its placeholders filled in
+/// Runs once per new worktree, after the checkout, so later edits to the files are the command's
+pub fn write(plan: &WorktreePlan) -> Result<()> {
+    let Some(templates) = &plan.config.templates else {
+        return Ok(());
+    };
+
+    for (destination, source) in templates {
+        let target = plan.path.join(checked_destination(destination)?);
+        let source = checked_source(&plan.repo.root, source, plan.config.repo_templates)?;
+        let template = fs::read_to_string(&source)
+            .context(format!("reading template {}", source.display()))?;
+        if plan.config.repo_templates && uses_env(&template) {
+            return Err(ArboristError::InvalidConfig(format!(
+                "template {} uses {{env:...}}, which only templates from the user config may",
+                source.display()
+            )));
+        }
+
+        verbose!("Writing {} from template {}", destination, source.display());
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&target, render(&template, plan))
+            .context(format!("writing {}", target.display()))?;
+    }
+    Ok(())
+}
+
+/// Rejects destinations outside the worktree, and repository sources outside the repository,
+/// before anything is created
+pub fn validate(plan: &WorktreePlan) -> Result<()> {
+    let Some(templates) = &plan.config.templates else {
+        return Ok(());
+    };
+    for (destination, source) in templates {
+        checked_destination(destination)?;
+        checked_source(&plan.repo.root, source, plan.config.repo_templates)?;
+    }
+    Ok(())
+}
+
+/// Worktree paths templates are written to; as arborist's files, they never count as changes
+pub fn destinations(plan: &WorktreePlan) -> Vec<String> {
+    plan.config
+        .templates
+        .iter()
+        .flat_map(|templates| templates.keys().cloned())
+        .collect()
+}
+
+// A template's destination must stay inside the worktree
+fn checked_destination(destination: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(destination);
+    let inside = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !inside || destination.is_empty() {
+        return Err(ArboristError::InvalidConfig(format!(
+            "template destination {} must be a relative path inside the worktree",
+            destination
+        )));
+    }
+    Ok(path)
+}
+
+// Relative sources are files of the repository, read from its checkout; a repository's own
+// templates can't read anything outside it, through `..`, `~` or a symlink
+fn checked_source(root: &Path, source: &Path, from_repo: bool) -> Result<PathBuf> {
+    let path = root.join(config::expand_home(source));
+    if !from_repo {
+        return Ok(path);
+    }
+    let relative = source
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    // A missing file is reported when it's read
+    let inside = relative
+        && match (path.canonicalize(), root.canonicalize()) {
+            (Ok(path), Ok(root)) => path.starts_with(root),
+            (path, _) => path.is_err(),
+        };
+    if !inside {
+        return Err(ArboristError::InvalidConfig(format!(
+            "template source {} is outside the repository; only the user config may use one",
+            source.display()
+        )));
+    }
+    Ok(path)
+}
+
+// Whether a template reads the environment, which only the user's own templates may
+fn uses_env(template: &str) -> bool {
+    template.contains("{env:")
+}
+
+// Fills in {name}, {branch}, {path}, {repo}, {base_branch}, {base_commit} and {env:NAME};
+// anything else in braces is left as it is
+fn render(template: &str, plan: &WorktreePlan) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after
+            .find('}')
+            .and_then(|end| Some((placeholder(&after[..end], plan)?, end)));
+        match value {
+            Some((value, end)) => {
+                rendered.push_str(&value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+fn placeholder(key: &str, plan: &WorktreePlan) -> Option<String> {
+    if let Some(name) = key.strip_prefix("env:") {
+        return Some(env::var(name).unwrap_or_default());
+    }
+    Some(match key {
+        "name" => plan.name.clone(),
+        "branch" => plan.branch_name().to_string(),
+        "path" => plan.path.display().to_string(),
+        "repo" => plan.repo.root.display().to_string(),
+        "base_branch" => plan.repo.base_name(),
+        "base_commit" => plan.repo.current_commit.clone(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    // A scratch repository root holding .arborist/task.md, with outside.md next to it
+    fn scratch(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "arborist-templates-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let root = dir.join("repo");
+        fs::create_dir_all(root.join(".arborist")).unwrap();
+        fs::write(root.join(".arborist/task.md"), "{name}").unwrap();
+        fs::write(dir.join("outside.md"), "secret").unwrap();
+        root
+    }
+
+    #[test]
+    fn destinations_stay_inside_the_worktree() {
+        assert!(checked_destination("notes/task.md").is_ok());
+        assert!(checked_destination("").is_err());
+        assert!(checked_destination("../task.md").is_err());
+        assert!(checked_destination("/etc/profile").is_err());
+        assert!(checked_destination("notes/../../task.md").is_err());
+    }
+
+    #[test]
+    fn repository_sources_stay_inside_the_repository() {
+        let root = scratch("sources");
+        symlink(root.join("../outside.md"), root.join("link.md")).unwrap();
+
+        let source = |path: &str| checked_source(&root, Path::new(path), true);
+        assert_eq!(
+            source(".arborist/task.md").unwrap(),
+            root.join(".arborist/task.md")
+        );
+        assert!(source("./.arborist/task.md").is_ok());
+        assert!(source("../outside.md").is_err());
+        assert!(source(".arborist/../../outside.md").is_err());
+        assert!(source(root.join("../outside.md").to_str().unwrap()).is_err());
+        assert!(source("link.md").is_err());
+        // Missing files fail when they're read, but not by escaping first
+        assert!(source("missing.md").is_ok());
+        assert!(source("../missing.md").is_err());
+
+        fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn user_sources_can_be_anywhere() {
+        let root = scratch("user");
+        let outside = root.join("../outside.md");
+        assert_eq!(
+            checked_source(&root, Path::new("../outside.md"), false).unwrap(),
+            outside
+        );
+        assert_eq!(checked_source(&root, &outside, false).unwrap(), outside);
+
+        fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn finds_environment_placeholders() {
+        assert!(uses_env("task: {env:TASK}"));
+        assert!(!uses_env("{name} on {branch}, {environment}"));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::style;
+use serde_json::{Map, Value};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// A phase of a run, timed for `--timings`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    RepoDetection,
+    WorktreeCreation,
+    Setup,
+    Command,
+    StatusCheck,
+    Cleanup,
+}
+
+impl Phase {
+    const ALL: [Phase; 6] = [
+        Phase::RepoDetection,
+        Phase::WorktreeCreation,
+        Phase::Setup,
+        Phase::Command,
+        Phase::StatusCheck,
+        Phase::Cleanup,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Phase::RepoDetection => "repo detection",
+            Phase::WorktreeCreation => "worktree creation",
+            Phase::Setup => "setup",
+            Phase::Command => "command",
+            Phase::StatusCheck => "status check",
+            Phase::Cleanup => "cleanup",
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            Phase::RepoDetection => "repo_detection_ms",
+            Phase::WorktreeCreation => "worktree_creation_ms",
+            Phase::Setup => "setup_ms",
+            Phase::Command => "command_ms",
+            Phase::StatusCheck => "status_check_ms",
+            Phase::Cleanup => "cleanup_ms",
+        }
+    }
+}
+
+// When arborist started, and the time spent in each phase so far; a phase that runs more than
+// once (e.g. for every --branches worktree) adds up
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static SPENT: Mutex<Vec<(Phase, Duration)>> = Mutex::new(Vec::new());
+
+/// Marks the start of the run, against which the total is measured
+pub fn start() {
+    STARTED.get_or_init(Instant::now);
+}
+
+/// Adds `duration` to the time spent in `phase`
+pub fn record(phase: Phase, duration: Duration) {
+    let mut spent = SPENT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    match spent.iter_mut().find(|(recorded, _)| *recorded == phase) {
+        Some((_, total)) => *total += duration,
+        None => spent.push((phase, duration)),
+    }
+}
+
+/// Runs `f`, adding the time it takes to `phase`
+pub fn time<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    record(phase, started.elapsed());
+    result
+}
+
+// Time spent in each phase that ran, in the order of a run
+fn phases() -> Vec<(Phase, Duration)> {
+    let spent = SPENT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    Phase::ALL
+        .into_iter()
+        .filter_map(|phase| {
+            spent
+                .iter()
+                .find(|(recorded, _)| *recorded == phase)
+                .copied()
+        })
+        .collect()
+}
+
+fn total() -> Duration {
+    STARTED.get().map(Instant::elapsed).unwrap_or_default()
+}
+
+/// Prints how long each phase took, what was spent outside of them, and the total
+pub fn print() {
+    let phases = phases();
+    let total = total();
+    let other = total.saturating_sub(phases.iter().map(|(_, duration)| *duration).sum());
+
+    eprintln!("{} timings", style::info("arborist:"));
+    for (label, duration) in phases
+        .iter()
+        .map(|(phase, duration)| (phase.label(), *duration))
+        .chain([("other", other), ("total", total)])
+    {
+        eprintln!("  {:<18} {:>10.2?}", label, duration);
+    }
+}
+
+/// The timings as a JSON object of milliseconds per phase, plus `total_ms`
+pub fn to_json() -> Value {
+    let mut object = Map::new();
+    for (phase, duration) in phases() {
+        object.insert(phase.key().to_string(), millis(duration).into());
+    }
+    object.insert("total_ms".to_string(), millis(total()).into());
+    Value::Object(object)
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
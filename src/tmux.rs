@@ -0,0 +1,90 @@
+use crate::tools::shell_join;
+use crate::{WorktreePlan, style};
+use arborist::error::{ArboristError, Result};
+use duct::cmd;
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+// Set on the arborist started inside the new window or pane to the worktree it should use
+const NAME_VAR: &str = "ARBORIST_TMUX_NAME";
+
+/// Where --tmux opens the worktree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Target {
+    /// A new window in the current session
+    Window,
+    /// A new pane split from the current one
+    Pane,
+}
+
+/// Worktree name handed to an arborist running inside a window or pane opened by --tmux
+pub fn inner_name() -> Option<String> {
+    env::var(NAME_VAR).ok().filter(|name| !name.is_empty())
+}
+
+/// Opens a tmux window or pane named after the worktree that re-runs this arborist
+/// invocation there, so the command runs (and is cleaned up) in the same worktree
+pub fn open(target: Target, plan: &WorktreePlan) -> Result<()> {
+    if env::var_os("TMUX").is_none() {
+        return Err(ArboristError::InvalidConfig(
+            "--tmux must be used from inside a tmux session".to_string(),
+        ));
+    }
+
+    let mut argv = vec!["env".to_string(), format!("{}={}", NAME_VAR, plan.name)];
+    argv.push(env::current_exe()?.display().to_string());
+    argv.extend(env::args().skip(1));
+    let script = shell_join(&argv);
+
+    // Start from the current directory so relative paths in the options still resolve
+    let cwd = env::current_dir()?.display().to_string();
+    match target {
+        Target::Window => {
+            verbose!("Opening tmux window '{}'", plan.name);
+            cmd!("tmux", "new-window", "-n", &plan.name, "-c", &cwd, &script).run()?;
+        }
+        Target::Pane => {
+            verbose!("Opening tmux pane '{}'", plan.name);
+            let pane = cmd!(
+                "tmux",
+                "split-window",
+                "-P",
+                "-F",
+                "#{pane_id}",
+                "-c",
+                &cwd,
+                &script
+            )
+            .read()?;
+            cmd!("tmux", "select-pane", "-t", pane.trim(), "-T", &plan.name).run()?;
+        }
+    }
+
+    notice!(
+        "Opened worktree {} in a tmux {}",
+        plan.name,
+        match target {
+            Target::Window => "window",
+            Target::Pane => "pane",
+        }
+    );
+    Ok(())
+}
+
+/// Keeps the window or pane open after the command so its output can be read,
+/// unless `close` was requested and the command succeeded
+pub fn finish(exit_code: i32, close: bool) -> Result<()> {
+    if (close && exit_code == 0) || !io::stdin().is_terminal() {
+        return Ok(());
+    }
+
+    eprint!(
+        "\n{} command exited with status {}; press enter to close",
+        style::info("[arborist]"),
+        exit_code
+    );
+    io::stderr().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(())
+}
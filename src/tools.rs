@@ -0,0 +1,103 @@
+use crate::Invocation;
+use arborist::config::{Config, Tool};
+use arborist::error::{ArboristError, Result};
+use std::env;
+
+/// Resolves a tool from the manifest into what should run in the worktree
+/// Setup commands run first; the first failing step stops the run with its exit code
+pub fn resolve(config: &Config, name: &str, extra_args: &[String]) -> Result<Invocation> {
+    let tool = find_tool(config, name)?;
+
+    let mut command = tool.cmd.clone();
+    for arg in extra_args {
+        command.push(' ');
+        command.push_str(&shell_quote(arg));
+    }
+
+    let script = tool
+        .setup
+        .iter()
+        .chain(std::iter::once(&command))
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(" && ");
+
+    Ok(Invocation {
+        command: shell_argv(&script),
+        env: tool
+            .env
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        ..Default::default()
+    })
+}
+
+/// Prints the tools defined in the manifest
+pub fn print_tools(config: &Config) {
+    let tools = config.tools.as_ref().filter(|tools| !tools.is_empty());
+    match tools {
+        Some(tools) => {
+            for (name, tool) in tools {
+                println!("{:<16} {}", name, tool.cmd);
+            }
+        }
+        None => eprintln!("No tools defined; add [tools.<name>] entries to .arborist.toml"),
+    }
+}
+
+fn find_tool<'a>(config: &'a Config, name: &str) -> Result<&'a Tool> {
+    let tools = config.tools.as_ref();
+    tools.and_then(|tools| tools.get(name)).ok_or_else(|| {
+        let known = tools
+            .map(|tools| tools.keys().cloned().collect::<Vec<_>>().join(", "))
+            .unwrap_or_default();
+        ArboristError::InvalidConfig(format!(
+            "no tool named '{}' (available: {})",
+            name,
+            if known.is_empty() { "none" } else { &known }
+        ))
+    })
+}
+
+/// Builds the argv that runs a command string through the user's shell
+pub fn shell_argv(script: &str) -> Vec<String> {
+    if cfg!(windows) {
+        vec!["cmd".to_string(), "/C".to_string(), script.to_string()]
+    } else {
+        vec![user_shell(), "-c".to_string(), script.to_string()]
+    }
+}
+
+/// The user's interactive shell ($SHELL, falling back to /bin/sh; cmd on Windows)
+pub fn user_shell() -> String {
+    if cfg!(windows) {
+        return "cmd".to_string();
+    }
+    env::var("SHELL")
+        .ok()
+        .filter(|shell| !shell.is_empty())
+        .unwrap_or_else(|| "/bin/sh".to_string())
+}
+
+// Render an argv as a POSIX shell command line
+pub fn shell_join(argv: &[String]) -> String {
+    argv.iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Quote an argument for inclusion in a POSIX shell command string
+pub fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c));
+
+    if safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
@@ -0,0 +1,448 @@
+use crate::pick::{self, Candidate};
+use crate::{
+    DirectoryGuard, archive, diff, emit_removed, environment, heartbeat, interactive,
+    remove_planned_worktree, tools,
+};
+use arborist::error::Result;
+use arborist::{git, state};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::{cursor, execute, queue, terminal};
+use duct::cmd;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// How often the screen is redrawn while waiting for keys
+const TICK: Duration = Duration::from_millis(250);
+
+const KEYS: &str = "[enter] shell  [d] diff  [x] delete  [r] refresh  [q] quit";
+
+// One worktree on the dashboard
+struct Row {
+    candidate: Candidate,
+    running: Option<state::RunInfo>,
+    // Filled in by a background thread; None until measured
+    size: Option<u64>,
+}
+
+struct Dashboard {
+    rows: Vec<Row>,
+    repos: usize,
+    selected: usize,
+    message: String,
+    // Waiting for y/n before deleting the selected worktree
+    confirm_delete: bool,
+    sizes: mpsc::Receiver<(usize, u64)>,
+}
+
+// Restores the terminal however the dashboard exits
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> Result<Self> {
+        let guard = TerminalGuard;
+        guard.resume()?;
+        Ok(guard)
+    }
+
+    // Hand the normal screen back, e.g. while a shell runs
+    fn suspend(&self) -> Result<()> {
+        execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<()> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(())
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = self.suspend();
+    }
+}
+
+// `arborist ui`: full-screen view of the worktrees of every known repository
+pub fn run_ui() -> Result<i32> {
+    if !interactive::is_available() {
+        eprintln!("arborist ui needs a terminal");
+        return Ok(1);
+    }
+
+    let mut dashboard = Dashboard::load()?;
+    let screen = TerminalGuard::enter()?;
+
+    loop {
+        dashboard.collect_sizes();
+        dashboard.draw()?;
+
+        if !event::poll(TICK)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        if dashboard.confirm_delete {
+            dashboard.confirm_delete = false;
+            dashboard.message.clear();
+            if key.code == KeyCode::Char('y') {
+                dashboard.delete_selected()?;
+            }
+            continue;
+        }
+
+        match key {
+            KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => break,
+            KeyEvent { code, .. } => match code {
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                KeyCode::Up | KeyCode::Char('k') => {
+                    dashboard.selected = dashboard.selected.saturating_sub(1);
+                }
+                KeyCode::Down | KeyCode::Char('j')
+                    if dashboard.selected + 1 < dashboard.rows.len() =>
+                {
+                    dashboard.selected += 1;
+                }
+                KeyCode::Char('r') => dashboard.reload()?,
+                KeyCode::Enter | KeyCode::Char('s') => {
+                    if let Some(row) = dashboard.rows.get(dashboard.selected) {
+                        screen.suspend()?;
+                        open_shell(row)?;
+                        screen.resume()?;
+                        dashboard.reload()?;
+                    }
+                }
+                KeyCode::Char('d') => {
+                    if let Some(row) = dashboard.rows.get(dashboard.selected) {
+                        screen.suspend()?;
+                        show_diff(row)?;
+                        screen.resume()?;
+                    }
+                }
+                KeyCode::Char('x') => {
+                    if let Some(row) = dashboard.rows.get(dashboard.selected) {
+                        let plan = &row.candidate.plan;
+                        dashboard.message = match plan.owned_branch() {
+                            Some(branch) => format!(
+                                "Delete worktree {} and branch {}? [y/N]",
+                                plan.name, branch
+                            ),
+                            None => format!("Delete worktree {}? [y/N]", plan.name),
+                        };
+                        dashboard.confirm_delete = true;
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+
+    drop(screen);
+    Ok(0)
+}
+
+impl Dashboard {
+    fn load() -> Result<Self> {
+        let (rows, repos) = load_rows()?;
+        let sizes = measure_sizes(&rows);
+        Ok(Dashboard {
+            rows,
+            repos,
+            selected: 0,
+            message: String::new(),
+            confirm_delete: false,
+            sizes,
+        })
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        let (rows, repos) = load_rows()?;
+        self.sizes = measure_sizes(&rows);
+        self.rows = rows;
+        self.repos = repos;
+        self.selected = self.selected.min(self.rows.len().saturating_sub(1));
+        Ok(())
+    }
+
+    fn collect_sizes(&mut self) {
+        while let Ok((index, size)) = self.sizes.try_recv() {
+            if let Some(row) = self.rows.get_mut(index) {
+                row.size = Some(size);
+            }
+        }
+    }
+
+    fn delete_selected(&mut self) -> Result<()> {
+        let Some(row) = self.rows.get(self.selected) else {
+            return Ok(());
+        };
+        let plan = &row.candidate.plan;
+
+        if row.running.is_some() {
+            self.message = format!("{} is running a command; not deleting it", plan.name);
+            return Ok(());
+        }
+
+        let removed = {
+            let _guard = DirectoryGuard::with_path(&plan.repo.root)?;
+            archive::archive_worktree(plan)
+                .and_then(|archived| remove_planned_worktree(plan, false).map(|()| archived))
+        };
+        self.message = match removed {
+            Ok(archived) => {
+                state::remove_worktree_meta(&plan.repo.root, &plan.name)?;
+                emit_removed(plan);
+                let mut message = match plan.owned_branch() {
+                    Some(branch) => format!("Removed worktree {} and branch {}", plan.name, branch),
+                    None => format!("Removed worktree {}", plan.name),
+                };
+                if let Some(dir) = archived {
+                    message.push_str(&format!("; its work is saved in {}", dir.display()));
+                }
+                message
+            }
+            Err(err) => format!("Failed to remove {}: {}", plan.name, err.chain()),
+        };
+        self.reload()
+    }
+
+    fn draw(&self) -> Result<()> {
+        let (width, height) = terminal::size()?;
+        let width = width as usize;
+        let mut out = io::stdout();
+
+        queue!(out, terminal::Clear(terminal::ClearType::All))?;
+        let title = format!(
+            "arborist: {} worktree(s) in {} repo(s)",
+            self.rows.len(),
+            self.repos
+        );
+        queue!(out, cursor::MoveTo(0, 0), Print(fit(&title, width)))?;
+        let header = format!(
+            "{:<10} {:<20} {:<28} {:>5} {:<9} {:>6}  {}",
+            "NAME", "BRANCH", "REPO", "AGE", "STATE", "SIZE", "RUNNING"
+        );
+        queue!(
+            out,
+            cursor::MoveTo(0, 2),
+            SetAttribute(Attribute::Bold),
+            Print(fit(&header, width)),
+            SetAttribute(Attribute::Reset)
+        )?;
+
+        // Keep the selection visible when there are more rows than lines
+        let visible = (height as usize).saturating_sub(6).max(1);
+        let first = self.selected.saturating_sub(visible - 1);
+        for (line, (index, row)) in self
+            .rows
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(visible)
+            .enumerate()
+        {
+            queue!(out, cursor::MoveTo(0, 3 + line as u16))?;
+            if index == self.selected {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(
+                out,
+                Print(fit(&row_line(row), width)),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+        if self.rows.is_empty() {
+            queue!(out, cursor::MoveTo(0, 3), Print("No arborist worktrees"))?;
+        }
+
+        queue!(
+            out,
+            cursor::MoveTo(0, height.saturating_sub(2)),
+            Print(fit(&self.message, width)),
+            cursor::MoveTo(0, height.saturating_sub(1)),
+            Print(fit(KEYS, width))
+        )?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+// Worktrees of every repository arborist has recorded
+// Returns: (rows, number of repositories they came from)
+fn load_rows() -> Result<(Vec<Row>, usize)> {
+    let mut rows = Vec::new();
+    let mut repos = 0;
+
+    for (_, repo_root) in state::known_repos()? {
+        if !repo_root.is_dir() {
+            continue;
+        }
+        let candidates = {
+            let _guard = DirectoryGuard::with_path(&repo_root)?;
+            // A repository that can no longer be read is left off the dashboard
+            match git::read_repo_info() {
+                Ok(Some(repo)) => pick::collect_candidates(&repo).unwrap_or_default(),
+                _ => continue,
+            }
+        };
+        if candidates.is_empty() {
+            continue;
+        }
+
+        repos += 1;
+        for candidate in candidates {
+            let plan = &candidate.plan;
+            let running = heartbeat::active_run(&plan.repo.root, &plan.name)?;
+            rows.push(Row {
+                candidate,
+                running,
+                size: None,
+            });
+        }
+    }
+
+    Ok((rows, repos))
+}
+
+fn row_line(row: &Row) -> String {
+    let plan = &row.candidate.plan;
+    let size = row
+        .size
+        .map(format_size)
+        .unwrap_or_else(|| "...".to_string());
+    let running = row
+        .running
+        .as_ref()
+        .map(|run| format!("{} (pid {})", run.command, run.pid))
+        .unwrap_or_default();
+
+    format!(
+        "{:<10} {:<20} {:<28} {:>5} {:<9} {:>6}  {}",
+        plan.name,
+        plan.branch_name(),
+        tail(&plan.repo.root.display().to_string(), 28),
+        row.candidate.age(),
+        row.candidate.state(),
+        size,
+        running
+    )
+}
+
+// Measure each worktree's disk usage in the background
+fn measure_sizes(rows: &[Row]) -> mpsc::Receiver<(usize, u64)> {
+    let paths: Vec<PathBuf> = rows
+        .iter()
+        .map(|row| row.candidate.plan.path.clone())
+        .collect();
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for (index, path) in paths.iter().enumerate() {
+            // The receiver is gone once the dashboard reloads or exits
+            if sender.send((index, disk_usage(path))).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+// Total size of the files under a directory, not following symlinks
+fn disk_usage(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return 0;
+    };
+
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => disk_usage(&entry.path()),
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            _ => 0,
+        })
+        .sum()
+}
+
+fn open_shell(row: &Row) -> Result<()> {
+    let plan = &row.candidate.plan;
+    println!(
+        "arborist: opening a shell in {} (exit to return)",
+        plan.name
+    );
+
+    let mut shell = cmd!(tools::user_shell()).dir(&plan.path).unchecked();
+    for (key, value) in environment::worktree_vars(plan) {
+        shell = shell.env(key, value);
+    }
+    shell.run()?;
+    Ok(())
+}
+
+// Everything the worktree changed since it was created, committed or not
+fn show_diff(row: &Row) -> Result<()> {
+    diff::show_changes(&row.candidate.plan, false, true)?;
+
+    // The pager may exit immediately for short diffs; wait so the output can be read
+    print!("-- press any key to return --");
+    io::stdout().flush()?;
+    terminal::enable_raw_mode()?;
+    let read = wait_for_key();
+    terminal::disable_raw_mode()?;
+    read
+}
+
+fn wait_for_key() -> Result<()> {
+    loop {
+        if let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            return Ok(());
+        }
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "K", "M", "G", "T"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}{}", size, UNITS[unit])
+    }
+}
+
+// Truncate a line to the terminal width
+fn fit(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
+
+// Keep the end of a long value, which is the distinctive part of a path
+fn tail(value: &str, width: usize) -> String {
+    let count = value.chars().count();
+    if count <= width {
+        return value.to_string();
+    }
+    let skip = count - width + 1;
+    format!("…{}", value.chars().skip(skip).collect::<String>())
+}
@@ -0,0 +1,170 @@
+use crate::lifecycle::{self, Hook};
+use crate::{
+    Args, DirectoryGuard, Invocation, WorktreePlan, cancel, create_planned_worktree, environment,
+    excluded_paths, plan_worktree, require_repo, resolve_subdir, style, wrap_invocation,
+};
+use arborist::error::{ArboristError, Result};
+use arborist::{git, process};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+// Worktree recycled by every watch session of a repository
+const WATCH_NAME: &str = "watch";
+
+// Quiet period after a change, so saving several files restarts the command once
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+// How often a running command is checked for having exited
+const POLL: Duration = Duration::from_millis(100);
+
+type Events = Receiver<notify::Result<notify::Event>>;
+
+// `arborist watch`: mirror the checkout into a worktree and re-run the command on every change
+pub fn run_watch(args: &Args, command: &[String]) -> Result<i32> {
+    verbose!("Checking repository...");
+    let repo = require_repo()?;
+    if repo.is_bare {
+        return Err(ArboristError::InvalidPath(
+            "watch needs a checkout to watch; run it from a non-bare repository".to_string(),
+        ));
+    }
+
+    let root = repo.root.clone();
+    let plan = plan_worktree(repo, WATCH_NAME.to_string())?;
+    create_planned_worktree(&plan, args)?;
+    let excluded = excluded_paths(&plan)?;
+
+    let mut invocation = Invocation::from_argv(command).with_user_env(args)?;
+    invocation.env.extend(environment::worktree_vars(&plan));
+    let run_dir = resolve_subdir(&plan, args.subdir.as_deref())?;
+    let workdir = run_dir.unwrap_or_else(|| plan.path.clone());
+    {
+        let _guard = DirectoryGuard::with_path(&plan.path)?;
+        wrap_invocation(&plan, args, &mut invocation, workdir.clone())?;
+    }
+    invocation.dir = Some(workdir);
+
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(event);
+    })
+    .map_err(watch_failed)?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(watch_failed)?;
+
+    notice!(
+        "Watching {} and running in {}; press Ctrl-C to stop",
+        root.display(),
+        plan.path.display()
+    );
+
+    // Ctrl-C stops the running command and ends the session instead of killing arborist
+    let _supervising = cancel::Supervising::new();
+    let git_dir = root.join(".git");
+    let mut tree = git::snapshot_tree(&root, &[])?;
+    loop {
+        git::checkout_tree(&plan.path, &tree, &excluded)?;
+        // Each run gets the pre-run and post-run hooks; a failing pre-run skips it
+        let mut child = match lifecycle::run(&plan, Hook::PreRun, &[]) {
+            Ok(()) => {
+                notice!(
+                    "{} running {}",
+                    style::info("[arborist]"),
+                    invocation.command.join(" ")
+                );
+                Some(invocation.command().spawn()?)
+            }
+            Err(err) => {
+                warning!("{}; waiting for changes", err.chain());
+                None
+            }
+        };
+
+        let mut running = child.is_some();
+        tree = loop {
+            if running
+                && let Some(child) = &mut child
+                && let Some(status) = child.try_status()?
+            {
+                let exit_code = process::exit_code(&status);
+                notice!(
+                    "{} exited with status {}; waiting for changes",
+                    style::info("[arborist]"),
+                    exit_code
+                );
+                post_run(&plan, exit_code);
+                running = false;
+            }
+            if cancel::is_cancelled() {
+                if running && let Some(child) = &mut child {
+                    stop(child)?;
+                    post_run(&plan, cancel::INTERRUPTED);
+                }
+                return Ok(cancel::INTERRUPTED);
+            }
+
+            if !wait_for_change(&events, &git_dir)? {
+                continue;
+            }
+            // Changes to ignored files leave the snapshot as it was
+            let next = git::snapshot_tree(&root, &[])?;
+            if next != tree {
+                if running && let Some(child) = &mut child {
+                    stop(child)?;
+                    post_run(&plan, cancel::INTERRUPTED);
+                    notice!("{} files changed; restarting", style::info("[arborist]"));
+                }
+                break next;
+            }
+        };
+    }
+}
+
+// Kills a running command and waits for it to be gone
+fn stop(child: &mut process::Child) -> Result<()> {
+    child.kill()?;
+    process::block_on(child.status())?;
+    Ok(())
+}
+
+// Runs the post-run hook for a run that exited with `exit_code`, or was stopped (INTERRUPTED); a
+// failing hook is only a warning, as after any other run
+fn post_run(plan: &WorktreePlan, exit_code: i32) {
+    if let Err(err) = lifecycle::run(
+        plan,
+        Hook::PostRun,
+        &[("ARBORIST_EXIT_CODE", exit_code.to_string())],
+    ) {
+        warning!("{}", err.chain());
+    }
+}
+
+// Whether a change to the checkout arrived within the poll interval, after letting it settle
+fn wait_for_change(events: &Events, git_dir: &Path) -> Result<bool> {
+    match events.recv_timeout(POLL) {
+        Ok(event) => {
+            if !is_change(event.map_err(watch_failed)?, git_dir) {
+                return Ok(false);
+            }
+            while events.recv_timeout(DEBOUNCE).is_ok() {}
+            Ok(true)
+        }
+        Err(RecvTimeoutError::Timeout) => Ok(false),
+        Err(RecvTimeoutError::Disconnected) => Err(ArboristError::WatchFailed(
+            "file watcher stopped unexpectedly".to_string(),
+        )),
+    }
+}
+
+// Reads (including snapshotting itself) and git's own bookkeeping aren't changes
+fn is_change(event: notify::Event, git_dir: &Path) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+        && event.paths.iter().any(|path| !path.starts_with(git_dir))
+}
+
+fn watch_failed(err: notify::Error) -> ArboristError {
+    ArboristError::WatchFailed(err.to_string())
+}
@@ -0,0 +1,43 @@
+use crate::error::{ArboristError, Result};
+use duct::cmd;
+use serde::Serialize;
+
+// Seconds before an unresponsive endpoint is given up on
+const POST_TIMEOUT_SECS: &str = "10";
+
+/// POSTs a JSON payload to the given URL using curl
+pub fn post_json<T: Serialize>(url: &str, payload: &T) -> Result<()> {
+    let body = serde_json::to_vec(payload)?;
+
+    let output = cmd!(
+        "curl",
+        "--silent",
+        "--show-error",
+        "--fail",
+        "--max-time",
+        POST_TIMEOUT_SECS,
+        "--request",
+        "POST",
+        "--header",
+        "Content-Type: application/json",
+        "--data-binary",
+        "@-",
+        url
+    )
+    .stdin_bytes(body)
+    .stderr_capture()
+    .stdout_null()
+    .unchecked()
+    .run()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(ArboristError::WebhookFailed(format!(
+            "{}: {}",
+            url,
+            stderr.trim()
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,276 @@
+use crate::{
+    Args, CleanupOutcome, DirectoryGuard, Invocation, Provisioning, WorktreePlan, artifacts,
+    cleanup_worktree, create_planned_worktree, error_exit_code, heartbeat, limits, lock,
+    plan_new_worktree, select_name, style,
+};
+use crate::{lifecycle, steps};
+use arborist::context::RunContext;
+use arborist::error::{ArboristError, Result};
+use arborist::{config, git, process, state};
+use serde_json::json;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+// Manifest of the member worktrees, in the workspace view
+const MANIFEST_FILE: &str = "arborist-workspace.json";
+
+// A member repository's worktree for the run
+struct Member {
+    // Path relative to the workspace root
+    member: PathBuf,
+    plan: WorktreePlan,
+    provisioning: Provisioning,
+}
+
+// `arborist ws`: give every repository of the workspace a worktree of the same name, run the
+// command once across them, then keep or remove each worktree on its own merits
+pub fn run_workspace(args: &Args, command: &[String]) -> Result<i32> {
+    if args.container.is_some() || args.nix {
+        return Err(ArboristError::InvalidConfig(
+            "--container and --nix apply to a single worktree and can't be used with ws"
+                .to_string(),
+        ));
+    }
+    let cwd = env::current_dir()?;
+    let workspace = config::find_workspace(&cwd)?.ok_or_else(|| {
+        ArboristError::InvalidConfig(format!(
+            "no arborist-workspace.toml in {} or above it",
+            cwd.display()
+        ))
+    })?;
+
+    let name = select_name(args, command)?;
+    let mut members = Vec::new();
+    for member in &workspace.members {
+        match provision(&workspace, member, &name, args) {
+            Ok(provisioned) => members.push(provisioned),
+            Err(err) => {
+                // Don't strand the worktrees already made for the other members
+                for provisioned in &members {
+                    cleanup(provisioned, args, None)?;
+                }
+                return Err(err);
+            }
+        }
+    }
+
+    let view = workspace_view(&workspace, &name, &members)?;
+    let mut invocation = Invocation::from_argv(command).with_user_env(args)?;
+    invocation
+        .env
+        .extend(workspace_vars(&name, &view, &members));
+    invocation.limits = limits::Limits::from_args(args);
+    // Start where the user is, as seen through the view
+    let relative = cwd.strip_prefix(&workspace.root).unwrap_or(Path::new(""));
+    let start = view.join(relative);
+    invocation.dir = Some(if start.is_dir() { start } else { view.clone() });
+
+    let run = state::RunInfo {
+        pid: std::process::id(),
+        command: invocation.command.join(" "),
+        started_at: state::unix_now(),
+        heartbeat_at: 0,
+        host: heartbeat::host(),
+    };
+    let started = Instant::now();
+    let results = members
+        .iter()
+        .map(|member| {
+            let heartbeat =
+                heartbeat::Heartbeat::start(&member.plan.repo.root, &member.plan.name, &run)?;
+            Ok((heartbeat, lock::RunLock::acquire(&member.plan.path)?))
+        })
+        .collect::<Result<Vec<_>>>()
+        .and_then(|_held| {
+            let plans: Vec<&WorktreePlan> = members.iter().map(|member| &member.plan).collect();
+            process::block_on(lifecycle::run_steps(&plans, &invocation, args))
+        });
+    let duration = started.elapsed();
+    for member in &members {
+        state::remove_run(&member.plan.repo.root, &member.plan.name)?;
+    }
+    let ran = results.is_ok();
+    let attempts = results.as_deref().map_or(0, steps::attempts);
+    let exit_code = match &results {
+        Ok(results) => steps::exit_code(results),
+        Err(err) => {
+            eprintln!("{} {}", style::error("Error:"), err.chain());
+            error_exit_code()
+        }
+    };
+
+    notice!(
+        "{} workspace {}",
+        style::info("arborist:"),
+        workspace.root.display()
+    );
+    let width = members
+        .iter()
+        .map(|member| member.member.display().to_string().len())
+        .max()
+        .unwrap_or(0);
+    for member in &members {
+        let outcome = cleanup(member, args, ran.then_some(exit_code))?;
+        record_history(
+            member,
+            &run,
+            exit_code,
+            attempts,
+            duration.as_millis() as u64,
+            &outcome,
+        );
+        let path = member.plan.path.display().to_string();
+        notice!(
+            "  {}  {}",
+            style::worktree(
+                &member.plan.name,
+                &format!("{:<width$}", member.member.display(), width = width)
+            ),
+            if outcome.kept() {
+                format!("kept at {}", path)
+            } else {
+                "removed".to_string()
+            }
+        );
+    }
+    if let Err(err) = fs::remove_dir_all(&view) {
+        verbose!("Warning: failed to remove {}: {}", view.display(), err);
+    }
+    if let Some(parent) = view.parent() {
+        let _ = fs::remove_dir(parent);
+    }
+
+    Ok(exit_code)
+}
+
+// Plan and create the worktree of one member repository
+fn provision(
+    workspace: &config::Workspace,
+    member: &Path,
+    name: &str,
+    args: &Args,
+) -> Result<Member> {
+    let dir = workspace.root.join(member);
+    let _guard = DirectoryGuard::with_path(&dir)?;
+    let repo = git::get_repo_info()?.ok_or_else(|| ArboristError::NotARepo { dir: dir.clone() })?;
+    let plan = plan_new_worktree(repo, name.to_string(), args)?;
+    let provisioning = create_planned_worktree(&plan, args)?;
+    Ok(Member {
+        member: member.to_path_buf(),
+        plan,
+        provisioning,
+    })
+}
+
+fn cleanup(member: &Member, args: &Args, exit_code: Option<i32>) -> Result<CleanupOutcome> {
+    // Removal happens from the directory left, which must be in the member's repository
+    let _repo = DirectoryGuard::with_path(&member.plan.repo.root)?;
+    let context = RunContext::enter(&member.plan.path, &member.plan.repo.root)?;
+    cleanup_worktree(&member.plan, context, args, exit_code)
+}
+
+// A directory laid out like the workspace, with each member linked to its worktree, and the
+// manifest of the worktrees next to them
+fn workspace_view(
+    workspace: &config::Workspace,
+    name: &str,
+    members: &[Member],
+) -> Result<PathBuf> {
+    let view = git::worktree_root()
+        .join("workspaces")
+        .join(git::path_hash(&workspace.root))
+        .join(name);
+    if view.exists() {
+        fs::remove_dir_all(&view)?;
+    }
+    fs::create_dir_all(&view)?;
+
+    let mut manifest = Vec::new();
+    for member in members {
+        let link = view.join(&member.member);
+        if let Some(parent) = link.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        artifacts::symlink_dir(&member.plan.path, &link)?;
+        manifest.push(json!({
+            "member": member.member,
+            "repo": member.plan.repo.root.to_string_lossy(),
+            "path": member.plan.path.to_string_lossy(),
+            "branch": member.plan.branch,
+            "base_commit": member.plan.repo.current_commit,
+        }));
+    }
+    let manifest = json!({
+        "name": name,
+        "workspace": workspace.root.to_string_lossy(),
+        "members": manifest,
+    });
+    fs::write(
+        view.join(MANIFEST_FILE),
+        serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    Ok(view)
+}
+
+// ARBORIST_WORKSPACE and the manifest, plus ARBORIST_WS_<MEMBER> with each member's worktree,
+// e.g. ARBORIST_WS_LIBS_COMMON for libs/common
+fn workspace_vars(name: &str, view: &Path, members: &[Member]) -> Vec<(String, String)> {
+    let mut vars = vec![
+        ("ARBORIST_NAME".to_string(), name.to_string()),
+        ("ARBORIST_WORKSPACE".to_string(), view.display().to_string()),
+        (
+            "ARBORIST_WORKSPACE_MANIFEST".to_string(),
+            view.join(MANIFEST_FILE).display().to_string(),
+        ),
+    ];
+    for member in members {
+        let key: String = member
+            .member
+            .display()
+            .to_string()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        vars.push((
+            format!("ARBORIST_WS_{}", key),
+            member.plan.path.display().to_string(),
+        ));
+    }
+    vars
+}
+
+fn record_history(
+    member: &Member,
+    run: &state::RunInfo,
+    exit_code: i32,
+    attempts: u32,
+    duration_ms: u64,
+    outcome: &CleanupOutcome,
+) {
+    let record = state::RunRecord {
+        name: member.plan.name.clone(),
+        command: run.command.clone(),
+        started_at: run.started_at,
+        duration_ms,
+        finished_at: Some(run.started_at + duration_ms / 1000),
+        exit_code,
+        attempts,
+        head: None,
+        reused: member.provisioning.reused,
+        setup_ms: (!member.provisioning.reused)
+            .then_some(member.provisioning.duration.as_millis() as u64),
+        kept: outcome.kept(),
+        log: None,
+    };
+    if let Err(err) = state::append_history(&member.plan.repo.root, &record) {
+        verbose!("Warning: failed to record run history: {}", err.chain());
+    }
+}